@@ -1,3 +1,6 @@
+use std::env;
+use std::fs;
+use std::path::Path;
 use vergen::{generate_cargo_keys, ConstantsFlags};
 
 fn main() {
@@ -5,4 +8,72 @@ fn main() {
     flags.toggle(ConstantsFlags::SHA_SHORT);
 
     generate_cargo_keys(flags).expect("Unable to generate the cargo keys!");
+
+    embed_migration_versions();
+
+    if env::var_os("CARGO_FEATURE_EMBEDDED_ASSETS").is_some() {
+        embed_asset_etags();
+    }
+}
+
+/* Diesel derives migration versions from the directory names, e.g. `2018-07-09-044212_create_notebooks`
+is version `20180709044212`. The readiness check compares these against the applied migrations. */
+fn embed_migration_versions() {
+    let mut versions: Vec<String> = fs::read_dir("migrations")
+        .expect("Unable to read migrations directory!")
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let version = name.split('_').next()?.replace('-', "");
+            Some(version)
+        })
+        .collect();
+    versions.sort();
+
+    println!("cargo:rerun-if-changed=migrations");
+    println!(
+        "cargo:rustc-env=PRAGMA_MIGRATION_VERSIONS={}",
+        versions.join(",")
+    );
+}
+
+/* Strong ETags for the embedded assets, from a hash of their contents. Written to
+`asset_etags.rs` as a sorted list of paths relative to `assets/` and their ETags. */
+fn embed_asset_etags() {
+    let mut etags = vec![];
+    collect_asset_etags(Path::new("assets"), "", &mut etags);
+    etags.sort();
+
+    let entries: Vec<String> = etags
+        .iter()
+        .map(|(path, etag)| format!("    ({:?}, {:?}),\n", path, etag))
+        .collect();
+    let source = format!(
+        "static ASSET_ETAGS: &[(&str, &str)] = &[\n{}];\n",
+        entries.concat()
+    );
+
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not set!");
+    fs::write(Path::new(&out_dir).join("asset_etags.rs"), source)
+        .expect("Unable to write asset ETags!");
+
+    println!("cargo:rerun-if-changed=assets");
+}
+
+fn collect_asset_etags(directory: &Path, prefix: &str, etags: &mut Vec<(String, String)>) {
+    let entries = fs::read_dir(directory).expect("Unable to read assets directory!");
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = format!("{}{}", prefix, name);
+
+        if entry.path().is_dir() {
+            collect_asset_etags(&entry.path(), &format!("{}/", path), etags);
+        } else {
+            let contents = fs::read(entry.path()).expect("Unable to read asset!");
+            let etag = format!("\"{}\"", sha1::Sha1::from(&contents).digest());
+            etags.push((path, etag));
+        }
+    }
 }
@@ -0,0 +1,8 @@
+CREATE TABLE api_tokens (
+  id VARCHAR(10) NOT NULL PRIMARY KEY,
+  name VARCHAR NOT NULL UNIQUE,
+  token_hash VARCHAR NOT NULL UNIQUE,
+  scope VARCHAR NOT NULL,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_used_at DATETIME
+);
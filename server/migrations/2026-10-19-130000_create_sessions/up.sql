@@ -0,0 +1,6 @@
+CREATE TABLE sessions (
+  id_hash VARCHAR NOT NULL PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  created_at DATETIME NOT NULL,
+  expires_at DATETIME NOT NULL
+);
@@ -0,0 +1,14 @@
+CREATE TABLE two_factor (
+  username VARCHAR NOT NULL PRIMARY KEY,
+  secret VARCHAR NOT NULL,
+  confirmed BOOLEAN NOT NULL DEFAULT 0,
+  last_used_step BIGINT,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE recovery_codes (
+  code_hash VARCHAR NOT NULL PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  used_at DATETIME,
+  FOREIGN KEY(username) REFERENCES two_factor(username) ON DELETE CASCADE
+);
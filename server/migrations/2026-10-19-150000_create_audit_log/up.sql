@@ -0,0 +1,12 @@
+CREATE TABLE audit_log (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  resource_type VARCHAR NOT NULL,
+  resource_id VARCHAR NOT NULL,
+  action VARCHAR NOT NULL,
+  actor VARCHAR NOT NULL,
+  client_ip VARCHAR,
+  user_agent VARCHAR
+);
+
+CREATE INDEX audit_log_created_at ON audit_log (created_at);
@@ -0,0 +1 @@
+-- Sanitized HTML can't be restored.
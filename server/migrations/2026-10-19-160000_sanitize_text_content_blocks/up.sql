@@ -0,0 +1 @@
+-- Text content blocks are sanitized by repo::setup after this migration is applied.
@@ -0,0 +1,25 @@
+-- SQLite can't drop columns, so both tables are rebuilt. Pending enrollments are lost.
+CREATE TABLE recovery_codes_backup AS
+  SELECT code_hash, username, used_at FROM recovery_codes WHERE NOT pending;
+DROP TABLE recovery_codes;
+
+CREATE TABLE two_factor_new (
+  username VARCHAR NOT NULL PRIMARY KEY,
+  secret VARCHAR NOT NULL,
+  confirmed BOOLEAN NOT NULL DEFAULT 0,
+  last_used_step BIGINT,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+INSERT INTO two_factor_new
+  SELECT username, secret, confirmed, last_used_step, created_at FROM two_factor;
+DROP TABLE two_factor;
+ALTER TABLE two_factor_new RENAME TO two_factor;
+
+CREATE TABLE recovery_codes (
+  code_hash VARCHAR NOT NULL PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  used_at DATETIME,
+  FOREIGN KEY(username) REFERENCES two_factor(username) ON DELETE CASCADE
+);
+INSERT INTO recovery_codes SELECT code_hash, username, used_at FROM recovery_codes_backup;
+DROP TABLE recovery_codes_backup;
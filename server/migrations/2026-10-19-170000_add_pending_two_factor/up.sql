@@ -0,0 +1,3 @@
+-- A new enrollment is pending until confirmed, while the confirmed one stays in effect.
+ALTER TABLE two_factor ADD COLUMN pending_secret VARCHAR;
+ALTER TABLE recovery_codes ADD COLUMN pending BOOLEAN NOT NULL DEFAULT 0;
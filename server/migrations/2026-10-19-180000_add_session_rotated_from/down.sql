@@ -0,0 +1,10 @@
+-- SQLite can't drop columns, so the table is rebuilt.
+CREATE TABLE sessions_new (
+  id_hash VARCHAR NOT NULL PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  created_at DATETIME NOT NULL,
+  expires_at DATETIME NOT NULL
+);
+INSERT INTO sessions_new SELECT id_hash, username, created_at, expires_at FROM sessions;
+DROP TABLE sessions;
+ALTER TABLE sessions_new RENAME TO sessions;
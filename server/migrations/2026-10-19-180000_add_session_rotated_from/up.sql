@@ -0,0 +1,2 @@
+-- Links a rotated session to the one it replaced, so logging out ends both.
+ALTER TABLE sessions ADD COLUMN rotated_from VARCHAR;
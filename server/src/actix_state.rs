@@ -1,6 +1,22 @@
+use super::auth;
+use super::cors;
+use super::diagram::SvgCache;
+use super::metrics::Metrics;
+use super::proxy::TrustedProxies;
+use super::rate_limit::RateLimiter;
 use super::repo_actor;
-use ::actix::prelude::*;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 pub struct State {
-    pub db: Addr<repo_actor::DbExecutor>,
+    pub db: repo_actor::Db,
+    pub auth: auth::Settings,
+    pub cors: cors::Settings,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub trusted_proxies: Arc<TrustedProxies>,
+    pub metrics: Arc<Metrics>,
+    pub database_path: PathBuf,
+    pub assets_directory: Option<PathBuf>,
+    pub base_path: String,
+    pub svg_cache: Arc<SvgCache>,
 }
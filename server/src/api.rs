@@ -1,13 +1,22 @@
-use actix_web::http::{Method, StatusCode};
+use actix_web::http::{header, Method, StatusCode};
 use actix_web::{App, AsyncResponder, Error, HttpRequest, HttpResponse, Json, Path, Query};
 use chrono::prelude::*;
 
 use super::actix_state::State;
+use super::assets;
+use super::audit;
 use super::auth;
 use super::build_info;
+use super::cors;
 use super::data::*;
+use super::diagram;
+use super::markdown;
+use super::metrics;
+use super::render;
 use super::repo_actor::*;
-use futures::future::Future;
+use super::table;
+use super::two_factor;
+use futures::future::{self, Future};
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -34,15 +43,29 @@ struct Resource {
     type_: String,
 }
 
+#[derive(Serialize)]
+struct HtmlResponse {
+    html: String,
+}
+
+#[derive(Deserialize)]
+struct MarkdownRequest {
+    markdown: String,
+}
+
 #[derive(Deserialize)]
 struct GetDataQuery {
     since_revision: Option<DateTime<Utc>>,
 }
 
-pub fn mount(app: App<State>, auth_token: String) -> App<State> {
+pub fn mount(app: App<State>) -> App<State> {
     #[cfg_attr(rustfmt, rustfmt_skip)]
     app.scope("/api", |scope| {
-       scope.middleware(auth::middleware(auth_token))
+       scope.middleware(metrics::scope_middleware("/api"))
+            .middleware(cors::middleware())
+            .middleware(auth::middleware())
+            .route("/login", Method::POST, auth::login)
+            .route("/logout", Method::POST, auth::logout)
             .route("/auth", Method::POST, auth::check_token)
             .route("/data", Method::GET, get_data)
             .route("/notes", Method::POST, create_note)
@@ -52,8 +75,21 @@ pub fn mount(app: App<State>, auth_token: String) -> App<State> {
             .route("/notebooks/{id}", Method::PUT, update_notebook)
             .route("/notebooks/{id}", Method::DELETE, delete_notebook)
             .route("/content_blocks", Method::POST, create_content_block)
+            .route("/content_blocks/csv", Method::POST, import_csv)
             .route("/content_blocks/{id}", Method::PUT, update_content_block)
             .route("/content_blocks/{id}", Method::DELETE, delete_content_block)
+            .route("/content_blocks/{id}/html", Method::GET, get_content_block_html)
+            .route("/content_blocks/{id}/svg", Method::GET, get_content_block_svg)
+            .route("/content_blocks/{id}/csv", Method::GET, export_csv)
+            .route("/markdown", Method::POST, render_markdown)
+            .route("/checklist_items", Method::GET, get_open_checklist_items)
+            .route("/tokens", Method::GET, get_api_tokens)
+            .route("/tokens", Method::POST, create_api_token)
+            .route("/tokens/{id}", Method::DELETE, delete_api_token)
+            .route("/two_factor", Method::POST, enroll_two_factor)
+            .route("/two_factor", Method::DELETE, disable_two_factor)
+            .route("/two_factor/confirm", Method::POST, confirm_two_factor)
+            .route("/audit", Method::GET, get_audit_log)
     })
     .route("/version", Method::GET, |_: HttpRequest<State>|
         HttpResponse::Ok()
@@ -66,10 +102,11 @@ pub fn mount(app: App<State>, auth_token: String) -> App<State> {
 fn create_notebook(
     (req, new_notebook): (HttpRequest<State>, Json<NewNotebook>),
 ) -> Box<Future<Item = HttpResponse, Error = Error>> {
-    let db = &req.state().db;
+    let db = &req.state().db.for_request(&req);
 
     db.send(CreateNotebookMessage {
         new_notebook: new_notebook.into_inner(),
+        audit: audit::context(&req),
     })
     .from_err()
     .and_then(move |res| match res {
@@ -82,10 +119,11 @@ fn create_notebook(
 fn create_note(
     (req, new_note): (HttpRequest<State>, Json<NewNote>),
 ) -> Box<Future<Item = HttpResponse, Error = Error>> {
-    let db = &req.state().db;
+    let db = &req.state().db.for_request(&req);
 
     db.send(CreateNoteMessage {
         new_note: new_note.into_inner(),
+        audit: audit::context(&req),
     })
     .from_err()
     .and_then(move |res| match res {
@@ -98,10 +136,45 @@ fn create_note(
 fn create_content_block(
     (req, new_content_block): (HttpRequest<State>, Json<NewContentBlock>),
 ) -> Box<Future<Item = HttpResponse, Error = Error>> {
-    let db = &req.state().db;
+    if let Err(reason) = new_content_block.content.validate() {
+        return Box::new(future::ok(HttpResponse::BadRequest().body(reason)));
+    }
+
+    let db = &req.state().db.for_request(&req);
 
     db.send(CreateContentBlockMessage {
         new_content_block: new_content_block.into_inner(),
+        audit: audit::context(&req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(content_block) => Ok(HttpResponse::Ok().json(content_block)),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
+}
+
+/* Creates a table block from a CSV file, e.g. exported from a spreadsheet. */
+fn import_csv(
+    (req, import, csv): (HttpRequest<State>, Query<CsvImport>, String),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let table = match table::from_csv(&csv) {
+        Ok(table) => table,
+        Err(reason) => return Box::new(future::ok(HttpResponse::BadRequest().body(reason))),
+    };
+    let now = Utc::now();
+
+    let db = &req.state().db.for_request(&req);
+
+    db.send(CreateContentBlockMessage {
+        new_content_block: NewContentBlock {
+            id: None,
+            content: Content::Table(table),
+            created_at: now,
+            updated_at: now,
+            note_id: import.into_inner().note_id,
+        },
+        audit: audit::context(&req),
     })
     .from_err()
     .and_then(move |res| match res {
@@ -118,13 +191,18 @@ fn update_content_block(
         Json<ContentBlockUpdate>,
     ),
 ) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    if let Err(reason) = content_block_update.content.validate() {
+        return Box::new(future::ok(HttpResponse::BadRequest().body(reason)));
+    }
+
     let id = params.into_inner();
 
-    let db = &req.state().db;
+    let db = &req.state().db.for_request(&req);
 
     db.send(UpdateContentBlockMessage {
         id,
         update: content_block_update.into_inner(),
+        audit: audit::context(&req),
     })
     .from_err()
     .and_then(move |res| match res {
@@ -139,15 +217,126 @@ fn delete_content_block(
 ) -> Box<Future<Item = HttpResponse, Error = Error>> {
     let id = params.into_inner();
 
-    let db = &req.state().db;
+    let db = &req.state().db.for_request(&req);
 
-    db.send(DeleteContentBlockMessage { id })
-        .from_err()
-        .and_then(move |res| match res {
-            Ok(_) => Ok(HttpResponse::Ok().finish()),
+    db.send(DeleteContentBlockMessage {
+        id,
+        audit: audit::context(&req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
+}
+
+fn get_content_block_html(
+    (req, params): (HttpRequest<State>, Path<String>),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let db = &req.state().db.for_request(&req);
+
+    db.send(GetContentBlockMessage {
+        id: params.into_inner(),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(Some(content_block)) => match render::to_html(&content_block.content) {
+            Ok(html) => Ok(HttpResponse::Ok().json(HtmlResponse { html })),
             Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
-        })
-        .responder()
+        },
+        Ok(None) => Ok(HttpResponse::NotFound().finish()),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
+}
+
+/* Diagrams are rendered once per source and revalidated with an ETag of the source's hash. */
+fn get_content_block_svg(
+    (req, params): (HttpRequest<State>, Path<String>),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let db = &req.state().db.for_request(&req);
+
+    db.send(GetContentBlockMessage {
+        id: params.into_inner(),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(Some(ContentBlock {
+            content: Content::Diagram { dot },
+            ..
+        })) => {
+            let hash = diagram::source_hash(&dot);
+            let etag = format!("\"{}\"", hash);
+            if assets::is_not_modified(&req, &etag) {
+                return Ok(HttpResponse::NotModified()
+                    .header(header::ETAG, etag)
+                    .finish());
+            }
+
+            match req.state().svg_cache.get_or_render(&hash, &dot) {
+                Ok(svg) => Ok(HttpResponse::Ok()
+                    .content_type("image/svg+xml")
+                    .header(header::ETAG, etag)
+                    .header(header::CACHE_CONTROL, "no-cache")
+                    .body(svg.as_str().to_string())),
+                Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+            }
+        }
+        Ok(_) => Ok(HttpResponse::NotFound().finish()),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
+}
+
+fn export_csv(
+    (req, params): (HttpRequest<State>, Path<String>),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let db = &req.state().db.for_request(&req);
+
+    db.send(GetContentBlockMessage {
+        id: params.into_inner(),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(Some(ContentBlock {
+            content: Content::Table(table),
+            ..
+        })) => Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .header(
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"table.csv\"",
+            )
+            .body(table::to_csv(&table))),
+        Ok(_) => Ok(HttpResponse::NotFound().finish()),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
+}
+
+/* Renders Markdown that isn't stored yet, e.g. to preview it while editing. */
+fn render_markdown(request: Json<MarkdownRequest>) -> HttpResponse {
+    match markdown::to_html(&request.markdown) {
+        Ok(html) => HttpResponse::Ok().json(HtmlResponse { html }),
+        Err(reason) => HttpResponse::BadRequest().body(reason),
+    }
+}
+
+fn get_open_checklist_items(
+    (req, filter): (HttpRequest<State>, Query<ChecklistItemFilter>),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let db = &req.state().db.for_request(&req);
+
+    db.send(GetOpenChecklistItemsMessage {
+        filter: filter.into_inner(),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(open_items) => Ok(HttpResponse::Ok().json(open_items)),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
 }
 
 fn update_note(
@@ -155,11 +344,12 @@ fn update_note(
 ) -> Box<Future<Item = HttpResponse, Error = Error>> {
     let id = params.into_inner();
 
-    let db = &req.state().db;
+    let db = &req.state().db.for_request(&req);
 
     db.send(UpdateNoteMessage {
         id,
         update: note_update.into_inner(),
+        audit: audit::context(&req),
     })
     .from_err()
     .and_then(move |res| match res {
@@ -174,15 +364,18 @@ fn delete_note(
 ) -> Box<Future<Item = HttpResponse, Error = Error>> {
     let id = params.into_inner();
 
-    let db = &req.state().db;
+    let db = &req.state().db.for_request(&req);
 
-    db.send(DeleteNoteMessage { id })
-        .from_err()
-        .and_then(move |res| match res {
-            Ok(_) => Ok(HttpResponse::Ok().finish()),
-            Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
-        })
-        .responder()
+    db.send(DeleteNoteMessage {
+        id,
+        audit: audit::context(&req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
 }
 
 fn update_notebook(
@@ -190,11 +383,12 @@ fn update_notebook(
 ) -> Box<Future<Item = HttpResponse, Error = Error>> {
     let id = params.into_inner();
 
-    let db = &req.state().db;
+    let db = &req.state().db.for_request(&req);
 
     db.send(UpdateNotebookMessage {
         id,
         update: notebook_update.into_inner(),
+        audit: audit::context(&req),
     })
     .from_err()
     .and_then(move |res| match res {
@@ -209,21 +403,213 @@ fn delete_notebook(
 ) -> Box<Future<Item = HttpResponse, Error = Error>> {
     let id = params.into_inner();
 
-    let db = &req.state().db;
+    let db = &req.state().db.for_request(&req);
 
-    db.send(DeleteNotebookMessage { id })
+    db.send(DeleteNotebookMessage {
+        id,
+        audit: audit::context(&req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
+}
+
+fn get_api_tokens(req: HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let db = &req.state().db.for_request(&req);
+
+    db.send(GetApiTokensMessage)
         .from_err()
         .and_then(move |res| match res {
-            Ok(_) => Ok(HttpResponse::Ok().finish()),
+            Ok(api_tokens) => Ok(HttpResponse::Ok().json(api_tokens)),
             Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
         })
         .responder()
 }
 
+fn create_api_token(
+    (req, new_api_token): (HttpRequest<State>, Json<NewApiToken>),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let db = &req.state().db.for_request(&req);
+    let token = auth::random_token();
+
+    db.send(CreateApiTokenMessage {
+        new_api_token: new_api_token.into_inner(),
+        token_hash: auth::hash_token(&token),
+        audit: audit::context(&req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(api_token) => Ok(HttpResponse::Ok().json(CreatedApiToken { api_token, token })),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
+}
+
+fn delete_api_token(
+    (req, params): (HttpRequest<State>, Path<String>),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let id = params.into_inner();
+
+    let db = &req.state().db.for_request(&req);
+
+    db.send(DeleteApiTokenMessage {
+        id,
+        audit: audit::context(&req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
+}
+
+/* Two-factor authentication can only be changed after a password login, as tokens, client
+certificates and proxy users never passed a second factor. */
+fn require_user(req: &HttpRequest<State>) -> Option<HttpResponse> {
+    let is_user = req
+        .extensions()
+        .get::<auth::Identity>()
+        .is_some_and(auth::Identity::is_user);
+
+    if is_user {
+        None
+    } else {
+        Some(
+            HttpResponse::Forbidden().body(
+                "Two-factor authentication can only be changed after logging in with a password",
+            ),
+        )
+    }
+}
+
+/* A missing or wrong current code counts as a failed attempt, like at login. */
+fn invalid_current_code(req: &HttpRequest<State>, code: &Option<String>) -> HttpResponse {
+    if code.is_none() {
+        return HttpResponse::Forbidden().body("Two-factor code required");
+    }
+
+    let client = req.state().trusted_proxies.client_ip(req);
+    req.state().rate_limiter.record_failure(client);
+    HttpResponse::Forbidden().body("Invalid two-factor code")
+}
+
+fn current_code(code: Option<Json<TwoFactorCode>>) -> Option<String> {
+    code.map(|code| code.into_inner().code)
+}
+
+/* If two-factor authentication is enabled, replacing it needs a current code or recovery code. */
+fn enroll_two_factor(
+    (req, code): (HttpRequest<State>, Option<Json<TwoFactorCode>>),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    if let Some(response) = require_user(&req) {
+        return Box::new(future::ok(response));
+    }
+    let username = match req.state().auth.username() {
+        Some(username) => username.to_string(),
+        None => {
+            return Box::new(future::ok(
+                HttpResponse::BadRequest().body("Two-factor authentication requires a username"),
+            ))
+        }
+    };
+
+    let db = &req.state().db.for_request(&req);
+    let current_code = current_code(code);
+    let two_factor::Enrollment {
+        secret,
+        recovery_code_hashes,
+        enrollment,
+    } = two_factor::new_enrollment(&username);
+
+    db.send(EnrollTwoFactorMessage {
+        username,
+        secret,
+        recovery_code_hashes,
+        current_code: current_code.clone(),
+        audit: audit::context(&req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(true) => Ok(HttpResponse::Ok().json(enrollment)),
+        Ok(false) => Ok(invalid_current_code(&req, &current_code)),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
+}
+
+fn confirm_two_factor(
+    (req, code): (HttpRequest<State>, Json<TwoFactorCode>),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    if let Some(response) = require_user(&req) {
+        return Box::new(future::ok(response));
+    }
+    let username = req.state().auth.username().unwrap_or_default().to_string();
+
+    let db = &req.state().db.for_request(&req);
+
+    db.send(ConfirmTwoFactorMessage {
+        username,
+        code: code.into_inner().code,
+        audit: audit::context(&req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::BadRequest().body("Invalid code")),
+        Err(reason) => Ok(HttpResponse::BadRequest().body(reason)),
+    })
+    .responder()
+}
+
+fn disable_two_factor(
+    (req, code): (HttpRequest<State>, Option<Json<TwoFactorCode>>),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    if let Some(response) = require_user(&req) {
+        return Box::new(future::ok(response));
+    }
+    let username = req.state().auth.username().unwrap_or_default().to_string();
+
+    let db = &req.state().db.for_request(&req);
+    let current_code = current_code(code);
+
+    db.send(DisableTwoFactorMessage {
+        username,
+        current_code: current_code.clone(),
+        audit: audit::context(&req),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(invalid_current_code(&req, &current_code)),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
+}
+
+fn get_audit_log(
+    (req, filter): (HttpRequest<State>, Query<AuditFilter>),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let db = &req.state().db.for_request(&req);
+
+    db.send(GetAuditLogMessage {
+        filter: filter.into_inner(),
+    })
+    .from_err()
+    .and_then(move |res| match res {
+        Ok(audit_page) => Ok(HttpResponse::Ok().json(audit_page)),
+        Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+    })
+    .responder()
+}
+
 fn get_data(
     (req, query): (HttpRequest<State>, Query<GetDataQuery>),
 ) -> Box<Future<Item = HttpResponse, Error = Error>> {
-    let db = &req.state().db;
+    let db = &req.state().db.for_request(&req);
 
     let req_1 = db.send(GetNoteBooksMessage {
         since_revision: query.since_revision,
@@ -245,7 +631,12 @@ fn get_data(
             (Ok(notebooks), Ok(notes), Ok(content_blocks), Ok(deleted_records)) => {
                 let data_response =
                     build_response(notebooks, notes, content_blocks, deleted_records);
-                Ok(HttpResponse::Ok().json(data_response))
+                let body = serde_json::to_string(&data_response)?;
+                req.state().metrics.record_sync_payload(body.len());
+
+                Ok(HttpResponse::Ok()
+                    .content_type("application/json")
+                    .body(body))
             }
             _ => Ok(HttpResponse::InternalServerError().into()),
         })
@@ -5,37 +5,244 @@ use actix_web::http::{header, StatusCode};
 use actix_web::{App, Error, HttpRequest, HttpResponse};
 use lazy_static::lazy_static;
 use mime_guess::guess_mime_type;
+use openssl::sha::sha1;
 use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 lazy_static! {
-    static ref RE_IS_CACHABLE_ASSET: Regex = Regex::new(r"\.(?:jpg|jpeg|png|svg|css|js)$").unwrap();
+    /* The web app's build puts a hash of the contents in these file names, so they never change. */
+    static ref RE_IS_CACHABLE_ASSET: Regex =
+        Regex::new(r"\.[0-9a-f]{8}\.(?:chunk\.)?(?:jpg|jpeg|png|svg|css|js)$").unwrap();
+    static ref DIRECTORY_ETAGS: Mutex<HashMap<PathBuf, CachedEtag>> = Mutex::new(HashMap::new());
 }
 
-#[derive(RustEmbed)]
-#[folder = "assets/"]
-struct Asset;
+const INDEX: &str = "index.html";
+
+/* A file of the web app, from the assets directory or embedded in the binary. */
+struct AssetFile {
+    contents: Contents,
+    etag: Option<String>,
+}
+
+/* Files in the assets directory are only read when their contents are sent. */
+enum Contents {
+    Loaded(Vec<u8>),
+    File(PathBuf),
+}
+
+impl Contents {
+    fn read(self) -> Option<Vec<u8>> {
+        match self {
+            Contents::Loaded(contents) => Some(contents),
+            Contents::File(path) => fs::read(path).ok(),
+        }
+    }
+}
+
+/* The ETag of a file in the assets directory, valid while its modification time and size stay
+the same. */
+struct CachedEtag {
+    modified: SystemTime,
+    len: u64,
+    etag: String,
+}
 
 pub fn mount(app: App<State>) -> App<State> {
     app.route("/{path:.*}", Method::GET, handler)
+        .route("/{path:.*}", Method::HEAD, handler)
 }
 
+/* Paths that aren't files are routes of the web app, which serves them from `index.html`
+so deep links work on reload. Responses to HEAD requests are sent without body. */
 #[allow(clippy::needless_pass_by_value)]
 pub fn handler(req: HttpRequest<State>) -> Result<HttpResponse, Error> {
-    let asset_path = asset_path(&req);
+    let state = req.state();
+
+    Ok(respond(
+        &req,
+        state.assets_directory.as_deref(),
+        &state.base_path,
+        asset_path(&req),
+    ))
+}
 
-    match Asset::get(asset_path) {
-        Some(asset) => {
-            let mime = guess_mime_type(asset_path);
+fn respond<S>(
+    req: &HttpRequest<S>,
+    directory: Option<&Path>,
+    base_path: &str,
+    requested_path: &str,
+) -> HttpResponse {
+    let (asset_path, asset) = match find(directory, requested_path) {
+        Some(asset) => (requested_path, asset),
+        None if is_app_route(requested_path) => match find(directory, INDEX) {
+            Some(asset) => (INDEX, asset),
+            None => return HttpResponse::NotFound().finish(),
+        },
+        None => return HttpResponse::NotFound().finish(),
+    };
 
-            let mut response = HttpResponse::Ok();
-            with_caching_headers(asset_path, &mut response);
+    let mut response = HttpResponse::Ok();
+    with_caching_headers(asset_path, &mut response);
 
-            Ok(response.header(header::CONTENT_TYPE, mime).body(asset))
+    // The page differs per base path, so the ETag has to as well.
+    let etag = match asset.etag {
+        Some(ref etag) if asset_path == INDEX => {
+            Some(format!("\"{}{}/\"", etag.trim_matches('"'), base_path))
         }
-        None => Ok(HttpResponse::Ok().status(StatusCode::NOT_FOUND).finish()),
+        etag => etag,
+    };
+
+    if let Some(ref etag) = etag {
+        response.header(header::ETAG, etag.as_str());
+
+        if is_not_modified(req, etag) {
+            return response.status(StatusCode::NOT_MODIFIED).finish();
+        }
+    }
+
+    match asset.contents.read() {
+        Some(contents) if asset_path == INDEX => response
+            .header(header::CONTENT_TYPE, guess_mime_type(asset_path))
+            .body(with_base_href(contents, base_path)),
+        Some(contents) => response
+            .header(header::CONTENT_TYPE, guess_mime_type(asset_path))
+            .body(contents),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/* The web app loads its files with relative URLs, which resolve against the directory of a
+nested route like `/notes/a/b` instead of the app. A `<base>` element makes them resolve against
+the base path. The base path is checked to be a plain path, so it needs no escaping. */
+fn with_base_href(contents: Vec<u8>, base_path: &str) -> Vec<u8> {
+    let head = b"<head>";
+    let position = contents
+        .windows(head.len())
+        .position(|window| window.eq_ignore_ascii_case(head));
+
+    match position {
+        Some(position) => {
+            let base = format!("<base href=\"{}/\">", base_path);
+            let mut html = Vec::with_capacity(contents.len() + base.len());
+            html.extend_from_slice(&contents[..position + head.len()]);
+            html.extend_from_slice(base.as_bytes());
+            html.extend_from_slice(&contents[position + head.len()..]);
+            html
+        }
+        None => contents,
     }
 }
 
+/* Files in the assets directory take precedence, so it can override single files such as
+a stylesheet and fall back to the embedded web app for the rest. */
+fn find(directory: Option<&Path>, path: &str) -> Option<AssetFile> {
+    directory
+        .and_then(|directory| find_in_directory(directory, path))
+        .or_else(|| find_embedded(path))
+}
+
+fn find_in_directory(directory: &Path, path: &str) -> Option<AssetFile> {
+    let relative = Path::new(path);
+
+    // Only plain file names, so requests can't escape the directory.
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    let full_path = directory.join(relative);
+    let metadata = fs::metadata(&full_path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    let modified = metadata.modified().ok()?;
+
+    let cached_etag = DIRECTORY_ETAGS
+        .lock()
+        .unwrap()
+        .get(&full_path)
+        .filter(|cached| cached.modified == modified && cached.len == metadata.len())
+        .map(|cached| cached.etag.clone());
+    if let Some(etag) = cached_etag {
+        return Some(AssetFile {
+            contents: Contents::File(full_path),
+            etag: Some(etag),
+        });
+    }
+
+    let contents = fs::read(&full_path).ok()?;
+    let hash: String = sha1(&contents)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let etag = format!("\"{}\"", hash);
+
+    DIRECTORY_ETAGS.lock().unwrap().insert(
+        full_path,
+        CachedEtag {
+            modified,
+            len: metadata.len(),
+            etag: etag.clone(),
+        },
+    );
+
+    Some(AssetFile {
+        contents: Contents::Loaded(contents),
+        etag: Some(etag),
+    })
+}
+
+#[cfg(feature = "embedded_assets")]
+fn find_embedded(path: &str) -> Option<AssetFile> {
+    embedded::Asset::get(path).map(|contents| AssetFile {
+        contents: Contents::Loaded(contents),
+        etag: embedded::etag(path).map(String::from),
+    })
+}
+
+#[cfg(not(feature = "embedded_assets"))]
+fn find_embedded(_path: &str) -> Option<AssetFile> {
+    None
+}
+
+#[cfg(feature = "embedded_assets")]
+mod embedded {
+    // Generated by build.rs.
+    include!(concat!(env!("OUT_DIR"), "/asset_etags.rs"));
+
+    #[derive(RustEmbed)]
+    #[folder = "assets/"]
+    pub struct Asset;
+
+    pub fn etag(path: &str) -> Option<&'static str> {
+        ASSET_ETAGS
+            .binary_search_by_key(&path, |&(asset_path, _)| asset_path)
+            .ok()
+            .map(|index| ASSET_ETAGS[index].1)
+    }
+}
+
+fn is_app_route(path: &str) -> bool {
+    !path.rsplit('/').next().unwrap_or("").contains('.')
+}
+
+/* If-None-Match uses weak comparison, so `W/"..."` matches as well. */
+pub fn is_not_modified<S>(req: &HttpRequest<S>, etag: &str) -> bool {
+    req.headers()
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|candidate| candidate.trim().trim_start_matches("W/"))
+        .any(|candidate| candidate == etag || candidate == "*")
+}
+
 fn asset_path<'a>(req: &'a HttpRequest<State>) -> &'a str {
     let path = req.match_info().get("path").unwrap_or("index.html");
 
@@ -55,10 +262,224 @@ fn with_caching_headers<'a>(
             .header(header::EXPIRES, "0"),
         _ => {
             if RE_IS_CACHABLE_ASSET.is_match(path) {
-                response.header(header::CACHE_CONTROL, "max-age=31536000, public")
+                response.header(header::CACHE_CONTROL, "max-age=31536000, public, immutable")
             } else {
-                response
+                // Revalidated with the ETag on every use.
+                response.header(header::CACHE_CONTROL, "no-cache")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{TestRequest, TestServer};
+    use actix_web::HttpMessage;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const INDEX_HTML: &str = "<!DOCTYPE html><html><head><title>Pragma</title></head></html>";
+
+    /* A directory with a web app, removed when dropped. */
+    struct TestAssets(PathBuf);
+
+    impl TestAssets {
+        fn new() -> Self {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let directory = std::env::temp_dir().join(format!(
+                "pragma-assets-{}-{}",
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::SeqCst)
+            ));
+
+            fs::create_dir_all(directory.join("static")).unwrap();
+            fs::write(directory.join(INDEX), INDEX_HTML).unwrap();
+            fs::write(directory.join("static/app.0123abcd.js"), "app()").unwrap();
+            TestAssets(directory)
+        }
+    }
+
+    impl Drop for TestAssets {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn get(
+        assets: &TestAssets,
+        base_path: &str,
+        path: &str,
+        request: TestRequest<()>,
+    ) -> HttpResponse {
+        respond(&request.finish(), Some(&assets.0), base_path, path)
+    }
+
+    fn body(response: &HttpResponse) -> String {
+        match response.body() {
+            actix_web::Body::Binary(binary) => {
+                String::from_utf8_lossy(binary.as_ref()).into_owned()
             }
+            _ => String::new(),
         }
     }
+
+    fn etag_header(response: &HttpResponse) -> String {
+        response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn serves_files() {
+        let assets = TestAssets::new();
+        let response = get(
+            &assets,
+            "",
+            "static/app.0123abcd.js",
+            TestRequest::default(),
+        );
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(&response), "app()");
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "max-age=31536000, public, immutable"
+        );
+        assert!(response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .contains("javascript"));
+    }
+
+    #[test]
+    fn serves_app_routes_from_index() {
+        let assets = TestAssets::new();
+
+        for path in &["", "index.html", "notes", "notes/1/edit"] {
+            let response = get(&assets, "", path, TestRequest::default());
+            assert_eq!(response.status(), StatusCode::OK, "{}", path);
+            assert_eq!(
+                body(&response),
+                "<!DOCTYPE html><html><head><base href=\"/\"><title>Pragma</title></head></html>"
+            );
+            assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+        }
+
+        let response = get(&assets, "/notes", "notebooks/1", TestRequest::default());
+        assert!(body(&response).contains("<head><base href=\"/notes/\"><title>"));
+    }
+
+    #[test]
+    fn rejects_missing_files() {
+        let assets = TestAssets::new();
+
+        for path in &[
+            "missing.js",
+            "static/missing.css",
+            "../index.html",
+            "/etc/ld.so.conf",
+        ] {
+            let response = get(&assets, "", path, TestRequest::default());
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", path);
+        }
+        // Paths outside the directory without extension are app routes.
+        let response = get(&assets, "", "/etc/passwd", TestRequest::default());
+        assert!(body(&response).contains("<title>Pragma</title>"));
+
+        let response = respond(&TestRequest::default().finish(), None, "", "notes");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn varies_index_etag_with_base_path() {
+        let assets = TestAssets::new();
+        let root = etag_header(&get(&assets, "", "notes", TestRequest::default()));
+        let nested = etag_header(&get(&assets, "/notes", "notes", TestRequest::default()));
+
+        assert!(root.starts_with('"') && root.ends_with("/\""));
+        assert_ne!(root, nested);
+    }
+
+    #[test]
+    fn answers_conditional_requests() {
+        let assets = TestAssets::new();
+        let path = "static/app.0123abcd.js";
+        let etag = etag_header(&get(&assets, "", path, TestRequest::default()));
+
+        let matching = vec![
+            etag.clone(),
+            format!("W/{}", etag),
+            "*".to_string(),
+            format!("\"other\", {}", etag),
+        ];
+        for if_none_match in matching {
+            let request = TestRequest::with_header(header::IF_NONE_MATCH, if_none_match.as_str());
+            let response = get(&assets, "", path, request);
+            assert_eq!(
+                response.status(),
+                StatusCode::NOT_MODIFIED,
+                "{}",
+                if_none_match
+            );
+            assert_eq!(response.headers()[header::ETAG], etag.as_str());
+            assert_eq!(body(&response), "");
+        }
+
+        for if_none_match in &["\"other\"", "W/\"other\"", ""] {
+            let request = TestRequest::with_header(header::IF_NONE_MATCH, *if_none_match);
+            let response = get(&assets, "", path, request);
+            assert_eq!(response.status(), StatusCode::OK, "{}", if_none_match);
+        }
+
+        let index_etag = etag_header(&get(&assets, "", INDEX, TestRequest::default()));
+        let request = TestRequest::with_header(header::IF_NONE_MATCH, index_etag.as_str());
+        let response = get(&assets, "", "notes/1", request);
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn answers_head_requests_without_body() {
+        let assets = TestAssets::new();
+        let directory = assets.0.clone();
+        let mut server = TestServer::new(move |app| {
+            let directory = directory.clone();
+            app.resource("/{path:.*}", move |r| {
+                r.f(move |req| {
+                    let path = req.match_info().get("path").unwrap_or("").to_string();
+                    respond(req, Some(&directory), "", &path)
+                })
+            });
+        });
+
+        let get = server.execute(
+            server
+                .get()
+                .uri(server.url("/notes/1"))
+                .finish()
+                .unwrap()
+                .send(),
+        );
+        let get = get.unwrap();
+        let get_body = server.execute(get.body()).unwrap();
+        assert_eq!(get.status(), StatusCode::OK);
+        assert!(!get_body.is_empty());
+
+        let head = server.execute(
+            server
+                .head()
+                .uri(server.url("/notes/1"))
+                .finish()
+                .unwrap()
+                .send(),
+        );
+        let head = head.unwrap();
+        assert_eq!(head.status(), StatusCode::OK);
+        assert_eq!(head.headers()[header::ETAG], get.headers()[header::ETAG]);
+        assert_eq!(
+            head.headers()[header::CONTENT_LENGTH],
+            get_body.len().to_string().as_str()
+        );
+        assert!(server.execute(head.body()).unwrap().is_empty());
+    }
 }
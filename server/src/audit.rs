@@ -0,0 +1,29 @@
+use actix_web::{HttpMessage, HttpRequest};
+
+use super::actix_state::State;
+use super::auth::Identity;
+use super::data::AuditContext;
+
+/* Describes the caller of a request for the audit log. Requests that passed AuthMiddleware
+carry an Identity, others are anonymous. */
+pub fn context(req: &HttpRequest<State>) -> AuditContext {
+    let actor = req
+        .extensions()
+        .get::<Identity>()
+        .map(|identity| identity.name.clone())
+        .unwrap_or_else(|| "anonymous".to_string());
+
+    AuditContext {
+        actor,
+        client_ip: req
+            .state()
+            .trusted_proxies
+            .client_ip(req)
+            .map(|ip| ip.to_string()),
+        user_agent: req
+            .headers()
+            .get("User-Agent")
+            .and_then(|header| header.to_str().ok())
+            .map(str::to_string),
+    }
+}
@@ -1,20 +1,171 @@
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
-use actix_web::http::StatusCode;
+use actix_web::http::{header, Cookie, Method, StatusCode};
 use actix_web::middleware::{Middleware, Response, Started};
-use actix_web::{error, HttpRequest, HttpResponse, Result};
+use actix_web::{
+    error, AsyncResponder, Error, HttpMessage, HttpRequest, HttpResponse, Json, Result,
+};
+use chrono::{DateTime, Duration, Utc};
+use cookie::SameSite;
+use futures::future::{self, Future};
+use openssl::memcmp;
+use openssl::sha::sha256;
+use std::time::Duration as StdDuration;
 
-pub struct AuthMiddleware {
-    token: String,
+use super::actix_state::State;
+use super::audit;
+use super::client_cert::ClientCertificate;
+use super::config::Config;
+use super::data::{ApiToken, AuditContext, Credentials, Scope, Session};
+use super::repo_actor::{
+    AuthenticateApiTokenMessage, CreateSessionMessage, DeleteSessionMessage, GetSessionMessage,
+    RotateSessionMessage, VerifySecondFactorMessage,
+};
+use super::two_factor;
+
+static SESSION_COOKIE: &str = "pragma_session";
+
+pub struct AuthMiddleware;
+
+#[derive(Clone)]
+pub struct Settings {
+    token_hash: Option<String>,
+    username: Option<String>,
+    password_hash: Option<String>,
+    session_lifetime: Duration,
+    secure_cookies: bool,
+    base_path: String,
+    require_two_factor: bool,
+    proxy_user_header: Option<String>,
+    proxy_allowed_users: Vec<String>,
+}
+
+impl Settings {
+    /* The configured token (AUTH_TOKEN) is accepted with admin scope, next to tokens stored in the database. */
+    pub fn from_config(config: &Config) -> Self {
+        Settings {
+            token_hash: config.auth.token.as_ref().map(|token| hash_token(token)),
+            username: config.auth.username.clone(),
+            password_hash: config.auth.password_hash.clone(),
+            session_lifetime: Duration::days(i64::from(config.auth.session_days)),
+            secure_cookies: config.tls.enabled,
+            base_path: config.listen.base_path().to_string(),
+            require_two_factor: config.auth.require_two_factor,
+            proxy_user_header: config.proxy.user_header.clone(),
+            proxy_allowed_users: config.proxy.allowed_users.clone(),
+        }
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    fn verify_credentials(&self, credentials: &Credentials) -> bool {
+        match (&self.username, &self.password_hash) {
+            (Some(username), Some(password_hash)) => {
+                let password_matches =
+                    argon2::verify_encoded(password_hash, credentials.password.as_bytes())
+                        .unwrap_or(false);
+
+                password_matches && constant_time_eq(username, &credentials.username)
+            }
+            _ => false,
+        }
+    }
+
+    fn session_cookie(&self, session_id: String) -> Cookie<'static> {
+        let path = if self.base_path.is_empty() {
+            "/".to_string()
+        } else {
+            self.base_path.clone()
+        };
+
+        Cookie::build(SESSION_COOKIE, session_id)
+            .path(path)
+            .http_only(true)
+            .secure(self.secure_cookies)
+            .same_site(SameSite::Strict)
+            .max_age(self.session_lifetime)
+            .finish()
+    }
 }
 
+/* The authenticated caller, available from request extensions after AuthMiddleware ran.
+The name says how the caller authenticated, e.g. `token:backup-script` or `user:alice`. */
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub name: String,
+    pub scope: Scope,
+}
+
+impl Identity {
+    /* Whether the caller logged in with a password, and the second factor if enabled. */
+    pub fn is_user(&self) -> bool {
+        self.name.starts_with("user:")
+    }
+}
+
+impl From<ApiToken> for Identity {
+    fn from(api_token: ApiToken) -> Self {
+        Identity {
+            name: format!("token:{}", api_token.name),
+            scope: api_token.scope,
+        }
+    }
+}
+
+impl From<Session> for Identity {
+    fn from(session: Session) -> Self {
+        Identity {
+            name: format!("user:{}", session.username),
+            scope: Scope::Admin,
+        }
+    }
+}
+
+/* After rotation, the previous session id stays valid this long for requests that were already
+sent with it. */
+fn session_rotation_grace() -> Duration {
+    Duration::minutes(2)
+}
+
+/* Sessions are rotated once they're a day old. Sessions that expire within the grace period
+were already rotated, or are about to expire anyway. */
+fn needs_rotation(session: &Session, now: DateTime<Utc>) -> bool {
+    now - session.created_at >= Duration::days(1)
+        && session.expires_at - now > session_rotation_grace()
+}
+
+/* A rotated session id, to be sent to the client with the response. */
+struct RotatedSession(String);
+
 pub fn random_token() -> String {
     thread_rng().sample_iter(&Alphanumeric).take(32).collect()
 }
 
-pub fn middleware(token: String) -> AuthMiddleware {
-    AuthMiddleware { token }
+/* Tokens are random and long, so a fast hash is sufficient here. */
+pub fn hash_token(token: &str) -> String {
+    sha256(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt: String = thread_rng().sample_iter(&Alphanumeric).take(16).collect();
+
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        ..argon2::Config::default()
+    };
+
+    argon2::hash_encoded(password.as_bytes(), salt.as_bytes(), &config)
+        .map_err(|e| format!("{}", e))
+}
+
+pub fn middleware() -> AuthMiddleware {
+    AuthMiddleware
 }
 
 #[allow(clippy::needless_pass_by_value)]
@@ -23,24 +174,474 @@ pub fn check_token<S>(_req: HttpRequest<S>) -> HttpResponse {
     HttpResponse::Ok().status(StatusCode::NO_CONTENT).finish()
 }
 
-fn extract_bearer_token<S>(req: &HttpRequest<S>) -> Option<String> {
+/* After the password, a code from the authenticator app or a recovery code is required
+if two-factor authentication is enabled. Wrong codes count as failed attempts. */
+pub fn login(
+    (req, credentials): (HttpRequest<State>, Json<Credentials>),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let settings = &req.state().auth;
+    let rate_limiter = req.state().rate_limiter.clone();
+    let client = req.state().trusted_proxies.client_ip(&req);
+
+    if let Some(remaining) = rate_limiter.lockout(client) {
+        return Box::new(future::ok(too_many_requests(remaining)));
+    }
+
+    if !settings.verify_credentials(&credentials) {
+        rate_limiter.record_failure(client);
+
+        return Box::new(future::ok(
+            HttpResponse::Unauthorized().body("Invalid username or password"),
+        ));
+    }
+
+    let credentials = credentials.into_inner();
+    let require_two_factor = settings.require_two_factor;
+    let db = req.state().db.for_request(&req);
+    let req = req.clone();
+
+    db.send(VerifySecondFactorMessage {
+        username: credentials.username.clone(),
+        recovery_code_hash: credentials
+            .code
+            .as_ref()
+            .map(|code| two_factor::recovery_code_hash(code)),
+        code: credentials.code.clone(),
+    })
+    .from_err()
+    .and_then(move |res| {
+        let rejection = match res {
+            Ok(Some(true)) => None,
+            Ok(None) if !require_two_factor => None,
+            Ok(None) => Some("Two-factor authentication is required but not enrolled"),
+            Ok(Some(false)) if credentials.code.is_none() => Some("Two-factor code required"),
+            Ok(Some(false)) => {
+                rate_limiter.record_failure(client);
+                Some("Invalid two-factor code")
+            }
+            Err(reason) => {
+                return future::Either::A(future::ok(
+                    HttpResponse::InternalServerError().body(reason),
+                ))
+            }
+        };
+
+        if let Some(rejection) = rejection {
+            return future::Either::A(future::ok(HttpResponse::Unauthorized().body(rejection)));
+        }
+
+        rate_limiter.record_success(client);
+
+        let settings = &req.state().auth;
+        let session_id = random_token();
+        let now = Utc::now();
+        let session = Session {
+            username: credentials.username,
+            created_at: now,
+            expires_at: now + settings.session_lifetime,
+        };
+        let cookie = settings.session_cookie(session_id.clone());
+        let audit = AuditContext {
+            actor: format!("user:{}", session.username),
+            ..audit::context(&req)
+        };
+
+        future::Either::B(
+            db.send(CreateSessionMessage {
+                session_hash: hash_token(&session_id),
+                session,
+                audit,
+            })
+            .from_err()
+            .and_then(move |res| match res {
+                Ok(_) => Ok(HttpResponse::NoContent().cookie(cookie).finish()),
+                Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+            }),
+        )
+    })
+    .responder()
+}
+
+pub fn logout(req: HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let mut removal_cookie = req.state().auth.session_cookie(String::new());
+    removal_cookie.set_max_age(Duration::zero());
+
+    let session_hash = match req.cookie(SESSION_COOKIE) {
+        Some(cookie) => hash_token(cookie.value()),
+        None => {
+            return Box::new(future::ok(
+                HttpResponse::NoContent().cookie(removal_cookie).finish(),
+            ))
+        }
+    };
+
+    req.state()
+        .db
+        .for_request(&req)
+        .send(DeleteSessionMessage {
+            session_hash,
+            audit: audit::context(&req),
+        })
+        .from_err()
+        .and_then(move |res| match res {
+            Ok(_) => Ok(HttpResponse::NoContent().cookie(removal_cookie).finish()),
+            Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+        })
+        .responder()
+}
+
+pub fn extract_bearer_token<S>(req: &HttpRequest<S>) -> Option<String> {
     req.headers()
         .get("Authorization")
         .and_then(|header| header.to_str().ok())
-        .and_then(|header_value| header_value.split(char::is_whitespace).last())
+        .and_then(|header_value| header_value.split(char::is_whitespace).next_back())
         .map(|string| string.to_owned())
 }
 
-impl<S> Middleware<S> for AuthMiddleware {
-    fn start(&self, _req: &HttpRequest<S>) -> Result<Started> {
-        Ok(Started::Done)
+/* Paths are matched without the base path the app is served under. */
+fn api_path<'a>(path: &'a str, base_path: &str) -> &'a str {
+    path.get(base_path.len()..).unwrap_or("")
+}
+
+/* Logging in and out are the only API requests that don't need to be authenticated. */
+fn is_public(path: &str) -> bool {
+    path == "/api/login" || path == "/api/logout"
+}
+
+fn required_scope(method: &Method, path: &str) -> Scope {
+    if path.starts_with("/api/tokens")
+        || path.starts_with("/api/two_factor")
+        || path.starts_with("/api/audit")
+    {
+        Scope::Admin
+    } else if method == Method::GET
+        || method == Method::HEAD
+        || path == "/api/auth"
+        || path == "/api/markdown"
+    {
+        Scope::ReadOnly
+    } else {
+        Scope::ReadWrite
     }
+}
+
+fn authorize(req: &HttpRequest<State>, identity: Identity) -> Result<()> {
+    let path = api_path(req.path(), &req.state().auth.base_path);
+    if identity.scope < required_scope(req.method(), path) {
+        return Err(error::ErrorForbidden("Forbidden"));
+    }
+
+    req.extensions_mut().insert(identity);
+    Ok(())
+}
+
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && memcmp::eq(a.as_bytes(), b.as_bytes())
+}
+
+fn too_many_requests(retry_after: StdDuration) -> HttpResponse {
+    HttpResponse::build(StatusCode::TOO_MANY_REQUESTS)
+        .header(header::RETRY_AFTER, (retry_after.as_secs() + 1).to_string())
+        .body("Too many failed attempts, try again later")
+}
+
+/* Client certificates are verified during the TLS handshake, so a certificate
+here is trusted. Its subject identifies the device. */
+fn client_cert_identity(req: &HttpRequest<State>) -> Option<Identity> {
+    let certificate = req.stream_extensions()?.get::<ClientCertificate>()?;
+
+    Some(Identity {
+        name: format!("cert:{}", certificate.subject),
+        scope: Scope::ReadWrite,
+    })
+}
+
+/* With a user header configured, a trusted reverse proxy such as oauth2-proxy can authenticate
+users. The header is ignored on requests that don't come directly from a trusted proxy. */
+fn proxy_identity(req: &HttpRequest<State>) -> Option<Result<Identity>> {
+    let settings = &req.state().auth;
+    let user_header = settings.proxy_user_header.as_ref()?;
+
+    if !req.state().trusted_proxies.is_trusted_peer(req) {
+        return None;
+    }
+
+    let user = req
+        .headers()
+        .get(user_header.as_str())
+        .and_then(|header| header.to_str().ok())
+        .map(str::trim)
+        .filter(|user| !user.is_empty())?;
+
+    if !settings.proxy_allowed_users.is_empty()
+        && !settings
+            .proxy_allowed_users
+            .iter()
+            .any(|allowed| allowed == user)
+    {
+        return Some(Err(error::ErrorForbidden("Forbidden")));
+    }
+
+    Some(Ok(Identity {
+        name: format!("proxy:{}", user),
+        scope: Scope::Admin,
+    }))
+}
+
+type IdentityFuture = Box<Future<Item = Option<Identity>, Error = Error>>;
+
+/* Resolves to None if the token is unknown. */
+fn authenticate_token(req: &HttpRequest<State>, token: &str) -> IdentityFuture {
+    let token_hash = hash_token(token);
+
+    let is_configured_token = match req.state().auth.token_hash {
+        Some(ref configured_hash) => constant_time_eq(configured_hash, &token_hash),
+        None => false,
+    };
+
+    if is_configured_token {
+        return Box::new(future::ok(Some(Identity {
+            name: "token:AUTH_TOKEN".to_string(),
+            scope: Scope::Admin,
+        })));
+    }
+
+    let authenticate = req
+        .state()
+        .db
+        .for_request(req)
+        .send(AuthenticateApiTokenMessage { token_hash })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(api_token) => Ok(api_token.map(Identity::from)),
+            Err(reason) => Err(error::ErrorInternalServerError(reason)),
+        });
+
+    Box::new(authenticate)
+}
+
+/* Resolves to None if the session is unknown or expired.
+Sessions are rotated once a day while in use, which also extends their expiry. The previous
+session expires shortly after, and isn't rotated again in the meantime. */
+fn authenticate_session(req: &HttpRequest<State>, session_id: &str) -> IdentityFuture {
+    let session_hash = hash_token(session_id);
+    let db = req.state().db.for_request(req);
+    let req = req.clone();
+
+    let authenticate = db
+        .send(GetSessionMessage {
+            session_hash: session_hash.clone(),
+        })
+        .from_err()
+        .and_then(move |res| {
+            let session = match res {
+                Ok(Some(session)) => session,
+                Ok(None) => return future::Either::A(future::ok(None)),
+                Err(reason) => {
+                    return future::Either::A(future::err(error::ErrorInternalServerError(reason)))
+                }
+            };
 
-    fn response(&self, req: &HttpRequest<S>, resp: HttpResponse) -> Result<Response> {
-        match extract_bearer_token(req) {
-            Some(ref token) if token == &self.token => Ok(Response::Done(resp)),
+            let now = Utc::now();
+            if !needs_rotation(&session, now) {
+                return future::Either::A(future::ok(Some(session.into())));
+            }
 
-            _ => Err(error::ErrorUnauthorized("Unauthorized")),
+            let new_session_id = random_token();
+            let rotated_session = Session {
+                username: session.username.clone(),
+                created_at: now,
+                expires_at: now + req.state().auth.session_lifetime,
+            };
+
+            future::Either::B(
+                db.send(RotateSessionMessage {
+                    old_session_hash: session_hash,
+                    new_session_hash: hash_token(&new_session_id),
+                    session: rotated_session,
+                    old_session_expires_at: now + session_rotation_grace(),
+                })
+                .from_err()
+                .and_then(move |res| match res {
+                    Ok(_) => {
+                        req.extensions_mut().insert(RotatedSession(new_session_id));
+                        Ok(Some(session.into()))
+                    }
+                    Err(reason) => Err(error::ErrorInternalServerError(reason)),
+                }),
+            )
+        });
+
+    Box::new(authenticate)
+}
+
+/* Authentication happens before the request is handled. Failed attempts are counted per client,
+and clients with too many failures are rejected without checking their credentials. */
+impl Middleware<State> for AuthMiddleware {
+    fn start(&self, req: &HttpRequest<State>) -> Result<Started> {
+        if is_public(api_path(req.path(), &req.state().auth.base_path)) {
+            return Ok(Started::Done);
+        }
+
+        let rate_limiter = req.state().rate_limiter.clone();
+        let client = req.state().trusted_proxies.client_ip(req);
+
+        if let Some(remaining) = rate_limiter.lockout(client) {
+            return Ok(Started::Response(too_many_requests(remaining)));
+        }
+
+        let identity = if let Some(token) = extract_bearer_token(req) {
+            authenticate_token(req, &token)
+        } else if let Some(identity) = client_cert_identity(req) {
+            Box::new(future::ok(Some(identity)))
+        } else if let Some(identity) = proxy_identity(req) {
+            match identity {
+                Ok(identity) => Box::new(future::ok(Some(identity))),
+                Err(e) => return Err(e),
+            }
+        } else if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+            authenticate_session(req, cookie.value())
+        } else {
+            Box::new(future::ok(None))
+        };
+
+        let req = req.clone();
+        let authorized = identity.and_then(move |identity| match identity {
+            Some(identity) => {
+                rate_limiter.record_success(client);
+                authorize(&req, identity).map(|_| None)
+            }
+            None => {
+                rate_limiter.record_failure(client);
+                Err(error::ErrorUnauthorized("Unauthorized"))
+            }
+        });
+
+        Ok(Started::Future(Box::new(authorized)))
+    }
+
+    fn response(&self, req: &HttpRequest<State>, mut resp: HttpResponse) -> Result<Response> {
+        if let Some(RotatedSession(session_id)) = req.extensions_mut().remove::<RotatedSession>() {
+            resp.add_cookie(&req.state().auth.session_cookie(session_id))?;
         }
+
+        Ok(Response::Done(resp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_base_path() {
+        assert_eq!(api_path("/api/notes", ""), "/api/notes");
+        assert_eq!(api_path("/notes/api/notes", "/notes"), "/api/notes");
+        assert_eq!(api_path("/notes", "/notes"), "");
+        assert_eq!(api_path("/n", "/notes"), "");
+
+        assert!(is_public("/api/login"));
+        assert!(is_public("/api/logout"));
+        assert!(!is_public("/api/login/other"));
+        assert!(!is_public("/api/auth"));
+    }
+
+    #[test]
+    fn requires_scope_for_requests() {
+        let cases = [
+            (Method::GET, "/api/notes", Scope::ReadOnly),
+            (Method::HEAD, "/api/notes", Scope::ReadOnly),
+            (Method::POST, "/api/notes", Scope::ReadWrite),
+            (Method::PUT, "/api/notes/1", Scope::ReadWrite),
+            (Method::DELETE, "/api/notebooks/1", Scope::ReadWrite),
+            (Method::POST, "/api/auth", Scope::ReadOnly),
+            // Rendering Markdown changes nothing, so read-only tokens can preview too.
+            (Method::POST, "/api/markdown", Scope::ReadOnly),
+            (Method::GET, "/api/tokens", Scope::Admin),
+            (Method::DELETE, "/api/tokens/1", Scope::Admin),
+            (Method::POST, "/api/two_factor/enroll", Scope::Admin),
+            (Method::GET, "/api/audit", Scope::Admin),
+        ];
+        for (method, path, scope) in &cases {
+            assert_eq!(required_scope(method, path), *scope, "{} {}", method, path);
+        }
+
+        let read_only = Scope::ReadOnly;
+        assert!(read_only >= required_scope(&Method::POST, "/api/markdown"));
+        assert!(read_only < required_scope(&Method::POST, "/api/notes"));
+        assert!(Scope::ReadWrite < required_scope(&Method::GET, "/api/tokens"));
+        assert!(Scope::Admin >= required_scope(&Method::GET, "/api/tokens"));
+    }
+
+    #[test]
+    fn identifies_callers() {
+        let api_token = ApiToken {
+            id: "1".to_string(),
+            name: "backup".to_string(),
+            scope: Scope::ReadOnly,
+            created_at: Utc::now(),
+            last_used_at: None,
+        };
+        let identity = Identity::from(api_token);
+        assert_eq!(identity.name, "token:backup");
+        assert_eq!(identity.scope, Scope::ReadOnly);
+        assert!(!identity.is_user());
+
+        let session = Session {
+            username: "alice".to_string(),
+            created_at: Utc::now(),
+            expires_at: Utc::now(),
+        };
+        let identity = Identity::from(session);
+        assert_eq!(identity.name, "user:alice");
+        assert_eq!(identity.scope, Scope::Admin);
+        assert!(identity.is_user());
+    }
+
+    #[test]
+    fn rotates_sessions_once_a_day() {
+        let now = Utc::now();
+        let session = |age: Duration, remaining: Duration| Session {
+            username: "alice".to_string(),
+            created_at: now - age,
+            expires_at: now + remaining,
+        };
+
+        assert!(!needs_rotation(
+            &session(Duration::hours(23), Duration::days(29)),
+            now
+        ));
+        assert!(needs_rotation(
+            &session(Duration::days(1), Duration::days(29)),
+            now
+        ));
+        assert!(needs_rotation(
+            &session(Duration::days(20), Duration::days(10)),
+            now
+        ));
+        // Rotated sessions expire after the grace period and aren't rotated again.
+        assert!(!needs_rotation(
+            &session(Duration::days(2), session_rotation_grace()),
+            now
+        ));
+        assert!(!needs_rotation(
+            &session(Duration::days(2), Duration::seconds(1)),
+            now
+        ));
+    }
+
+    #[test]
+    fn hashes_tokens() {
+        let token = random_token();
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, random_token());
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_eq!(
+            hash_token("secret"),
+            "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"
+        );
+        assert!(constant_time_eq(&token, &token.clone()));
+        assert!(!constant_time_eq(&token, &random_token()));
+        assert!(!constant_time_eq("a", "ab"));
     }
 }
@@ -2,11 +2,11 @@ use directories::ProjectDirs;
 use std::{fs, thread};
 
 fn main() {
-    configure_logger();
+    pragma::logging::init(&pragma::config::LoggingConfig::default());
 
     /* We have to use a fixed port :(. If we'd use an ephemeral port the webview will lose
     the data stored in localStorage and IndexedDB because this is scoped by origin (which includes port). */
-    let port = "60773";
+    let port = 60773;
 
     thread::spawn(move || {
         let dirs = ProjectDirs::from("me", "pascalw", "pragma")
@@ -19,11 +19,10 @@ fn main() {
             .to_string_lossy()
             .into_owned();
 
-        let config = pragma::Config {
-            port: port.to_string(),
-            auth_token: "desktop".to_string(),
-            database_url: database_path,
-        };
+        let mut config = pragma::Config::default();
+        config.listen.port = port;
+        config.auth.token = Some("desktop".to_string());
+        config.database.url = database_path;
 
         let sys = pragma::build(config);
         let _ = sys.run();
@@ -43,9 +42,3 @@ fn main() {
         "#, port))
         .start();
 }
-
-fn configure_logger() {
-    env_logger::Builder::from_default_env()
-        .filter_level(log::LevelFilter::Info)
-        .init();
-}
@@ -1,39 +1,47 @@
-use log::warn;
-use std::env;
+use std::io::{self, BufRead};
+use std::process;
 
 fn main() {
-    configure_logger();
-
-    let config = pragma::Config {
-        port: port(),
-        auth_token: token(),
-        database_url: database_url(),
+    let config = pragma::config::load().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    pragma::logging::init(&config.logging);
+
+    let args = pragma::config::command_args();
+    let command_result = match args.first().map(String::as_str) {
+        Some("tokens") => Some(pragma::tokens::run(&config, &args[1..])),
+        Some("two-factor") => Some(pragma::two_factor::run(&config, &args[1..])),
+        Some("hash-password") => Some(hash_password()),
+        Some("healthcheck") => Some(pragma::health::check(&config)),
+        _ => None,
     };
 
+    if let Some(result) = command_result {
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+
+        return;
+    }
+
     let sys = pragma::build(config);
     let _ = sys.run();
 }
 
-fn configure_logger() {
-    env_logger::Builder::from_default_env()
-        .filter_level(log::LevelFilter::Info)
-        .init();
-}
-
-fn token() -> String {
-    env::var("AUTH_TOKEN").unwrap_or_else(|_| {
-        let token = pragma::auth::random_token();
-
-        warn!("No AUTH_TOKEN was specified, using random token {}", token);
+fn hash_password() -> Result<(), String> {
+    eprintln!("Enter password:");
 
-        token
-    })
-}
+    let mut password = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .map_err(|e| format!("{}", e))?;
 
-fn port() -> String {
-    env::var("PORT").unwrap_or_else(|_| "8000".to_string())
-}
+    let hash = pragma::auth::hash_password(password.trim_end_matches(&['\r', '\n'][..]))?;
+    println!("{}", hash);
 
-fn database_url() -> String {
-    env::var("DATABASE_URL").unwrap_or_else(|_| "pragma.sqlite".to_owned())
+    Ok(())
 }
@@ -0,0 +1,163 @@
+use actix_net::service::{NewService, Service};
+use actix_web::server::IoStream;
+use actix_web::Extensions;
+use futures::future::{self, Future, FutureResult};
+use futures::{Async, Poll};
+use openssl::x509::{X509Ref, X509VerifyResult};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::rc::Rc;
+use std::time;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_openssl::{SslAcceptorExt, SslStream};
+use tokio_tcp::TcpStream;
+
+use super::tls::ReloadableAcceptor;
+
+/* The subject of a verified client certificate, available from the request's stream extensions. */
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    pub subject: String,
+}
+
+/* A TLS stream that remembers the subject of the client certificate, if the client sent one.
+actix-web only exposes the connection to handlers through stream extensions. */
+pub struct ClientCertStream {
+    stream: SslStream<TcpStream>,
+    subject: Option<String>,
+}
+
+impl ClientCertStream {
+    fn new(stream: SslStream<TcpStream>) -> Self {
+        let ssl = stream.get_ref().ssl();
+
+        let subject = if ssl.verify_result() == X509VerifyResult::OK {
+            ssl.peer_certificate().map(|cert| subject(&cert))
+        } else {
+            None
+        };
+
+        ClientCertStream { stream, subject }
+    }
+}
+
+/* Formats the subject like `CN=laptop,O=Example`. */
+fn subject(cert: &X509Ref) -> String {
+    cert.subject_name()
+        .entries()
+        .map(|entry| {
+            let name = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry
+                .data()
+                .as_utf8()
+                .map(|value| value.to_string())
+                .unwrap_or_default();
+
+            format!("{}={}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/* Accepts TLS connections with the current acceptor, so reloaded certificates apply to
+new connections. Failed handshakes are dropped, like `HttpServer::listen_ssl` does. */
+pub struct ClientCertAcceptor(ReloadableAcceptor);
+
+pub fn acceptor(
+    acceptor: ReloadableAcceptor,
+) -> impl Fn() -> ClientCertAcceptor + Send + Clone + 'static {
+    move || ClientCertAcceptor(acceptor.clone())
+}
+
+impl NewService for ClientCertAcceptor {
+    type Request = TcpStream;
+    type Response = ClientCertStream;
+    type Error = ();
+    type Service = ClientCertAcceptorService;
+    type InitError = ();
+    type Future = FutureResult<Self::Service, Self::InitError>;
+
+    fn new_service(&self) -> Self::Future {
+        future::ok(ClientCertAcceptorService(self.0.clone()))
+    }
+}
+
+pub struct ClientCertAcceptorService(ReloadableAcceptor);
+
+impl Service for ClientCertAcceptorService {
+    type Request = TcpStream;
+    type Response = ClientCertStream;
+    type Error = ();
+    type Future = Box<Future<Item = ClientCertStream, Error = ()>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, stream: TcpStream) -> Self::Future {
+        Box::new(
+            self.0
+                .current()
+                .accept_async(stream)
+                .map(ClientCertStream::new)
+                .map_err(|_| ()),
+        )
+    }
+}
+
+impl Read for ClientCertStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for ClientCertStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl AsyncRead for ClientCertStream {}
+
+impl AsyncWrite for ClientCertStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        AsyncWrite::shutdown(&mut self.stream)
+    }
+}
+
+impl IoStream for ClientCertStream {
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        IoStream::shutdown(&mut self.stream, how)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    fn set_nodelay(&mut self, nodelay: bool) -> io::Result<()> {
+        self.stream.set_nodelay(nodelay)
+    }
+
+    fn set_linger(&mut self, dur: Option<time::Duration>) -> io::Result<()> {
+        self.stream.set_linger(dur)
+    }
+
+    fn set_keepalive(&mut self, dur: Option<time::Duration>) -> io::Result<()> {
+        self.stream.set_keepalive(dur)
+    }
+
+    fn extensions(&self) -> Option<Rc<Extensions>> {
+        let subject = self.subject.as_ref()?;
+
+        let mut extensions = Extensions::new();
+        extensions.insert(ClientCertificate {
+            subject: subject.clone(),
+        });
+
+        Some(Rc::new(extensions))
+    }
+}
@@ -0,0 +1,967 @@
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::Method;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::proxy::{Network, TrustedProxies};
+
+/* Known configuration keys, per section. Used to report all invalid keys at once,
+instead of failing on the first one. */
+static KNOWN_KEYS: &[(&str, &[&str])] = &[
+    (
+        "listen",
+        &[
+            "host",
+            "port",
+            "shutdown_timeout_seconds",
+            "unix_socket",
+            "unix_socket_mode",
+            "base_path",
+        ],
+    ),
+    (
+        "tls",
+        &["enabled", "key", "cert", "client_ca", "require_client_cert"],
+    ),
+    ("database", &["url"]),
+    (
+        "auth",
+        &[
+            "token",
+            "username",
+            "password_hash",
+            "session_days",
+            "require_two_factor",
+        ],
+    ),
+    (
+        "rate_limit",
+        &["max_failures", "lockout_seconds", "max_lockout_seconds"],
+    ),
+    (
+        "proxy",
+        &["trusted_addresses", "user_header", "allowed_users"],
+    ),
+    (
+        "cors",
+        &[
+            "allowed_origins",
+            "allowed_methods",
+            "allowed_headers",
+            "max_age_seconds",
+            "allow_credentials",
+        ],
+    ),
+    (
+        "security_headers",
+        &[
+            "content_security_policy",
+            "frame_options",
+            "referrer_policy",
+            "hsts_max_age_seconds",
+        ],
+    ),
+    ("logging", &["level", "format"]),
+    ("metrics", &["token", "listen"]),
+    ("assets", &["directory"]),
+    ("backup", &["directory", "interval_hours", "keep"]),
+    ("retention", &["deletions_days", "audit_days"]),
+];
+
+/* The web app only loads its own scripts and styles. Inline styles are used by the editor,
+images in notes may come from anywhere. */
+const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; script-src 'self'; \
+     style-src 'self' 'unsafe-inline'; img-src 'self' data: https:; font-src 'self' data:; \
+     connect-src 'self'; worker-src 'self'; manifest-src 'self'; object-src 'none'; \
+     base-uri 'self'; form-action 'self'; frame-ancestors 'none'";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub listen: ListenConfig,
+    pub tls: TlsConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub rate_limit: RateLimitConfig,
+    pub proxy: ProxyConfig,
+    pub cors: CorsConfig,
+    pub security_headers: SecurityHeadersConfig,
+    pub logging: LoggingConfig,
+    pub metrics: MetricsConfig,
+    pub assets: AssetsConfig,
+    pub backup: BackupConfig,
+    pub retention: RetentionConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ListenConfig {
+    pub host: String,
+    pub port: u16,
+    pub shutdown_timeout_seconds: u16,
+    pub unix_socket: Option<PathBuf>,
+    pub unix_socket_mode: String,
+    pub base_path: String,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        ListenConfig {
+            host: "127.0.0.1".to_string(),
+            port: 8000,
+            shutdown_timeout_seconds: 30,
+            unix_socket: None,
+            unix_socket_mode: "660".to_string(),
+            base_path: "/".to_string(),
+        }
+    }
+}
+
+impl ListenConfig {
+    /* Permissions of the socket file, in octal like `chmod`. */
+    pub fn unix_socket_mode(&self) -> Result<u32, String> {
+        u32::from_str_radix(&self.unix_socket_mode, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+            .ok_or_else(|| format!("invalid mode {}", self.unix_socket_mode))
+    }
+
+    /* The path the app is served under without trailing slash, empty at the domain root. */
+    pub fn base_path(&self) -> &str {
+        self.base_path.trim_end_matches('/')
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    pub key: Option<PathBuf>,
+    pub cert: Option<PathBuf>,
+    pub client_ca: Option<PathBuf>,
+    pub require_client_cert: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub url: String,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: "pragma.sqlite".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub token: Option<String>,
+    pub username: Option<String>,
+    pub password_hash: Option<String>,
+    pub session_days: u32,
+    pub require_two_factor: bool,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            token: None,
+            username: None,
+            password_hash: None,
+            session_days: 30,
+            require_two_factor: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub max_failures: u32,
+    pub lockout_seconds: u64,
+    pub max_lockout_seconds: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            max_failures: 5,
+            lockout_seconds: 30,
+            max_lockout_seconds: 60 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ProxyConfig {
+    pub trusted_addresses: Vec<String>,
+    pub user_header: Option<String>,
+    pub allowed_users: Vec<String>,
+}
+
+impl ProxyConfig {
+    pub fn trusted_proxies(&self) -> Result<TrustedProxies, String> {
+        self.trusted_addresses
+            .iter()
+            .map(|address| address.parse::<Network>())
+            .collect::<Result<Vec<_>, _>>()
+            .map(TrustedProxies::new)
+    }
+}
+
+/* Cross-origin requests to the API are only allowed from `allowed_origins`. */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub max_age_seconds: u32,
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: vec![],
+            allowed_methods: vec![
+                "GET".to_string(),
+                "POST".to_string(),
+                "PUT".to_string(),
+                "DELETE".to_string(),
+            ],
+            allowed_headers: vec![
+                "Authorization".to_string(),
+                "Content-Type".to_string(),
+                "X-Request-Id".to_string(),
+            ],
+            max_age_seconds: 3600,
+            allow_credentials: false,
+        }
+    }
+}
+
+/* Headers sent with every response. An empty value leaves the header out. */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SecurityHeadersConfig {
+    pub content_security_policy: String,
+    pub frame_options: String,
+    pub referrer_policy: String,
+    pub hsts_max_age_seconds: u32,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        SecurityHeadersConfig {
+            content_security_policy: DEFAULT_CONTENT_SECURITY_POLICY.to_string(),
+            frame_options: "DENY".to_string(),
+            referrer_policy: "no-referrer".to_string(),
+            hsts_max_age_seconds: 365 * 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub format: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: "info".to_string(),
+            format: "text".to_string(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    pub fn level_filter(&self) -> log::LevelFilter {
+        log::LevelFilter::from_str(&self.level).unwrap_or(log::LevelFilter::Info)
+    }
+
+    pub fn json(&self) -> bool {
+        self.format == "json"
+    }
+}
+
+/* Metrics are only served if a token or a separate listen address is configured. */
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub token: Option<String>,
+    pub listen: Option<String>,
+}
+
+/* Files in `directory` are served in place of the embedded web app. */
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct AssetsConfig {
+    pub directory: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BackupConfig {
+    pub directory: Option<PathBuf>,
+    pub interval_hours: u64,
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            directory: None,
+            interval_hours: 24,
+            keep: 7,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RetentionConfig {
+    pub deletions_days: Option<u32>,
+    pub audit_days: Option<u32>,
+}
+
+/// Loads the configuration file given by `--config` or `CONFIG_FILE` (if any)
+/// and applies environment variable overrides on top of it.
+pub fn load() -> Result<Config, String> {
+    let mut config = match config_path() {
+        Some(path) => from_file(&path)?,
+        None => Config::default(),
+    };
+
+    apply_env(&mut config)?;
+    validate(&config)?;
+
+    Ok(config)
+}
+
+/// Command line arguments without the `--config` option, e.g. `["tokens", "list"]`.
+pub fn command_args() -> Vec<String> {
+    let mut args = env::args().skip(1);
+    let mut command_args = vec![];
+
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            args.next();
+        } else if !arg.starts_with("--config=") {
+            command_args.push(arg);
+        }
+    }
+
+    command_args
+}
+
+fn config_path() -> Option<PathBuf> {
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    env::var("CONFIG_FILE").ok().map(PathBuf::from)
+}
+
+fn from_file(path: &Path) -> Result<Config, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+
+    let value: toml::Value = contents
+        .parse()
+        .map_err(|e| format!("Failed to parse config file {}: {}", path.display(), e))?;
+
+    let invalid_keys = invalid_keys(&value);
+    if !invalid_keys.is_empty() {
+        return Err(format!(
+            "Invalid keys in config file {}: {}",
+            path.display(),
+            invalid_keys.join(", ")
+        ));
+    }
+
+    value
+        .try_into()
+        .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))
+}
+
+fn invalid_keys(value: &toml::Value) -> Vec<String> {
+    let known: BTreeMap<&str, &[&str]> = KNOWN_KEYS.iter().cloned().collect();
+    let mut invalid = vec![];
+
+    let table = match value.as_table() {
+        Some(table) => table,
+        None => return invalid,
+    };
+
+    for (section, section_value) in table {
+        match (known.get(section.as_str()), section_value.as_table()) {
+            (Some(keys), Some(section_table)) => {
+                for key in section_table.keys() {
+                    if !keys.contains(&key.as_str()) {
+                        invalid.push(format!("{}.{}", section, key));
+                    }
+                }
+            }
+            _ => invalid.push(section.to_string()),
+        }
+    }
+
+    invalid
+}
+
+fn apply_env(config: &mut Config) -> Result<(), String> {
+    if let Ok(host) = env::var("LISTEN_HOST") {
+        config.listen.host = host;
+    }
+    if let Some(port) = parse_env("PORT")? {
+        config.listen.port = port;
+    }
+    if let Some(timeout) = parse_env("SHUTDOWN_TIMEOUT_SECONDS")? {
+        config.listen.shutdown_timeout_seconds = timeout;
+    }
+    if let Ok(unix_socket) = env::var("LISTEN_UNIX_SOCKET") {
+        config.listen.unix_socket = Some(PathBuf::from(unix_socket));
+    }
+    if let Ok(mode) = env::var("LISTEN_UNIX_SOCKET_MODE") {
+        config.listen.unix_socket_mode = mode;
+    }
+    if let Ok(base_path) = env::var("BASE_PATH") {
+        config.listen.base_path = base_path;
+    }
+    if env::var("SSL").is_ok() {
+        config.tls.enabled = true;
+    }
+    if let Ok(key) = env::var("SSL_KEY") {
+        config.tls.key = Some(PathBuf::from(key));
+    }
+    if let Ok(cert) = env::var("SSL_CERT") {
+        config.tls.cert = Some(PathBuf::from(cert));
+    }
+    if let Ok(client_ca) = env::var("SSL_CLIENT_CA") {
+        config.tls.client_ca = Some(PathBuf::from(client_ca));
+    }
+    if let Some(require_client_cert) = parse_env("SSL_REQUIRE_CLIENT_CERT")? {
+        config.tls.require_client_cert = require_client_cert;
+    }
+    if let Ok(url) = env::var("DATABASE_URL") {
+        config.database.url = url;
+    }
+    if let Ok(token) = env::var("AUTH_TOKEN") {
+        config.auth.token = Some(token);
+    }
+    if let Ok(username) = env::var("AUTH_USERNAME") {
+        config.auth.username = Some(username);
+    }
+    if let Ok(password_hash) = env::var("AUTH_PASSWORD_HASH") {
+        config.auth.password_hash = Some(password_hash);
+    }
+    if let Some(session_days) = parse_env("AUTH_SESSION_DAYS")? {
+        config.auth.session_days = session_days;
+    }
+    if let Some(require_two_factor) = parse_env("AUTH_REQUIRE_TWO_FACTOR")? {
+        config.auth.require_two_factor = require_two_factor;
+    }
+    if let Some(max_failures) = parse_env("RATE_LIMIT_MAX_FAILURES")? {
+        config.rate_limit.max_failures = max_failures;
+    }
+    if let Some(lockout_seconds) = parse_env("RATE_LIMIT_LOCKOUT_SECONDS")? {
+        config.rate_limit.lockout_seconds = lockout_seconds;
+    }
+    if let Some(max_lockout_seconds) = parse_env("RATE_LIMIT_MAX_LOCKOUT_SECONDS")? {
+        config.rate_limit.max_lockout_seconds = max_lockout_seconds;
+    }
+    if let Ok(addresses) = env::var("TRUSTED_PROXIES") {
+        config.proxy.trusted_addresses = parse_list(&addresses);
+    }
+    if let Ok(user_header) = env::var("PROXY_USER_HEADER") {
+        config.proxy.user_header = Some(user_header);
+    }
+    if let Ok(users) = env::var("PROXY_ALLOWED_USERS") {
+        config.proxy.allowed_users = parse_list(&users);
+    }
+    if let Ok(origins) = env::var("CORS_ALLOWED_ORIGINS") {
+        config.cors.allowed_origins = parse_list(&origins);
+    }
+    if let Ok(methods) = env::var("CORS_ALLOWED_METHODS") {
+        config.cors.allowed_methods = parse_list(&methods);
+    }
+    if let Ok(headers) = env::var("CORS_ALLOWED_HEADERS") {
+        config.cors.allowed_headers = parse_list(&headers);
+    }
+    if let Some(max_age) = parse_env("CORS_MAX_AGE_SECONDS")? {
+        config.cors.max_age_seconds = max_age;
+    }
+    if let Some(allow_credentials) = parse_env("CORS_ALLOW_CREDENTIALS")? {
+        config.cors.allow_credentials = allow_credentials;
+    }
+    if let Ok(policy) = env::var("CONTENT_SECURITY_POLICY") {
+        config.security_headers.content_security_policy = policy;
+    }
+    if let Ok(frame_options) = env::var("FRAME_OPTIONS") {
+        config.security_headers.frame_options = frame_options;
+    }
+    if let Ok(referrer_policy) = env::var("REFERRER_POLICY") {
+        config.security_headers.referrer_policy = referrer_policy;
+    }
+    if let Some(max_age) = parse_env("HSTS_MAX_AGE_SECONDS")? {
+        config.security_headers.hsts_max_age_seconds = max_age;
+    }
+    if let Ok(level) = env::var("LOG_LEVEL") {
+        config.logging.level = level;
+    }
+    if let Ok(format) = env::var("LOG_FORMAT") {
+        config.logging.format = format;
+    }
+    if let Ok(token) = env::var("METRICS_TOKEN") {
+        config.metrics.token = Some(token);
+    }
+    if let Ok(listen) = env::var("METRICS_LISTEN") {
+        config.metrics.listen = Some(listen);
+    }
+    if let Ok(directory) = env::var("ASSETS_DIRECTORY") {
+        config.assets.directory = Some(PathBuf::from(directory));
+    }
+    if let Ok(directory) = env::var("BACKUP_DIRECTORY") {
+        config.backup.directory = Some(PathBuf::from(directory));
+    }
+    if let Some(interval_hours) = parse_env("BACKUP_INTERVAL_HOURS")? {
+        config.backup.interval_hours = interval_hours;
+    }
+    if let Some(keep) = parse_env("BACKUP_KEEP")? {
+        config.backup.keep = keep;
+    }
+    if let Some(days) = parse_env("DELETIONS_RETENTION_DAYS")? {
+        config.retention.deletions_days = Some(days);
+    }
+    if let Some(days) = parse_env("AUDIT_RETENTION_DAYS")? {
+        config.retention.audit_days = Some(days);
+    }
+
+    Ok(())
+}
+
+/* Comma separated lists, like `TRUSTED_PROXIES=10.0.0.1, 10.0.0.2`. */
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn parse_env<T: FromStr>(name: &str) -> Result<Option<T>, String> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("Invalid value for {}: {}", name, value)),
+        Err(_) => Ok(None),
+    }
+}
+
+/* Browsers send the origin as scheme, host and optional port, without a path. Extensions
+have their own schemes, like `moz-extension://`. */
+fn is_valid_origin(origin: &str) -> bool {
+    if origin == "*" {
+        return true;
+    }
+
+    match origin.split("://").collect::<Vec<_>>().as_slice() {
+        [scheme, host] => {
+            !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+                && !host.is_empty()
+                && !host.contains('/')
+        }
+        _ => false,
+    }
+}
+
+/* The base path ends up in cookies and HTML, so only plain paths are accepted. Browsers resolve
+`.` and `..`, which would no longer match the path the server expects. */
+fn is_valid_base_path(base_path: &str) -> bool {
+    base_path.starts_with('/')
+        && !base_path.contains("//")
+        && !base_path
+            .split('/')
+            .any(|segment| segment == "." || segment == "..")
+        && base_path
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.~/".contains(c))
+}
+
+fn validate(config: &Config) -> Result<(), String> {
+    let mut errors = vec![];
+
+    if log::LevelFilter::from_str(&config.logging.level).is_err() {
+        errors.push(format!(
+            "logging.level: unknown level {}",
+            config.logging.level
+        ));
+    }
+    if config.logging.format != "text" && config.logging.format != "json" {
+        errors.push(format!(
+            "logging.format: must be text or json, not {}",
+            config.logging.format
+        ));
+    }
+    if let Err(e) = config.listen.unix_socket_mode() {
+        errors.push(format!("listen.unix_socket_mode: {}", e));
+    }
+    if !is_valid_base_path(&config.listen.base_path) {
+        errors.push(format!(
+            "listen.base_path: must be a plain path like /notes, not {}",
+            config.listen.base_path
+        ));
+    }
+    if config.listen.unix_socket.is_some() && config.tls.enabled {
+        errors.push("listen.unix_socket: can't be used with TLS".to_string());
+    }
+    if config.tls.enabled && config.tls.key.is_none() {
+        errors.push("tls.key: required when TLS is enabled".to_string());
+    }
+    if config.tls.enabled && config.tls.cert.is_none() {
+        errors.push("tls.cert: required when TLS is enabled".to_string());
+    }
+    if config.tls.client_ca.is_some() && !config.tls.enabled {
+        errors.push("tls.client_ca: requires TLS to be enabled".to_string());
+    }
+    if config.tls.require_client_cert && config.tls.client_ca.is_none() {
+        errors.push("tls.require_client_cert: requires tls.client_ca".to_string());
+    }
+    if config.auth.username.is_some() != config.auth.password_hash.is_some() {
+        errors.push("auth.username and auth.password_hash: must be set together".to_string());
+    }
+    if config.auth.session_days == 0 {
+        errors.push("auth.session_days: must be greater than 0".to_string());
+    }
+    if config.auth.require_two_factor && config.auth.username.is_none() {
+        errors.push("auth.require_two_factor: requires auth.username".to_string());
+    }
+    if config.rate_limit.max_failures == 0 {
+        errors.push("rate_limit.max_failures: must be greater than 0".to_string());
+    }
+    if config.rate_limit.max_lockout_seconds < config.rate_limit.lockout_seconds {
+        errors.push(
+            "rate_limit.max_lockout_seconds: must be at least rate_limit.lockout_seconds"
+                .to_string(),
+        );
+    }
+    if let Err(e) = config.proxy.trusted_proxies() {
+        errors.push(format!("proxy.trusted_addresses: {}", e));
+    }
+    if config.proxy.user_header.is_some()
+        && config.proxy.trusted_addresses.is_empty()
+        && config.listen.unix_socket.is_none()
+    {
+        errors.push(
+            "proxy.user_header: requires proxy.trusted_addresses or listen.unix_socket".to_string(),
+        );
+    }
+    for origin in &config.cors.allowed_origins {
+        if !is_valid_origin(origin) {
+            errors.push(format!(
+                "cors.allowed_origins: invalid origin {}, expected e.g. https://example.org",
+                origin
+            ));
+        }
+    }
+    if config.cors.allow_credentials
+        && config
+            .cors
+            .allowed_origins
+            .iter()
+            .any(|origin| origin == "*")
+    {
+        errors.push(
+            "cors.allow_credentials: can't be combined with allowed_origins *, list the origins instead"
+                .to_string(),
+        );
+    }
+    for method in &config.cors.allowed_methods {
+        if Method::from_bytes(method.as_bytes()).is_err() {
+            errors.push(format!("cors.allowed_methods: invalid method {}", method));
+        }
+    }
+    for header in &config.cors.allowed_headers {
+        if HeaderName::from_bytes(header.as_bytes()).is_err() {
+            errors.push(format!("cors.allowed_headers: invalid header {}", header));
+        }
+    }
+    for (key, value) in &[
+        (
+            "content_security_policy",
+            &config.security_headers.content_security_policy,
+        ),
+        ("frame_options", &config.security_headers.frame_options),
+        ("referrer_policy", &config.security_headers.referrer_policy),
+    ] {
+        if HeaderValue::from_str(value).is_err() {
+            errors.push(format!("security_headers.{}: invalid header value", key));
+        }
+    }
+    if let Some(ref listen) = config.metrics.listen {
+        if listen.parse::<SocketAddr>().is_err() {
+            errors.push(format!("metrics.listen: invalid address {}", listen));
+        }
+    }
+    if let Some(ref directory) = config.assets.directory {
+        if !directory.is_dir() {
+            errors.push(format!(
+                "assets.directory: {} is not a directory",
+                directory.display()
+            ));
+        }
+    }
+    if config.backup.interval_hours == 0 {
+        errors.push("backup.interval_hours: must be greater than 0".to_string());
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Invalid configuration: {}", errors.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /* Makes a default config invalid. */
+    type Change = fn(&mut Config);
+
+    /* A config file with the given contents, removed when dropped. */
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(contents: &str) -> Self {
+            let path = env::temp_dir().join(format!("pragma-config-{}.toml", std::process::id()));
+            fs::write(&path, contents).unwrap();
+            TestFile(path)
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn knows_every_key() {
+        let value = serde_json::to_value(Config::default()).unwrap();
+        let sections = value.as_object().unwrap();
+
+        let known: BTreeMap<&str, &[&str]> = KNOWN_KEYS.iter().cloned().collect();
+        assert_eq!(
+            sections.keys().map(String::as_str).collect::<BTreeSet<_>>(),
+            known.keys().cloned().collect::<BTreeSet<_>>()
+        );
+        for (section, keys) in sections {
+            assert_eq!(
+                keys.as_object()
+                    .unwrap()
+                    .keys()
+                    .map(String::as_str)
+                    .collect::<BTreeSet<_>>(),
+                known[section.as_str()]
+                    .iter()
+                    .cloned()
+                    .collect::<BTreeSet<_>>(),
+                "{}",
+                section
+            );
+        }
+    }
+
+    #[test]
+    fn reports_all_invalid_keys() {
+        let value: toml::Value = "
+            unknown = 1
+            logging = \"debug\"
+            [listen]
+            port = 9000
+            prot = 9000
+            [cors]
+            allowed_origin = [\"https://example.org\"]
+            allow_credentials = true
+            [extra]
+            key = 1
+        "
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            invalid_keys(&value),
+            vec![
+                "cors.allowed_origin",
+                "extra",
+                "listen.prot",
+                "logging",
+                "unknown"
+            ]
+        );
+    }
+
+    #[test]
+    fn applies_environment_over_file() {
+        let file = TestFile::new(
+            "[listen]\nhost = \"0.0.0.0\"\nport = 9000\n\
+             [cors]\nallowed_origins = [\"https://a.example.org\"]\nmax_age_seconds = 60\n",
+        );
+        env::set_var("PORT", "9100");
+        env::set_var(
+            "CORS_ALLOWED_ORIGINS",
+            "https://b.example.org, https://c.example.org,",
+        );
+
+        let mut config = from_file(&file.0).unwrap();
+        assert_eq!(config.listen.port, 9000);
+        apply_env(&mut config).unwrap();
+        assert_eq!(config.listen.host, "0.0.0.0");
+        assert_eq!(config.listen.port, 9100);
+        assert_eq!(
+            config.cors.allowed_origins,
+            vec!["https://b.example.org", "https://c.example.org"]
+        );
+        assert_eq!(config.cors.max_age_seconds, 60);
+        // Keys that are in neither keep their defaults.
+        assert_eq!(config.cors.allowed_methods.len(), 4);
+
+        env::set_var("PORT", "port");
+        assert_eq!(
+            apply_env(&mut config).err(),
+            Some("Invalid value for PORT: port".to_string())
+        );
+        env::remove_var("PORT");
+        env::remove_var("CORS_ALLOWED_ORIGINS");
+
+        let file = TestFile::new("[listen]\nport = 9000\nprot = 9000\n[extra]\n");
+        assert_eq!(
+            from_file(&file.0).err(),
+            Some(format!(
+                "Invalid keys in config file {}: extra, listen.prot",
+                file.0.display()
+            ))
+        );
+    }
+
+    #[test]
+    fn validates_config() {
+        assert_eq!(validate(&Config::default()), Ok(()));
+
+        let cases: &[(Change, &str)] = &[
+            (|c| c.logging.level = "loud".to_string(), "logging.level: unknown level loud"),
+            (
+                |c| c.logging.format = "xml".to_string(),
+                "logging.format: must be text or json, not xml",
+            ),
+            (
+                |c| c.listen.unix_socket_mode = "999".to_string(),
+                "listen.unix_socket_mode: invalid mode 999",
+            ),
+            (
+                |c| c.listen.base_path = "/notes/../x".to_string(),
+                "listen.base_path: must be a plain path like /notes, not /notes/../x",
+            ),
+            (
+                |c| c.tls.enabled = true,
+                "tls.key: required when TLS is enabled, tls.cert: required when TLS is enabled",
+            ),
+            (
+                |c| c.tls.require_client_cert = true,
+                "tls.require_client_cert: requires tls.client_ca",
+            ),
+            (
+                |c| c.auth.username = Some("alice".to_string()),
+                "auth.username and auth.password_hash: must be set together",
+            ),
+            (|c| c.auth.session_days = 0, "auth.session_days: must be greater than 0"),
+            (
+                |c| c.rate_limit.max_failures = 0,
+                "rate_limit.max_failures: must be greater than 0",
+            ),
+            (
+                |c| c.rate_limit.max_lockout_seconds = 1,
+                "rate_limit.max_lockout_seconds: must be at least rate_limit.lockout_seconds",
+            ),
+            (
+                |c| c.proxy.user_header = Some("X-Forwarded-User".to_string()),
+                "proxy.user_header: requires proxy.trusted_addresses or listen.unix_socket",
+            ),
+            (
+                |c| c.cors.allowed_origins = vec!["example.org".to_string()],
+                "cors.allowed_origins: invalid origin example.org, expected e.g. https://example.org",
+            ),
+            (
+                |c| {
+                    c.cors.allowed_origins = vec!["*".to_string()];
+                    c.cors.allow_credentials = true;
+                },
+                "cors.allow_credentials: can't be combined with allowed_origins *, list the origins instead",
+            ),
+            (
+                |c| c.cors.allowed_headers = vec!["X Header".to_string()],
+                "cors.allowed_headers: invalid header X Header",
+            ),
+            (
+                |c| c.security_headers.frame_options = "DENY\n".to_string(),
+                "security_headers.frame_options: invalid header value",
+            ),
+            (
+                |c| c.metrics.listen = Some("localhost".to_string()),
+                "metrics.listen: invalid address localhost",
+            ),
+            (
+                |c| c.backup.interval_hours = 0,
+                "backup.interval_hours: must be greater than 0",
+            ),
+        ];
+        for (change, expected) in cases {
+            let mut config = Config::default();
+            change(&mut config);
+            assert_eq!(
+                validate(&config),
+                Err(format!("Invalid configuration: {}", expected))
+            );
+        }
+
+        // All errors are reported at once.
+        let mut config = Config::default();
+        config.auth.session_days = 0;
+        config.backup.interval_hours = 0;
+        assert_eq!(
+            validate(&config),
+            Err(
+                "Invalid configuration: auth.session_days: must be greater than 0, \
+                 backup.interval_hours: must be greater than 0"
+                    .to_string()
+            )
+        );
+    }
+}
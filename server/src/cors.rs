@@ -0,0 +1,304 @@
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::{Middleware, Response, Started};
+use actix_web::{HttpRequest, HttpResponse, Result};
+
+use super::actix_state::State;
+use super::config::CorsConfig;
+
+/* Without allowed origins only same-origin requests work, as browsers block the others. */
+#[derive(Clone)]
+pub struct Settings {
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<String>,
+    allowed_headers: Vec<String>,
+    max_age_seconds: u32,
+    allow_credentials: bool,
+}
+
+impl Settings {
+    pub fn from_config(config: &CorsConfig) -> Self {
+        Settings {
+            allowed_origins: config
+                .allowed_origins
+                .iter()
+                .map(|origin| origin.to_lowercase())
+                .collect(),
+            allowed_methods: config
+                .allowed_methods
+                .iter()
+                .map(|method| method.to_uppercase())
+                .collect(),
+            allowed_headers: config
+                .allowed_headers
+                .iter()
+                .map(|header| header.to_lowercase())
+                .collect(),
+            max_age_seconds: config.max_age_seconds,
+            allow_credentials: config.allow_credentials,
+        }
+    }
+
+    fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.allows_any_origin()
+            || self
+                .allowed_origins
+                .iter()
+                .any(|allowed| *allowed == origin.to_lowercase())
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        self.allowed_methods
+            .iter()
+            .any(|allowed| *allowed == method.to_uppercase())
+    }
+
+    fn allows_headers(&self, headers: &str) -> bool {
+        headers
+            .split(',')
+            .map(|header| header.trim().to_lowercase())
+            .filter(|header| !header.is_empty())
+            .all(|header| self.allowed_headers.contains(&header))
+    }
+
+    /* With `*` any origin is allowed, the response then doesn't depend on it. Browsers only
+    pass responses to credentialed requests to the page if credentials are allowed. The session
+    cookie and client certificates are credentials, API tokens in the Authorization header are
+    not. The session cookie is `SameSite=Strict`, so only origins on the same site send it. */
+    fn allow_origin(&self, origin: &str, headers: &mut HeaderMap) {
+        let allowed = if self.allows_any_origin() {
+            "*"
+        } else {
+            origin
+        };
+
+        if let Ok(value) = HeaderValue::from_str(allowed) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        }
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        let varies_by_origin = headers
+            .get_all(header::VARY)
+            .iter()
+            .any(|vary| vary == "Origin");
+        if !self.allows_any_origin() && !varies_by_origin {
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+    }
+
+    /* The answer to a preflight request, or None if the request isn't one. */
+    fn preflight<S>(&self, req: &HttpRequest<S>) -> Option<HttpResponse> {
+        let request_method = header_str(req, header::ACCESS_CONTROL_REQUEST_METHOD);
+
+        let (origin, request_method) = match (origin(req), request_method) {
+            (Some(origin), Some(method)) if *req.method() == Method::OPTIONS => (origin, method),
+            _ => return None,
+        };
+
+        let request_headers = header_str(req, header::ACCESS_CONTROL_REQUEST_HEADERS).unwrap_or("");
+
+        if !self.allows_origin(&origin)
+            || !self.allows_method(request_method)
+            || !self.allows_headers(request_headers)
+        {
+            return Some(HttpResponse::Forbidden().body("Cross-origin request not allowed"));
+        }
+
+        let mut response = HttpResponse::build(StatusCode::NO_CONTENT)
+            .header(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                self.allowed_methods.join(", "),
+            )
+            .header(
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                self.allowed_headers.join(", "),
+            )
+            .header(
+                header::ACCESS_CONTROL_MAX_AGE,
+                self.max_age_seconds.to_string(),
+            )
+            .finish();
+        self.allow_origin(&origin, response.headers_mut());
+
+        Some(response)
+    }
+
+    fn add_headers<S>(&self, req: &HttpRequest<S>, resp: &mut HttpResponse) {
+        if let Some(origin) = origin(req) {
+            if self.allows_origin(&origin) {
+                self.allow_origin(&origin, resp.headers_mut());
+                resp.headers_mut().insert(
+                    header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                    HeaderValue::from_static("X-Request-Id"),
+                );
+            }
+        }
+    }
+}
+
+pub struct Cors;
+
+pub fn middleware() -> Cors {
+    Cors
+}
+
+fn origin<S>(req: &HttpRequest<S>) -> Option<String> {
+    req.headers()
+        .get(header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+        .map(String::from)
+}
+
+fn header_str<S>(req: &HttpRequest<S>, name: header::HeaderName) -> Option<&str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+/* Preflight requests are answered before authentication, since browsers send them without
+credentials. Disallowed preflights are rejected, so the browser won't send the request. */
+impl Middleware<State> for Cors {
+    fn start(&self, req: &HttpRequest<State>) -> Result<Started> {
+        match req.state().cors.preflight(req) {
+            Some(response) => Ok(Started::Response(response)),
+            None => Ok(Started::Done),
+        }
+    }
+
+    fn response(&self, req: &HttpRequest<State>, mut resp: HttpResponse) -> Result<Response> {
+        req.state().cors.add_headers(req, &mut resp);
+        Ok(Response::Done(resp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn settings(allowed_origins: &[&str], allow_credentials: bool) -> Settings {
+        Settings::from_config(&CorsConfig {
+            allowed_origins: allowed_origins.iter().map(|s| s.to_string()).collect(),
+            allow_credentials,
+            ..CorsConfig::default()
+        })
+    }
+
+    fn preflight(origin: &str, method: &str, headers: &str) -> HttpRequest {
+        TestRequest::with_header(header::ORIGIN, origin)
+            .method(Method::OPTIONS)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, headers)
+            .finish()
+    }
+
+    fn header_value(resp: &HttpResponse, name: header::HeaderName) -> Option<&str> {
+        resp.headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    #[test]
+    fn allows_only_same_origin_by_default() {
+        let settings = settings(&[], false);
+
+        let resp = settings
+            .preflight(&preflight("https://other.example.org", "GET", ""))
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(header_value(&resp, header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        let req = TestRequest::with_header(header::ORIGIN, "https://other.example.org").finish();
+        let mut resp = HttpResponse::Ok().finish();
+        settings.add_headers(&req, &mut resp);
+        assert!(resp.headers().is_empty());
+
+        // Requests without an origin or a requested method aren't preflights.
+        let req = TestRequest::default().method(Method::OPTIONS).finish();
+        assert!(settings.preflight(&req).is_none());
+        let req = TestRequest::with_header(header::ORIGIN, "https://other.example.org")
+            .method(Method::OPTIONS)
+            .finish();
+        assert!(settings.preflight(&req).is_none());
+    }
+
+    #[test]
+    fn answers_preflight_from_allowed_origins() {
+        let settings = settings(&["https://Dashboard.example.org"], false);
+
+        let req = preflight(
+            "https://dashboard.example.org",
+            "put",
+            "Authorization, content-type",
+        );
+        let resp = settings.preflight(&req).unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            header_value(&resp, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some("https://dashboard.example.org")
+        );
+        assert_eq!(
+            header_value(&resp, header::ACCESS_CONTROL_ALLOW_METHODS),
+            Some("GET, POST, PUT, DELETE")
+        );
+        assert_eq!(
+            header_value(&resp, header::ACCESS_CONTROL_ALLOW_HEADERS),
+            Some("authorization, content-type, x-request-id")
+        );
+        assert_eq!(
+            header_value(&resp, header::ACCESS_CONTROL_MAX_AGE),
+            Some("3600")
+        );
+        assert_eq!(header_value(&resp, header::VARY), Some("Origin"));
+        assert!(header_value(&resp, header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+
+        let disallowed = [
+            preflight("https://other.example.org", "GET", ""),
+            preflight("https://dashboard.example.org", "PATCH", ""),
+            preflight("https://dashboard.example.org", "GET", "X-Other"),
+        ];
+        for req in &disallowed {
+            let resp = settings.preflight(req).unwrap();
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+    }
+
+    #[test]
+    fn adds_headers_to_responses() {
+        let req =
+            TestRequest::with_header(header::ORIGIN, "https://dashboard.example.org").finish();
+
+        let mut resp = HttpResponse::Ok().header(header::VARY, "Origin").finish();
+        settings(&["https://dashboard.example.org"], true).add_headers(&req, &mut resp);
+        assert_eq!(
+            header_value(&resp, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some("https://dashboard.example.org")
+        );
+        assert_eq!(
+            header_value(&resp, header::ACCESS_CONTROL_ALLOW_CREDENTIALS),
+            Some("true")
+        );
+        assert_eq!(
+            header_value(&resp, header::ACCESS_CONTROL_EXPOSE_HEADERS),
+            Some("X-Request-Id")
+        );
+        assert_eq!(resp.headers().get_all(header::VARY).iter().count(), 1);
+
+        // With any origin allowed, responses don't vary by origin.
+        let mut resp = HttpResponse::Ok().finish();
+        settings(&["*"], false).add_headers(&req, &mut resp);
+        assert_eq!(
+            header_value(&resp, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some("*")
+        );
+        assert!(header_value(&resp, header::VARY).is_none());
+    }
+}
@@ -1,4 +1,9 @@
+use super::diagram;
+use super::markdown;
+use super::math;
+use super::table;
 use chrono::prelude::*;
+use serde_json::Number;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -64,6 +69,74 @@ pub struct NoteUpdate {
 pub enum Content {
     Text { text: String },
     Code { language: String, code: String },
+    Checklist { items: Vec<ChecklistItem> },
+    Markdown { markdown: String },
+    Math { latex: String, display: bool },
+    Diagram { dot: String },
+    Table(Table),
+}
+
+impl Content {
+    /* Checks what deserializing doesn't, like Markdown nested too deeply, invalid LaTeX,
+    diagrams too large to lay out or table rows without a cell for every column. */
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Content::Markdown { markdown } => markdown::validate(markdown),
+            Content::Math { latex, .. } => math::validate(latex),
+            Content::Diagram { dot } => diagram::validate(dot),
+            Content::Table(table) => table::validate(table),
+            _ => Ok(()),
+        }
+    }
+}
+
+/* Item text is plain text, not HTML. */
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChecklistItem {
+    pub text: String,
+    #[serde(default)]
+    pub checked: bool,
+    #[serde(default)]
+    pub due_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub items: Vec<ChecklistItem>,
+}
+
+/* Every row has a cell for each column. */
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Table {
+    pub columns: Vec<TableColumn>,
+    pub rows: Vec<Vec<TableCell>>,
+}
+
+/* Header text is plain text, not HTML. */
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TableColumn {
+    pub header: String,
+    #[serde(default)]
+    pub align: ColumnAlign,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ColumnAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/* Cells are JSON values: null when empty, booleans, numbers or plain text. */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum TableCell {
+    Empty,
+    Boolean(bool),
+    Number(Number),
+    Text(String),
 }
 
 pub type Tag = String;
@@ -105,3 +178,184 @@ pub struct Deletion {
     pub resource_id: String,
     pub system_updated_at: DateTime<Utc>,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    ReadOnly,
+    ReadWrite,
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::ReadOnly => "read-only",
+            Scope::ReadWrite => "read-write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        match scope {
+            "read-only" => Ok(Scope::ReadOnly),
+            "read-write" => Ok(Scope::ReadWrite),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(format!("Unknown scope {}", scope)),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub scope: Scope,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewApiToken {
+    pub name: String,
+    pub scope: Scope,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedApiToken {
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+    pub token: String,
+}
+
+#[derive(Debug)]
+pub struct Session {
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+    pub code: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TwoFactorEnrollment {
+    pub uri: String,
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TwoFactorCode {
+    pub code: String,
+}
+
+/* Who made a change and from where, recorded in the audit log. */
+#[derive(Debug, Clone)]
+pub struct AuditContext {
+    pub actor: String,
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AuditContext {
+    /* Changes made with `pragma-server` subcommands. */
+    pub fn command_line() -> Self {
+        AuditContext {
+            actor: "cli".to_string(),
+            client_ip: None,
+            user_agent: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+}
+
+impl AuditAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub resource_type: String,
+    pub resource_id: String,
+    pub action: String,
+    pub actor: String,
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct AuditFilter {
+    pub resource_type: Option<String>,
+    pub resource_id: Option<String>,
+    pub action: Option<String>,
+    pub actor: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub before_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub next_before_id: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ChecklistItemFilter {
+    pub due_by: Option<NaiveDate>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CsvImport {
+    pub note_id: String,
+}
+
+/* An unchecked checklist item, with the note it's in. */
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenChecklistItem {
+    pub note_id: String,
+    pub note_title: String,
+    pub content_block_id: String,
+    /* Positions of the item's parents and the item itself, starting at the top level. */
+    pub path: Vec<usize>,
+    pub text: String,
+    pub due_date: Option<NaiveDate>,
+}
+
+#[derive(Debug)]
+pub struct ResourceCounts {
+    pub notebooks: i64,
+    pub notes: i64,
+    pub content_blocks: i64,
+    pub deletions: i64,
+}
@@ -0,0 +1,2932 @@
+/* Lays out Graphviz DOT diagrams and renders them to SVG, without depending on Graphviz.
+
+Reads the DOT language with nodes, edges, attribute statements, subgraphs, clusters and
+`rank=same`. The layout works like Graphviz's dot: nodes are put in ranks along the edges,
+ordered to reduce crossings and then spaced out so edges run as straight as possible. The
+commonly used attributes are supported and others are ignored, like Graphviz ignores
+attributes it doesn't know. */
+
+use openssl::sha::sha1;
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::SQRT_2;
+use std::sync::{Arc, Mutex};
+
+/* Subgraphs nested deeper than this are rejected, which keeps parsing from exhausting the
+stack. */
+const MAX_NESTING: usize = 32;
+
+const MAX_NODES: usize = 1000;
+const MAX_EDGES: usize = 2000;
+
+/* Edges across several ranks get a virtual node in every rank in between, so the layout works
+with more nodes than the diagram has. */
+const MAX_LAYOUT_NODES: usize = 20000;
+
+const MAX_CACHED_DIAGRAMS: usize = 100;
+
+const FONT_SIZE: f64 = 14.0;
+const LINE_HEIGHT: f64 = 1.2;
+
+/* Graphviz's defaults in points: nodes are at least 0.75 by 0.5 inches, 0.25 inches apart, and
+ranks are 0.5 inches apart. */
+const MIN_NODE_WIDTH: f64 = 54.0;
+const MIN_NODE_HEIGHT: f64 = 36.0;
+const NODE_SEPARATION: f64 = 18.0;
+const RANK_SEPARATION: f64 = 36.0;
+
+const ARROW_LENGTH: f64 = 10.0;
+const ARROW_WIDTH: f64 = 7.0;
+const LOOP_SIZE: f64 = 30.0;
+const CLUSTER_PADDING: f64 = 8.0;
+const MARGIN: f64 = 4.0;
+
+const ORDERING_ITERATIONS: usize = 24;
+const POSITIONING_ITERATIONS: usize = 8;
+
+const CSS_COLORS: &[&str] = &[
+    "aliceblue",
+    "antiquewhite",
+    "aqua",
+    "aquamarine",
+    "azure",
+    "beige",
+    "bisque",
+    "black",
+    "blanchedalmond",
+    "blue",
+    "blueviolet",
+    "brown",
+    "burlywood",
+    "cadetblue",
+    "chartreuse",
+    "chocolate",
+    "coral",
+    "cornflowerblue",
+    "cornsilk",
+    "crimson",
+    "cyan",
+    "darkblue",
+    "darkcyan",
+    "darkgoldenrod",
+    "darkgray",
+    "darkgreen",
+    "darkgrey",
+    "darkkhaki",
+    "darkmagenta",
+    "darkolivegreen",
+    "darkorange",
+    "darkorchid",
+    "darkred",
+    "darksalmon",
+    "darkseagreen",
+    "darkslateblue",
+    "darkslategray",
+    "darkslategrey",
+    "darkturquoise",
+    "darkviolet",
+    "deeppink",
+    "deepskyblue",
+    "dimgray",
+    "dimgrey",
+    "dodgerblue",
+    "firebrick",
+    "floralwhite",
+    "forestgreen",
+    "fuchsia",
+    "gainsboro",
+    "ghostwhite",
+    "gold",
+    "goldenrod",
+    "gray",
+    "green",
+    "greenyellow",
+    "grey",
+    "honeydew",
+    "hotpink",
+    "indianred",
+    "indigo",
+    "ivory",
+    "khaki",
+    "lavender",
+    "lavenderblush",
+    "lawngreen",
+    "lemonchiffon",
+    "lightblue",
+    "lightcoral",
+    "lightcyan",
+    "lightgoldenrodyellow",
+    "lightgray",
+    "lightgreen",
+    "lightgrey",
+    "lightpink",
+    "lightsalmon",
+    "lightseagreen",
+    "lightskyblue",
+    "lightslategray",
+    "lightslategrey",
+    "lightsteelblue",
+    "lightyellow",
+    "lime",
+    "limegreen",
+    "linen",
+    "magenta",
+    "maroon",
+    "mediumaquamarine",
+    "mediumblue",
+    "mediumorchid",
+    "mediumpurple",
+    "mediumseagreen",
+    "mediumslateblue",
+    "mediumspringgreen",
+    "mediumturquoise",
+    "mediumvioletred",
+    "midnightblue",
+    "mintcream",
+    "mistyrose",
+    "moccasin",
+    "navajowhite",
+    "navy",
+    "oldlace",
+    "olive",
+    "olivedrab",
+    "orange",
+    "orangered",
+    "orchid",
+    "palegoldenrod",
+    "palegreen",
+    "paleturquoise",
+    "palevioletred",
+    "papayawhip",
+    "peachpuff",
+    "peru",
+    "pink",
+    "plum",
+    "powderblue",
+    "purple",
+    "rebeccapurple",
+    "red",
+    "rosybrown",
+    "royalblue",
+    "saddlebrown",
+    "salmon",
+    "sandybrown",
+    "seagreen",
+    "seashell",
+    "sienna",
+    "silver",
+    "skyblue",
+    "slateblue",
+    "slategray",
+    "slategrey",
+    "snow",
+    "springgreen",
+    "steelblue",
+    "tan",
+    "teal",
+    "thistle",
+    "tomato",
+    "turquoise",
+    "violet",
+    "wheat",
+    "white",
+    "whitesmoke",
+    "yellow",
+    "yellowgreen",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /* An identifier, number or string. Strings are never keywords. */
+    Id { value: String, quoted: bool },
+    Punct(char),
+    Edge(&'static str),
+}
+
+type Attributes = HashMap<String, String>;
+
+#[derive(Default)]
+struct Graph {
+    strict: bool,
+    directed: bool,
+    name: String,
+    attributes: Attributes,
+    nodes: Vec<Node>,
+    node_indices: HashMap<String, usize>,
+    edges: Vec<Edge>,
+    clusters: Vec<Cluster>,
+    /* Groups of nodes in the same rank, from subgraphs with `rank=same`. */
+    same_ranks: Vec<Vec<usize>>,
+}
+
+struct Node {
+    name: String,
+    attributes: Attributes,
+}
+
+struct Edge {
+    from: usize,
+    to: usize,
+    attributes: Attributes,
+}
+
+struct Cluster {
+    attributes: Attributes,
+    /* Including the nodes of nested subgraphs. */
+    nodes: Vec<usize>,
+    parent: Option<usize>,
+}
+
+/* Attributes of nodes and edges created from here on, set with `node [...]` and `edge [...]`. */
+#[derive(Clone, Default)]
+struct Defaults {
+    node: Attributes,
+    edge: Attributes,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    graph: Graph,
+    depth: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Direction {
+    TopBottom,
+    BottomTop,
+    LeftRight,
+    RightLeft,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Shape {
+    Box,
+    Ellipse,
+    Circle,
+    DoubleCircle,
+    Diamond,
+    Point,
+    Plain,
+    Cylinder,
+    Hexagon,
+    Octagon,
+    Triangle,
+    Parallelogram,
+    Note,
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+struct Label {
+    lines: Vec<(String, Align)>,
+    font_size: f64,
+    width: f64,
+    height: f64,
+}
+
+#[derive(Clone, Copy)]
+struct Rect {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+struct PlacedNode {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    shape: Shape,
+    label: Label,
+}
+
+struct PlacedEdge {
+    path: String,
+    head: Option<[(f64, f64); 3]>,
+    tail: Option<[(f64, f64); 3]>,
+    label: Option<(Label, f64, f64)>,
+}
+
+struct Layout {
+    nodes: Vec<PlacedNode>,
+    edges: Vec<PlacedEdge>,
+    clusters: Vec<Option<(Rect, Label)>>,
+    label: Option<(Label, f64, f64)>,
+    bounds: Rect,
+}
+
+/* Rendered diagrams by the hash of their source, shared between all workers. Only the most
+recently rendered ones are kept. */
+#[derive(Default)]
+pub struct SvgCache {
+    entries: Mutex<CachedSvgs>,
+}
+
+#[derive(Default)]
+struct CachedSvgs {
+    svgs: HashMap<String, Arc<String>>,
+    /* Hashes from the oldest to the newest. */
+    order: VecDeque<String>,
+}
+
+impl SvgCache {
+    pub fn get_or_render(&self, hash: &str, dot: &str) -> Result<Arc<String>, String> {
+        if let Some(svg) = self.entries.lock().unwrap().svgs.get(hash) {
+            return Ok(svg.clone());
+        }
+
+        // Rendered without holding the lock, so other requests don't wait for it.
+        let svg = Arc::new(to_svg(dot)?);
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.svgs.insert(hash.to_string(), svg.clone()).is_none() {
+            entries.order.push_back(hash.to_string());
+        }
+        if entries.order.len() > MAX_CACHED_DIAGRAMS {
+            if let Some(oldest) = entries.order.pop_front() {
+                entries.svgs.remove(&oldest);
+            }
+        }
+        Ok(svg)
+    }
+}
+
+/* Identifies a rendered diagram in the cache and in ETags. */
+pub fn source_hash(dot: &str) -> String {
+    sha1(dot.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn validate(dot: &str) -> Result<(), String> {
+    layout(&parse(dot)?).map(|_| ())
+}
+
+pub fn to_svg(dot: &str) -> Result<String, String> {
+    let graph = parse(dot)?;
+    let layout = layout(&graph)?;
+    Ok(render(&graph, &layout))
+}
+
+fn parse(dot: &str) -> Result<Graph, String> {
+    let mut parser = Parser {
+        tokens: tokenize(dot)?,
+        position: 0,
+        graph: Graph::default(),
+        depth: 0,
+    };
+    parser.parse_graph()?;
+    Ok(parser.graph)
+}
+
+/* Tokens with their line numbers. */
+fn tokenize(dot: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = dot.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut line_start = true;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        if c == '\n' {
+            line += 1;
+            line_start = true;
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        // Lines starting with # are output of the C preprocessor, which Graphviz ignores.
+        if (c == '#' && line_start) || (c == '/' && next == Some('/')) {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        line_start = false;
+
+        if c == '/' && next == Some('*') {
+            let start_line = line;
+            i += 2;
+            loop {
+                match chars.get(i) {
+                    None => return Err(format!("Unterminated comment in line {}", start_line)),
+                    Some('*') if chars.get(i + 1) == Some(&'/') => break,
+                    Some('\n') => line += 1,
+                    _ => (),
+                }
+                i += 1;
+            }
+            i += 2;
+            continue;
+        }
+
+        let token_line = line;
+        let token = match c {
+            '{' | '}' | '[' | ']' | ';' | ',' | '=' | ':' => {
+                i += 1;
+                Token::Punct(c)
+            }
+            '-' if next == Some('>') || next == Some('-') => {
+                i += 2;
+                Token::Edge(if next == Some('>') { "->" } else { "--" })
+            }
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    i += 1;
+                    match chars.get(i) {
+                        None => return Err(format!("Unterminated string in line {}", token_line)),
+                        Some('"') => {
+                            // "a" + "b" is one string.
+                            let mut j = i + 1;
+                            while chars.get(j).is_some_and(|c| c.is_whitespace()) {
+                                j += 1;
+                            }
+                            if chars.get(j) != Some(&'+') {
+                                break;
+                            }
+                            j += 1;
+                            while chars.get(j).is_some_and(|c| c.is_whitespace()) {
+                                j += 1;
+                            }
+                            if chars.get(j) != Some(&'"') {
+                                break;
+                            }
+                            line += chars[i..j].iter().filter(|&&c| c == '\n').count();
+                            i = j;
+                        }
+                        Some('\\') if chars.get(i + 1) == Some(&'"') => {
+                            value.push('"');
+                            i += 1;
+                        }
+                        // A backslash at the end of a line continues the string.
+                        Some('\\') if chars.get(i + 1) == Some(&'\n') => {
+                            line += 1;
+                            i += 1;
+                        }
+                        Some(&c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            value.push(c);
+                        }
+                    }
+                }
+                i += 1;
+                Token::Id {
+                    value,
+                    quoted: true,
+                }
+            }
+            '<' => {
+                let mut depth = 0;
+                let start = i;
+                loop {
+                    match chars.get(i) {
+                        None => {
+                            return Err(format!("Unterminated HTML string in line {}", token_line))
+                        }
+                        Some('<') => depth += 1,
+                        Some('>') => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        Some('\n') => line += 1,
+                        _ => (),
+                    }
+                    i += 1;
+                }
+                i += 1;
+                let html: String = chars[start + 1..i - 1].iter().collect();
+                Token::Id {
+                    value: html_label(&html),
+                    quoted: true,
+                }
+            }
+            _ if c.is_ascii_digit()
+                || (c == '.' && next.is_some_and(|c| c.is_ascii_digit()))
+                || (c == '-' && next.is_some_and(|c| c.is_ascii_digit() || c == '.')) =>
+            {
+                let start = i;
+                i += 1;
+                while chars
+                    .get(i)
+                    .is_some_and(|&c| c.is_ascii_digit() || c == '.')
+                {
+                    i += 1;
+                }
+                Token::Id {
+                    value: chars[start..i].iter().collect(),
+                    quoted: false,
+                }
+            }
+            _ if c.is_alphabetic() || c == '_' || !c.is_ascii() => {
+                let start = i;
+                while chars
+                    .get(i)
+                    .is_some_and(|&c| c.is_alphanumeric() || c == '_' || !c.is_ascii())
+                {
+                    i += 1;
+                }
+                Token::Id {
+                    value: chars[start..i].iter().collect(),
+                    quoted: false,
+                }
+            }
+            _ => return Err(format!("Unexpected '{}' in line {}", c, line)),
+        };
+        tokens.push((token, token_line));
+    }
+
+    Ok(tokens)
+}
+
+/* HTML-like labels are shown as their text, with line breaks. */
+fn html_label(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start].replace('\\', "\\\\"));
+        let end = rest[start..]
+            .find('>')
+            .map_or(rest.len(), |end| start + end + 1);
+        let tag = rest[start..end].to_ascii_lowercase();
+        if tag.starts_with("<br") || tag.starts_with("</tr") {
+            text.push_str("\\n");
+        }
+        rest = &rest[end..];
+    }
+    text.push_str(&rest.replace('\\', "\\\\"));
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&nbsp;", "\u{A0}")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+fn is_keyword(value: &str) -> bool {
+    ["node", "edge", "graph", "digraph", "subgraph", "strict"]
+        .iter()
+        .any(|keyword| value.eq_ignore_ascii_case(keyword))
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Id {
+                value,
+                quoted: false,
+            }) => value.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    /* The next identifier or string, unless it's a keyword. */
+    fn id(&mut self) -> Option<String> {
+        match self.peek() {
+            Some(Token::Id { value, quoted }) if *quoted || !is_keyword(value) => {
+                let value = value.clone();
+                self.position += 1;
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.expected(&format!("'{}'", c)))
+        }
+    }
+
+    fn expected(&self, what: &str) -> String {
+        match self.tokens.get(self.position) {
+            Some((token, line)) => format!(
+                "Expected {} in line {} but found {}",
+                what,
+                line,
+                describe(token)
+            ),
+            None => format!("Expected {} but the diagram ended", what),
+        }
+    }
+
+    fn parse_graph(&mut self) -> Result<(), String> {
+        if self.is_keyword("strict") {
+            self.graph.strict = true;
+            self.position += 1;
+        }
+        if self.is_keyword("digraph") {
+            self.graph.directed = true;
+        } else if !self.is_keyword("graph") {
+            return Err(self.expected("graph or digraph"));
+        }
+        self.position += 1;
+        if let Some(name) = self.id() {
+            self.graph.name = name;
+        }
+        self.expect('{')?;
+
+        let mut attributes = Attributes::new();
+        self.parse_statements(&mut Defaults::default(), &mut attributes, None)?;
+        self.expect('}')?;
+        self.graph.attributes = attributes;
+
+        match self.tokens.get(self.position) {
+            Some((token, line)) => Err(format!(
+                "Unexpected {} in line {} after the end of the graph",
+                describe(token),
+                line
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /* Statements up to the closing brace. Returns the nodes they mention. */
+    fn parse_statements(
+        &mut self,
+        defaults: &mut Defaults,
+        attributes: &mut Attributes,
+        cluster: Option<usize>,
+    ) -> Result<Vec<usize>, String> {
+        let mut members = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::Punct('}')) => return Ok(members),
+                Some(Token::Punct(';')) => self.position += 1,
+                _ if self.is_keyword("graph")
+                    || self.is_keyword("node")
+                    || self.is_keyword("edge") =>
+                {
+                    let kind = match self.peek() {
+                        Some(Token::Id { value, .. }) => value.to_ascii_lowercase(),
+                        _ => String::new(),
+                    };
+                    self.position += 1;
+                    if self.peek() != Some(&Token::Punct('[')) {
+                        return Err(self.expected("'['"));
+                    }
+                    let list = self.parse_attribute_lists()?;
+                    match kind.as_str() {
+                        "graph" => attributes.extend(list),
+                        "node" => defaults.node.extend(list),
+                        _ => defaults.edge.extend(list),
+                    }
+                }
+                _ => self.parse_statement(defaults, attributes, cluster, &mut members)?,
+            }
+        }
+    }
+
+    fn parse_statement(
+        &mut self,
+        defaults: &Defaults,
+        attributes: &mut Attributes,
+        cluster: Option<usize>,
+        members: &mut Vec<usize>,
+    ) -> Result<(), String> {
+        // `name = value` sets an attribute of the graph or subgraph.
+        let is_assignment =
+            self.tokens.get(self.position + 1).map(|(token, _)| token) == Some(&Token::Punct('='));
+        if is_assignment {
+            if let Some(name) = self.id() {
+                self.position += 1;
+                let value = self.id().ok_or_else(|| self.expected("a value"))?;
+                attributes.insert(name, value);
+                return Ok(());
+            }
+        }
+
+        let mut endpoints = vec![self.parse_endpoint(defaults, cluster)?];
+        while let Some(&Token::Edge(operator)) = self.peek() {
+            let (expected, kind) = if self.graph.directed {
+                ("->", "digraph")
+            } else {
+                ("--", "graph")
+            };
+            if operator != expected {
+                return Err(format!(
+                    "Edges in a {} are written {}, in line {}",
+                    kind, expected, self.tokens[self.position].1
+                ));
+            }
+            self.position += 1;
+            endpoints.push(self.parse_endpoint(defaults, cluster)?);
+        }
+        let list = self.parse_attribute_lists()?;
+
+        for (nodes, _) in &endpoints {
+            members.extend(nodes);
+        }
+        match endpoints.as_slice() {
+            [(nodes, true)] => {
+                for &node in nodes {
+                    self.graph.nodes[node].attributes.extend(list.clone());
+                }
+            }
+            [_] => (),
+            _ => {
+                for pair in endpoints.windows(2) {
+                    for &from in &pair[0].0 {
+                        for &to in &pair[1].0 {
+                            let mut edge_attributes = defaults.edge.clone();
+                            edge_attributes.extend(list.clone());
+                            self.add_edge(from, to, edge_attributes)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* A node or a subgraph, and whether it's a node. */
+    fn parse_endpoint(
+        &mut self,
+        defaults: &Defaults,
+        cluster: Option<usize>,
+    ) -> Result<(Vec<usize>, bool), String> {
+        if self.is_keyword("subgraph") || self.peek() == Some(&Token::Punct('{')) {
+            return Ok((self.parse_subgraph(defaults, cluster)?, false));
+        }
+
+        let name = self
+            .id()
+            .ok_or_else(|| self.expected("a node or a subgraph"))?;
+        // Ports like `node:port:n` only affect where edges attach, which the layout ignores.
+        while self.peek() == Some(&Token::Punct(':')) {
+            self.position += 1;
+            self.id().ok_or_else(|| self.expected("a port"))?;
+        }
+        Ok((vec![self.node(name, defaults)?], true))
+    }
+
+    fn parse_subgraph(
+        &mut self,
+        defaults: &Defaults,
+        parent: Option<usize>,
+    ) -> Result<Vec<usize>, String> {
+        let mut name = String::new();
+        if self.is_keyword("subgraph") {
+            self.position += 1;
+            name = self.id().unwrap_or_default();
+        }
+        self.expect('{')?;
+        if self.depth == MAX_NESTING {
+            return Err(format!(
+                "Subgraphs can be nested at most {} levels deep",
+                MAX_NESTING
+            ));
+        }
+
+        let cluster = if name.starts_with("cluster") {
+            self.graph.clusters.push(Cluster {
+                attributes: Attributes::new(),
+                nodes: Vec::new(),
+                parent,
+            });
+            Some(self.graph.clusters.len() - 1)
+        } else {
+            None
+        };
+
+        self.depth += 1;
+        let mut attributes = Attributes::new();
+        let members =
+            self.parse_statements(&mut defaults.clone(), &mut attributes, cluster.or(parent))?;
+        self.expect('}')?;
+        self.depth -= 1;
+
+        if let Some(rank) = attributes.get("rank") {
+            if ["same", "min", "source", "max", "sink"].contains(&rank.as_str()) {
+                self.graph.same_ranks.push(members.clone());
+            }
+        }
+        if let Some(cluster) = cluster {
+            self.graph.clusters[cluster].attributes = attributes;
+            self.graph.clusters[cluster].nodes = members.clone();
+        }
+        Ok(members)
+    }
+
+    fn parse_attribute_lists(&mut self) -> Result<Attributes, String> {
+        let mut attributes = Attributes::new();
+        while self.peek() == Some(&Token::Punct('[')) {
+            self.position += 1;
+            loop {
+                match self.peek() {
+                    Some(Token::Punct(']')) => {
+                        self.position += 1;
+                        break;
+                    }
+                    Some(Token::Punct(',')) | Some(Token::Punct(';')) => self.position += 1,
+                    _ => {
+                        let name = self.id().ok_or_else(|| self.expected("an attribute"))?;
+                        self.expect('=')?;
+                        let value = self.id().ok_or_else(|| self.expected("a value"))?;
+                        attributes.insert(name, value);
+                    }
+                }
+            }
+        }
+        Ok(attributes)
+    }
+
+    fn node(&mut self, name: String, defaults: &Defaults) -> Result<usize, String> {
+        if let Some(&index) = self.graph.node_indices.get(&name) {
+            return Ok(index);
+        }
+        if self.graph.nodes.len() == MAX_NODES {
+            return Err(format!("Diagrams can have at most {} nodes", MAX_NODES));
+        }
+
+        let index = self.graph.nodes.len();
+        self.graph.node_indices.insert(name.clone(), index);
+        self.graph.nodes.push(Node {
+            name,
+            attributes: defaults.node.clone(),
+        });
+        Ok(index)
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize, attributes: Attributes) -> Result<(), String> {
+        // Strict graphs merge edges between the same nodes.
+        if self.graph.strict {
+            let directed = self.graph.directed;
+            let existing = self.graph.edges.iter_mut().find(|edge| {
+                (edge.from == from && edge.to == to)
+                    || (!directed && edge.from == to && edge.to == from)
+            });
+            if let Some(edge) = existing {
+                edge.attributes.extend(attributes);
+                return Ok(());
+            }
+        }
+        if self.graph.edges.len() == MAX_EDGES {
+            return Err(format!("Diagrams can have at most {} edges", MAX_EDGES));
+        }
+
+        self.graph.edges.push(Edge {
+            from,
+            to,
+            attributes,
+        });
+        Ok(())
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Id { value, .. } if value.chars().count() > 20 => {
+            format!("'{}...'", value.chars().take(20).collect::<String>())
+        }
+        Token::Id { value, .. } => format!("'{}'", value),
+        Token::Punct(c) => format!("'{}'", c),
+        Token::Edge(operator) => format!("'{}'", operator),
+    }
+}
+
+fn layout(graph: &Graph) -> Result<Layout, String> {
+    let direction = match graph
+        .attributes
+        .get("rankdir")
+        .map(|rankdir| rankdir.to_ascii_uppercase())
+        .as_deref()
+    {
+        Some("LR") => Direction::LeftRight,
+        Some("BT") => Direction::BottomTop,
+        Some("RL") => Direction::RightLeft,
+        _ => Direction::TopBottom,
+    };
+    let horizontal = direction == Direction::LeftRight || direction == Direction::RightLeft;
+    let node_separation = inches(graph.attributes.get("nodesep"))
+        .unwrap_or(NODE_SEPARATION)
+        .max(2.0);
+    let rank_separation = inches(graph.attributes.get("ranksep"))
+        .unwrap_or(RANK_SEPARATION)
+        .max(2.0);
+
+    let mut nodes: Vec<PlacedNode> = graph
+        .nodes
+        .iter()
+        .map(|node| {
+            let shape = shape(&node.attributes);
+            let label = node_label(node, &graph.name, shape);
+            let (width, height) = node_size(shape, &label, &node.attributes);
+            PlacedNode {
+                x: 0.0,
+                y: 0.0,
+                width,
+                height,
+                shape,
+                label,
+            }
+        })
+        .collect();
+    let mut edge_labels: Vec<Option<Label>> = graph
+        .edges
+        .iter()
+        .map(|edge| {
+            edge.attributes
+                .get("label")
+                .filter(|label| !label.is_empty())
+                .map(|text| label(text, "", &graph.name, font_size(&edge.attributes)))
+        })
+        .collect();
+    let has_edge_labels = edge_labels.iter().any(Option::is_some);
+
+    // Sizes along the ranks and across them.
+    let along = |width: f64, height: f64| if horizontal { height } else { width };
+    let across = |width: f64, height: f64| if horizontal { width } else { height };
+
+    let ranks = rank_nodes(graph, has_edge_labels);
+
+    // Virtual nodes start after the real ones.
+    let mut rank = ranks;
+    let mut breadth: Vec<f64> = nodes
+        .iter()
+        .map(|node| along(node.width, node.height))
+        .collect();
+    let mut depth: Vec<f64> = nodes
+        .iter()
+        .map(|node| across(node.width, node.height))
+        .collect();
+    let mut extra = vec![0.0; nodes.len()];
+    let mut loops = vec![0; nodes.len()];
+    let mut paths = Vec::with_capacity(graph.edges.len());
+    let mut label_nodes = vec![None; graph.edges.len()];
+    for (index, edge) in graph.edges.iter().enumerate() {
+        if edge.from == edge.to {
+            loops[edge.from] += 1;
+            let label_breadth = edge_labels[index]
+                .as_ref()
+                .map_or(0.0, |label| along(label.width, label.height) + 4.0);
+            extra[edge.from] = f64::max(
+                extra[edge.from],
+                LOOP_SIZE * loops[edge.from] as f64 + label_breadth,
+            );
+            paths.push(Vec::new());
+            continue;
+        }
+
+        let (upper, lower) = if rank[edge.from] <= rank[edge.to] {
+            (edge.from, edge.to)
+        } else {
+            (edge.to, edge.from)
+        };
+        let middle = (rank[upper] + rank[lower]) / 2;
+        let mut path = vec![upper];
+        for virtual_rank in rank[upper] + 1..rank[lower] {
+            if rank.len() == MAX_LAYOUT_NODES {
+                return Err("The diagram is too large to lay out".to_string());
+            }
+            let node = rank.len();
+            rank.push(virtual_rank);
+            // The label of an edge takes up room in its middle rank, next to the edge.
+            match edge_labels[index] {
+                Some(ref label) if virtual_rank == middle => {
+                    breadth.push(along(label.width, label.height) + 6.0);
+                    depth.push(across(label.width, label.height));
+                    label_nodes[index] = Some(node);
+                }
+                _ => {
+                    breadth.push(0.0);
+                    depth.push(0.0);
+                }
+            }
+            extra.push(0.0);
+            path.push(node);
+        }
+        path.push(lower);
+        paths.push(path);
+    }
+    let count = rank.len();
+    let is_real = |node: usize| node < graph.nodes.len();
+
+    let mut up = vec![Vec::new(); count];
+    let mut down = vec![Vec::new(); count];
+    for path in &paths {
+        for pair in path.windows(2) {
+            if rank[pair[0]] != rank[pair[1]] {
+                down[pair[0]].push(pair[1]);
+                up[pair[1]].push(pair[0]);
+            }
+        }
+    }
+
+    // Nodes of a top-level cluster, and edges within one, are kept together.
+    let mut cluster_of = vec![None; count];
+    for (index, cluster) in graph.clusters.iter().enumerate() {
+        if cluster.parent.is_none() {
+            for &node in &cluster.nodes {
+                cluster_of[node] = cluster_of[node].or(Some(index));
+            }
+        }
+    }
+    for path in &paths {
+        if let (Some(&first), Some(&last)) = (path.first(), path.last()) {
+            if cluster_of[first] == cluster_of[last] {
+                for &node in &path[1..path.len() - 1] {
+                    cluster_of[node] = cluster_of[first];
+                }
+            }
+        }
+    }
+
+    let layers = order_nodes(graph.nodes.len(), &rank, &up, &down, &cluster_of);
+
+    let gap = |left: usize, right: usize| {
+        let separation = if is_real(left) && is_real(right) {
+            node_separation
+        } else {
+            node_separation / 2.0
+        };
+        (breadth[left] + breadth[right]) / 2.0 + extra[left] + separation
+    };
+    let coordinates = position_nodes(&layers, &up, &down, &gap, &is_real, count);
+
+    let mut rank_depths = vec![0.0f64; layers.len()];
+    for node in 0..count {
+        rank_depths[rank[node]] = rank_depths[rank[node]].max(depth[node]);
+    }
+    // With edge labels every other rank holds labels, so ranks are half as far apart.
+    let rank_separation = if has_edge_labels {
+        rank_separation / 2.0
+    } else {
+        rank_separation
+    };
+    let mut rank_coordinates = Vec::with_capacity(layers.len());
+    let mut rank_coordinate = 0.0;
+    for (index, rank_depth) in rank_depths.iter().enumerate() {
+        if index > 0 {
+            rank_coordinate += (rank_depths[index - 1] + rank_depth) / 2.0 + rank_separation;
+        }
+        rank_coordinates.push(rank_coordinate);
+    }
+
+    // Layout coordinates are along and across ranks, which the direction maps to x and y.
+    let point = |along: f64, across: f64| match direction {
+        Direction::TopBottom => (along, across),
+        Direction::BottomTop => (along, -across),
+        Direction::LeftRight => (across, along),
+        Direction::RightLeft => (-across, along),
+    };
+    let position = |node: usize| point(coordinates[node], rank_coordinates[rank[node]]);
+
+    for (index, node) in nodes.iter_mut().enumerate() {
+        let (x, y) = position(index);
+        node.x = x;
+        node.y = y;
+    }
+
+    let mut edges = Vec::with_capacity(graph.edges.len());
+    for (index, edge) in graph.edges.iter().enumerate() {
+        let label = edge_labels[index].take();
+        let (head, tail) = arrows(graph, &edge.attributes);
+
+        if edge.from == edge.to {
+            edges.push(self_loop(
+                &nodes[edge.from],
+                coordinates[edge.from],
+                rank_coordinates[rank[edge.from]],
+                breadth[edge.from],
+                depth[edge.from],
+                &point,
+                label,
+                (head, tail),
+            ));
+            loops[edge.from] -= 1;
+            continue;
+        }
+
+        let path = &paths[index];
+        let mut points: Vec<(f64, f64)> = path
+            .iter()
+            .map(|&node| {
+                if Some(node) == label_nodes[index] {
+                    point(
+                        coordinates[node] - breadth[node] / 2.0,
+                        rank_coordinates[rank[node]],
+                    )
+                } else {
+                    position(node)
+                }
+            })
+            .collect();
+        if path[0] != edge.from {
+            points.reverse();
+        }
+
+        let last = points.len() - 1;
+        points[0] = clip(&nodes[edge.from], points[1]);
+        points[last] = clip(&nodes[edge.to], points[last - 1]);
+        let head = if head {
+            Some(arrow(&mut points, true))
+        } else {
+            None
+        };
+        let tail = if tail {
+            Some(arrow(&mut points, false))
+        } else {
+            None
+        };
+
+        let label = label.map(|label| {
+            let (x, y) = match label_nodes[index] {
+                Some(node) => point(coordinates[node] + 3.0, rank_coordinates[rank[node]]),
+                None => {
+                    let (x, y) = middle(&points);
+                    let flat = rank[edge.from] == rank[edge.to];
+                    let (dx, dy) = if flat {
+                        point(0.0, -across(label.width, label.height) / 2.0 - 4.0)
+                    } else {
+                        point(along(label.width, label.height) / 2.0 + 4.0, 0.0)
+                    };
+                    (x + dx, y + dy)
+                }
+            };
+            (label, x, y)
+        });
+
+        edges.push(PlacedEdge {
+            path: path_data(&points),
+            head,
+            tail,
+            label,
+        });
+    }
+
+    let mut clusters: Vec<Option<(Rect, Label)>> = graph.clusters.iter().map(|_| None).collect();
+    // Inner clusters first, so outer ones are drawn around them.
+    let mut cluster_order: Vec<usize> = (0..graph.clusters.len()).collect();
+    cluster_order.sort_by_key(|&index| {
+        let mut depth = 0;
+        let mut parent = graph.clusters[index].parent;
+        while let Some(index) = parent {
+            depth += 1;
+            parent = graph.clusters[index].parent;
+        }
+        std::cmp::Reverse(depth)
+    });
+    for index in cluster_order {
+        let cluster = &graph.clusters[index];
+        let mut bounds: Option<Rect> = None;
+        for &node in &cluster.nodes {
+            bounds = Some(union(bounds, node_rect(&nodes[node])));
+        }
+        for (child, other) in graph.clusters.iter().enumerate() {
+            if other.parent == Some(index) {
+                if let Some((rect, _)) = clusters[child] {
+                    bounds = Some(union(bounds, rect));
+                }
+            }
+        }
+        if let Some(bounds) = bounds {
+            let label = label(
+                cluster.attributes.get("label").map_or("", String::as_str),
+                "",
+                &graph.name,
+                font_size(&cluster.attributes),
+            );
+            let label_height = if label.lines.is_empty() {
+                0.0
+            } else {
+                label.height + 4.0
+            };
+            let rect = Rect {
+                x: bounds.x - CLUSTER_PADDING,
+                y: bounds.y - CLUSTER_PADDING - label_height,
+                width: bounds.width.max(label.width) + 2.0 * CLUSTER_PADDING,
+                height: bounds.height + 2.0 * CLUSTER_PADDING + label_height,
+            };
+            clusters[index] = Some((rect, label));
+        }
+    }
+
+    let mut bounds: Option<Rect> = None;
+    for node in &nodes {
+        bounds = Some(union(bounds, node_rect(node)));
+    }
+    for (rect, _) in clusters.iter().flatten() {
+        bounds = Some(union(bounds, *rect));
+    }
+    for edge in &edges {
+        for (x, y) in path_points(&edge.path) {
+            bounds = Some(union(
+                bounds,
+                Rect {
+                    x,
+                    y,
+                    width: 0.0,
+                    height: 0.0,
+                },
+            ));
+        }
+        if let Some((ref label, x, y)) = edge.label {
+            bounds = Some(union(bounds, label_rect(label, x, y)));
+        }
+    }
+    let mut bounds = bounds.unwrap_or(Rect {
+        x: 0.0,
+        y: 0.0,
+        width: 0.0,
+        height: 0.0,
+    });
+
+    let label = graph
+        .attributes
+        .get("label")
+        .filter(|label| !label.is_empty())
+        .map(|text| {
+            let label = label(text, "", &graph.name, font_size(&graph.attributes));
+            let x = bounds.x + bounds.width / 2.0;
+            let y = if graph.attributes.get("labelloc").map(String::as_str) == Some("t") {
+                bounds.y - 4.0 - label.height / 2.0
+            } else {
+                bounds.y + bounds.height + 4.0 + label.height / 2.0
+            };
+            bounds = union(Some(bounds), label_rect(&label, x, y));
+            (label, x, y)
+        });
+
+    Ok(Layout {
+        nodes,
+        edges,
+        clusters,
+        label,
+        bounds,
+    })
+}
+
+/* Ranks of the nodes, like Graphviz by the longest path to each node after reversing edges
+that close cycles. Nodes without predecessors move down next to their successors. */
+fn rank_nodes(graph: &Graph, label_ranks: bool) -> Vec<usize> {
+    let count = graph.nodes.len();
+
+    // Nodes in the same rank are ranked as one.
+    let mut group: Vec<usize> = (0..count).collect();
+    for same in &graph.same_ranks {
+        if let Some(&first) = same.first() {
+            for &node in same {
+                let (a, b) = (find(&mut group, first), find(&mut group, node));
+                group[b] = a;
+            }
+        }
+    }
+    let group: Vec<usize> = (0..count).map(|node| find(&mut group, node)).collect();
+
+    let mut successors = vec![Vec::new(); count];
+    for edge in &graph.edges {
+        if edge.attributes.get("constraint").map(String::as_str) == Some("false") {
+            continue;
+        }
+        let (from, to) = (group[edge.from], group[edge.to]);
+        if from != to {
+            let minimum_length = edge
+                .attributes
+                .get("minlen")
+                .and_then(|minlen| minlen.parse::<usize>().ok())
+                .unwrap_or(1)
+                .min(10);
+            // Labels go in extra ranks between the nodes.
+            let minimum_length = if label_ranks {
+                minimum_length * 2
+            } else {
+                minimum_length
+            };
+            successors[from].push((to, minimum_length));
+        }
+    }
+
+    // A depth-first search finds the edges that close cycles, which are reversed.
+    let mut state = vec![0u8; count];
+    let mut dag = vec![Vec::new(); count];
+    for start in 0..count {
+        if group[start] != start || state[start] != 0 {
+            continue;
+        }
+        state[start] = 1;
+        let mut stack = vec![(start, 0)];
+        while let Some(&(node, next)) = stack.last() {
+            if next == successors[node].len() {
+                state[node] = 2;
+                stack.pop();
+                continue;
+            }
+            let top = stack.len() - 1;
+            stack[top].1 += 1;
+
+            let (successor, minimum_length) = successors[node][next];
+            match state[successor] {
+                0 => {
+                    state[successor] = 1;
+                    stack.push((successor, 0));
+                    dag[node].push((successor, minimum_length));
+                }
+                1 => dag[successor].push((node, minimum_length)),
+                _ => dag[node].push((successor, minimum_length)),
+            }
+        }
+    }
+
+    let mut incoming = vec![0; count];
+    for edges in &dag {
+        for &(successor, _) in edges {
+            incoming[successor] += 1;
+        }
+    }
+    let has_predecessors: Vec<bool> = incoming.iter().map(|&incoming| incoming > 0).collect();
+    let mut queue: VecDeque<usize> = (0..count)
+        .filter(|&node| group[node] == node && incoming[node] == 0)
+        .collect();
+    let mut order = Vec::with_capacity(count);
+    let mut rank = vec![0; count];
+    while let Some(node) = queue.pop_front() {
+        order.push(node);
+        for &(successor, minimum_length) in &dag[node] {
+            rank[successor] = rank[successor].max(rank[node] + minimum_length);
+            incoming[successor] -= 1;
+            if incoming[successor] == 0 {
+                queue.push_back(successor);
+            }
+        }
+    }
+    for &node in order.iter().rev() {
+        if !has_predecessors[node] {
+            let lowest = dag[node]
+                .iter()
+                .map(|&(successor, minimum_length)| rank[successor] - minimum_length)
+                .min();
+            if let Some(lowest) = lowest {
+                rank[node] = lowest;
+            }
+        }
+    }
+
+    let first = order.iter().map(|&node| rank[node]).min().unwrap_or(0);
+    (0..count).map(|node| rank[group[node]] - first).collect()
+}
+
+fn find(group: &mut [usize], node: usize) -> usize {
+    let mut root = node;
+    while group[root] != root {
+        root = group[root];
+    }
+    let mut node = node;
+    while group[node] != root {
+        let next = group[node];
+        group[node] = root;
+        node = next;
+    }
+    root
+}
+
+/* Nodes of each rank from left to right, reordered by the average position of their neighbors
+in the previous rank until edges cross the least. */
+fn order_nodes(
+    real_count: usize,
+    rank: &[usize],
+    up: &[Vec<usize>],
+    down: &[Vec<usize>],
+    cluster_of: &[Option<usize>],
+) -> Vec<Vec<usize>> {
+    let count = rank.len();
+    let rank_count = rank.iter().max().map_or(0, |rank| rank + 1);
+    let mut layers = vec![Vec::new(); rank_count];
+
+    // A depth-first search from the nodes in order of appearance keeps related nodes together.
+    let mut visited = vec![false; count];
+    for start in 0..real_count {
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            if visited[node] {
+                continue;
+            }
+            visited[node] = true;
+            layers[rank[node]].push(node);
+            stack.extend(down[node].iter().rev().filter(|&&next| !visited[next]));
+        }
+    }
+
+    let mut positions = vec![0; count];
+    update_positions(&layers, &mut positions);
+    let mut best = layers.clone();
+    let mut best_crossings = crossings(&layers, down, &positions);
+
+    for iteration in 0..ORDERING_ITERATIONS {
+        if best_crossings == 0 {
+            break;
+        }
+        if iteration % 2 == 0 {
+            for layer in layers.iter_mut().skip(1) {
+                reorder(layer, up, &mut positions, cluster_of);
+            }
+        } else {
+            for index in (0..layers.len().saturating_sub(1)).rev() {
+                reorder(&mut layers[index], down, &mut positions, cluster_of);
+            }
+        }
+
+        let crossings = crossings(&layers, down, &positions);
+        if crossings < best_crossings {
+            best = layers.clone();
+            best_crossings = crossings;
+        }
+    }
+    best
+}
+
+fn update_positions(layers: &[Vec<usize>], positions: &mut [usize]) {
+    for layer in layers {
+        for (position, &node) in layer.iter().enumerate() {
+            positions[node] = position;
+        }
+    }
+}
+
+fn reorder(
+    layer: &mut [usize],
+    neighbors: &[Vec<usize>],
+    positions: &mut [usize],
+    cluster_of: &[Option<usize>],
+) {
+    let barycenters: Vec<f64> = layer
+        .iter()
+        .map(|&node| {
+            if neighbors[node].is_empty() {
+                positions[node] as f64
+            } else {
+                let sum: usize = neighbors[node]
+                    .iter()
+                    .map(|&neighbor| positions[neighbor])
+                    .sum();
+                sum as f64 / neighbors[node].len() as f64
+            }
+        })
+        .collect();
+
+    // Members of a cluster stay together, where their average puts them.
+    let mut cluster_barycenters: HashMap<usize, (f64, usize)> = HashMap::new();
+    for (&node, &barycenter) in layer.iter().zip(&barycenters) {
+        if let Some(cluster) = cluster_of[node] {
+            let entry = cluster_barycenters.entry(cluster).or_insert((0.0, 0));
+            entry.0 += barycenter;
+            entry.1 += 1;
+        }
+    }
+    let mut keyed: Vec<((f64, usize, f64), usize)> = layer
+        .iter()
+        .zip(&barycenters)
+        .map(|(&node, &barycenter)| {
+            let key = match cluster_of[node] {
+                Some(cluster) => {
+                    let (sum, count) = cluster_barycenters[&cluster];
+                    (sum / count as f64, cluster + 1, barycenter)
+                }
+                None => (barycenter, 0, barycenter),
+            };
+            (key, node)
+        })
+        .collect();
+    keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+    for (position, (_, node)) in keyed.into_iter().enumerate() {
+        layer[position] = node;
+        positions[node] = position;
+    }
+}
+
+/* Crossings between ranks are pairs of edges whose ends are in opposite orders, counted with a
+Fenwick tree. */
+fn crossings(layers: &[Vec<usize>], down: &[Vec<usize>], positions: &[usize]) -> usize {
+    let mut total = 0;
+    for layer in layers {
+        let mut pairs: Vec<(usize, usize)> = layer
+            .iter()
+            .flat_map(|&node| {
+                down[node]
+                    .iter()
+                    .map(move |&next| (positions[node], positions[next]))
+            })
+            .collect();
+        pairs.sort_unstable();
+
+        let size = pairs.iter().map(|&(_, lower)| lower + 1).max().unwrap_or(0);
+        let mut tree = vec![0; size + 1];
+        for (index, &(_, lower)) in pairs.iter().enumerate() {
+            let mut not_after = 0;
+            let mut i = lower + 1;
+            while i > 0 {
+                not_after += tree[i];
+                i &= i - 1;
+            }
+            total += index - not_after;
+
+            let mut i = lower + 1;
+            while i <= size {
+                tree[i] += 1;
+                i += i & i.wrapping_neg();
+            }
+        }
+    }
+    total
+}
+
+/* Positions along the ranks. Starting from packed ranks, nodes move toward the average of their
+neighbors as far as the spacing allows, first downward, then upward and finally toward both.
+Virtual nodes move more readily, which straightens long edges. */
+fn position_nodes(
+    layers: &[Vec<usize>],
+    up: &[Vec<usize>],
+    down: &[Vec<usize>],
+    gap: &dyn Fn(usize, usize) -> f64,
+    is_real: &dyn Fn(usize) -> bool,
+    count: usize,
+) -> Vec<f64> {
+    let mut coordinates = vec![0.0; count];
+    for layer in layers {
+        let mut coordinate = 0.0;
+        for (index, &node) in layer.iter().enumerate() {
+            if index > 0 {
+                coordinate += gap(layer[index - 1], node);
+            }
+            coordinates[node] = coordinate;
+        }
+    }
+
+    for iteration in 0..=POSITIONING_ITERATIONS {
+        let upward = iteration % 2 == 1;
+        let both = iteration == POSITIONING_ITERATIONS;
+        let mut indices: Vec<usize> = (0..layers.len()).collect();
+        if upward {
+            indices.reverse();
+        }
+
+        for index in indices {
+            let layer = &layers[index];
+            let desired: Vec<f64> = layer
+                .iter()
+                .map(|&node| {
+                    let neighbors: Vec<usize> = if both {
+                        up[node].iter().chain(&down[node]).cloned().collect()
+                    } else if upward {
+                        down[node].clone()
+                    } else {
+                        up[node].clone()
+                    };
+                    if neighbors.is_empty() {
+                        coordinates[node]
+                    } else {
+                        let sum: f64 = neighbors
+                            .iter()
+                            .map(|&neighbor| coordinates[neighbor])
+                            .sum();
+                        sum / neighbors.len() as f64
+                    }
+                })
+                .collect();
+            let weights: Vec<f64> = layer
+                .iter()
+                .map(|&node| if is_real(node) { 1.0 } else { 4.0 })
+                .collect();
+            let gaps: Vec<f64> = layer.windows(2).map(|pair| gap(pair[0], pair[1])).collect();
+
+            for (&node, coordinate) in layer.iter().zip(place(&desired, &weights, &gaps)) {
+                coordinates[node] = coordinate;
+            }
+        }
+    }
+    coordinates
+}
+
+/* Coordinates as close to the desired ones as possible, by weight, that keep the order and are
+at least `gaps` apart. Solved by pooling adjacent violators into blocks of touching nodes. */
+fn place(desired: &[f64], weights: &[f64], gaps: &[f64]) -> Vec<f64> {
+    let offsets: Vec<f64> = std::iter::once(0.0)
+        .chain(gaps.iter().scan(0.0, |offset, gap| {
+            *offset += gap;
+            Some(*offset)
+        }))
+        .collect();
+
+    // Weighted sums, total weights and sizes of blocks.
+    let mut blocks: Vec<(f64, f64, usize)> = Vec::new();
+    for ((&desired, &weight), &offset) in desired.iter().zip(weights).zip(&offsets) {
+        blocks.push((weight * (desired - offset), weight, 1));
+        while blocks.len() > 1 {
+            let (sum, weight, size) = blocks[blocks.len() - 1];
+            let (previous_sum, previous_weight, previous_size) = blocks[blocks.len() - 2];
+            if previous_sum / previous_weight <= sum / weight {
+                break;
+            }
+            blocks.pop();
+            let last = blocks.len() - 1;
+            blocks[last] = (
+                previous_sum + sum,
+                previous_weight + weight,
+                previous_size + size,
+            );
+        }
+    }
+
+    let mut coordinates = Vec::with_capacity(desired.len());
+    for (sum, weight, size) in blocks {
+        for _ in 0..size {
+            coordinates.push(sum / weight + offsets[coordinates.len()]);
+        }
+    }
+    coordinates
+}
+
+#[allow(clippy::too_many_arguments)]
+fn self_loop(
+    node: &PlacedNode,
+    along: f64,
+    across: f64,
+    breadth: f64,
+    depth: f64,
+    point: &dyn Fn(f64, f64) -> (f64, f64),
+    label: Option<Label>,
+    (head, tail): (bool, bool),
+) -> PlacedEdge {
+    let side = along + breadth / 2.0;
+    let size = LOOP_SIZE;
+    let mut points = vec![
+        clip(node, point(side, across - depth / 3.0)),
+        point(side + size, across - depth / 2.0 - 8.0),
+        point(side + size, across + depth / 2.0 + 8.0),
+        clip(node, point(side, across + depth / 3.0)),
+    ];
+    let head = if head {
+        Some(arrow(&mut points, true))
+    } else {
+        None
+    };
+    let tail = if tail {
+        Some(arrow(&mut points, false))
+    } else {
+        None
+    };
+
+    let label = label.map(|label| {
+        let label_breadth = if point(1.0, 0.0).0 != 0.0 {
+            label.width
+        } else {
+            label.height
+        };
+        let (x, y) = point(side + size * 0.75 + 4.0 + label_breadth / 2.0, across);
+        (label, x, y)
+    });
+
+    PlacedEdge {
+        path: format!(
+            "M{:.1},{:.1} C{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}",
+            points[0].0,
+            points[0].1,
+            points[1].0,
+            points[1].1,
+            points[2].0,
+            points[2].1,
+            points[3].0,
+            points[3].1
+        ),
+        head,
+        tail,
+        label,
+    }
+}
+
+/* Shortens the edge at its head or tail for an arrow and returns the arrow's corners. */
+fn arrow(points: &mut [(f64, f64)], head: bool) -> [(f64, f64); 3] {
+    let last = points.len() - 1;
+    let (tip_index, from_index) = if head { (last, last - 1) } else { (0, 1) };
+    let tip = points[tip_index];
+    let from = points[from_index];
+
+    let (dx, dy) = (tip.0 - from.0, tip.1 - from.1);
+    let length = (dx * dx + dy * dy).sqrt().max(0.001);
+    let (ux, uy) = (dx / length, dy / length);
+    let base = (tip.0 - ux * ARROW_LENGTH, tip.1 - uy * ARROW_LENGTH);
+    points[tip_index] = base;
+
+    let half = ARROW_WIDTH / 2.0;
+    [
+        tip,
+        (base.0 - uy * half, base.1 + ux * half),
+        (base.0 + uy * half, base.1 - ux * half),
+    ]
+}
+
+/* Where the line from the node's center toward `toward` leaves its shape. */
+fn clip(node: &PlacedNode, toward: (f64, f64)) -> (f64, f64) {
+    let (dx, dy) = (toward.0 - node.x, toward.1 - node.y);
+    if dx == 0.0 && dy == 0.0 {
+        return (node.x, node.y);
+    }
+    let (half_width, half_height) = (node.width / 2.0, node.height / 2.0);
+    let scale = match node.shape {
+        Shape::Ellipse | Shape::Circle | Shape::DoubleCircle | Shape::Point => {
+            1.0 / ((dx / half_width).powi(2) + (dy / half_height).powi(2)).sqrt()
+        }
+        Shape::Diamond | Shape::Triangle => 1.0 / (dx.abs() / half_width + dy.abs() / half_height),
+        _ => f64::min(half_width / dx.abs(), half_height / dy.abs()),
+    };
+    (node.x + dx * scale, node.y + dy * scale)
+}
+
+/* A smooth curve through the points. */
+fn path_data(points: &[(f64, f64)]) -> String {
+    let mut path = format!("M{:.1},{:.1}", points[0].0, points[0].1);
+    if points.len() == 2 {
+        path.push_str(&format!(" L{:.1},{:.1}", points[1].0, points[1].1));
+        return path;
+    }
+
+    let last = points.len() - 1;
+    for index in 0..last {
+        let previous = points[index.saturating_sub(1)];
+        let start = points[index];
+        let end = points[index + 1];
+        let next = points[(index + 2).min(last)];
+        path.push_str(&format!(
+            " C{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}",
+            start.0 + (end.0 - previous.0) / 6.0,
+            start.1 + (end.1 - previous.1) / 6.0,
+            end.0 - (next.0 - start.0) / 6.0,
+            end.1 - (next.1 - start.1) / 6.0,
+            end.0,
+            end.1
+        ));
+    }
+    path
+}
+
+/* The coordinates in path data, including control points. */
+fn path_points(path: &str) -> Vec<(f64, f64)> {
+    path.split(|c: char| c == ' ' || c.is_ascii_uppercase())
+        .filter_map(|pair| {
+            let mut coordinates = pair.split(',').map(|value| value.parse::<f64>());
+            match (coordinates.next(), coordinates.next()) {
+                (Some(Ok(x)), Some(Ok(y))) => Some((x, y)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn middle(points: &[(f64, f64)]) -> (f64, f64) {
+    let middle = points.len() / 2;
+    if points.len() % 2 == 1 {
+        points[middle]
+    } else {
+        let (a, b) = (points[middle - 1], points[middle]);
+        ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0)
+    }
+}
+
+/* Whether the edge has an arrow at its head and at its tail. */
+fn arrows(graph: &Graph, attributes: &Attributes) -> (bool, bool) {
+    let default = if graph.directed { "forward" } else { "none" };
+    let direction = attributes.get("dir").map_or(default, String::as_str);
+    let head = (direction == "forward" || direction == "both")
+        && attributes.get("arrowhead").map(String::as_str) != Some("none");
+    let tail = (direction == "back" || direction == "both")
+        && attributes.get("arrowtail").map(String::as_str) != Some("none");
+    (head, tail)
+}
+
+fn shape(attributes: &Attributes) -> Shape {
+    match attributes.get("shape").map_or("ellipse", String::as_str) {
+        "ellipse" | "oval" => Shape::Ellipse,
+        "circle" => Shape::Circle,
+        "doublecircle" => Shape::DoubleCircle,
+        "diamond" => Shape::Diamond,
+        "point" => Shape::Point,
+        "plaintext" | "plain" | "none" => Shape::Plain,
+        "cylinder" => Shape::Cylinder,
+        "hexagon" => Shape::Hexagon,
+        "octagon" => Shape::Octagon,
+        "triangle" => Shape::Triangle,
+        "parallelogram" => Shape::Parallelogram,
+        "note" => Shape::Note,
+        // Like Graphviz, unknown shapes are boxes. Records are too, with their fields as text.
+        _ => Shape::Box,
+    }
+}
+
+fn node_label(node: &Node, graph_name: &str, shape: Shape) -> Label {
+    let text = match node.attributes.get("label") {
+        _ if shape == Shape::Point => "",
+        Some(text) => text.as_str(),
+        None => "\\N",
+    };
+    label(text, &node.name, graph_name, font_size(&node.attributes))
+}
+
+fn node_size(shape: Shape, label: &Label, attributes: &Attributes) -> (f64, f64) {
+    if shape == Shape::Point {
+        let size = inches(attributes.get("width")).unwrap_or(7.2).min(72.0);
+        return (size, size);
+    }
+
+    // Graphviz's margins of 0.11 by 0.055 inches around the label.
+    let (width, height) = (label.width + 16.0, label.height + 8.0);
+    let (width, height) = match shape {
+        Shape::Ellipse | Shape::Circle | Shape::DoubleCircle => (width * SQRT_2, height * SQRT_2),
+        Shape::Diamond | Shape::Triangle => (width * 2.0, height * 2.0),
+        Shape::Hexagon | Shape::Octagon => (width * 1.25, height * 1.25),
+        Shape::Parallelogram => (width + height, height),
+        Shape::Cylinder => (width, height + 12.0),
+        _ => (width, height),
+    };
+    let mut width = width.max(inches(attributes.get("width")).unwrap_or(MIN_NODE_WIDTH));
+    let mut height = height.max(inches(attributes.get("height")).unwrap_or(MIN_NODE_HEIGHT));
+    if shape == Shape::Circle || shape == Shape::DoubleCircle {
+        width = width.max(height);
+        height = width;
+    }
+    if shape == Shape::DoubleCircle {
+        width += 8.0;
+        height += 8.0;
+    }
+    (width, height)
+}
+
+/* Labels use Graphviz's escapes: `\n`, `\l` and `\r` end centered, left-aligned and
+right-aligned lines, `\N` is the node's name and `\G` the graph's. */
+fn label(text: &str, object_name: &str, graph_name: &str, font_size: f64) -> Label {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => lines.push((std::mem::take(&mut line), Align::Center)),
+                Some('l') => lines.push((std::mem::take(&mut line), Align::Left)),
+                Some('r') => lines.push((std::mem::take(&mut line), Align::Right)),
+                Some('N') => line.push_str(object_name),
+                Some('G') => line.push_str(graph_name),
+                Some(c) => line.push(c),
+                None => line.push('\\'),
+            },
+            '\n' => lines.push((std::mem::take(&mut line), Align::Center)),
+            '\r' => (),
+            _ => line.push(c),
+        }
+    }
+    if !line.is_empty() {
+        lines.push((line, Align::Center));
+    }
+
+    let width = lines
+        .iter()
+        .map(|(line, _)| text_width(line) * font_size)
+        .fold(0.0, f64::max);
+    let height = lines.len() as f64 * font_size * LINE_HEIGHT;
+    Label {
+        lines,
+        font_size,
+        width,
+        height,
+    }
+}
+
+/* An estimate in multiples of the font size, as fonts differ between viewers anyway. */
+fn text_width(text: &str) -> f64 {
+    text.chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | 'I' | '.' | ',' | ':' | ';' | '\'' | '!' | '|' | ' ' => 0.3,
+            'f' | 'r' | 't' | '(' | ')' | '[' | ']' | '-' => 0.4,
+            'm' | 'w' | 'M' | 'W' => 0.85,
+            'A'..='Z' => 0.68,
+            _ if c as u32 >= 0x2E80 => 1.0,
+            _ => 0.56,
+        })
+        .sum()
+}
+
+fn font_size(attributes: &Attributes) -> f64 {
+    attributes
+        .get("fontsize")
+        .and_then(|size| size.parse::<f64>().ok())
+        .filter(|size| size.is_finite())
+        .map_or(FONT_SIZE, |size| size.clamp(1.0, 100.0))
+}
+
+/* A length attribute in inches, in points. */
+fn inches(value: Option<&String>) -> Option<f64> {
+    value
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|value| value.is_finite() && *value >= 0.0)
+        .map(|value| value.min(20.0) * 72.0)
+}
+
+fn is_invisible(attributes: &Attributes) -> bool {
+    styles(attributes).contains(&"invis")
+}
+
+fn styles(attributes: &Attributes) -> Vec<&str> {
+    attributes
+        .get("style")
+        .map(|style| style.split(',').map(str::trim).collect())
+        .unwrap_or_default()
+}
+
+fn node_rect(node: &PlacedNode) -> Rect {
+    Rect {
+        x: node.x - node.width / 2.0,
+        y: node.y - node.height / 2.0,
+        width: node.width,
+        height: node.height,
+    }
+}
+
+fn label_rect(label: &Label, x: f64, y: f64) -> Rect {
+    Rect {
+        x: x - label.width / 2.0,
+        y: y - label.height / 2.0,
+        width: label.width,
+        height: label.height,
+    }
+}
+
+fn union(a: Option<Rect>, b: Rect) -> Rect {
+    match a {
+        None => b,
+        Some(a) => {
+            let x = a.x.min(b.x);
+            let y = a.y.min(b.y);
+            Rect {
+                x,
+                y,
+                width: (a.x + a.width).max(b.x + b.width) - x,
+                height: (a.y + a.height).max(b.y + b.height) - y,
+            }
+        }
+    }
+}
+
+fn render(graph: &Graph, layout: &Layout) -> String {
+    let width = layout.bounds.width + 2.0 * MARGIN;
+    let height = layout.bounds.height + 2.0 * MARGIN;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0:.0}pt\" height=\"{1:.0}pt\" \
+         viewBox=\"0 0 {0:.1} {1:.1}\" font-family=\"Helvetica,Arial,sans-serif\" \
+         font-size=\"{2}\">\n",
+        width, height, FONT_SIZE
+    );
+    if let Some(background) = color(graph.attributes.get("bgcolor")) {
+        svg.push_str(&format!(
+            "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>\n",
+            background
+        ));
+    }
+    svg.push_str(&format!(
+        "<g transform=\"translate({:.1} {:.1})\">\n",
+        MARGIN - layout.bounds.x,
+        MARGIN - layout.bounds.y
+    ));
+
+    for (cluster, placed) in graph.clusters.iter().zip(&layout.clusters) {
+        if let Some((rect, label)) = placed {
+            push_cluster(&mut svg, &cluster.attributes, rect, label);
+        }
+    }
+    for (edge, placed) in graph.edges.iter().zip(&layout.edges) {
+        push_edge(&mut svg, &edge.attributes, placed);
+    }
+    for (node, placed) in graph.nodes.iter().zip(&layout.nodes) {
+        push_node(&mut svg, &node.attributes, placed);
+    }
+    if let Some((ref label, x, y)) = layout.label {
+        let font_color = color(graph.attributes.get("fontcolor")).unwrap_or("black".to_string());
+        push_label(&mut svg, label, x, y, &font_color);
+    }
+
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
+
+fn push_cluster(svg: &mut String, attributes: &Attributes, rect: &Rect, label: &Label) {
+    if is_invisible(attributes) {
+        return;
+    }
+    let styles = styles(attributes);
+    let stroke = color(attributes.get("pencolor"))
+        .or_else(|| color(attributes.get("color")))
+        .unwrap_or("black".to_string());
+    let fill = if styles.contains(&"filled") {
+        color(attributes.get("fillcolor"))
+            .or_else(|| color(attributes.get("color")))
+            .unwrap_or("lightgrey".to_string())
+    } else {
+        "none".to_string()
+    };
+    svg.push_str(&format!(
+        "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"{} fill=\"{}\" \
+         stroke=\"{}\"{}/>\n",
+        rect.x,
+        rect.y,
+        rect.width,
+        rect.height,
+        if styles.contains(&"rounded") {
+            " rx=\"8\""
+        } else {
+            ""
+        },
+        fill,
+        stroke,
+        stroke_style(attributes)
+    ));
+    let font_color = color(attributes.get("fontcolor")).unwrap_or("black".to_string());
+    push_label(
+        svg,
+        label,
+        rect.x + rect.width / 2.0,
+        rect.y + CLUSTER_PADDING / 2.0 + label.height / 2.0,
+        &font_color,
+    );
+}
+
+fn push_edge(svg: &mut String, attributes: &Attributes, edge: &PlacedEdge) {
+    if is_invisible(attributes) {
+        return;
+    }
+    let stroke = color(attributes.get("color")).unwrap_or("black".to_string());
+    svg.push_str(&format!(
+        "<path d=\"{}\" fill=\"none\" stroke=\"{}\"{}/>\n",
+        edge.path,
+        stroke,
+        stroke_style(attributes)
+    ));
+
+    for (arrow, kind) in [
+        (edge.head, attributes.get("arrowhead")),
+        (edge.tail, attributes.get("arrowtail")),
+    ]
+    .iter()
+    {
+        if let Some(corners) = arrow {
+            let hollow = kind.is_some_and(|kind| kind == "empty" || kind == "onormal");
+            svg.push_str(&format!(
+                "<polygon points=\"{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}\" fill=\"{}\" \
+                 stroke=\"{}\"/>\n",
+                corners[0].0,
+                corners[0].1,
+                corners[1].0,
+                corners[1].1,
+                corners[2].0,
+                corners[2].1,
+                if hollow { "white" } else { &stroke },
+                stroke
+            ));
+        }
+    }
+
+    if let Some((ref label, x, y)) = edge.label {
+        let font_color = color(attributes.get("fontcolor")).unwrap_or("black".to_string());
+        push_label(svg, label, x, y, &font_color);
+    }
+}
+
+fn push_node(svg: &mut String, attributes: &Attributes, node: &PlacedNode) {
+    if is_invisible(attributes) {
+        return;
+    }
+    let styles = styles(attributes);
+    let stroke = color(attributes.get("color")).unwrap_or("black".to_string());
+    let fill = if styles.contains(&"filled") || node.shape == Shape::Point {
+        color(attributes.get("fillcolor"))
+            .or_else(|| color(attributes.get("color")))
+            .unwrap_or(
+                if node.shape == Shape::Point {
+                    "black"
+                } else {
+                    "lightgrey"
+                }
+                .to_string(),
+            )
+    } else {
+        "none".to_string()
+    };
+    let paint = format!(
+        " fill=\"{}\" stroke=\"{}\"{}",
+        fill,
+        stroke,
+        stroke_style(attributes)
+    );
+
+    let (x, y) = (node.x, node.y);
+    let (half_width, half_height) = (node.width / 2.0, node.height / 2.0);
+    let polygon = |points: &[(f64, f64)]| {
+        let points: Vec<String> = points
+            .iter()
+            .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+            .collect();
+        format!("<polygon points=\"{}\"{}/>\n", points.join(" "), paint)
+    };
+    let shape = match node.shape {
+        Shape::Box => format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"{}{}/>\n",
+            x - half_width,
+            y - half_height,
+            node.width,
+            node.height,
+            if styles.contains(&"rounded")
+                || attributes.get("shape").map(String::as_str) == Some("Mrecord")
+            {
+                " rx=\"6\""
+            } else {
+                ""
+            },
+            paint
+        ),
+        Shape::Plain if fill != "none" => format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"/>\n",
+            x - half_width,
+            y - half_height,
+            node.width,
+            node.height,
+            fill
+        ),
+        Shape::Plain => String::new(),
+        Shape::Ellipse => format!(
+            "<ellipse cx=\"{:.1}\" cy=\"{:.1}\" rx=\"{:.1}\" ry=\"{:.1}\"{}/>\n",
+            x, y, half_width, half_height, paint
+        ),
+        Shape::Circle | Shape::Point => format!(
+            "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{:.1}\"{}/>\n",
+            x, y, half_width, paint
+        ),
+        Shape::DoubleCircle => format!(
+            "<circle cx=\"{0:.1}\" cy=\"{1:.1}\" r=\"{2:.1}\"{3}/>\n\
+             <circle cx=\"{0:.1}\" cy=\"{1:.1}\" r=\"{4:.1}\" fill=\"none\" stroke=\"{5}\"{6}/>\n",
+            x,
+            y,
+            half_width,
+            paint,
+            half_width - 4.0,
+            stroke,
+            stroke_style(attributes)
+        ),
+        Shape::Diamond => polygon(&[
+            (x, y - half_height),
+            (x + half_width, y),
+            (x, y + half_height),
+            (x - half_width, y),
+        ]),
+        Shape::Triangle => polygon(&[
+            (x, y - half_height),
+            (x + half_width, y + half_height),
+            (x - half_width, y + half_height),
+        ]),
+        Shape::Hexagon => {
+            let slant = half_width / 4.0;
+            polygon(&[
+                (x - half_width, y),
+                (x - half_width + slant, y - half_height),
+                (x + half_width - slant, y - half_height),
+                (x + half_width, y),
+                (x + half_width - slant, y + half_height),
+                (x - half_width + slant, y + half_height),
+            ])
+        }
+        Shape::Octagon => {
+            let corner = half_width.min(half_height) * 0.6;
+            polygon(&[
+                (x - half_width + corner, y - half_height),
+                (x + half_width - corner, y - half_height),
+                (x + half_width, y - half_height + corner),
+                (x + half_width, y + half_height - corner),
+                (x + half_width - corner, y + half_height),
+                (x - half_width + corner, y + half_height),
+                (x - half_width, y + half_height - corner),
+                (x - half_width, y - half_height + corner),
+            ])
+        }
+        Shape::Parallelogram => {
+            let slant = half_height;
+            polygon(&[
+                (x - half_width + slant, y - half_height),
+                (x + half_width, y - half_height),
+                (x + half_width - slant, y + half_height),
+                (x - half_width, y + half_height),
+            ])
+        }
+        Shape::Note => {
+            let fold = 8.0;
+            let (right, top) = (x + half_width, y - half_height);
+            format!(
+                "{}<polyline points=\"{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}\" fill=\"none\" \
+                 stroke=\"{}\"/>\n",
+                polygon(&[
+                    (x - half_width, top),
+                    (right - fold, top),
+                    (right, top + fold),
+                    (right, y + half_height),
+                    (x - half_width, y + half_height),
+                ]),
+                right - fold,
+                top,
+                right - fold,
+                top + fold,
+                right,
+                top + fold,
+                stroke
+            )
+        }
+        Shape::Cylinder => {
+            let rim = (node.height / 8.0).min(8.0);
+            let (left, right) = (x - half_width, x + half_width);
+            let (top, bottom) = (y - half_height + rim, y + half_height - rim);
+            format!(
+                "<path d=\"M{0:.1},{2:.1} A{4:.1},{5:.1} 0 0 1 {1:.1},{2:.1} V{3:.1} \
+                 A{4:.1},{5:.1} 0 0 1 {0:.1},{3:.1} Z\"{6}/>\n\
+                 <path d=\"M{0:.1},{2:.1} A{4:.1},{5:.1} 0 0 0 {1:.1},{2:.1}\" fill=\"none\" \
+                 stroke=\"{7}\"/>\n",
+                left, right, top, bottom, half_width, rim, paint, stroke
+            )
+        }
+    };
+    svg.push_str(&shape);
+
+    let font_color = color(attributes.get("fontcolor")).unwrap_or("black".to_string());
+    push_label(svg, &node.label, x, y, &font_color);
+}
+
+fn push_label(svg: &mut String, label: &Label, x: f64, y: f64, color: &str) {
+    let line_height = label.font_size * LINE_HEIGHT;
+    for (index, (text, align)) in label.lines.iter().enumerate() {
+        if text.is_empty() {
+            continue;
+        }
+        let (anchor, line_x) = match align {
+            Align::Left => ("start", x - label.width / 2.0),
+            Align::Center => ("middle", x),
+            Align::Right => ("end", x + label.width / 2.0),
+        };
+        let line_y = y - label.height / 2.0 + (index as f64 + 0.5) * line_height;
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"{}\" dominant-baseline=\"central\"",
+            line_x, line_y, anchor
+        ));
+        if label.font_size != FONT_SIZE {
+            svg.push_str(&format!(" font-size=\"{}\"", label.font_size));
+        }
+        if color != "black" {
+            svg.push_str(&format!(" fill=\"{}\"", color));
+        }
+        svg.push_str(&format!(">{}</text>\n", escape(text)));
+    }
+}
+
+fn stroke_style(attributes: &Attributes) -> String {
+    let styles = styles(attributes);
+    let mut style = String::new();
+    if styles.contains(&"dashed") {
+        style.push_str(" stroke-dasharray=\"5,2\"");
+    } else if styles.contains(&"dotted") {
+        style.push_str(" stroke-dasharray=\"1,5\"");
+    }
+    let width = attributes
+        .get("penwidth")
+        .and_then(|width| width.parse::<f64>().ok())
+        .filter(|width| width.is_finite())
+        .map(|width| width.clamp(0.0, 20.0))
+        .or(if styles.contains(&"bold") {
+            Some(2.0)
+        } else {
+            None
+        });
+    if let Some(width) = width {
+        style.push_str(&format!(" stroke-width=\"{}\"", width));
+    }
+    style
+}
+
+/* Colors that SVG understands, from Graphviz's `#rrggbb`, names and `grayN`. The first color of
+a list is used. Others fall back to the default. */
+fn color(value: Option<&String>) -> Option<String> {
+    let value = value?.split([':', ';']).next()?.trim();
+    let lowercase = value.to_ascii_lowercase();
+
+    if let Some(hex) = lowercase.strip_prefix('#') {
+        let valid = [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit());
+        return if valid { Some(lowercase) } else { None };
+    }
+    if lowercase == "none" || lowercase == "transparent" {
+        return Some("none".to_string());
+    }
+    let name = lowercase.trim_end_matches(|c: char| c.is_ascii_digit());
+    let number = &lowercase[name.len()..];
+    if (name == "gray" || name == "grey") && !number.is_empty() {
+        let percent: u32 = number.parse().ok().filter(|percent| *percent <= 100)?;
+        let level = (percent * 255 + 50) / 100;
+        return Some(format!("#{0:02x}{0:02x}{0:02x}", level));
+    }
+    // X11 variants like `lightblue2` are shown as the base color.
+    if CSS_COLORS.contains(&name) {
+        Some(name.to_string())
+    } else {
+        None
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_names(graph: &Graph) -> Vec<&str> {
+        graph.nodes.iter().map(|node| node.name.as_str()).collect()
+    }
+
+    fn edge_names(graph: &Graph) -> Vec<(&str, &str)> {
+        graph
+            .edges
+            .iter()
+            .map(|edge| {
+                (
+                    graph.nodes[edge.from].name.as_str(),
+                    graph.nodes[edge.to].name.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_nodes_and_edges() {
+        let graph = parse(
+            "digraph G {\n  a -> b -> c [color=red];\n  {d e} -> f\n  \"quoted \\\"name\\\"\"\n}",
+        )
+        .unwrap();
+
+        assert!(graph.directed);
+        assert_eq!(graph.name, "G");
+        assert_eq!(
+            node_names(&graph),
+            vec!["a", "b", "c", "d", "e", "f", "quoted \"name\""]
+        );
+        assert_eq!(
+            edge_names(&graph),
+            vec![("a", "b"), ("b", "c"), ("d", "f"), ("e", "f")]
+        );
+        assert_eq!(graph.edges[1].attributes["color"], "red");
+    }
+
+    #[test]
+    fn parses_attributes_and_subgraphs() {
+        let graph = parse(
+            "strict graph {
+                rankdir = LR
+                node [shape=box]
+                a -- b
+                b -- a [label=\"back\"]
+                subgraph cluster_x { label = \"X\"; c; subgraph inner { rank = same; d e } }
+                a [shape=circle]
+                \"multi\" + \"part\" // comment
+                /* a block
+                   comment */
+            }",
+        )
+        .unwrap();
+
+        assert!(graph.strict);
+        assert!(!graph.directed);
+        assert_eq!(graph.attributes["rankdir"], "LR");
+        assert_eq!(
+            node_names(&graph),
+            vec!["a", "b", "c", "d", "e", "multipart"]
+        );
+        // Strict graphs merge the edges in both directions.
+        assert_eq!(edge_names(&graph), vec![("a", "b")]);
+        assert_eq!(graph.edges[0].attributes["label"], "back");
+        assert_eq!(graph.nodes[0].attributes["shape"], "circle");
+        assert_eq!(graph.nodes[1].attributes["shape"], "box");
+        assert_eq!(graph.clusters.len(), 1);
+        assert_eq!(graph.clusters[0].attributes["label"], "X");
+        assert_eq!(graph.clusters[0].nodes, vec![2, 3, 4]);
+        assert_eq!(graph.same_ranks, vec![vec![3, 4]]);
+    }
+
+    #[test]
+    fn parses_dot_syntax() {
+        let graph = parse(
+            "# preprocessor output
+            DiGraph {
+                NODE [shape=box]
+                a:n -> b:port:s
+                -1.5 -> .5
+                <x<b>y</b>>
+                \"long \\
+name\"
+            }",
+        )
+        .unwrap();
+        assert!(graph.directed);
+        assert_eq!(
+            node_names(&graph),
+            vec!["a", "b", "-1.5", ".5", "xy", "long name"]
+        );
+        assert_eq!(edge_names(&graph), vec![("a", "b"), ("-1.5", ".5")]);
+        assert!(graph
+            .nodes
+            .iter()
+            .all(|node| node.attributes["shape"] == "box"));
+
+        // Subgraphs as endpoints connect all of their nodes.
+        let graph = parse("digraph { a -> {b c} -> subgraph s { d } }").unwrap();
+        assert_eq!(
+            edge_names(&graph),
+            vec![("a", "b"), ("a", "c"), ("b", "d"), ("c", "d")]
+        );
+    }
+
+    #[test]
+    fn scopes_default_attributes() {
+        let graph = parse(
+            "digraph {
+                graph [label=\"L\"]
+                edge [color=red]
+                a -> b
+                subgraph { edge [color=blue]; node [shape=box]; c -> d }
+                e -> f [color=green][style=dashed]
+                c [shape=circle]
+            }",
+        )
+        .unwrap();
+
+        assert_eq!(graph.attributes["label"], "L");
+        let colors: Vec<&str> = graph
+            .edges
+            .iter()
+            .map(|edge| edge.attributes["color"].as_str())
+            .collect();
+        assert_eq!(colors, vec!["red", "blue", "green"]);
+        assert_eq!(graph.edges[2].attributes["style"], "dashed");
+        let shapes: Vec<Option<&str>> = graph
+            .nodes
+            .iter()
+            .map(|node| node.attributes.get("shape").map(String::as_str))
+            .collect();
+        assert_eq!(
+            shapes,
+            vec![None, None, Some("circle"), Some("box"), None, None]
+        );
+
+        // Strict digraphs merge repeated edges, but not edges in the other direction.
+        let graph = parse("strict digraph { a -> b; b -> a; a -> b [color=red] }").unwrap();
+        assert_eq!(edge_names(&graph), vec![("a", "b"), ("b", "a")]);
+        assert_eq!(graph.edges[0].attributes["color"], "red");
+    }
+
+    #[test]
+    fn reads_label_escapes() {
+        let label = label("a\\lb\\rc\\N\\G\\\\d\\nlast", "node", "graph", 14.0);
+        let lines: Vec<(&str, &str)> = label
+            .lines
+            .iter()
+            .map(|(text, align)| {
+                let align = match align {
+                    Align::Left => "left",
+                    Align::Center => "center",
+                    Align::Right => "right",
+                };
+                (text.as_str(), align)
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("a", "left"),
+                ("b", "right"),
+                ("cnodegraph\\d", "center"),
+                ("last", "center")
+            ]
+        );
+
+        assert_eq!(
+            html_label(
+                "<table><tr><td>a &amp;&nbsp;b</td></tr><tr><td>c<BR/>d\\e</td></tr></table>"
+            ),
+            "a &\u{A0}b\\nc\\nd\\\\e\\n"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_diagrams() {
+        let cases = [
+            ("", "Expected graph or digraph but the diagram ended"),
+            (
+                "flowchart {}",
+                "Expected graph or digraph in line 1 but found 'flowchart'",
+            ),
+            ("digraph { a", "Expected '}' but the diagram ended"),
+            (
+                "graph {\n a -> b }",
+                "Edges in a graph are written --, in line 2",
+            ),
+            (
+                "digraph { a -- b }",
+                "Edges in a digraph are written ->, in line 1",
+            ),
+            (
+                "digraph { a [color] }",
+                "Expected '=' in line 1 but found ']'",
+            ),
+            ("digraph { a @ b }", "Unexpected '@' in line 1"),
+            ("digraph { node }", "Expected '[' in line 1 but found '}'"),
+            (
+                "digraph { a -> }",
+                "Expected a node or a subgraph in line 1 but found '}'",
+            ),
+            ("digraph { a: }", "Expected a port in line 1 but found '}'"),
+            (
+                "digraph { a [=x] }",
+                "Expected an attribute in line 1 but found '='",
+            ),
+            (
+                "digraph { x = }",
+                "Expected a value in line 1 but found '}'",
+            ),
+            (
+                "digraph { subgraph x }",
+                "Expected '{' in line 1 but found '}'",
+            ),
+            ("digraph { \"a }", "Unterminated string in line 1"),
+            (
+                "digraph { a [label=<<b> }",
+                "Unterminated HTML string in line 1",
+            ),
+            ("digraph {\n/* a }", "Unterminated comment in line 2"),
+            (
+                "digraph {} b",
+                "Unexpected 'b' in line 1 after the end of the graph",
+            ),
+        ];
+        for (dot, expected) in &cases {
+            assert_eq!(parse(dot).err().as_deref(), Some(*expected), "{}", dot);
+        }
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let nested = |depth| {
+            format!(
+                "digraph {{ {}a{} }}",
+                "{ ".repeat(depth),
+                " }".repeat(depth)
+            )
+        };
+
+        assert!(parse(&nested(MAX_NESTING)).is_ok());
+        assert_eq!(
+            parse(&nested(MAX_NESTING + 1)).err(),
+            Some("Subgraphs can be nested at most 32 levels deep".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_too_many_nodes_and_edges() {
+        let nodes: String = (0..=MAX_NODES).map(|i| format!("n{}; ", i)).collect();
+        assert_eq!(
+            validate(&format!("digraph {{ {} }}", nodes)).err(),
+            Some("Diagrams can have at most 1000 nodes".to_string())
+        );
+
+        let edges = "a -> b; ".repeat(MAX_EDGES + 1);
+        assert_eq!(
+            validate(&format!("digraph {{ {} }}", edges)).err(),
+            Some("Diagrams can have at most 2000 edges".to_string())
+        );
+        // Strict graphs merge them into one.
+        assert!(validate(&format!("strict digraph {{ {} }}", edges)).is_ok());
+    }
+
+    #[test]
+    fn rejects_diagrams_too_large_to_lay_out() {
+        // Every edge across the chain needs a virtual node in each rank it passes.
+        let chain: Vec<String> = (0..200).map(|i| format!("n{}", i)).collect();
+        let long_edges = "n0 -> n199; ".repeat(MAX_LAYOUT_NODES / 198);
+        let dot = format!("digraph {{ {}; {} }}", chain.join(" -> "), long_edges);
+
+        assert_eq!(
+            validate(&dot).err(),
+            Some("The diagram is too large to lay out".to_string())
+        );
+        assert!(validate(&format!("digraph {{ {}; n0 -> n199 }}", chain.join(" -> "))).is_ok());
+    }
+
+    fn node_positions(dot: &str) -> Vec<(f64, f64)> {
+        let layout = layout(&parse(dot).unwrap()).unwrap();
+        layout.nodes.iter().map(|node| (node.x, node.y)).collect()
+    }
+
+    #[test]
+    fn ranks_nodes_along_edges() {
+        let positions = node_positions("digraph { a -> b -> c; a -> c }");
+        assert!(positions[0].1 < positions[1].1 && positions[1].1 < positions[2].1);
+
+        // Which way b is from a, as the signs of the offset.
+        let sign = |value: f64| (value > 0.0) as i32 - (value < 0.0) as i32;
+        for (rankdir, expected) in &[
+            ("TB", (0, 1)),
+            ("BT", (0, -1)),
+            ("LR", (1, 0)),
+            ("RL", (-1, 0)),
+        ] {
+            let positions = node_positions(&format!("digraph {{ rankdir={}; a -> b }}", rankdir));
+            let (a, b) = (positions[0], positions[1]);
+            assert_eq!((sign(b.0 - a.0), sign(b.1 - a.1)), *expected, "{}", rankdir);
+        }
+
+        // Cycles are laid out too, with one edge going back.
+        let positions = node_positions("digraph { a -> b -> c -> a }");
+        assert!(positions[0].1 < positions[1].1 && positions[1].1 < positions[2].1);
+
+        let positions = node_positions("digraph { a -> b; a -> c; { rank=same; a; c } }");
+        assert_eq!(positions[0].1, positions[2].1);
+        assert!(positions[1].1 > positions[0].1);
+    }
+
+    #[test]
+    fn keeps_nodes_apart() {
+        let dot = "digraph { a -> {b c d e}; b [label=\"a wide label\"] }";
+        let placed = layout(&parse(dot).unwrap()).unwrap();
+        let mut rank: Vec<&PlacedNode> = placed.nodes[1..].iter().collect();
+        rank.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+        for pair in rank.windows(2) {
+            assert_eq!(pair[0].y, pair[1].y);
+            let gap = (pair[1].x - pair[1].width / 2.0) - (pair[0].x + pair[0].width / 2.0);
+            assert!(gap >= NODE_SEPARATION - 0.01, "{}", gap);
+        }
+
+        // Clusters surround their nodes and nothing else.
+        let dot = "digraph { subgraph cluster_x { label=X; a -> b } c; a -> c }";
+        let placed = layout(&parse(dot).unwrap()).unwrap();
+        let (rect, _) = placed.clusters[0].as_ref().unwrap();
+        let inside = |node: &PlacedNode| {
+            let node = node_rect(node);
+            node.x >= rect.x
+                && node.y >= rect.y
+                && node.x + node.width <= rect.x + rect.width
+                && node.y + node.height <= rect.y + rect.height
+        };
+        assert!(inside(&placed.nodes[0]) && inside(&placed.nodes[1]));
+        assert!(!inside(&placed.nodes[2]));
+    }
+
+    #[test]
+    fn renders_svg() {
+        let svg = to_svg("digraph { a -> b [label=\"yes\"] }").unwrap();
+
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.ends_with("</g>\n</svg>\n"));
+        assert_eq!(svg.matches("<ellipse ").count(), 2);
+        assert_eq!(svg.matches("<path ").count(), 1);
+        assert_eq!(svg.matches("<polygon ").count(), 1);
+        for text in &[">a</text>", ">b</text>", ">yes</text>"] {
+            assert!(svg.contains(text), "{} in {}", text, svg);
+        }
+    }
+
+    #[test]
+    fn renders_shapes() {
+        let cases = [
+            ("box", "<rect "),
+            ("rect", "<rect "),
+            ("record", "<rect "),
+            ("Mrecord", " rx=\"6\""),
+            ("ellipse", "<ellipse "),
+            ("oval", "<ellipse "),
+            ("circle", "<circle "),
+            ("point", "<circle "),
+            ("diamond", "<polygon "),
+            ("triangle", "<polygon "),
+            ("hexagon", "<polygon "),
+            ("octagon", "<polygon "),
+            ("parallelogram", "<polygon "),
+            ("note", "<polyline "),
+            ("cylinder", "<path "),
+        ];
+        for (shape, element) in &cases {
+            let svg = to_svg(&format!("digraph {{ a [shape={}] }}", shape)).unwrap();
+            assert!(svg.contains(element), "{}: {}", shape, svg);
+        }
+
+        let svg = to_svg("digraph { a [shape=doublecircle] }").unwrap();
+        assert_eq!(svg.matches("<circle ").count(), 2);
+        let svg = to_svg("digraph { a [shape=plaintext] }").unwrap();
+        assert!(!svg.contains("<rect ") && !svg.contains("<ellipse "));
+        assert!(svg.contains(">a</text>"));
+        // Points have no label.
+        let svg = to_svg("digraph { a [shape=point] }").unwrap();
+        assert!(!svg.contains("<text"));
+    }
+
+    #[test]
+    fn renders_styles() {
+        let cases = [
+            (
+                "digraph { a [shape=box, style=\"filled,dashed\", penwidth=3] }",
+                "<rect x=\"-27.0\" y=\"-18.0\" width=\"54.0\" height=\"36.0\" \
+                 fill=\"lightgrey\" stroke=\"black\" stroke-dasharray=\"5,2\" stroke-width=\"3\"/>",
+            ),
+            (
+                "digraph { a [style=\"rounded,filled\", shape=box, fillcolor=yellow, color=blue] }",
+                " rx=\"6\" fill=\"yellow\" stroke=\"blue\"/>",
+            ),
+            (
+                "digraph { a [style=dotted] }",
+                " stroke-dasharray=\"1,5\"/>",
+            ),
+            ("digraph { a [style=bold] }", " stroke-width=\"2\"/>"),
+            (
+                "digraph { a [fontsize=20, fontcolor=red] }",
+                " font-size=\"20\" fill=\"red\">a</text>",
+            ),
+            (
+                "digraph { bgcolor=lightblue; a }",
+                "<rect width=\"100%\" height=\"100%\" fill=\"lightblue\"/>",
+            ),
+            (
+                "digraph { subgraph cluster_a { label=A; style=filled; a } }",
+                " fill=\"lightgrey\" stroke=\"black\"/>\n<text x=\"0.0\" y=\"-34.4\" \
+                 text-anchor=\"middle\" dominant-baseline=\"central\">A</text>",
+            ),
+            (
+                "digraph { a -> b [color=red, style=dashed] }",
+                "fill=\"none\" stroke=\"red\" stroke-dasharray=\"5,2\"/>",
+            ),
+        ];
+        for (dot, expected) in &cases {
+            let svg = to_svg(dot).unwrap();
+            assert!(svg.contains(expected), "{} in {}", expected, svg);
+        }
+
+        // Invisible nodes, edges and clusters take up space but aren't drawn.
+        let svg = to_svg(
+            "digraph { a; b [style=invis]; a -> c [style=invis]; \
+             subgraph cluster_x { style=invis; d } }",
+        )
+        .unwrap();
+        assert_eq!(svg.matches("<ellipse ").count(), 3);
+        assert!(!svg.contains("<path ") && !svg.contains("<rect "));
+        assert!(!svg.contains(">b</text>"));
+    }
+
+    #[test]
+    fn renders_arrows() {
+        let cases = [
+            ("digraph { a -> b }", 1, 0),
+            ("graph { a -- b }", 0, 0),
+            ("graph { a -- b [dir=forward] }", 1, 0),
+            ("digraph { a -> b [dir=back] }", 1, 0),
+            ("digraph { a -> b [dir=both] }", 2, 0),
+            ("digraph { a -> b [dir=none] }", 0, 0),
+            ("digraph { a -> b [arrowhead=none] }", 0, 0),
+            ("digraph { a -> b [dir=both, arrowtail=none] }", 1, 0),
+            ("digraph { a -> b [arrowhead=empty] }", 1, 1),
+            ("digraph { a -> b [dir=both, arrowtail=onormal] }", 2, 1),
+        ];
+        for (dot, arrows, hollow) in &cases {
+            let svg = to_svg(dot).unwrap();
+            assert_eq!(svg.matches("<polygon ").count(), *arrows, "{}", dot);
+            assert_eq!(svg.matches("fill=\"white\"").count(), *hollow, "{}", dot);
+        }
+
+        // Loops leave the node and come back to it.
+        let svg = to_svg("digraph { a -> a }").unwrap();
+        assert!(svg.contains("<path d=\"M22.5,-10.0 C57.0,-26.0 57.0,26.0 31.5,14.2\""));
+        assert_eq!(svg.matches("<polygon ").count(), 1);
+    }
+
+    #[test]
+    fn escapes_labels() {
+        let cases = [
+            (
+                "digraph { a [label=\"<script>alert('x')</script>\"] }",
+                ">&lt;script&gt;alert('x')&lt;/script&gt;</text>",
+            ),
+            (
+                "digraph { a [label=\"\\\"&amp;\\\"\"] }",
+                ">&quot;&amp;amp;&quot;</text>",
+            ),
+            ("digraph { \"<b>\" }", ">&lt;b&gt;</text>"),
+            (
+                "digraph { a [label=<<b>&lt;i&gt; &amp; x</b>>] }",
+                ">&lt;i&gt; &amp; x</text>",
+            ),
+            (
+                "digraph { label = \"</text><svg>\" }",
+                ">&lt;/text&gt;&lt;svg&gt;</text>",
+            ),
+            ("digraph { a -> b [label=\"a<b\"] }", ">a&lt;b</text>"),
+        ];
+        for (dot, expected) in &cases {
+            let svg = to_svg(dot).unwrap();
+            assert!(svg.contains(expected), "{} in {}", expected, svg);
+            assert!(!svg.contains("<script") && !svg.contains("<b>"), "{}", svg);
+        }
+    }
+
+    #[test]
+    fn only_renders_valid_attribute_values() {
+        let injection = "red\\\" onload=\\\"alert(1)";
+        let dot = format!(
+            "digraph {{ bgcolor=\"{0}\"; a [color=\"{0}\", fillcolor=\"{0}\", style=filled, \
+             fontcolor=\"{0}\", penwidth=\"{0}\"]; a -> b [color=\"{0}\"] }}",
+            injection
+        );
+        let svg = to_svg(&dot).unwrap();
+        assert!(!svg.contains("onload"), "{}", svg);
+        assert!(!svg.contains("alert"), "{}", svg);
+
+        let cases = [
+            (Some("#FF0000"), Some("#ff0000")),
+            (Some("#ff00zz"), None),
+            (Some("LightBlue2"), Some("lightblue")),
+            (Some("gray50"), Some("#808080")),
+            (Some("gray150"), None),
+            (Some("red:blue"), Some("red")),
+            (Some("transparent"), Some("none")),
+            (Some("url(#x)"), None),
+            (Some("red\" onload=\"x"), None),
+            (None, None),
+        ];
+        for (value, expected) in &cases {
+            let value = value.map(str::to_string);
+            assert_eq!(color(value.as_ref()).as_deref(), *expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn caches_rendered_diagrams() {
+        let cache = SvgCache::default();
+        let dot = "digraph { a -> b }";
+        let hash = source_hash(dot);
+        assert_eq!(hash.len(), 40);
+        assert_ne!(hash, source_hash("digraph { a -> c }"));
+
+        let svg = cache.get_or_render(&hash, dot).unwrap();
+        assert_eq!(*svg, to_svg(dot).unwrap());
+        assert!(Arc::ptr_eq(&svg, &cache.get_or_render(&hash, dot).unwrap()));
+
+        // Errors aren't cached.
+        let invalid = "digraph {";
+        assert!(cache.get_or_render(&source_hash(invalid), invalid).is_err());
+        assert!(!cache
+            .entries
+            .lock()
+            .unwrap()
+            .svgs
+            .contains_key(&source_hash(invalid)));
+
+        // The oldest diagram is dropped when the cache is full.
+        for i in 0..MAX_CACHED_DIAGRAMS {
+            let dot = format!("digraph {{ n{} }}", i);
+            cache.get_or_render(&source_hash(&dot), &dot).unwrap();
+        }
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!(entries.svgs.len(), MAX_CACHED_DIAGRAMS);
+        assert_eq!(entries.order.len(), MAX_CACHED_DIAGRAMS);
+        assert!(!entries.svgs.contains_key(&hash));
+    }
+}
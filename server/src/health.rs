@@ -0,0 +1,319 @@
+use actix::MailboxError;
+use actix_web::{AsyncResponder, Error, HttpRequest, HttpResponse};
+use futures::future::Future;
+use lazy_static::lazy_static;
+use log::warn;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::actix_state::State;
+use super::build_info;
+use super::config::Config;
+use super::repo_actor::CheckReadinessMessage;
+
+/* Long enough for a busy database, short enough to answer before orchestrators give up. */
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(5);
+const WRITE_CHECK_FILE: &str = ".pragma-write-check";
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+/* Probes may come every second, which shouldn't mean a synced write every second. */
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref DISK_CHECK: DiskCheck = DiskCheck::default();
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    version: String,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    checks: Checks,
+}
+
+#[derive(Serialize)]
+struct Checks {
+    database: Check,
+    migrations: Check,
+    disk: Check,
+}
+
+/* The endpoint is public, so errors are only logged. */
+#[derive(Serialize)]
+struct Check {
+    status: &'static str,
+    #[serde(skip)]
+    error: Option<String>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Check {
+            status: "ok",
+            error: None,
+        }
+    }
+
+    fn failed(error: String) -> Self {
+        Check {
+            status: "failed",
+            error: Some(error),
+        }
+    }
+
+    fn skipped() -> Self {
+        Check {
+            status: "skipped",
+            error: None,
+        }
+    }
+
+    fn is_failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    fn log_failure(&self, name: &str) {
+        if let Some(ref error) = self.error {
+            warn!("Readiness check {} failed: {}", name, error);
+        }
+    }
+}
+
+/* Liveness: answers as long as the workers are running, without touching the database. */
+pub fn healthz(_: HttpRequest<State>) -> HttpResponse {
+    HttpResponse::Ok()
+        .header("Cache-Control", "no-store")
+        .json(Health {
+            status: "ok",
+            version: build_info::build_version(),
+        })
+}
+
+/* Readiness: the database answers in time, all migrations are applied and the data
+directory is writable. Responds with 503 if any check fails. */
+pub fn readyz(req: HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let database_path = req.state().database_path.clone();
+
+    req.state()
+        .db
+        .send_timeout(CheckReadinessMessage, DATABASE_TIMEOUT)
+        .then(move |res| {
+            let (database, migrations) = match res {
+                Ok(Ok(ref pending)) if pending.is_empty() => (Check::ok(), Check::ok()),
+                Ok(Ok(pending)) => (
+                    Check::ok(),
+                    Check::failed(format!("Pending migrations: {}", pending.join(", "))),
+                ),
+                Ok(Err(reason)) => (Check::failed(reason), Check::skipped()),
+                Err(MailboxError::Timeout) => (
+                    Check::failed("Timed out waiting for the database".to_string()),
+                    Check::skipped(),
+                ),
+                Err(e) => (Check::failed(format!("{}", e)), Check::skipped()),
+            };
+
+            let checks = Checks {
+                database,
+                migrations,
+                disk: match DISK_CHECK.check(&database_path, Instant::now()) {
+                    Ok(()) => Check::ok(),
+                    Err(e) => Check::failed(e),
+                },
+            };
+
+            checks.database.log_failure("database");
+            checks.migrations.log_failure("migrations");
+            checks.disk.log_failure("disk");
+
+            let ready = !(checks.database.is_failed()
+                || checks.migrations.is_failed()
+                || checks.disk.is_failed());
+
+            let mut response = if ready {
+                HttpResponse::Ok()
+            } else {
+                HttpResponse::ServiceUnavailable()
+            };
+
+            Ok(response
+                .header("Cache-Control", "no-store")
+                .json(Readiness {
+                    status: if ready { "ok" } else { "unavailable" },
+                    checks,
+                }))
+        })
+        .responder()
+}
+
+/* The result of the last disk check, reused for `DISK_CHECK_INTERVAL`. */
+#[derive(Default)]
+struct DiskCheck {
+    last: Mutex<Option<(Instant, Result<(), String>)>>,
+}
+
+impl DiskCheck {
+    fn check(&self, database_path: &Path, now: Instant) -> Result<(), String> {
+        let mut last = self.last.lock().unwrap();
+
+        match *last {
+            Some((checked_at, ref result)) if now - checked_at < DISK_CHECK_INTERVAL => {
+                result.clone()
+            }
+            _ => {
+                let result = check_disk(database_path);
+                *last = Some((now, result.clone()));
+                result
+            }
+        }
+    }
+}
+
+/* Writes and removes a small file next to the database. */
+fn check_disk(database_path: &Path) -> Result<(), String> {
+    let directory = match database_path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+        Some(parent) => parent,
+        None => Path::new("."),
+    };
+    let path = directory.join(WRITE_CHECK_FILE);
+
+    fs::File::create(&path)
+        .and_then(|mut file| {
+            file.write_all(b"ok")?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::remove_file(&path))
+        .map_err(|e| format!("{}: {}", directory.display(), e))
+}
+
+/* `pragma-server healthcheck`: requests `/readyz` from the running server, for container
+health checks in images without an HTTP client. Fails unless the server is ready. */
+pub fn check(config: &Config) -> Result<(), String> {
+    let response = match config.listen.unix_socket {
+        Some(ref path) => check_unix_socket(path, config)?,
+        None => check_tcp(config)?,
+    };
+
+    let mut parts = response.splitn(2, "\r\n\r\n");
+    let status_line = parts
+        .next()
+        .and_then(|head| head.lines().next())
+        .unwrap_or("");
+    println!("{}", parts.next().unwrap_or(""));
+
+    match status_line.split_whitespace().nth(1) {
+        Some("200") => Ok(()),
+        _ => Err(format!("Not ready: {}", status_line)),
+    }
+}
+
+fn check_unix_socket(path: &Path, config: &Config) -> Result<String, String> {
+    let mut stream = UnixStream::connect(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    stream
+        .set_read_timeout(Some(CHECK_TIMEOUT))
+        .map_err(|e| format!("{}", e))?;
+
+    request_over(&mut stream, &readyz_request(config, "localhost"))
+}
+
+fn check_tcp(config: &Config) -> Result<String, String> {
+    let host = match config.listen.host.as_str() {
+        "0.0.0.0" => "127.0.0.1",
+        "::" => "::1",
+        host => host,
+    };
+
+    let stream = TcpStream::connect((host, config.listen.port)).map_err(|e| format!("{}", e))?;
+    stream
+        .set_read_timeout(Some(CHECK_TIMEOUT))
+        .map_err(|e| format!("{}", e))?;
+
+    let request = readyz_request(config, host);
+
+    if config.tls.enabled {
+        /* The server is checked on its own address, which its certificate is unlikely to cover. */
+        let mut connector =
+            SslConnector::builder(SslMethod::tls()).map_err(|e| format!("{}", e))?;
+        connector.set_verify(SslVerifyMode::NONE);
+
+        let mut stream = connector
+            .build()
+            .connect(host, stream)
+            .map_err(|e| format!("{}", e))?;
+
+        request_over(&mut stream, &request)
+    } else {
+        let mut stream = stream;
+        request_over(&mut stream, &request)
+    }
+}
+
+fn readyz_request(config: &Config, host: &str) -> String {
+    format!(
+        "GET {}/readyz HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        config.listen.base_path(),
+        host
+    )
+}
+
+fn request_over<S: Read + Write>(stream: &mut S, request: &str) -> Result<String, String> {
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| format!("{}", e))?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| format!("{}", e))?;
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hides_errors() {
+        let checks = Checks {
+            database: Check::failed("/var/lib/pragma: Permission denied".to_string()),
+            migrations: Check::skipped(),
+            disk: Check::ok(),
+        };
+
+        assert_eq!(
+            serde_json::to_string(&checks).unwrap(),
+            r#"{"database":{"status":"failed"},"migrations":{"status":"skipped"},"disk":{"status":"ok"}}"#
+        );
+    }
+
+    #[test]
+    fn caches_disk_check() {
+        let directory = std::env::temp_dir().join(format!("pragma-disk-{}", std::process::id()));
+        let database_path = directory.join("pragma.db");
+        let disk_check = DiskCheck::default();
+        let now = Instant::now();
+
+        assert!(disk_check.check(&database_path, now).is_err());
+
+        fs::create_dir_all(&directory).unwrap();
+        assert!(disk_check
+            .check(&database_path, now + Duration::from_secs(1))
+            .is_err());
+        assert!(disk_check
+            .check(&database_path, now + DISK_CHECK_INTERVAL)
+            .is_ok());
+        assert!(!directory.join(WRITE_CHECK_FILE).exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -12,83 +12,291 @@ extern crate diesel_migrations;
 #[macro_use]
 extern crate rust_embed;
 
-#[cfg(feature = "embedded_assets")]
-pub mod assets;
-
 // </EMBEDDED ASSETS>
 
 mod actix_state;
 mod api;
+pub mod assets;
+mod audit;
 pub mod auth;
 mod build_info;
+mod client_cert;
+pub mod config;
+mod cors;
 mod data;
+mod diagram;
+pub mod health;
+pub mod logging;
+mod maintenance;
+mod markdown;
+mod math;
+mod metrics;
+mod proxy;
+mod rate_limit;
+mod render;
 mod repo;
 mod repo_actor;
 mod repo_connection;
 mod repo_id;
+mod sanitize;
 mod schema;
+mod security_headers;
+mod signals;
+mod systemd;
+mod table;
+mod tls;
+pub mod tokens;
+mod totp;
+pub mod two_factor;
+mod unix_socket;
 
 use self::actix_state::State;
+pub use self::config::Config;
 use ::actix::{prelude::*, SystemRunner};
-use actix_web::{server, App};
+use actix_web::http::header;
+use actix_web::http::Method;
+use actix_web::{server, App, HttpResponse};
 use listenfd::ListenFd;
-use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod};
-use std::env;
-
-pub struct Config {
-    pub port: String,
-    pub auth_token: String,
-    pub database_url: String,
-}
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub fn build(config: Config) -> SystemRunner {
-    let pool = init_repo(&config.database_url);
+    let pool = init_repo(&config.database.url);
 
-    let port = config.port;
-    let auth_token = config.auth_token;
+    let auth_settings = auth::Settings::from_config(&config);
+    let rate_limiter = Arc::new(rate_limit::RateLimiter::new(&config.rate_limit));
+    let trusted_proxies = Arc::new(
+        config
+            .proxy
+            .trusted_proxies()
+            .expect("Invalid trusted proxy addresses."),
+    );
+
+    if config.auth.token.is_none() && config.auth.username.is_none() {
+        let connection = pool.get().unwrap();
+        tokens::ensure_initial_token(&connection).expect("Failed to create initial token.");
+    }
+
+    let shared = Shared {
+        pool: pool.clone(),
+        auth_settings,
+        cors_settings: cors::Settings::from_config(&config.cors),
+        rate_limiter,
+        trusted_proxies,
+        metrics: Arc::new(metrics::Metrics::new(&config)),
+        database_path: PathBuf::from(&config.database.url),
+        in_flight: signals::InFlightRequests::default(),
+        security_headers: security_headers::SecurityHeaders::from_config(&config),
+        base_path: config.listen.base_path().to_string(),
+        assets_directory: config.assets.directory.clone(),
+        svg_cache: Arc::new(diagram::SvgCache::default()),
+    };
 
     let sys = actix::System::new("pragma");
-    let mut server =
-        server::HttpServer::new(move || build_actix_app(pool.clone(), auth_token.clone()));
+
+    start_maintenance(&config, pool.clone());
+
+    // Metrics are served on the main listener only if they don't have their own.
+    let serve_metrics = config.metrics.token.is_some() && config.metrics.listen.is_none();
+    let app_shared = shared.clone();
+    let server = server::HttpServer::new(move || build_actix_app(&app_shared, serve_metrics))
+        .disable_signals();
 
     let mut listenfd = ListenFd::from_env();
+    let tls_acceptor = if config.tls.enabled {
+        Some(tls::ReloadableAcceptor::new(&config.tls).unwrap_or_else(|e| panic!("{}", e)))
+    } else {
+        None
+    };
+
+    let mut servers = vec![];
+    let mut unix_socket = None;
 
-    server = if let Ok(Some(listener)) = listenfd.take_tcp_listener(0) {
-        match env::var("SSL") {
-            Ok(_) => server.listen_ssl(listener, ssl_acceptor()).unwrap(),
-            Err(_) => server.listen(listener),
-        }
+    if let Ok(Some(listener)) = listenfd.take_tcp_listener(0) {
+        servers.push(
+            match tls_acceptor {
+                Some(ref acceptor) => {
+                    server.listen_with(listener, client_cert::acceptor(acceptor.clone()))
+                }
+                None => server.listen(listener),
+            }
+            .start(),
+        );
+    } else if let Ok(Some(listener)) = listenfd.take_unix_listener(0) {
+        unix_socket = Some(
+            unix_socket::UnixSocket::start(server, listener, None)
+                .unwrap_or_else(|e| panic!("Can not listen on socket: {}", e)),
+        );
+    } else if let Some(ref path) = config.listen.unix_socket {
+        let mode = config.listen.unix_socket_mode().unwrap();
+        let listener = unix_socket::bind(path, mode)
+            .unwrap_or_else(|e| panic!("Can not bind to socket: {}", e));
+
+        unix_socket = Some(
+            unix_socket::UnixSocket::start(server, listener, Some(path.clone()))
+                .unwrap_or_else(|e| panic!("Can not listen on socket: {}", e)),
+        );
     } else {
-        let host = host();
+        let address = format!("{}:{}", config.listen.host, config.listen.port);
 
-        match env::var("SSL") {
-            Ok(_) => server.bind_ssl(format!("{}:{}", host, port), ssl_acceptor()),
-            Err(_) => server.bind(format!("{}:{}", host, port)),
-        }
-        .unwrap_or_else(|_| panic!("Can not bind to {}:{}", host, port))
-    };
+        servers.push(
+            match tls_acceptor {
+                Some(ref acceptor) => {
+                    server.bind_with(&address, client_cert::acceptor(acceptor.clone()))
+                }
+                None => server.bind(&address),
+            }
+            .unwrap_or_else(|_| panic!("Can not bind to {}", address))
+            .start(),
+        );
+    }
+
+    let in_flight = shared.in_flight.clone();
+
+    if let Some(ref address) = config.metrics.listen {
+        let metrics_shared = shared.clone();
+        servers.push(
+            server::HttpServer::new(move || build_metrics_app(&metrics_shared))
+                .disable_signals()
+                .bind(address)
+                .unwrap_or_else(|_| panic!("Can not bind metrics listener to {}", address))
+                .start(),
+        );
+    }
+
+    if systemd::enabled() {
+        start_watchdog(&shared);
+        systemd::notify("READY=1");
+    }
+
+    let certificates = tls_acceptor.map(|acceptor| tls::CertificateWatcher::new(acceptor).start());
+    let shutdown_timeout = Duration::from_secs(u64::from(config.listen.shutdown_timeout_seconds));
+    signals::SignalHandler::new(
+        servers,
+        unix_socket,
+        in_flight,
+        shutdown_timeout,
+        pool,
+        certificates,
+    )
+    .start();
 
-    server.start();
     sys
 }
 
-fn build_actix_app(pool: repo_connection::Pool, auth_token: String) -> App<State> {
+/* Everything the apps of all workers share. */
+#[derive(Clone)]
+struct Shared {
+    pool: repo_connection::Pool,
+    auth_settings: auth::Settings,
+    cors_settings: cors::Settings,
+    rate_limiter: Arc<rate_limit::RateLimiter>,
+    trusted_proxies: Arc<proxy::TrustedProxies>,
+    metrics: Arc<metrics::Metrics>,
+    database_path: PathBuf,
+    in_flight: signals::InFlightRequests,
+    security_headers: security_headers::SecurityHeaders,
+    base_path: String,
+    assets_directory: Option<PathBuf>,
+    svg_cache: Arc<diagram::SvgCache>,
+}
+
+fn build_state(shared: &Shared) -> State {
+    let pool = shared.pool.clone();
     let addr = SyncArbiter::start(1, move || repo_actor::DbExecutor(pool.clone()));
 
-    let mut app = App::with_state(State { db: addr.clone() });
+    State {
+        db: repo_actor::Db {
+            addr,
+            metrics: shared.metrics.clone(),
+            request_id: None,
+        },
+        auth: shared.auth_settings.clone(),
+        cors: shared.cors_settings.clone(),
+        rate_limiter: shared.rate_limiter.clone(),
+        trusted_proxies: shared.trusted_proxies.clone(),
+        metrics: shared.metrics.clone(),
+        database_path: shared.database_path.clone(),
+        assets_directory: shared.assets_directory.clone(),
+        base_path: shared.base_path.clone(),
+        svg_cache: shared.svg_cache.clone(),
+    }
+}
+
+fn build_actix_app(shared: &Shared, serve_metrics: bool) -> App<State> {
+    let mut app = App::with_state(build_state(shared));
+
+    // The web app uses relative URLs, so `/notes` has to become `/notes/`.
+    if !shared.base_path.is_empty() {
+        let location = format!("{}/", shared.base_path);
+        app = app
+            .prefix(shared.base_path.as_str())
+            .resource("", move |r| {
+                r.f(move |_| {
+                    HttpResponse::MovedPermanently()
+                        .header(header::LOCATION, location.as_str())
+                        .finish()
+                })
+            });
+    }
+
+    app = app
+        .middleware(shared.in_flight.clone())
+        .middleware(shared.security_headers.clone())
+        .middleware(logging::middleware())
+        .middleware(metrics::middleware());
+
+    if serve_metrics {
+        app = app.route("/metrics", Method::GET, metrics::metrics);
+    }
+
+    app = app.route("/healthz", Method::GET, health::healthz).route(
+        "/readyz",
+        Method::GET,
+        health::readyz,
+    );
 
-    app = api::mount(app, auth_token);
-    maybe_serve_embedded_assets(app)
+    app = api::mount(app);
+    maybe_serve_assets(app, shared)
 }
 
-#[cfg(not(feature = "embedded_assets"))]
-fn maybe_serve_embedded_assets(app: App<State>) -> App<State> {
-    app
+fn build_metrics_app(shared: &Shared) -> App<State> {
+    App::with_state(build_state(shared))
+        .middleware(shared.security_headers.clone())
+        .route("/metrics", Method::GET, metrics::metrics)
 }
 
-#[cfg(feature = "embedded_assets")]
-fn maybe_serve_embedded_assets(app: App<State>) -> App<State> {
-    assets::mount(app)
+fn start_watchdog(shared: &Shared) {
+    let pool = shared.pool.clone();
+    let db = repo_actor::Db {
+        addr: SyncArbiter::start(1, move || repo_actor::DbExecutor(pool.clone())),
+        metrics: shared.metrics.clone(),
+        request_id: None,
+    };
+
+    systemd::Watchdog::new(db, shared.metrics.clone(), shared.in_flight.clone()).start();
+}
+
+fn start_maintenance(config: &Config, pool: repo_connection::Pool) {
+    let db = SyncArbiter::start(1, move || repo_actor::DbExecutor(pool.clone()));
+
+    maintenance::Maintenance {
+        db,
+        database_path: PathBuf::from(&config.database.url),
+        backup: config.backup.clone(),
+        retention: config.retention.clone(),
+    }
+    .start();
+}
+
+/* The web app is served if it's embedded, or from the assets directory. */
+fn maybe_serve_assets(app: App<State>, shared: &Shared) -> App<State> {
+    if cfg!(feature = "embedded_assets") || shared.assets_directory.is_some() {
+        assets::mount(app)
+    } else {
+        app
+    }
 }
 
 fn init_repo(database_url: &str) -> repo_connection::Pool {
@@ -99,20 +307,3 @@ fn init_repo(database_url: &str) -> repo_connection::Pool {
 
     pool
 }
-
-fn ssl_acceptor() -> SslAcceptorBuilder {
-    let key_file = env::var("SSL_KEY").expect("Missing SSL_KEY environment variable.");
-    let cert_file = env::var("SSL_CERT").expect("Missing SSL_CERT environment variable");
-
-    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-    builder
-        .set_private_key_file(key_file, SslFiletype::PEM)
-        .unwrap();
-    builder.set_certificate_chain_file(cert_file).unwrap();
-
-    builder
-}
-
-fn host() -> String {
-    env::var("LISTEN_HOST").unwrap_or_else(|_| "127.0.0.1".to_string())
-}
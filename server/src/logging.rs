@@ -0,0 +1,177 @@
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::{Middleware, Response, Started};
+use actix_web::{Body, HttpRequest, HttpResponse, Result};
+use env_logger::fmt::Formatter;
+use log::{error, info, Record};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use super::actix_state::State;
+use super::config::LoggingConfig;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+thread_local! {
+    /* The request a log line belongs to, set while logging on behalf of that request. */
+    static CURRENT_REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/* Configures `env_logger` for both binaries, with either the default plain text format
+or one JSON object per line. */
+pub fn init(config: &LoggingConfig) {
+    let mut builder = env_logger::Builder::from_default_env();
+    builder.filter_level(config.level_filter());
+
+    if config.json() {
+        builder.format(format_json);
+    } else {
+        builder.format(format_text);
+    }
+
+    builder.init();
+}
+
+/* Like env_logger's default format, with the request ID in front of the message. */
+fn format_text(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let level_style = buf.default_level_style(record.level());
+    write!(
+        buf,
+        "{:>5} {}: ",
+        level_style.value(record.level()),
+        buf.timestamp()
+    )?;
+
+    if let Some(module_path) = record.module_path() {
+        write!(buf, "{}: ", module_path)?;
+    }
+
+    match current_request_id() {
+        Some(request_id) => writeln!(buf, "[{}] {}", request_id, record.args()),
+        None => writeln!(buf, "{}", record.args()),
+    }
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    timestamp: String,
+    level: String,
+    target: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+fn format_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let line = JsonLine {
+        timestamp: buf.timestamp().to_string(),
+        level: record.level().to_string(),
+        target: record.target(),
+        message: record.args().to_string(),
+        request_id: current_request_id(),
+    };
+
+    let json = serde_json::to_string(&line).map_err(io::Error::other)?;
+    writeln!(buf, "{}", json)
+}
+
+fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.with(|current| current.borrow().clone())
+}
+
+/* Attaches the request ID to everything logged by `f`. */
+pub fn with_request_id<T, F: FnOnce() -> T>(request_id: &str, f: F) -> T {
+    CURRENT_REQUEST_ID.with(|current| *current.borrow_mut() = Some(request_id.to_string()));
+    let result = f();
+    CURRENT_REQUEST_ID.with(|current| *current.borrow_mut() = None);
+    result
+}
+
+/* The ID of the request, from its `X-Request-Id` header or generated. */
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+struct RequestStart(Instant);
+
+pub struct RequestLogger;
+
+pub fn middleware() -> RequestLogger {
+    RequestLogger
+}
+
+/* Incoming IDs end up in log lines and response headers, so only plain ones are accepted. */
+fn is_valid_request_id(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LENGTH
+        && request_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:~".contains(c))
+}
+
+impl Middleware<State> for RequestLogger {
+    fn start(&self, req: &HttpRequest<State>) -> Result<Started> {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|request_id| is_valid_request_id(request_id))
+            .map(String::from)
+            .unwrap_or_else(nanoid::simple);
+
+        req.extensions_mut().insert(RequestId(request_id));
+        req.extensions_mut().insert(RequestStart(Instant::now()));
+
+        Ok(Started::Done)
+    }
+
+    fn response(&self, req: &HttpRequest<State>, mut resp: HttpResponse) -> Result<Response> {
+        let request_id = req.extensions().get::<RequestId>().cloned();
+        let start = req.extensions_mut().remove::<RequestStart>();
+
+        if let (Some(RequestId(request_id)), Some(RequestStart(start))) = (request_id, start) {
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                resp.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+
+            let elapsed = start.elapsed();
+            let millis = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+
+            with_request_id(&request_id, || {
+                if resp.status().is_server_error() {
+                    error!(
+                        "{} {} {} {}ms: {}",
+                        req.method(),
+                        req.path(),
+                        resp.status().as_u16(),
+                        millis,
+                        error_reason(&resp)
+                    );
+                } else {
+                    info!(
+                        "{} {} {} {}ms",
+                        req.method(),
+                        req.path(),
+                        resp.status().as_u16(),
+                        millis
+                    );
+                }
+            });
+        }
+
+        Ok(Response::Done(resp))
+    }
+}
+
+/* Handlers respond to failed `repo` calls with the error as the body. */
+fn error_reason(resp: &HttpResponse) -> String {
+    if let Some(error) = resp.error() {
+        return format!("{}", error);
+    }
+
+    match resp.body() {
+        Body::Binary(binary) => String::from_utf8_lossy(binary.as_ref()).into_owned(),
+        _ => "(no details)".to_string(),
+    }
+}
@@ -0,0 +1,92 @@
+use super::config::{BackupConfig, RetentionConfig};
+use super::repo_actor::{BackupMessage, DbExecutor, PruneAuditLogMessage, PruneDeletionsMessage};
+use ::actix::prelude::*;
+use chrono::{Duration as ChronoDuration, Utc};
+use futures::future::Future;
+use log::{error, info};
+use std::path::PathBuf;
+use std::time::Duration;
+
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/* Periodically runs database backups and prunes old records, as configured. */
+pub struct Maintenance {
+    pub db: Addr<DbExecutor>,
+    pub database_path: PathBuf,
+    pub backup: BackupConfig,
+    pub retention: RetentionConfig,
+}
+
+impl Actor for Maintenance {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if self.backup.directory.is_some() {
+            let interval = Duration::from_secs(self.backup.interval_hours * 60 * 60);
+            ctx.run_interval(interval, |act, _ctx| act.backup());
+        }
+
+        if self.retention.deletions_days.is_some() || self.retention.audit_days.is_some() {
+            self.prune();
+            ctx.run_interval(RETENTION_INTERVAL, |act, _ctx| act.prune());
+        }
+    }
+}
+
+impl Maintenance {
+    fn backup(&self) {
+        let directory = match self.backup.directory {
+            Some(ref directory) => directory.clone(),
+            None => return,
+        };
+
+        let message = BackupMessage {
+            database_path: self.database_path.clone(),
+            directory,
+            keep: self.backup.keep,
+        };
+
+        Arbiter::spawn(self.db.send(message).then(|res| {
+            match res {
+                Ok(Ok(path)) => info!("Wrote database backup to {}", path.display()),
+                Ok(Err(reason)) => error!("Database backup failed: {}", reason),
+                Err(e) => error!("Database backup failed: {}", e),
+            }
+
+            Ok(())
+        }));
+    }
+
+    fn prune(&self) {
+        if let Some(days) = self.retention.deletions_days {
+            let message = PruneDeletionsMessage {
+                older_than: Utc::now() - ChronoDuration::days(i64::from(days)),
+            };
+
+            Arbiter::spawn(self.db.send(message).then(|res| {
+                log_pruned("deletion records", res);
+                Ok(())
+            }));
+        }
+
+        if let Some(days) = self.retention.audit_days {
+            let message = PruneAuditLogMessage {
+                older_than: Utc::now() - ChronoDuration::days(i64::from(days)),
+            };
+
+            Arbiter::spawn(self.db.send(message).then(|res| {
+                log_pruned("audit log entries", res);
+                Ok(())
+            }));
+        }
+    }
+}
+
+fn log_pruned(records: &str, res: Result<Result<usize, String>, MailboxError>) {
+    match res {
+        Ok(Ok(count)) if count > 0 => info!("Pruned {} {}", count, records),
+        Ok(Ok(_)) => {}
+        Ok(Err(reason)) => error!("Pruning {} failed: {}", records, reason),
+        Err(e) => error!("Pruning {} failed: {}", records, e),
+    }
+}
@@ -0,0 +1,2234 @@
+/* Renders Markdown content blocks to HTML.
+
+Covers CommonMark's headings, paragraphs, block quotes, lists, code blocks, thematic breaks,
+links, images, emphasis and inline HTML, plus GitHub Flavored Markdown's tables, task lists
+and strikethrough. Block-level HTML is treated as a paragraph. The output is sanitized, so raw
+HTML in the source is cleaned like text blocks.
+
+The tests check the examples from the CommonMark spec that apply. Known differences are that
+character references are left for the browser to decode and URLs aren't percent-encoded, link
+text can contain links, a link reference definition's label, destination and title can't span
+lines, labels are matched by lowercase instead of case folding, and a lazy continuation line
+can be a setext underline. */
+
+use super::sanitize;
+use std::collections::HashMap;
+
+/* Block quotes and lists nested deeper than this are rejected, which keeps parsing from
+exhausting the stack. */
+const MAX_NESTING: usize = 32;
+
+/* Link text can contain images, but brackets nested deeper than this are plain text. */
+const MAX_INLINE_NESTING: usize = 8;
+
+/* Like cmark, parentheses in link destinations nest at most this deep. */
+const MAX_DESTINATION_NESTING: usize = 32;
+
+/* Like CommonMark, longer link labels are never references. Otherwise every `[` would
+normalize the rest of the text to look it up. */
+const MAX_LABEL_LENGTH: usize = 999;
+
+/* Longer autolinks and inline tags are text, so every `<` only looks ahead this far. */
+const MAX_INLINE_HTML_LENGTH: usize = 2048;
+
+enum Block {
+    Heading(usize, String),
+    Paragraph(String),
+    Code(String, String),
+    Quote(Vec<Block>),
+    List(List),
+    Rule,
+    Table(Table),
+}
+
+struct List {
+    /* The start number of an ordered list. */
+    start: Option<u64>,
+    tight: bool,
+    items: Vec<ListItem>,
+}
+
+struct ListItem {
+    /* Set for task list items. */
+    checked: Option<bool>,
+    blocks: Vec<Block>,
+}
+
+struct Table {
+    alignments: Vec<Option<&'static str>>,
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+/* Link reference definitions, by normalized label. */
+type References = HashMap<String, Link>;
+
+#[derive(Clone)]
+struct Link {
+    destination: String,
+    title: Option<String>,
+}
+
+/* Follows the lines of a block quote or list item to tell whether they end in a paragraph,
+which only then takes lazy continuation lines. Looks through nested quote and list markers. */
+#[derive(Default)]
+struct OpenParagraph {
+    fence: Option<(char, usize)>,
+    open: bool,
+}
+
+impl OpenParagraph {
+    fn push(&mut self, line: &str) {
+        if let Some(fence) = self.fence {
+            if is_closing_fence(line, fence) {
+                self.fence = None;
+            }
+            return;
+        }
+
+        let mut rest = line;
+        loop {
+            let indent = indentation(rest);
+            if is_blank(rest) {
+                self.open = false;
+                return;
+            }
+            if indent >= 4 {
+                // Indented code, unless it continues a paragraph.
+                return;
+            }
+            rest = &rest[indent..];
+            if let Some(content) = rest.strip_prefix('>') {
+                rest = content.strip_prefix(' ').unwrap_or(content);
+            } else if let Some(marker) = list_marker(rest) {
+                rest = rest.get(marker.width..).unwrap_or("");
+            } else {
+                break;
+            }
+        }
+
+        if let Some((fence, _)) = opening_fence(rest) {
+            self.fence = Some(fence);
+            self.open = false;
+        } else {
+            self.open = atx_heading(rest).is_none() && !is_thematic_break(rest);
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.open && self.fence.is_none()
+    }
+}
+
+struct ListMarker {
+    bullet: Option<char>,
+    number: u64,
+    delimiter: char,
+    /* Width of the marker and the spaces after it. */
+    width: usize,
+}
+
+pub fn validate(markdown: &str) -> Result<(), String> {
+    parse(markdown).map(|_| ())
+}
+
+pub fn to_html(markdown: &str) -> Result<String, String> {
+    let (blocks, references) = parse(markdown)?;
+
+    let mut html = String::new();
+    render_blocks(&blocks, &references, false, &mut html);
+
+    Ok(sanitize::sanitize_markdown_html(&html))
+}
+
+fn parse(markdown: &str) -> Result<(Vec<Block>, References), String> {
+    let lines: Vec<String> = markdown
+        .split('\n')
+        .map(|line| expand_indentation(line.trim_end_matches('\r')))
+        .collect();
+
+    let mut references = References::new();
+    let (blocks, _) = parse_blocks(&lines, 0, &mut references)?;
+
+    Ok((blocks, references))
+}
+
+/* Returns the blocks and whether any of them are separated by blank lines. */
+fn parse_blocks(
+    lines: &[String],
+    depth: usize,
+    references: &mut References,
+) -> Result<(Vec<Block>, bool), String> {
+    if depth > MAX_NESTING {
+        return Err(format!(
+            "Block quotes and lists can be nested at most {} levels deep",
+            MAX_NESTING
+        ));
+    }
+
+    let mut blocks = vec![];
+    let mut separated = false;
+    let mut after_blank = false;
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = &lines[i];
+        if is_blank(line) {
+            after_blank = true;
+            i += 1;
+            continue;
+        }
+        if after_blank && !blocks.is_empty() {
+            separated = true;
+        }
+        after_blank = false;
+
+        let indent = indentation(line);
+        if indent >= 4 {
+            let mut code_lines = vec![];
+            while i < lines.len() && (is_blank(&lines[i]) || indentation(&lines[i]) >= 4) {
+                code_lines.push(strip_indentation(&lines[i], 4));
+                i += 1;
+            }
+            while code_lines.last().is_some_and(|line| is_blank(line)) {
+                code_lines.pop();
+            }
+            blocks.push(Block::Code(String::new(), join_code_lines(&code_lines)));
+            continue;
+        }
+
+        let rest = &line[indent..];
+
+        if let Some((fence, info)) = opening_fence(rest) {
+            let mut code_lines = vec![];
+            i += 1;
+            while i < lines.len() {
+                if is_closing_fence(&lines[i], fence) {
+                    i += 1;
+                    break;
+                }
+                code_lines.push(strip_indentation(&lines[i], indent));
+                i += 1;
+            }
+            blocks.push(Block::Code(info, join_code_lines(&code_lines)));
+            continue;
+        }
+
+        if let Some((level, text)) = atx_heading(rest) {
+            blocks.push(Block::Heading(level, text));
+            i += 1;
+            continue;
+        }
+
+        if is_thematic_break(rest) {
+            blocks.push(Block::Rule);
+            i += 1;
+            continue;
+        }
+
+        if rest.starts_with('>') {
+            let mut quote_lines: Vec<String> = vec![];
+            let mut paragraph = OpenParagraph::default();
+            while i < lines.len() {
+                let line = &lines[i];
+                let indent = indentation(line);
+                if indent < 4 && line[indent..].starts_with('>') {
+                    let content = &line[indent + 1..];
+                    let content = content.strip_prefix(' ').unwrap_or(content);
+                    paragraph.push(content);
+                    quote_lines.push(content.to_string());
+                } else if !is_blank(line) && paragraph.is_open() && !starts_block(line) {
+                    // A lazy continuation line of a paragraph in the quote. It keeps its
+                    // indentation, so an indented marker doesn't start a block in the quote.
+                    quote_lines.push(line.to_string());
+                } else {
+                    break;
+                }
+                i += 1;
+            }
+
+            let (quote_blocks, _) = parse_blocks(&quote_lines, depth + 1, references)?;
+            blocks.push(Block::Quote(quote_blocks));
+            continue;
+        }
+
+        if list_marker(rest).is_some() {
+            let (list, next) = parse_list(lines, i, depth, references)?;
+            blocks.push(Block::List(list));
+            i = next;
+            continue;
+        }
+
+        if let Some((table, next)) = parse_table(lines, i) {
+            blocks.push(Block::Table(table));
+            i = next;
+            continue;
+        }
+
+        let mut paragraph_lines = vec![rest.to_string()];
+        i += 1;
+        let mut heading_level = None;
+        while i < lines.len() && !is_blank(&lines[i]) {
+            let line = &lines[i];
+            if let Some(level) = setext_underline(line) {
+                heading_level = Some(level);
+                i += 1;
+                break;
+            }
+            if starts_block(line) {
+                break;
+            }
+            paragraph_lines.push(line.trim_start().to_string());
+            i += 1;
+        }
+
+        let text = paragraph_lines.join("\n");
+        let text = parse_reference_definitions(&text, references);
+        let text = text.trim_end();
+
+        match heading_level {
+            Some(level) if !text.is_empty() => blocks.push(Block::Heading(level, text.into())),
+            _ if !text.is_empty() => blocks.push(Block::Paragraph(text.into())),
+            _ => (),
+        }
+    }
+
+    Ok((blocks, separated))
+}
+
+fn parse_list(
+    lines: &[String],
+    start: usize,
+    depth: usize,
+    references: &mut References,
+) -> Result<(List, usize), String> {
+    let first_indent = indentation(&lines[start]);
+    let first_marker = list_marker(&lines[start][first_indent..]).unwrap();
+
+    let mut items = vec![];
+    let mut tight = true;
+    let mut i = start;
+
+    loop {
+        let indent = indentation(&lines[i]);
+        let marker = list_marker(&lines[i][indent..]).unwrap();
+        let width = indent + marker.width;
+
+        let first_line = lines[i].get(width..).unwrap_or("").to_string();
+        let mut paragraph = OpenParagraph::default();
+        paragraph.push(&first_line);
+        let mut item_lines = vec![first_line];
+        i += 1;
+
+        while i < lines.len() {
+            let line = &lines[i];
+            if is_blank(line) {
+                paragraph.push("");
+                item_lines.push(String::new());
+            } else if item_lines.len() > 1 && item_lines.iter().all(|line| is_blank(line)) {
+                // An item can start with at most one blank line.
+                break;
+            } else if indentation(line) >= width {
+                paragraph.push(&line[width..]);
+                item_lines.push(line[width..].to_string());
+            } else if paragraph.is_open()
+                && !starts_block(line)
+                && (indentation(line) >= 4 || list_marker(line.trim_start()).is_none())
+            {
+                // A lazy continuation line of a paragraph in the item.
+                item_lines.push(line.to_string());
+            } else {
+                break;
+            }
+            i += 1;
+        }
+
+        let mut trailing_blank_lines = 0;
+        while item_lines.len() > 1 && item_lines.last().is_some_and(|line| is_blank(line)) {
+            item_lines.pop();
+            trailing_blank_lines += 1;
+        }
+
+        let checked = task_marker(&item_lines[0]);
+        if checked.is_some() {
+            item_lines[0] = item_lines[0][3..].trim_start().to_string();
+        }
+
+        let (blocks, separated) = parse_blocks(&item_lines, depth + 1, references)?;
+        if separated {
+            tight = false;
+        }
+        items.push(ListItem { checked, blocks });
+
+        let continues = i < lines.len() && {
+            let indent = indentation(&lines[i]);
+            let rest = &lines[i][indent..];
+            indent < 4
+                && !is_thematic_break(rest)
+                && list_marker(rest).is_some_and(|marker| {
+                    marker.bullet == first_marker.bullet
+                        && marker.delimiter == first_marker.delimiter
+                })
+        };
+        if !continues {
+            // Blank lines after the list separate it from the next block of the parent.
+            i -= trailing_blank_lines;
+            break;
+        }
+        if trailing_blank_lines > 0 {
+            tight = false;
+        }
+    }
+
+    let list = List {
+        start: match first_marker.bullet {
+            Some(_) => None,
+            None => Some(first_marker.number),
+        },
+        tight,
+        items,
+    };
+
+    Ok((list, i))
+}
+
+fn parse_table(lines: &[String], start: usize) -> Option<(Table, usize)> {
+    let header_line = &lines[start];
+    let delimiter_line = lines.get(start + 1)?;
+    if !header_line.contains('|') || indentation(delimiter_line) >= 4 {
+        return None;
+    }
+
+    let header = table_cells(header_line);
+    let alignments = table_alignments(delimiter_line)?;
+    if header.len() != alignments.len() {
+        return None;
+    }
+
+    let mut rows = vec![];
+    let mut i = start + 2;
+    while i < lines.len() && !is_blank(&lines[i]) && !starts_block(&lines[i]) {
+        let mut row = table_cells(&lines[i]);
+        row.resize(header.len(), String::new());
+        rows.push(row);
+        i += 1;
+    }
+
+    Some((
+        Table {
+            alignments,
+            header,
+            rows,
+        },
+        i,
+    ))
+}
+
+fn table_cells(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = match line.strip_suffix('|') {
+        Some(stripped) if !stripped.ends_with('\\') => stripped,
+        _ => line,
+    };
+
+    let mut cells = vec![];
+    let mut cell = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cell.push('|');
+                chars.next();
+            }
+            '|' => cells.push(std::mem::take(&mut cell).trim().to_string()),
+            _ => cell.push(c),
+        }
+    }
+    cells.push(cell.trim().to_string());
+
+    cells
+}
+
+fn table_alignments(line: &str) -> Option<Vec<Option<&'static str>>> {
+    if !line.contains('|') {
+        return None;
+    }
+
+    table_cells(line)
+        .iter()
+        .map(|cell| {
+            let left = cell.starts_with(':');
+            let right = cell.ends_with(':');
+            let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+
+            Some(match (left, right) {
+                (true, true) => Some("center"),
+                (true, false) => Some("left"),
+                (false, true) => Some("right"),
+                (false, false) => None,
+            })
+        })
+        .collect()
+}
+
+/* Consumes link reference definitions at the start of a paragraph and returns the rest. The
+destination and the title can each be on a line of their own. */
+fn parse_reference_definitions<'a>(text: &'a str, references: &mut References) -> &'a str {
+    let mut rest = text;
+
+    while rest.starts_with('[') {
+        let (line, mut next) = split_line(rest);
+
+        let label_end = match line.find("]:") {
+            Some(end) => end,
+            None => break,
+        };
+        let label = normalize_label(&line[1..label_end]);
+        if label.is_empty()
+            || line[1..label_end].contains(['[', ']'])
+            || is_too_long(&line[1..label_end])
+        {
+            break;
+        }
+
+        let mut definition = line[label_end + 2..].trim();
+        if definition.is_empty() {
+            let (line, after) = split_line(next);
+            definition = line.trim();
+            next = after;
+        }
+        let (destination, after) = match link_destination(definition) {
+            Some(parsed) => parsed,
+            None => break,
+        };
+        if !after.is_empty() && !after.starts_with(char::is_whitespace) {
+            break;
+        }
+
+        let after = after.trim();
+        let title = if after.is_empty() {
+            // A title on the next line is optional, that line is text if it isn't one.
+            let (line, after) = split_line(next);
+            match link_title(line.trim()) {
+                Some((title, "")) => {
+                    next = after;
+                    Some(title)
+                }
+                _ => None,
+            }
+        } else {
+            match link_title(after) {
+                Some((title, "")) => Some(title),
+                _ => break,
+            }
+        };
+
+        // The first definition of a label wins.
+        references
+            .entry(label)
+            .or_insert(Link { destination, title });
+
+        rest = next;
+    }
+
+    rest
+}
+
+/* The first line of the text and the text after it. */
+fn split_line(text: &str) -> (&str, &str) {
+    match text.find('\n') {
+        Some(end) => (&text[..end], &text[end + 1..]),
+        None => (text, ""),
+    }
+}
+
+fn render_blocks(blocks: &[Block], references: &References, tight: bool, html: &mut String) {
+    for (index, block) in blocks.iter().enumerate() {
+        match block {
+            Block::Heading(level, text) => {
+                html.push_str(&format!("<h{}>", level));
+                html.push_str(&render_inline(text, references, 0, false));
+                html.push_str(&format!("</h{}>\n", level));
+            }
+            Block::Paragraph(text) if tight => {
+                html.push_str(&render_inline(text, references, 0, false));
+                if index + 1 < blocks.len() {
+                    html.push('\n');
+                }
+            }
+            Block::Paragraph(text) => {
+                html.push_str("<p>");
+                html.push_str(&render_inline(text, references, 0, false));
+                html.push_str("</p>\n");
+            }
+            Block::Code(info, code) => {
+                match info.split_whitespace().next() {
+                    Some(language) => {
+                        html.push_str("<pre><code class=\"language-");
+                        html.push_str(&escape_html(language));
+                        html.push_str("\">");
+                    }
+                    None => html.push_str("<pre><code>"),
+                }
+                html.push_str(&escape_html(code));
+                html.push_str("</code></pre>\n");
+            }
+            Block::Quote(blocks) => {
+                html.push_str("<blockquote>\n");
+                render_blocks(blocks, references, false, html);
+                html.push_str("</blockquote>\n");
+            }
+            Block::List(list) => render_list(list, references, html),
+            Block::Rule => html.push_str("<hr />\n"),
+            Block::Table(table) => render_table(table, references, html),
+        }
+    }
+}
+
+fn render_list(list: &List, references: &References, html: &mut String) {
+    let tag = match list.start {
+        Some(1) => {
+            html.push_str("<ol>\n");
+            "ol"
+        }
+        Some(start) => {
+            html.push_str(&format!("<ol start=\"{}\">\n", start));
+            "ol"
+        }
+        None => {
+            html.push_str("<ul>\n");
+            "ul"
+        }
+    };
+
+    for item in &list.items {
+        let mut item_html = String::new();
+        render_blocks(&item.blocks, references, list.tight, &mut item_html);
+
+        if let Some(checked) = item.checked {
+            let checkbox = if checked {
+                "<input type=\"checkbox\" checked=\"\" disabled=\"\" /> "
+            } else {
+                "<input type=\"checkbox\" disabled=\"\" /> "
+            };
+            match item_html.strip_prefix("<p>") {
+                Some(paragraph) => item_html = format!("<p>{}{}", checkbox, paragraph),
+                None => item_html.insert_str(0, checkbox),
+            }
+        }
+
+        html.push_str("<li>");
+        let starts_with_text =
+            list.tight && matches!(item.blocks.first(), Some(Block::Paragraph(_)));
+        if !starts_with_text && !item_html.is_empty() {
+            html.push('\n');
+        }
+        html.push_str(&item_html);
+        html.push_str("</li>\n");
+    }
+
+    html.push_str(&format!("</{}>\n", tag));
+}
+
+fn render_table(table: &Table, references: &References, html: &mut String) {
+    let render_row = |html: &mut String, cells: &[String], tag: &str| {
+        html.push_str("<tr>\n");
+        for (cell, alignment) in cells.iter().zip(&table.alignments) {
+            match alignment {
+                Some(alignment) => html.push_str(&format!("<{} align=\"{}\">", tag, alignment)),
+                None => html.push_str(&format!("<{}>", tag)),
+            }
+            html.push_str(&render_inline(cell, references, 0, false));
+            html.push_str(&format!("</{}>\n", tag));
+        }
+        html.push_str("</tr>\n");
+    };
+
+    html.push_str("<table>\n<thead>\n");
+    render_row(html, &table.header, "th");
+    html.push_str("</thead>\n");
+
+    if !table.rows.is_empty() {
+        html.push_str("<tbody>\n");
+        for row in &table.rows {
+            render_row(html, row, "td");
+        }
+        html.push_str("</tbody>\n");
+    }
+
+    html.push_str("</table>\n");
+}
+
+enum Inline {
+    Html(String),
+    Delimiter(Delimiter),
+}
+
+/* A run of `*`, `_` or `~` that can open or close emphasis. Tags are added around the
+remaining characters as runs are matched. */
+struct Delimiter {
+    character: char,
+    length: usize,
+    remaining: usize,
+    can_open: bool,
+    can_close: bool,
+    before: String,
+    after: String,
+}
+
+fn render_inline(text: &str, references: &References, depth: usize, in_link: bool) -> String {
+    let mut inlines = vec![];
+    let mut buffer = String::new();
+    let code_spans = CodeSpans::new(text);
+    let brackets = matching_brackets(text, &code_spans);
+
+    let mut i = 0;
+    while i < text.len() {
+        let c = text[i..].chars().next().unwrap();
+
+        match c {
+            '\\' => match text[i + 1..].chars().next() {
+                Some('\n') => {
+                    flush(&mut buffer, &mut inlines);
+                    inlines.push(Inline::Html("<br />\n".into()));
+                    i = skip_spaces(text, i + 2);
+                    continue;
+                }
+                Some(escaped) if escaped.is_ascii_punctuation() => {
+                    push_escaped_char(&mut buffer, escaped);
+                    i += 2;
+                    continue;
+                }
+                _ => buffer.push('\\'),
+            },
+            '`' => {
+                let length = run_length(text, i, '`');
+                match code_spans.closing(i, length) {
+                    Some(end) => {
+                        flush(&mut buffer, &mut inlines);
+                        let code = code_span_content(&text[i + length..end]);
+                        inlines.push(Inline::Html(format!("<code>{}</code>", escape_html(&code))));
+                        i = end + length;
+                    }
+                    None => {
+                        buffer.push_str(&text[i..i + length]);
+                        i += length;
+                    }
+                }
+                continue;
+            }
+            '*' | '_' | '~' => {
+                let length = run_length(text, i, c);
+                if c == '~' && length > 2 {
+                    buffer.push_str(&text[i..i + length]);
+                } else {
+                    flush(&mut buffer, &mut inlines);
+                    inlines.push(Inline::Delimiter(delimiter(text, i, length, c)));
+                }
+                i += length;
+                continue;
+            }
+            '!' | '[' => {
+                let start = if c == '!' { i + 1 } else { i };
+                let is_image = c == '!';
+                let link = if text[start..].starts_with('[')
+                    && depth < MAX_INLINE_NESTING
+                    && (is_image || !in_link)
+                {
+                    brackets
+                        .get(&start)
+                        .and_then(|end| parse_link(text, start, *end, references))
+                } else {
+                    None
+                };
+
+                match link {
+                    Some((label, link, next)) => {
+                        flush(&mut buffer, &mut inlines);
+                        // Image descriptions can contain links, only their text is used.
+                        let content =
+                            render_inline(label, references, depth + 1, in_link || !is_image);
+                        inlines.push(Inline::Html(if is_image {
+                            image_html(&content, &link)
+                        } else {
+                            link_html(&content, &link)
+                        }));
+                        i = next;
+                        continue;
+                    }
+                    None => buffer.push(c),
+                }
+            }
+            '<' => match autolink_or_html(&text[i..]) {
+                Some((html, length)) => {
+                    flush(&mut buffer, &mut inlines);
+                    inlines.push(Inline::Html(html));
+                    i += length;
+                    continue;
+                }
+                None => buffer.push_str("&lt;"),
+            },
+            '&' => {
+                let length = entity_length(&text[i..]);
+                if length > 0 {
+                    buffer.push_str(&text[i..i + length]);
+                    i += length;
+                    continue;
+                }
+                buffer.push_str("&amp;");
+            }
+            '\n' => {
+                let spaces = buffer.len() - buffer.trim_end_matches(' ').len();
+                buffer.truncate(buffer.len() - spaces);
+                buffer.push_str(if spaces >= 2 { "<br />\n" } else { "\n" });
+                i = skip_spaces(text, i + 1);
+                continue;
+            }
+            _ => push_escaped_char(&mut buffer, c),
+        }
+
+        i += c.len_utf8();
+    }
+    flush(&mut buffer, &mut inlines);
+
+    process_emphasis(&mut inlines);
+
+    let mut html = String::new();
+    for inline in inlines {
+        match inline {
+            Inline::Html(fragment) => html.push_str(&fragment),
+            Inline::Delimiter(delimiter) => {
+                html.push_str(&delimiter.before);
+                for _ in 0..delimiter.remaining {
+                    html.push(delimiter.character);
+                }
+                html.push_str(&delimiter.after);
+            }
+        }
+    }
+
+    html
+}
+
+fn flush(buffer: &mut String, inlines: &mut Vec<Inline>) {
+    if !buffer.is_empty() {
+        inlines.push(Inline::Html(std::mem::take(buffer)));
+    }
+}
+
+fn delimiter(text: &str, start: usize, length: usize, character: char) -> Delimiter {
+    let before = text[..start].chars().next_back().unwrap_or(' ');
+    let after = text[start + length..].chars().next().unwrap_or(' ');
+
+    let is_punctuation =
+        |c: char| c.is_ascii_punctuation() || (!c.is_alphanumeric() && !c.is_whitespace());
+    let left_flanking = !after.is_whitespace()
+        && (!is_punctuation(after) || before.is_whitespace() || is_punctuation(before));
+    let right_flanking = !before.is_whitespace()
+        && (!is_punctuation(before) || after.is_whitespace() || is_punctuation(after));
+
+    let (can_open, can_close) = if character == '_' {
+        (
+            left_flanking && (!right_flanking || is_punctuation(before)),
+            right_flanking && (!left_flanking || is_punctuation(after)),
+        )
+    } else {
+        (left_flanking, right_flanking)
+    };
+
+    Delimiter {
+        character,
+        length,
+        remaining: length,
+        can_open,
+        can_close,
+        before: String::new(),
+        after: String::new(),
+    }
+}
+
+/* CommonMark's "process emphasis" algorithm: every closer is matched with the nearest
+compatible opener before it, and openers in between become plain text. */
+fn process_emphasis(inlines: &mut [Inline]) {
+    let mut openers: Vec<usize> = vec![];
+    // Openers before these positions were already found not to match a kind of closer.
+    let mut openers_bottom: HashMap<(char, bool, usize), usize> = HashMap::new();
+
+    for closer_index in 0..inlines.len() {
+        let (character, length, can_open, can_close) = match &inlines[closer_index] {
+            Inline::Delimiter(delimiter) => (
+                delimiter.character,
+                delimiter.length,
+                delimiter.can_open,
+                delimiter.can_close,
+            ),
+            Inline::Html(_) => continue,
+        };
+
+        let key = (character, can_open, length % 3);
+        while can_close && delimiter_at(inlines, closer_index).remaining > 0 {
+            let bottom = openers_bottom.get(&key).copied().unwrap_or(0);
+            let closer = delimiter_at(inlines, closer_index);
+
+            let mut opener_position = None;
+            for (position, opener_index) in openers.iter().enumerate().rev() {
+                if *opener_index < bottom {
+                    break;
+                }
+                if can_match(delimiter_at(inlines, *opener_index), closer) {
+                    opener_position = Some(position);
+                    break;
+                }
+            }
+
+            let opener_position = match opener_position {
+                Some(opener_position) => opener_position,
+                None => {
+                    openers_bottom.insert(key, closer_index);
+                    break;
+                }
+            };
+
+            let opener_index = openers[opener_position];
+            openers.truncate(opener_position + 1);
+
+            let opener_remaining = delimiter_at(inlines, opener_index).remaining;
+            let (used, tag) = match character {
+                '~' => (closer.remaining, "del"),
+                _ if closer.remaining >= 2 && opener_remaining >= 2 => (2, "strong"),
+                _ => (1, "em"),
+            };
+
+            if let Inline::Delimiter(opener) = &mut inlines[opener_index] {
+                opener.remaining -= used;
+                opener.after.insert_str(0, &format!("<{}>", tag));
+            }
+            if let Inline::Delimiter(closer) = &mut inlines[closer_index] {
+                closer.remaining -= used;
+                closer.before.push_str(&format!("</{}>", tag));
+            }
+
+            if opener_remaining == used {
+                openers.pop();
+            }
+        }
+
+        if can_open && delimiter_at(inlines, closer_index).remaining > 0 {
+            openers.push(closer_index);
+        }
+    }
+}
+
+/* Strikethrough needs runs of the same length. For emphasis, a run that can both open and
+close only matches if the lengths don't add up to a multiple of three, so `*a**b*` works. */
+fn can_match(opener: &Delimiter, closer: &Delimiter) -> bool {
+    opener.character == closer.character
+        && (opener.character != '~' || opener.remaining == closer.remaining)
+        && !((opener.can_close || closer.can_open)
+            && (opener.length + closer.length).is_multiple_of(3)
+            && !(opener.length.is_multiple_of(3) && closer.length.is_multiple_of(3)))
+}
+
+fn delimiter_at(inlines: &[Inline], index: usize) -> &Delimiter {
+    match &inlines[index] {
+        Inline::Delimiter(delimiter) => delimiter,
+        Inline::Html(_) => unreachable!(),
+    }
+}
+
+/* Positions of the closing bracket for each opening bracket, skipping escaped brackets. */
+fn matching_brackets(text: &str, code_spans: &CodeSpans) -> HashMap<usize, usize> {
+    let mut matching = HashMap::new();
+    let mut open = vec![];
+    let mut i = 0;
+
+    while i < text.len() {
+        let c = text[i..].chars().next().unwrap();
+        match c {
+            '\\' => {
+                i += 1 + text[i + 1..].chars().next().map_or(0, char::len_utf8);
+                continue;
+            }
+            // Code spans, autolinks and inline HTML bind tighter than links.
+            '`' => {
+                let length = run_length(text, i, '`');
+                i = code_spans.closing(i, length).unwrap_or(i) + length;
+                continue;
+            }
+            '<' => {
+                if let Some((_, length)) = autolink_or_html(&text[i..]) {
+                    i += length;
+                    continue;
+                }
+            }
+            '[' => open.push(i),
+            ']' => {
+                if let Some(start) = open.pop() {
+                    matching.insert(start, i);
+                }
+            }
+            _ => (),
+        }
+        i += c.len_utf8();
+    }
+
+    matching
+}
+
+/* Backtick runs by length, so finding the end of a code span doesn't rescan the text. */
+struct CodeSpans {
+    runs: HashMap<usize, Vec<usize>>,
+}
+
+impl CodeSpans {
+    fn new(text: &str) -> Self {
+        let mut runs: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut i = 0;
+        while let Some(offset) = text[i..].find('`') {
+            let start = i + offset;
+            let length = run_length(text, start, '`');
+            runs.entry(length).or_default().push(start);
+            i = start + length;
+        }
+
+        CodeSpans { runs }
+    }
+
+    fn closing(&self, start: usize, length: usize) -> Option<usize> {
+        let runs = self.runs.get(&length)?;
+        let next = match runs.binary_search(&start) {
+            Ok(position) => position + 1,
+            Err(position) => position,
+        };
+        runs.get(next).copied()
+    }
+}
+
+fn code_span_content(code: &str) -> String {
+    let code = code.replace('\n', " ");
+    if code.len() > 2 && code.starts_with(' ') && code.ends_with(' ') && code.trim() != "" {
+        code[1..code.len() - 1].to_string()
+    } else {
+        code
+    }
+}
+
+/* Parses `[label](destination "title")`, `[label][reference]`, `[label][]` and `[label]`
+starting at the opening bracket. Returns the label, the link and where parsing continues. */
+fn parse_link<'a>(
+    text: &'a str,
+    start: usize,
+    end: usize,
+    references: &References,
+) -> Option<(&'a str, Link, usize)> {
+    let label = &text[start + 1..end];
+    let rest = &text[end + 1..];
+
+    // Without a valid destination, `[label](...)` can still be a shortcut reference.
+    if let Some((link, after)) = rest.strip_prefix('(').and_then(inline_link) {
+        return Some((label, link, text.len() - after.len()));
+    }
+
+    if let Some(reference) = rest.strip_prefix('[') {
+        let reference_end = reference.find(']')?;
+        let reference_label = &reference[..reference_end];
+        let after = &reference[reference_end + 1..];
+        let key = if reference_label.is_empty() {
+            label
+        } else {
+            reference_label
+        };
+
+        if let Some(link) = find_reference(key, references) {
+            return Some((label, link.clone(), text.len() - after.len()));
+        }
+        if !reference_label.is_empty() {
+            return None;
+        }
+    }
+
+    find_reference(label, references).map(|link| (label, link.clone(), end + 1))
+}
+
+/* Parses `destination "title")` after the opening parenthesis of an inline link. */
+fn inline_link(text: &str) -> Option<(Link, &str)> {
+    let text = text.trim_start();
+    if let Some(after) = text.strip_prefix(')') {
+        let link = Link {
+            destination: String::new(),
+            title: None,
+        };
+        return Some((link, after));
+    }
+
+    let (destination, after) = link_destination(text)?;
+    let after_destination = after.trim_start();
+    let (title, after) = if after_destination.len() < after.len() {
+        match link_title(after_destination) {
+            Some((title, after)) => (Some(title), after.trim_start()),
+            None => (None, after_destination),
+        }
+    } else {
+        (None, after)
+    };
+
+    let after = after.strip_prefix(')')?;
+    Some((Link { destination, title }, after))
+}
+
+fn find_reference<'a>(label: &str, references: &'a References) -> Option<&'a Link> {
+    if is_too_long(label) {
+        return None;
+    }
+    references.get(&normalize_label(label))
+}
+
+fn is_too_long(label: &str) -> bool {
+    label.chars().nth(MAX_LABEL_LENGTH).is_some()
+}
+
+fn link_destination(text: &str) -> Option<(String, &str)> {
+    if let Some(bracketed) = text.strip_prefix('<') {
+        let mut escaped = false;
+        for (index, c) in bracketed.char_indices() {
+            match c {
+                '\n' => return None,
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '>' => return Some((unescape(&bracketed[..index]), &bracketed[index + 1..])),
+                '<' => return None,
+                _ => (),
+            }
+        }
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut escaped = false;
+    let mut end = text.len();
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '(' if depth == MAX_DESTINATION_NESTING => return None,
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                end = index;
+                break;
+            }
+            ')' => depth -= 1,
+            _ if c.is_whitespace() || c.is_control() => {
+                end = index;
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    if end == 0 || depth != 0 {
+        return None;
+    }
+    Some((unescape(&text[..end]), &text[end..]))
+}
+
+fn link_title(text: &str) -> Option<(String, &str)> {
+    let close = match text.chars().next()? {
+        '"' => '"',
+        '\'' => '\'',
+        '(' => ')',
+        _ => return None,
+    };
+
+    let mut escaped = false;
+    for (index, c) in text.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == close => return Some((unescape(&text[1..index]), &text[index + 1..])),
+            '(' if close == ')' => return None,
+            _ => (),
+        }
+    }
+
+    None
+}
+
+fn link_html(content: &str, link: &Link) -> String {
+    let mut html = format!("<a href=\"{}\"", escape_attribute(&link.destination));
+    if let Some(title) = &link.title {
+        html.push_str(&format!(" title=\"{}\"", escape_attribute(title)));
+    }
+    html.push('>');
+    html.push_str(content);
+    html.push_str("</a>");
+    html
+}
+
+fn image_html(content: &str, link: &Link) -> String {
+    let mut html = format!(
+        "<img src=\"{}\" alt=\"{}\"",
+        escape_attribute(&link.destination),
+        strip_tags(content).replace('"', "&quot;")
+    );
+    if let Some(title) = &link.title {
+        html.push_str(&format!(" title=\"{}\"", escape_attribute(title)));
+    }
+    html.push_str(" />");
+    html
+}
+
+/* Autolinks like `<https://example.com>` and inline HTML are passed through, the sanitizer
+cleans the HTML afterwards. */
+fn autolink_or_html(text: &str) -> Option<(String, usize)> {
+    if text.starts_with("<!--") {
+        let limit = text.len().min(MAX_INLINE_HTML_LENGTH);
+        let comment_end = text.as_bytes()[..limit]
+            .windows(3)
+            .position(|bytes| bytes == b"-->")?;
+        return Some((text[..comment_end + 3].to_string(), comment_end + 3));
+    }
+    if !text[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/') {
+        return None;
+    }
+
+    // Neither autolinks nor tags contain '<', so a later '<' starts the next candidate.
+    let end = text
+        .bytes()
+        .take(MAX_INLINE_HTML_LENGTH)
+        .skip(1)
+        .position(|byte| byte == b'>' || byte == b'<')
+        .map(|position| position + 1)
+        .filter(|end| text.as_bytes()[*end] == b'>')?;
+    let inner = &text[1..end];
+
+    let scheme_end = inner.find(':').unwrap_or(0);
+    let is_uri = (2..=32).contains(&scheme_end)
+        && inner.starts_with(|c: char| c.is_ascii_alphabetic())
+        && inner[..scheme_end]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '.' || c == '-')
+        && !inner.contains(|c: char| c.is_whitespace() || c.is_control());
+    if is_uri {
+        let link = Link {
+            destination: inner.to_string(),
+            title: None,
+        };
+        return Some((link_html(&escape_html(inner), &link), end + 1));
+    }
+
+    let is_email = inner.contains('@')
+        && !inner.starts_with('@')
+        && !inner.ends_with('@')
+        && inner
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".!#$%&'*+/=?^_`{|}~-@".contains(c));
+    if is_email {
+        let link = Link {
+            destination: format!("mailto:{}", inner),
+            title: None,
+        };
+        return Some((link_html(&escape_html(inner), &link), end + 1));
+    }
+
+    let tag = inner.strip_prefix('/').unwrap_or(inner);
+    let name_end = tag
+        .find(|c: char| c.is_whitespace() || c == '/')
+        .unwrap_or(tag.len());
+    let is_tag = name_end > 0
+        && tag.starts_with(|c: char| c.is_ascii_alphabetic())
+        && tag[..name_end]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if is_tag {
+        return Some((text[..end + 1].to_string(), end + 1));
+    }
+
+    None
+}
+
+fn opening_fence(line: &str) -> Option<((char, usize), String)> {
+    let character = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let length = run_length(line, 0, character);
+    if length < 3 {
+        return None;
+    }
+
+    let info = line[length..].trim();
+    if character == '`' && info.contains('`') {
+        return None;
+    }
+
+    Some(((character, length), unescape(info)))
+}
+
+fn is_closing_fence(line: &str, (character, length): (char, usize)) -> bool {
+    let indent = indentation(line);
+    if indent >= 4 {
+        return false;
+    }
+
+    let rest = &line[indent..];
+    let run = run_length(rest, 0, character);
+    run >= length && rest[run..].trim().is_empty()
+}
+
+fn atx_heading(line: &str) -> Option<(usize, String)> {
+    let level = run_length(line, 0, '#');
+    if level == 0 || level > 6 {
+        return None;
+    }
+
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+
+    // An optional closing sequence of #s, which needs a space before it.
+    let text = rest.trim();
+    let without_closing = text.trim_end_matches('#');
+    let text = if without_closing.is_empty() {
+        ""
+    } else if without_closing.ends_with([' ', '\t']) {
+        without_closing.trim_end()
+    } else {
+        text
+    };
+
+    Some((level, text.to_string()))
+}
+
+fn setext_underline(line: &str) -> Option<usize> {
+    if indentation(line) >= 4 {
+        return None;
+    }
+
+    let line = line.trim();
+    if !line.is_empty() && line.chars().all(|c| c == '=') {
+        Some(1)
+    } else if !line.is_empty() && line.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+fn is_thematic_break(line: &str) -> bool {
+    let character = match line.chars().next() {
+        Some(c) if c == '-' || c == '*' || c == '_' => c,
+        _ => return false,
+    };
+
+    line.chars().filter(|c| *c == character).count() >= 3
+        && line
+            .chars()
+            .all(|c| c == character || c == ' ' || c == '\t')
+}
+
+fn list_marker(line: &str) -> Option<ListMarker> {
+    let (bullet, number, delimiter, marker_width) = match line.chars().next()? {
+        c @ '-' | c @ '+' | c @ '*' => (Some(c), 0, c, 1),
+        _ => {
+            let digits = line.find(|c: char| !c.is_ascii_digit())?;
+            if digits == 0 || digits > 9 {
+                return None;
+            }
+            let delimiter = line[digits..].chars().next()?;
+            if delimiter != '.' && delimiter != ')' {
+                return None;
+            }
+            (None, line[..digits].parse().ok()?, delimiter, digits + 1)
+        }
+    };
+
+    let rest = &line[marker_width..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+
+    // Content indented 5 or more spaces after the marker is an indented code block.
+    let spaces = indentation(rest);
+    let width = if rest.trim().is_empty() || spaces > 4 {
+        marker_width + 1
+    } else {
+        marker_width + spaces
+    };
+
+    Some(ListMarker {
+        bullet,
+        number,
+        delimiter,
+        width,
+    })
+}
+
+fn task_marker(line: &str) -> Option<bool> {
+    let checked = match line.get(..3)? {
+        "[ ]" => false,
+        "[x]" | "[X]" => true,
+        _ => return None,
+    };
+
+    if line[3..].starts_with([' ', '\t']) {
+        Some(checked)
+    } else {
+        None
+    }
+}
+
+/* Lines that start a block, ending a paragraph. */
+fn starts_block(line: &str) -> bool {
+    let indent = indentation(line);
+    if indent >= 4 {
+        return false;
+    }
+
+    let rest = &line[indent..];
+    rest.starts_with('>')
+        || opening_fence(rest).is_some()
+        || atx_heading(rest).is_some()
+        || is_thematic_break(rest)
+        || list_marker(rest).is_some_and(|marker| {
+            !rest[marker.width.min(rest.len())..].trim().is_empty()
+                && (marker.bullet.is_some() || marker.number == 1)
+        })
+}
+
+fn normalize_label(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(next) if c == '\\' && next.is_ascii_punctuation() => {
+                unescaped.push(*next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+    text
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        push_escaped_char(&mut escaped, c);
+    }
+    escaped
+}
+
+/* Like in text, character references in link destinations and titles are left for the browser
+to decode. */
+fn escape_attribute(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let length = if c == '&' {
+            entity_length(&text[i..])
+        } else {
+            0
+        };
+        if length > 0 {
+            escaped.push_str(&text[i..i + length]);
+            i += length;
+        } else {
+            push_escaped_char(&mut escaped, c);
+            i += c.len_utf8();
+        }
+    }
+    escaped
+}
+
+fn push_escaped_char(html: &mut String, c: char) {
+    match c {
+        '&' => html.push_str("&amp;"),
+        '<' => html.push_str("&lt;"),
+        '>' => html.push_str("&gt;"),
+        '"' => html.push_str("&quot;"),
+        '\0' => html.push('\u{FFFD}'),
+        _ => html.push(c),
+    }
+}
+
+/* Length of the entity or numeric character reference at the start of `text`, or 0. */
+fn entity_length(text: &str) -> usize {
+    let end = match text.bytes().take(33).position(|byte| byte == b';') {
+        Some(end) if end > 1 => end,
+        _ => return 0,
+    };
+
+    let name = &text[1..end];
+    let is_reference = match name.strip_prefix('#') {
+        Some(number) => match number.strip_prefix(['x', 'X']) {
+            Some(hex) => {
+                !hex.is_empty() && hex.len() <= 6 && hex.chars().all(|c| c.is_ascii_hexdigit())
+            }
+            None => {
+                !number.is_empty()
+                    && number.len() <= 7
+                    && number.chars().all(|c| c.is_ascii_digit())
+            }
+        },
+        None => {
+            name.starts_with(|c: char| c.is_ascii_alphabetic())
+                && name.chars().all(|c| c.is_ascii_alphanumeric())
+        }
+    };
+
+    if is_reference {
+        end + 1
+    } else {
+        0
+    }
+}
+
+fn run_length(text: &str, start: usize, character: char) -> usize {
+    text[start..]
+        .find(|c| c != character)
+        .unwrap_or(text.len() - start)
+}
+
+fn skip_spaces(text: &str, start: usize) -> usize {
+    start + run_length(text, start, ' ')
+}
+
+fn join_code_lines(lines: &[String]) -> String {
+    let mut code = lines.join("\n");
+    if !lines.is_empty() {
+        code.push('\n');
+    }
+    code
+}
+
+fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn strip_indentation(line: &str, width: usize) -> String {
+    line[indentation(line).min(width)..].to_string()
+}
+
+/* Tabs in indentation count as up to four spaces, so nesting can be measured in spaces. */
+fn expand_indentation(line: &str) -> String {
+    let content = line.trim_start_matches([' ', '\t']);
+    let mut width = 0;
+    for c in line[..line.len() - content.len()].chars() {
+        width = if c == '\t' {
+            (width / 4 + 1) * 4
+        } else {
+            width + 1
+        };
+    }
+
+    format!("{}{}", " ".repeat(width), content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn html(markdown: &str) -> String {
+        to_html(markdown).unwrap()
+    }
+
+    #[test]
+    fn renders_blocks() {
+        let cases = [
+            ("# Title", "<h1>Title</h1>\n"),
+            ("Title\n===", "<h1>Title</h1>\n"),
+            ("one\ntwo\n\nthree", "<p>one\ntwo</p>\n<p>three</p>\n"),
+            ("> quoted", "<blockquote>\n<p>quoted</p>\n</blockquote>\n"),
+            ("- a\n- b", "<ul>\n<li>a</li>\n<li>b</li>\n</ul>\n"),
+            (
+                "3. a\n4. b",
+                "<ol start=\"3\">\n<li>a</li>\n<li>b</li>\n</ol>\n",
+            ),
+            (
+                "```rust\nfn main() {}\n```",
+                "<pre><code class=\"language-rust\">fn main() {}\n</code></pre>\n",
+            ),
+            ("    indented", "<pre><code>indented\n</code></pre>\n"),
+            ("***", "<hr/>\n"),
+        ];
+        for (markdown, expected) in &cases {
+            assert_eq!(html(markdown), *expected, "{:?}", markdown);
+        }
+    }
+
+    #[test]
+    fn renders_inlines() {
+        let cases = [
+            (
+                "*a* **b** ~~c~~",
+                "<p><em>a</em> <strong>b</strong> <del>c</del></p>\n",
+            ),
+            ("`a < b`", "<p><code>a &lt; b</code></p>\n"),
+            ("[a](/b \"c\")", "<p><a href=\"/b\" title=\"c\">a</a></p>\n"),
+            ("![a](/b.png)", "<p><img src=\"/b.png\" alt=\"a\"/></p>\n"),
+            (
+                "<https://example.com>",
+                "<p><a href=\"https://example.com\">https://example.com</a></p>\n",
+            ),
+            ("\\*a\\*", "<p>*a*</p>\n"),
+            ("a &amp; b & c", "<p>a &amp; b &amp; c</p>\n"),
+        ];
+        for (markdown, expected) in &cases {
+            assert_eq!(html(markdown), *expected, "{:?}", markdown);
+        }
+    }
+
+    #[test]
+    fn renders_gfm_extensions() {
+        assert_eq!(
+            html("| a | b |\n| :- | -: |\n| 1 | 2 |"),
+            "<table>\n<thead>\n<tr>\n<th align=\"left\">a</th>\n<th align=\"right\">b</th>\n</tr>\n</thead>\n<tbody>\n<tr>\n<td align=\"left\">1</td>\n<td align=\"right\">2</td>\n</tr>\n</tbody>\n</table>\n"
+        );
+        assert_eq!(
+            html("- [x] done\n- [ ] open"),
+            "<ul>\n<li><input type=\"checkbox\" checked=\"\" disabled=\"\"/> done</li>\n<li><input type=\"checkbox\" disabled=\"\"/> open</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn resolves_references() {
+        let markdown = "[full][Ref], [collapsed][] and [shortcut]\n\n[ref]: /r\n[collapsed]: /c\n[shortcut]: /s \"t\"";
+        assert_eq!(
+            html(markdown),
+            "<p><a href=\"/r\">full</a>, <a href=\"/c\">collapsed</a> and <a href=\"/s\" title=\"t\">shortcut</a></p>\n"
+        );
+        assert_eq!(html("[missing]"), "<p>[missing]</p>\n");
+    }
+
+    #[test]
+    fn long_labels_are_not_references() {
+        let longest = "a".repeat(MAX_LABEL_LENGTH);
+        assert_eq!(
+            html(&format!("[{}]\n\n[{}]: /x", longest, longest)),
+            format!("<p><a href=\"/x\">{}</a></p>\n", longest)
+        );
+
+        let too_long = "a".repeat(MAX_LABEL_LENGTH + 1);
+        assert_eq!(
+            html(&format!("[{}]\n\n[{}]: /x", too_long, too_long)),
+            format!("<p>[{}]</p>\n<p>[{}]: /x</p>\n", too_long, too_long)
+        );
+    }
+
+    #[test]
+    fn nested_brackets_render_in_linear_time() {
+        // This took over 40 seconds when every `[` normalized the rest of the text.
+        let markdown = format!("{}{}", "[".repeat(30000), "]".repeat(30000));
+        let start = Instant::now();
+        assert_eq!(html(&markdown), format!("<p>{}</p>\n", markdown));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn sanitizes_html() {
+        assert_eq!(
+            html("<script>alert(1)</script> <kbd onclick=\"x()\">key</kbd>"),
+            "<p> <kbd>key</kbd></p>\n"
+        );
+        assert_eq!(html("[a](javascript:alert(1))"), "<p><a>a</a></p>\n");
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let quotes = ">".repeat(MAX_NESTING + 1);
+        assert!(validate(&quotes).is_err());
+        assert!(validate(&">".repeat(MAX_NESTING)).is_ok());
+    }
+
+    /* Examples from the CommonMark spec, with `<br/>`, `<hr/>` and `<img/>` written the way the
+    sanitizer writes them. */
+    fn assert_spec_examples(examples: &[(&str, &str)]) {
+        for (markdown, expected) in examples {
+            assert_eq!(
+                html(markdown),
+                expected.replace(" />", "/>"),
+                "{:?}",
+                markdown
+            );
+        }
+    }
+
+    #[test]
+    fn follows_spec_for_leaf_blocks() {
+        assert_spec_examples(&[
+            // Tabs
+            ("\tfoo\tbaz\t\tbim", "<pre><code>foo\tbaz\t\tbim\n</code></pre>\n"),
+            ("  \tfoo\tbaz\t\tbim", "<pre><code>foo\tbaz\t\tbim\n</code></pre>\n"),
+            ("- foo\n\n\tbar", "<ul>\n<li>\n<p>foo</p>\n<p>bar</p>\n</li>\n</ul>\n"),
+            ("*\t*\t*\t", "<hr />\n"),
+
+            // Thematic breaks
+            ("***\n---\n___", "<hr />\n<hr />\n<hr />\n"),
+            ("+++", "<p>+++</p>\n"),
+            ("===", "<p>===</p>\n"),
+            ("--\n**\n__", "<p>--\n**\n__</p>\n"),
+            (" ***\n  ***\n   ***", "<hr />\n<hr />\n<hr />\n"),
+            ("    ***", "<pre><code>***\n</code></pre>\n"),
+            ("Foo\n    ***", "<p>Foo\n***</p>\n"),
+            ("_____________________________________", "<hr />\n"),
+            (" - - -", "<hr />\n"),
+            (" **  * ** * ** * **", "<hr />\n"),
+            ("-     -      -      -", "<hr />\n"),
+            ("- - - -    ", "<hr />\n"),
+            ("_ _ _ _ a\n\na------\n\n---a---", "<p>_ _ _ _ a</p>\n<p>a------</p>\n<p>---a---</p>\n"),
+            (" *-*", "<p><em>-</em></p>\n"),
+            ("- foo\n***\n- bar", "<ul>\n<li>foo</li>\n</ul>\n<hr />\n<ul>\n<li>bar</li>\n</ul>\n"),
+            ("Foo\n***\nbar", "<p>Foo</p>\n<hr />\n<p>bar</p>\n"),
+            ("Foo\n---\nbar", "<h2>Foo</h2>\n<p>bar</p>\n"),
+            ("* Foo\n* * *\n* Bar", "<ul>\n<li>Foo</li>\n</ul>\n<hr />\n<ul>\n<li>Bar</li>\n</ul>\n"),
+            ("- Foo\n- * * *", "<ul>\n<li>Foo</li>\n<li>\n<hr />\n</li>\n</ul>\n"),
+
+            // ATX headings
+            ("# foo\n## foo\n### foo\n#### foo\n##### foo\n###### foo", "<h1>foo</h1>\n<h2>foo</h2>\n<h3>foo</h3>\n<h4>foo</h4>\n<h5>foo</h5>\n<h6>foo</h6>\n"),
+            ("####### foo", "<p>####### foo</p>\n"),
+            ("#5 bolt\n\n#hashtag", "<p>#5 bolt</p>\n<p>#hashtag</p>\n"),
+            ("\\## foo", "<p>## foo</p>\n"),
+            ("# foo *bar* \\*baz\\*", "<h1>foo <em>bar</em> *baz*</h1>\n"),
+            ("#                  foo                     ", "<h1>foo</h1>\n"),
+            (" ### foo\n  ## foo\n   # foo", "<h3>foo</h3>\n<h2>foo</h2>\n<h1>foo</h1>\n"),
+            ("    # foo", "<pre><code># foo\n</code></pre>\n"),
+            ("foo\n    # bar", "<p>foo\n# bar</p>\n"),
+            ("## foo ##\n  ###   bar    ###", "<h2>foo</h2>\n<h3>bar</h3>\n"),
+            ("# foo ##################################\n##### foo ##", "<h1>foo</h1>\n<h5>foo</h5>\n"),
+            ("### foo ###     ", "<h3>foo</h3>\n"),
+            ("### foo ### b", "<h3>foo ### b</h3>\n"),
+            ("# foo#", "<h1>foo#</h1>\n"),
+            ("### foo \\###\n## foo #\\##\n# foo \\#", "<h3>foo ###</h3>\n<h2>foo ###</h2>\n<h1>foo #</h1>\n"),
+            ("****\n## foo\n****", "<hr />\n<h2>foo</h2>\n<hr />\n"),
+            ("Foo bar\n# baz\nBar foo", "<p>Foo bar</p>\n<h1>baz</h1>\n<p>Bar foo</p>\n"),
+            ("## \n#\n### ###", "<h2></h2>\n<h1></h1>\n<h3></h3>\n"),
+
+            // Setext headings
+            ("Foo *bar*\n=========\n\nFoo *bar*\n---------", "<h1>Foo <em>bar</em></h1>\n<h2>Foo <em>bar</em></h2>\n"),
+            ("Foo *bar\nbaz*\n====", "<h1>Foo <em>bar\nbaz</em></h1>\n"),
+            ("Foo\n-------------------------\n\nFoo\n=", "<h2>Foo</h2>\n<h1>Foo</h1>\n"),
+            ("   Foo\n---\n\n  Foo\n-----\n\n  Foo\n  ===", "<h2>Foo</h2>\n<h2>Foo</h2>\n<h1>Foo</h1>\n"),
+            ("    Foo\n    ---\n\n    Foo\n---", "<pre><code>Foo\n---\n\nFoo\n</code></pre>\n<hr />\n"),
+            ("Foo\n   ----      ", "<h2>Foo</h2>\n"),
+            ("Foo\n    ---", "<p>Foo\n---</p>\n"),
+            ("Foo\n= =\n\nFoo\n--- -", "<p>Foo\n= =</p>\n<p>Foo</p>\n<hr />\n"),
+            ("Foo  \n-----", "<h2>Foo</h2>\n"),
+            ("Foo\\\n----", "<h2>Foo\\</h2>\n"),
+            ("> Foo\n---", "<blockquote>\n<p>Foo</p>\n</blockquote>\n<hr />\n"),
+            ("- Foo\n---", "<ul>\n<li>Foo</li>\n</ul>\n<hr />\n"),
+            ("Foo\nBar\n---", "<h2>Foo\nBar</h2>\n"),
+            ("---\nFoo\n---\nBar\n---\nBaz", "<hr />\n<h2>Foo</h2>\n<h2>Bar</h2>\n<p>Baz</p>\n"),
+            ("\n====", "<p>====</p>\n"),
+            ("---\n---", "<hr />\n<hr />\n"),
+            ("- foo\n-----", "<ul>\n<li>foo</li>\n</ul>\n<hr />\n"),
+            ("    foo\n---", "<pre><code>foo\n</code></pre>\n<hr />\n"),
+            ("> foo\n-----", "<blockquote>\n<p>foo</p>\n</blockquote>\n<hr />\n"),
+            ("\\> foo\n------", "<h2>&gt; foo</h2>\n"),
+            ("Foo\n\nbar\n---\nbaz", "<p>Foo</p>\n<h2>bar</h2>\n<p>baz</p>\n"),
+            ("Foo\nbar\n\n---\n\nbaz", "<p>Foo\nbar</p>\n<hr />\n<p>baz</p>\n"),
+            ("Foo\nbar\n* * *\nbaz", "<p>Foo\nbar</p>\n<hr />\n<p>baz</p>\n"),
+            ("Foo\nbar\n\\---\nbaz", "<p>Foo\nbar\n---\nbaz</p>\n"),
+
+            // Indented code blocks
+            ("    a simple\n      indented code block", "<pre><code>a simple\n  indented code block\n</code></pre>\n"),
+            ("  - foo\n\n    bar", "<ul>\n<li>\n<p>foo</p>\n<p>bar</p>\n</li>\n</ul>\n"),
+            ("1.  foo\n\n    - bar", "<ol>\n<li>\n<p>foo</p>\n<ul>\n<li>bar</li>\n</ul>\n</li>\n</ol>\n"),
+            ("    <a/>\n    *hi*\n\n    - one", "<pre><code>&lt;a/&gt;\n*hi*\n\n- one\n</code></pre>\n"),
+            ("    chunk1\n\n    chunk2\n  \n \n \n    chunk3", "<pre><code>chunk1\n\nchunk2\n\n\n\nchunk3\n</code></pre>\n"),
+            ("    chunk1\n      \n      chunk2", "<pre><code>chunk1\n  \n  chunk2\n</code></pre>\n"),
+            ("Foo\n    bar", "<p>Foo\nbar</p>\n"),
+            ("    foo\nbar", "<pre><code>foo\n</code></pre>\n<p>bar</p>\n"),
+            ("# Heading\n    foo\nHeading\n------\n    foo\n----", "<h1>Heading</h1>\n<pre><code>foo\n</code></pre>\n<h2>Heading</h2>\n<pre><code>foo\n</code></pre>\n<hr />\n"),
+            ("        foo\n    bar", "<pre><code>    foo\nbar\n</code></pre>\n"),
+            ("\n    \n    foo\n    ", "<pre><code>foo\n</code></pre>\n"),
+            ("    foo  ", "<pre><code>foo  \n</code></pre>\n"),
+
+            // Fenced code blocks
+            ("```\n<\n >\n```", "<pre><code>&lt;\n &gt;\n</code></pre>\n"),
+            ("~~~\n<\n >\n~~~", "<pre><code>&lt;\n &gt;\n</code></pre>\n"),
+            ("``\nfoo\n``", "<p><code>foo</code></p>\n"),
+            ("```\naaa\n~~~\n```", "<pre><code>aaa\n~~~\n</code></pre>\n"),
+            ("~~~\naaa\n```\n~~~", "<pre><code>aaa\n```\n</code></pre>\n"),
+            ("````\naaa\n```\n``````", "<pre><code>aaa\n```\n</code></pre>\n"),
+            ("~~~~\naaa\n~~~\n~~~~", "<pre><code>aaa\n~~~\n</code></pre>\n"),
+            ("```", "<pre><code></code></pre>\n"),
+            ("`````\n\n```\naaa", "<pre><code>\n```\naaa\n</code></pre>\n"),
+            ("> ```\n> aaa\n\nbbb", "<blockquote>\n<pre><code>aaa\n</code></pre>\n</blockquote>\n<p>bbb</p>\n"),
+            ("```\n\n  \n```", "<pre><code>\n  \n</code></pre>\n"),
+            ("```\n```", "<pre><code></code></pre>\n"),
+            (" ```\n aaa\naaa\n```", "<pre><code>aaa\naaa\n</code></pre>\n"),
+            ("  ```\naaa\n  aaa\naaa\n  ```", "<pre><code>aaa\naaa\naaa\n</code></pre>\n"),
+            ("   ```\n   aaa\n    aaa\n  aaa\n   ```", "<pre><code>aaa\n aaa\naaa\n</code></pre>\n"),
+            ("    ```\n    aaa\n    ```", "<pre><code>```\naaa\n```\n</code></pre>\n"),
+            ("```\naaa\n  ```", "<pre><code>aaa\n</code></pre>\n"),
+            ("   ```\naaa\n  ```", "<pre><code>aaa\n</code></pre>\n"),
+            ("```\naaa\n    ```", "<pre><code>aaa\n    ```\n</code></pre>\n"),
+            ("``` ```\naaa", "<p><code> </code>\naaa</p>\n"),
+            ("~~~~~~\naaa\n~~~ ~~", "<pre><code>aaa\n~~~ ~~\n</code></pre>\n"),
+            ("foo\n```\nbar\n```\nbaz", "<p>foo</p>\n<pre><code>bar\n</code></pre>\n<p>baz</p>\n"),
+            ("foo\n---\n~~~\nbar\n~~~\n# baz", "<h2>foo</h2>\n<pre><code>bar\n</code></pre>\n<h1>baz</h1>\n"),
+            ("```ruby\ndef foo(x)\n  return 3\nend\n```", "<pre><code class=\"language-ruby\">def foo(x)\n  return 3\nend\n</code></pre>\n"),
+            ("~~~~    ruby startline=3 $%@#$\ndef foo(x)\n  return 3\nend\n~~~~~~~", "<pre><code class=\"language-ruby\">def foo(x)\n  return 3\nend\n</code></pre>\n"),
+            ("``` aa ```\nfoo", "<p><code>aa</code>\nfoo</p>\n"),
+            ("~~~ aa ``` ~~~\nfoo\n~~~", "<pre><code class=\"language-aa\">foo\n</code></pre>\n"),
+            ("```\n``` aaa\n```", "<pre><code>``` aaa\n</code></pre>\n"),
+
+            // Paragraphs
+            ("aaa\n\nbbb", "<p>aaa</p>\n<p>bbb</p>\n"),
+            ("aaa\nbbb\n\nccc\nddd", "<p>aaa\nbbb</p>\n<p>ccc\nddd</p>\n"),
+            ("aaa\n\n\nbbb", "<p>aaa</p>\n<p>bbb</p>\n"),
+            ("  aaa\n bbb", "<p>aaa\nbbb</p>\n"),
+            ("aaa\n             bbb\n                                       ccc", "<p>aaa\nbbb\nccc</p>\n"),
+            ("   aaa\nbbb", "<p>aaa\nbbb</p>\n"),
+            ("    aaa\nbbb", "<pre><code>aaa\n</code></pre>\n<p>bbb</p>\n"),
+            ("aaa     \nbbb     ", "<p>aaa<br />\nbbb</p>\n"),
+
+            // Blank lines
+            ("  \n\naaa\n  \n\n# aaa\n\n  ", "<p>aaa</p>\n<h1>aaa</h1>\n"),
+        ]);
+    }
+
+    #[test]
+    fn follows_spec_for_container_blocks() {
+        assert_spec_examples(&[
+            // Block quotes
+            ("> # Foo\n> bar\n> baz", "<blockquote>\n<h1>Foo</h1>\n<p>bar\nbaz</p>\n</blockquote>\n"),
+            ("># Foo\n>bar\n> baz", "<blockquote>\n<h1>Foo</h1>\n<p>bar\nbaz</p>\n</blockquote>\n"),
+            ("   > # Foo\n   > bar\n > baz", "<blockquote>\n<h1>Foo</h1>\n<p>bar\nbaz</p>\n</blockquote>\n"),
+            ("    > # Foo\n    > bar\n    > baz", "<pre><code>&gt; # Foo\n&gt; bar\n&gt; baz\n</code></pre>\n"),
+            ("> # Foo\n> bar\nbaz", "<blockquote>\n<h1>Foo</h1>\n<p>bar\nbaz</p>\n</blockquote>\n"),
+            ("> bar\nbaz\n> foo", "<blockquote>\n<p>bar\nbaz\nfoo</p>\n</blockquote>\n"),
+            ("> foo\n---", "<blockquote>\n<p>foo</p>\n</blockquote>\n<hr />\n"),
+            ("> - foo\n- bar", "<blockquote>\n<ul>\n<li>foo</li>\n</ul>\n</blockquote>\n<ul>\n<li>bar</li>\n</ul>\n"),
+            (">     foo\n    bar", "<blockquote>\n<pre><code>foo\n</code></pre>\n</blockquote>\n<pre><code>bar\n</code></pre>\n"),
+            ("> ```\nfoo\n```", "<blockquote>\n<pre><code></code></pre>\n</blockquote>\n<p>foo</p>\n<pre><code></code></pre>\n"),
+            ("> foo\n    - bar", "<blockquote>\n<p>foo\n- bar</p>\n</blockquote>\n"),
+            (">", "<blockquote>\n</blockquote>\n"),
+            (">\n>  \n> ", "<blockquote>\n</blockquote>\n"),
+            (">\n> foo\n>  ", "<blockquote>\n<p>foo</p>\n</blockquote>\n"),
+            ("> foo\n\n> bar", "<blockquote>\n<p>foo</p>\n</blockquote>\n<blockquote>\n<p>bar</p>\n</blockquote>\n"),
+            ("> foo\n> bar", "<blockquote>\n<p>foo\nbar</p>\n</blockquote>\n"),
+            ("> foo\n>\n> bar", "<blockquote>\n<p>foo</p>\n<p>bar</p>\n</blockquote>\n"),
+            ("foo\n> bar", "<p>foo</p>\n<blockquote>\n<p>bar</p>\n</blockquote>\n"),
+            ("> aaa\n***\n> bbb", "<blockquote>\n<p>aaa</p>\n</blockquote>\n<hr />\n<blockquote>\n<p>bbb</p>\n</blockquote>\n"),
+            ("> bar\nbaz", "<blockquote>\n<p>bar\nbaz</p>\n</blockquote>\n"),
+            ("> bar\n\nbaz", "<blockquote>\n<p>bar</p>\n</blockquote>\n<p>baz</p>\n"),
+            ("> bar\n>\nbaz", "<blockquote>\n<p>bar</p>\n</blockquote>\n<p>baz</p>\n"),
+            ("> > > foo\nbar", "<blockquote>\n<blockquote>\n<blockquote>\n<p>foo\nbar</p>\n</blockquote>\n</blockquote>\n</blockquote>\n"),
+            (">>> foo\n> bar\n>>baz", "<blockquote>\n<blockquote>\n<blockquote>\n<p>foo\nbar\nbaz</p>\n</blockquote>\n</blockquote>\n</blockquote>\n"),
+            (">     code\n\n>    not code", "<blockquote>\n<pre><code>code\n</code></pre>\n</blockquote>\n<blockquote>\n<p>not code</p>\n</blockquote>\n"),
+
+            // List items
+            ("A paragraph\nwith two lines.\n\n    indented code\n\n> A block quote.", "<p>A paragraph\nwith two lines.</p>\n<pre><code>indented code\n</code></pre>\n<blockquote>\n<p>A block quote.</p>\n</blockquote>\n"),
+            ("1.  A paragraph\n    with two lines.\n\n        indented code\n\n    > A block quote.", "<ol>\n<li>\n<p>A paragraph\nwith two lines.</p>\n<pre><code>indented code\n</code></pre>\n<blockquote>\n<p>A block quote.</p>\n</blockquote>\n</li>\n</ol>\n"),
+            ("- one\n\n two", "<ul>\n<li>one</li>\n</ul>\n<p>two</p>\n"),
+            ("- one\n\n  two", "<ul>\n<li>\n<p>one</p>\n<p>two</p>\n</li>\n</ul>\n"),
+            (" -    one\n\n     two", "<ul>\n<li>one</li>\n</ul>\n<pre><code> two\n</code></pre>\n"),
+            (" -    one\n\n      two", "<ul>\n<li>\n<p>one</p>\n<p>two</p>\n</li>\n</ul>\n"),
+            ("   > > 1.  one\n>>\n>>     two", "<blockquote>\n<blockquote>\n<ol>\n<li>\n<p>one</p>\n<p>two</p>\n</li>\n</ol>\n</blockquote>\n</blockquote>\n"),
+            (">>- one\n>>\n  >  > two", "<blockquote>\n<blockquote>\n<ul>\n<li>one</li>\n</ul>\n<p>two</p>\n</blockquote>\n</blockquote>\n"),
+            ("-one\n\n2.two", "<p>-one</p>\n<p>2.two</p>\n"),
+            ("- foo\n\n\n  bar", "<ul>\n<li>\n<p>foo</p>\n<p>bar</p>\n</li>\n</ul>\n"),
+            ("1.  foo\n\n    ```\n    bar\n    ```\n\n    baz\n\n    > bam", "<ol>\n<li>\n<p>foo</p>\n<pre><code>bar\n</code></pre>\n<p>baz</p>\n<blockquote>\n<p>bam</p>\n</blockquote>\n</li>\n</ol>\n"),
+            ("- Foo\n\n      bar\n\n\n      baz", "<ul>\n<li>\n<p>Foo</p>\n<pre><code>bar\n\n\nbaz\n</code></pre>\n</li>\n</ul>\n"),
+            ("123456789. ok", "<ol start=\"123456789\">\n<li>ok</li>\n</ol>\n"),
+            ("1234567890. not ok", "<p>1234567890. not ok</p>\n"),
+            ("0. ok", "<ol start=\"0\">\n<li>ok</li>\n</ol>\n"),
+            ("003. ok", "<ol start=\"3\">\n<li>ok</li>\n</ol>\n"),
+            ("-1. not ok", "<p>-1. not ok</p>\n"),
+            ("- foo\n\n      bar", "<ul>\n<li>\n<p>foo</p>\n<pre><code>bar\n</code></pre>\n</li>\n</ul>\n"),
+            ("  10.  foo\n\n           bar", "<ol start=\"10\">\n<li>\n<p>foo</p>\n<pre><code>bar\n</code></pre>\n</li>\n</ol>\n"),
+            ("    indented code\n\nparagraph\n\n    more code", "<pre><code>indented code\n</code></pre>\n<p>paragraph</p>\n<pre><code>more code\n</code></pre>\n"),
+            ("1.     indented code\n\n   paragraph\n\n       more code", "<ol>\n<li>\n<pre><code>indented code\n</code></pre>\n<p>paragraph</p>\n<pre><code>more code\n</code></pre>\n</li>\n</ol>\n"),
+            ("   foo\n\nbar", "<p>foo</p>\n<p>bar</p>\n"),
+            ("-    foo\n\n  bar", "<ul>\n<li>foo</li>\n</ul>\n<p>bar</p>\n"),
+            ("-  foo\n\n   bar", "<ul>\n<li>\n<p>foo</p>\n<p>bar</p>\n</li>\n</ul>\n"),
+            ("-\n  foo\n-\n  ```\n  bar\n  ```\n-\n      baz", "<ul>\n<li>foo</li>\n<li>\n<pre><code>bar\n</code></pre>\n</li>\n<li>\n<pre><code>baz\n</code></pre>\n</li>\n</ul>\n"),
+            ("-   \n  foo", "<ul>\n<li>foo</li>\n</ul>\n"),
+            ("-\n\n  foo", "<ul>\n<li></li>\n</ul>\n<p>foo</p>\n"),
+            ("- foo\n-\n- bar", "<ul>\n<li>foo</li>\n<li></li>\n<li>bar</li>\n</ul>\n"),
+            ("- foo\n-   \n- bar", "<ul>\n<li>foo</li>\n<li></li>\n<li>bar</li>\n</ul>\n"),
+            ("1. foo\n2.\n3. bar", "<ol>\n<li>foo</li>\n<li></li>\n<li>bar</li>\n</ol>\n"),
+            ("*", "<ul>\n<li></li>\n</ul>\n"),
+            ("foo\n*\n\nfoo\n1.", "<p>foo\n*</p>\n<p>foo\n1.</p>\n"),
+            (" 1.  A paragraph\n     with two lines.\n\n         indented code\n\n     > A block quote.", "<ol>\n<li>\n<p>A paragraph\nwith two lines.</p>\n<pre><code>indented code\n</code></pre>\n<blockquote>\n<p>A block quote.</p>\n</blockquote>\n</li>\n</ol>\n"),
+            ("    1.  A paragraph\n        with two lines.\n\n            indented code\n\n        > A block quote.", "<pre><code>1.  A paragraph\n    with two lines.\n\n        indented code\n\n    &gt; A block quote.\n</code></pre>\n"),
+            ("  1.  A paragraph\nwith two lines.\n\n          indented code\n\n      > A block quote.", "<ol>\n<li>\n<p>A paragraph\nwith two lines.</p>\n<pre><code>indented code\n</code></pre>\n<blockquote>\n<p>A block quote.</p>\n</blockquote>\n</li>\n</ol>\n"),
+            ("  1.  A paragraph\n    with two lines.", "<ol>\n<li>A paragraph\nwith two lines.</li>\n</ol>\n"),
+            ("> 1. > Blockquote\ncontinued here.", "<blockquote>\n<ol>\n<li>\n<blockquote>\n<p>Blockquote\ncontinued here.</p>\n</blockquote>\n</li>\n</ol>\n</blockquote>\n"),
+            ("- foo\n  - bar\n    - baz\n      - boo", "<ul>\n<li>foo\n<ul>\n<li>bar\n<ul>\n<li>baz\n<ul>\n<li>boo</li>\n</ul>\n</li>\n</ul>\n</li>\n</ul>\n</li>\n</ul>\n"),
+            ("- foo\n - bar\n  - baz\n   - boo", "<ul>\n<li>foo</li>\n<li>bar</li>\n<li>baz</li>\n<li>boo</li>\n</ul>\n"),
+            ("10) foo\n    - bar", "<ol start=\"10\">\n<li>foo\n<ul>\n<li>bar</li>\n</ul>\n</li>\n</ol>\n"),
+            ("10) foo\n   - bar", "<ol start=\"10\">\n<li>foo</li>\n</ol>\n<ul>\n<li>bar</li>\n</ul>\n"),
+            ("- - foo", "<ul>\n<li>\n<ul>\n<li>foo</li>\n</ul>\n</li>\n</ul>\n"),
+            ("1. - 2. foo", "<ol>\n<li>\n<ul>\n<li>\n<ol start=\"2\">\n<li>foo</li>\n</ol>\n</li>\n</ul>\n</li>\n</ol>\n"),
+            ("- # Foo\n- Bar\n  ---\n  baz", "<ul>\n<li>\n<h1>Foo</h1>\n</li>\n<li>\n<h2>Bar</h2>\nbaz</li>\n</ul>\n"),
+
+            // Lists
+            ("- foo\n- bar\n+ baz", "<ul>\n<li>foo</li>\n<li>bar</li>\n</ul>\n<ul>\n<li>baz</li>\n</ul>\n"),
+            ("1. foo\n2. bar\n3) baz", "<ol>\n<li>foo</li>\n<li>bar</li>\n</ol>\n<ol start=\"3\">\n<li>baz</li>\n</ol>\n"),
+            ("Foo\n- bar\n- baz", "<p>Foo</p>\n<ul>\n<li>bar</li>\n<li>baz</li>\n</ul>\n"),
+            ("The number of windows in my house is\n14.  The number of doors is 6.", "<p>The number of windows in my house is\n14.  The number of doors is 6.</p>\n"),
+            ("The number of windows in my house is\n1.  The number of doors is 6.", "<p>The number of windows in my house is</p>\n<ol>\n<li>The number of doors is 6.</li>\n</ol>\n"),
+            ("- foo\n\n- bar\n\n\n- baz", "<ul>\n<li>\n<p>foo</p>\n</li>\n<li>\n<p>bar</p>\n</li>\n<li>\n<p>baz</p>\n</li>\n</ul>\n"),
+            ("- foo\n  - bar\n    - baz\n\n\n      bim", "<ul>\n<li>foo\n<ul>\n<li>bar\n<ul>\n<li>\n<p>baz</p>\n<p>bim</p>\n</li>\n</ul>\n</li>\n</ul>\n</li>\n</ul>\n"),
+            ("- a\n - b\n  - c\n   - d\n  - e\n - f\n- g", "<ul>\n<li>a</li>\n<li>b</li>\n<li>c</li>\n<li>d</li>\n<li>e</li>\n<li>f</li>\n<li>g</li>\n</ul>\n"),
+            ("1. a\n\n  2. b\n\n   3. c", "<ol>\n<li>\n<p>a</p>\n</li>\n<li>\n<p>b</p>\n</li>\n<li>\n<p>c</p>\n</li>\n</ol>\n"),
+            ("- a\n - b\n  - c\n   - d\n    - e", "<ul>\n<li>a</li>\n<li>b</li>\n<li>c</li>\n<li>d\n- e</li>\n</ul>\n"),
+            ("1. a\n\n  2. b\n\n    3. c", "<ol>\n<li>\n<p>a</p>\n</li>\n<li>\n<p>b</p>\n</li>\n</ol>\n<pre><code>3. c\n</code></pre>\n"),
+            ("- a\n- b\n\n- c", "<ul>\n<li>\n<p>a</p>\n</li>\n<li>\n<p>b</p>\n</li>\n<li>\n<p>c</p>\n</li>\n</ul>\n"),
+            ("* a\n*\n\n* c", "<ul>\n<li>\n<p>a</p>\n</li>\n<li></li>\n<li>\n<p>c</p>\n</li>\n</ul>\n"),
+            ("- a\n- b\n\n  c\n- d", "<ul>\n<li>\n<p>a</p>\n</li>\n<li>\n<p>b</p>\n<p>c</p>\n</li>\n<li>\n<p>d</p>\n</li>\n</ul>\n"),
+            ("- a\n- b\n\n  [ref]: /url\n- d", "<ul>\n<li>\n<p>a</p>\n</li>\n<li>\n<p>b</p>\n</li>\n<li>\n<p>d</p>\n</li>\n</ul>\n"),
+            ("- a\n- ```\n  b\n\n\n  ```\n- c", "<ul>\n<li>a</li>\n<li>\n<pre><code>b\n\n\n</code></pre>\n</li>\n<li>c</li>\n</ul>\n"),
+            ("- a\n  - b\n\n    c\n- d", "<ul>\n<li>a\n<ul>\n<li>\n<p>b</p>\n<p>c</p>\n</li>\n</ul>\n</li>\n<li>d</li>\n</ul>\n"),
+            ("* a\n  > b\n  >\n* c", "<ul>\n<li>a\n<blockquote>\n<p>b</p>\n</blockquote>\n</li>\n<li>c</li>\n</ul>\n"),
+            ("- a\n  > b\n  ```\n  c\n  ```\n- d", "<ul>\n<li>a\n<blockquote>\n<p>b</p>\n</blockquote>\n<pre><code>c\n</code></pre>\n</li>\n<li>d</li>\n</ul>\n"),
+            ("- a", "<ul>\n<li>a</li>\n</ul>\n"),
+            ("- a\n  - b", "<ul>\n<li>a\n<ul>\n<li>b</li>\n</ul>\n</li>\n</ul>\n"),
+            ("1. ```\n   foo\n   ```\n\n   bar", "<ol>\n<li>\n<pre><code>foo\n</code></pre>\n<p>bar</p>\n</li>\n</ol>\n"),
+            ("* foo\n  * bar\n\n  baz", "<ul>\n<li>\n<p>foo</p>\n<ul>\n<li>bar</li>\n</ul>\n<p>baz</p>\n</li>\n</ul>\n"),
+            ("- a\n  - b\n  - c\n\n- d\n  - e\n  - f", "<ul>\n<li>\n<p>a</p>\n<ul>\n<li>b</li>\n<li>c</li>\n</ul>\n</li>\n<li>\n<p>d</p>\n<ul>\n<li>e</li>\n<li>f</li>\n</ul>\n</li>\n</ul>\n"),
+        ]);
+    }
+
+    #[test]
+    fn follows_spec_for_inlines() {
+        assert_spec_examples(&[
+            // Backslash escapes
+            ("\\!\\\"\\#\\$\\%\\&\\'\\(\\)\\*\\+\\,\\-\\.\\/\\:\\;\\<\\=\\>\\?\\@\\[\\\\\\]\\^\\_\\`\\{\\|\\}\\~", "<p>!&quot;#$%&amp;'()*+,-./:;&lt;=&gt;?@[\\]^_`{|}~</p>\n"),
+            ("\\\t\\A\\a\\ \\3\\φ\\«", "<p>\\\t\\A\\a\\ \\3\\φ\\«</p>\n"),
+            ("\\*not emphasized*\n\\<br/> not a tag\n\\[not a link](/foo)\n\\`not code`\n1\\. not a list\n\\* not a list\n\\# not a heading\n\\[foo]: /url \"not a reference\"\n\\&ouml; not a character entity", "<p>*not emphasized*\n&lt;br/&gt; not a tag\n[not a link](/foo)\n`not code`\n1. not a list\n* not a list\n# not a heading\n[foo]: /url &quot;not a reference&quot;\n&amp;ouml; not a character entity</p>\n"),
+            ("\\\\*emphasis*", "<p>\\<em>emphasis</em></p>\n"),
+            ("foo\\\nbar", "<p>foo<br />\nbar</p>\n"),
+            ("`` \\[\\` ``", "<p><code>\\[\\`</code></p>\n"),
+            ("    \\[\\]", "<pre><code>\\[\\]\n</code></pre>\n"),
+            ("~~~\n\\[\\]\n~~~", "<pre><code>\\[\\]\n</code></pre>\n"),
+            ("[foo](/bar\\* \"ti\\*tle\")", "<p><a href=\"/bar*\" title=\"ti*tle\">foo</a></p>\n"),
+            ("``` foo\\+bar\nfoo\n```", "<pre><code class=\"language-foo+bar\">foo\n</code></pre>\n"),
+
+            // Entity and numeric character references
+            ("&copy", "<p>&amp;copy</p>\n"),
+            ("`f&ouml;&ouml;`", "<p><code>f&amp;ouml;&amp;ouml;</code></p>\n"),
+
+            // Code spans
+            ("`foo`", "<p><code>foo</code></p>\n"),
+            ("`` foo ` bar ``", "<p><code>foo ` bar</code></p>\n"),
+            ("` `` `", "<p><code>``</code></p>\n"),
+            ("`  ``  `", "<p><code> `` </code></p>\n"),
+            ("` a`", "<p><code> a</code></p>\n"),
+            ("`\u{a0}b\u{a0}`", "<p><code>\u{a0}b\u{a0}</code></p>\n"),
+            ("` `\n`  `", "<p><code> </code>\n<code>  </code></p>\n"),
+            ("``\nfoo\nbar  \nbaz\n``", "<p><code>foo bar   baz</code></p>\n"),
+            ("``\nfoo \n``", "<p><code>foo </code></p>\n"),
+            ("`foo   bar \nbaz`", "<p><code>foo   bar  baz</code></p>\n"),
+            ("`foo\\`bar`", "<p><code>foo\\</code>bar`</p>\n"),
+            ("``foo`bar``", "<p><code>foo`bar</code></p>\n"),
+            ("` foo `` bar `", "<p><code>foo `` bar</code></p>\n"),
+            ("*foo`*`", "<p>*foo<code>*</code></p>\n"),
+            ("[not a `link](/foo`)", "<p>[not a <code>link](/foo</code>)</p>\n"),
+            ("`<a href=\"`\">`", "<p><code>&lt;a href=&quot;</code>&quot;&gt;`</p>\n"),
+            ("```foo``", "<p>```foo``</p>\n"),
+            ("`foo", "<p>`foo</p>\n"),
+            ("`foo``bar``", "<p>`foo<code>bar</code></p>\n"),
+
+            // Emphasis and strong emphasis
+            ("*foo bar*", "<p><em>foo bar</em></p>\n"),
+            ("a * foo bar*", "<p>a * foo bar*</p>\n"),
+            ("a*\"foo\"*", "<p>a*&quot;foo&quot;*</p>\n"),
+            ("foo*bar*", "<p>foo<em>bar</em></p>\n"),
+            ("5*6*78", "<p>5<em>6</em>78</p>\n"),
+            ("_foo bar_", "<p><em>foo bar</em></p>\n"),
+            ("_ foo bar_", "<p>_ foo bar_</p>\n"),
+            ("a_\"foo\"_", "<p>a_&quot;foo&quot;_</p>\n"),
+            ("foo_bar_", "<p>foo_bar_</p>\n"),
+            ("5_6_78", "<p>5_6_78</p>\n"),
+            ("пристаням_стремятся_", "<p>пристаням_стремятся_</p>\n"),
+            ("aa_\"bb\"_cc", "<p>aa_&quot;bb&quot;_cc</p>\n"),
+            ("foo-_(bar)_", "<p>foo-<em>(bar)</em></p>\n"),
+            ("_foo*", "<p>_foo*</p>\n"),
+            ("*foo bar *", "<p>*foo bar *</p>\n"),
+            ("*foo bar\n*", "<p>*foo bar\n*</p>\n"),
+            ("*(*foo)", "<p>*(*foo)</p>\n"),
+            ("*(*foo*)*", "<p><em>(<em>foo</em>)</em></p>\n"),
+            ("*foo*bar", "<p><em>foo</em>bar</p>\n"),
+            ("_foo bar _", "<p>_foo bar _</p>\n"),
+            ("_(_foo)", "<p>_(_foo)</p>\n"),
+            ("_(_foo_)_", "<p><em>(<em>foo</em>)</em></p>\n"),
+            ("_foo_bar", "<p>_foo_bar</p>\n"),
+            ("_пристаням_стремятся", "<p>_пристаням_стремятся</p>\n"),
+            ("_foo_bar_baz_", "<p><em>foo_bar_baz</em></p>\n"),
+            ("_(bar)_.", "<p><em>(bar)</em>.</p>\n"),
+            ("**foo bar**", "<p><strong>foo bar</strong></p>\n"),
+            ("** foo bar**", "<p>** foo bar**</p>\n"),
+            ("a**\"foo\"**", "<p>a**&quot;foo&quot;**</p>\n"),
+            ("foo**bar**", "<p>foo<strong>bar</strong></p>\n"),
+            ("__foo bar__", "<p><strong>foo bar</strong></p>\n"),
+            ("__ foo bar__", "<p>__ foo bar__</p>\n"),
+            ("__\nfoo bar__", "<p>__\nfoo bar__</p>\n"),
+            ("a__\"foo\"__", "<p>a__&quot;foo&quot;__</p>\n"),
+            ("foo__bar__", "<p>foo__bar__</p>\n"),
+            ("5__6__78", "<p>5__6__78</p>\n"),
+            ("__foo, __bar__, baz__", "<p><strong>foo, <strong>bar</strong>, baz</strong></p>\n"),
+            ("foo-__(bar)__", "<p>foo-<strong>(bar)</strong></p>\n"),
+            ("**foo bar **", "<p>**foo bar **</p>\n"),
+            ("**(**foo)", "<p>**(**foo)</p>\n"),
+            ("*(**foo**)*", "<p><em>(<strong>foo</strong>)</em></p>\n"),
+            ("**foo \"*bar*\" foo**", "<p><strong>foo &quot;<em>bar</em>&quot; foo</strong></p>\n"),
+            ("**foo**bar", "<p><strong>foo</strong>bar</p>\n"),
+            ("__foo bar __", "<p>__foo bar __</p>\n"),
+            ("__(__foo)", "<p>__(__foo)</p>\n"),
+            ("_(__foo__)_", "<p><em>(<strong>foo</strong>)</em></p>\n"),
+            ("__foo__bar", "<p>__foo__bar</p>\n"),
+            ("__foo__bar__baz__", "<p><strong>foo__bar__baz</strong></p>\n"),
+            ("__(bar)__.", "<p><strong>(bar)</strong>.</p>\n"),
+            ("*foo [bar](/url)*", "<p><em>foo <a href=\"/url\">bar</a></em></p>\n"),
+            ("*foo\nbar*", "<p><em>foo\nbar</em></p>\n"),
+            ("_foo __bar__ baz_", "<p><em>foo <strong>bar</strong> baz</em></p>\n"),
+            ("_foo _bar_ baz_", "<p><em>foo <em>bar</em> baz</em></p>\n"),
+            ("__foo_ bar_", "<p><em><em>foo</em> bar</em></p>\n"),
+            ("*foo *bar**", "<p><em>foo <em>bar</em></em></p>\n"),
+            ("*foo **bar** baz*", "<p><em>foo <strong>bar</strong> baz</em></p>\n"),
+            ("*foo**bar**baz*", "<p><em>foo<strong>bar</strong>baz</em></p>\n"),
+            ("*foo**bar*", "<p><em>foo**bar</em></p>\n"),
+            ("***foo** bar*", "<p><em><strong>foo</strong> bar</em></p>\n"),
+            ("*foo **bar***", "<p><em>foo <strong>bar</strong></em></p>\n"),
+            ("*foo**bar***", "<p><em>foo<strong>bar</strong></em></p>\n"),
+            ("foo***bar***baz", "<p>foo<em><strong>bar</strong></em>baz</p>\n"),
+            ("foo******bar*********baz", "<p>foo<strong><strong><strong>bar</strong></strong></strong>***baz</p>\n"),
+            ("*foo **bar *baz* bim** bop*", "<p><em>foo <strong>bar <em>baz</em> bim</strong> bop</em></p>\n"),
+            ("*foo [*bar*](/url)*", "<p><em>foo <a href=\"/url\"><em>bar</em></a></em></p>\n"),
+            ("** is not an empty emphasis", "<p>** is not an empty emphasis</p>\n"),
+            ("**** is not an empty strong emphasis", "<p>**** is not an empty strong emphasis</p>\n"),
+            ("**foo [bar](/url)**", "<p><strong>foo <a href=\"/url\">bar</a></strong></p>\n"),
+            ("**foo\nbar**", "<p><strong>foo\nbar</strong></p>\n"),
+            ("__foo _bar_ baz__", "<p><strong>foo <em>bar</em> baz</strong></p>\n"),
+            ("__foo __bar__ baz__", "<p><strong>foo <strong>bar</strong> baz</strong></p>\n"),
+            ("____foo__ bar__", "<p><strong><strong>foo</strong> bar</strong></p>\n"),
+            ("**foo **bar****", "<p><strong>foo <strong>bar</strong></strong></p>\n"),
+            ("**foo *bar* baz**", "<p><strong>foo <em>bar</em> baz</strong></p>\n"),
+            ("**foo*bar*baz**", "<p><strong>foo<em>bar</em>baz</strong></p>\n"),
+            ("***foo* bar**", "<p><strong><em>foo</em> bar</strong></p>\n"),
+            ("**foo *bar***", "<p><strong>foo <em>bar</em></strong></p>\n"),
+            ("**foo *bar **baz**\nbim* bop**", "<p><strong>foo <em>bar <strong>baz</strong>\nbim</em> bop</strong></p>\n"),
+            ("**foo [*bar*](/url)**", "<p><strong>foo <a href=\"/url\"><em>bar</em></a></strong></p>\n"),
+            ("__ is not an empty emphasis", "<p>__ is not an empty emphasis</p>\n"),
+            ("____ is not an empty strong emphasis", "<p>____ is not an empty strong emphasis</p>\n"),
+            ("foo ***", "<p>foo ***</p>\n"),
+            ("foo *\\**", "<p>foo <em>*</em></p>\n"),
+            ("foo *_*", "<p>foo <em>_</em></p>\n"),
+            ("foo *****", "<p>foo *****</p>\n"),
+            ("foo **\\***", "<p>foo <strong>*</strong></p>\n"),
+            ("foo **_**", "<p>foo <strong>_</strong></p>\n"),
+            ("**foo*", "<p>*<em>foo</em></p>\n"),
+            ("*foo**", "<p><em>foo</em>*</p>\n"),
+            ("***foo**", "<p>*<strong>foo</strong></p>\n"),
+            ("****foo*", "<p>***<em>foo</em></p>\n"),
+            ("**foo***", "<p><strong>foo</strong>*</p>\n"),
+            ("*foo****", "<p><em>foo</em>***</p>\n"),
+            ("foo ___", "<p>foo ___</p>\n"),
+            ("foo _\\__", "<p>foo <em>_</em></p>\n"),
+            ("foo _*_", "<p>foo <em>*</em></p>\n"),
+            ("foo _____", "<p>foo _____</p>\n"),
+            ("foo __\\___", "<p>foo <strong>_</strong></p>\n"),
+            ("foo __*__", "<p>foo <strong>*</strong></p>\n"),
+            ("__foo_", "<p>_<em>foo</em></p>\n"),
+            ("_foo__", "<p><em>foo</em>_</p>\n"),
+            ("___foo__", "<p>_<strong>foo</strong></p>\n"),
+            ("____foo_", "<p>___<em>foo</em></p>\n"),
+            ("__foo___", "<p><strong>foo</strong>_</p>\n"),
+            ("_foo____", "<p><em>foo</em>___</p>\n"),
+            ("**foo**", "<p><strong>foo</strong></p>\n"),
+            ("*_foo_*", "<p><em><em>foo</em></em></p>\n"),
+            ("__foo__", "<p><strong>foo</strong></p>\n"),
+            ("_*foo*_", "<p><em><em>foo</em></em></p>\n"),
+            ("****foo****", "<p><strong><strong>foo</strong></strong></p>\n"),
+            ("____foo____", "<p><strong><strong>foo</strong></strong></p>\n"),
+            ("******foo******", "<p><strong><strong><strong>foo</strong></strong></strong></p>\n"),
+            ("***foo***", "<p><em><strong>foo</strong></em></p>\n"),
+            ("_____foo_____", "<p><em><strong><strong>foo</strong></strong></em></p>\n"),
+            ("*foo _bar* baz_", "<p><em>foo _bar</em> baz_</p>\n"),
+            ("*foo __bar *baz bim__ bam*", "<p><em>foo <strong>bar *baz bim</strong> bam</em></p>\n"),
+            ("**foo **bar baz**", "<p>**foo <strong>bar baz</strong></p>\n"),
+            ("*foo *bar baz*", "<p>*foo <em>bar baz</em></p>\n"),
+            ("*[bar*](/url)", "<p>*<a href=\"/url\">bar*</a></p>\n"),
+            ("_foo [bar_](/url)", "<p>_foo <a href=\"/url\">bar_</a></p>\n"),
+            ("**a<http://foo.bar/?q=**>", "<p>**a<a href=\"http://foo.bar/?q=**\">http://foo.bar/?q=**</a></p>\n"),
+            ("__a<http://foo.bar/?q=__>", "<p>__a<a href=\"http://foo.bar/?q=__\">http://foo.bar/?q=__</a></p>\n"),
+
+            // Autolinks
+            ("<http://foo.bar.baz>", "<p><a href=\"http://foo.bar.baz\">http://foo.bar.baz</a></p>\n"),
+            ("<http://foo.bar.baz/test?q=hello&id=22&boolean>", "<p><a href=\"http://foo.bar.baz/test?q=hello&amp;id=22&amp;boolean\">http://foo.bar.baz/test?q=hello&amp;id=22&amp;boolean</a></p>\n"),
+            ("<MAILTO:FOO@BAR.BAZ>", "<p><a href=\"MAILTO:FOO@BAR.BAZ\">MAILTO:FOO@BAR.BAZ</a></p>\n"),
+            ("<http://../>", "<p><a href=\"http://../\">http://../</a></p>\n"),
+            ("<http://foo.bar/baz bim>", "<p>&lt;http://foo.bar/baz bim&gt;</p>\n"),
+            ("<foo@bar.example.com>", "<p><a href=\"mailto:foo@bar.example.com\">foo@bar.example.com</a></p>\n"),
+            ("<foo+special@Bar.baz-bar0.com>", "<p><a href=\"mailto:foo+special@Bar.baz-bar0.com\">foo+special@Bar.baz-bar0.com</a></p>\n"),
+            ("<foo\\+@bar.example.com>", "<p>&lt;foo+@bar.example.com&gt;</p>\n"),
+            ("<>", "<p>&lt;&gt;</p>\n"),
+            ("< http://foo.bar >", "<p>&lt; http://foo.bar &gt;</p>\n"),
+            ("<m:abc>", "<p>&lt;m:abc&gt;</p>\n"),
+            ("<foo.bar.baz>", "<p>&lt;foo.bar.baz&gt;</p>\n"),
+            ("http://example.com", "<p>http://example.com</p>\n"),
+            ("foo@bar.example.com", "<p>foo@bar.example.com</p>\n"),
+
+            // Hard line breaks
+            ("foo  \nbaz", "<p>foo<br />\nbaz</p>\n"),
+            ("foo\\\nbaz", "<p>foo<br />\nbaz</p>\n"),
+            ("foo       \nbaz", "<p>foo<br />\nbaz</p>\n"),
+            ("foo  \n     bar", "<p>foo<br />\nbar</p>\n"),
+            ("foo\\\n     bar", "<p>foo<br />\nbar</p>\n"),
+            ("*foo  \nbar*", "<p><em>foo<br />\nbar</em></p>\n"),
+            ("*foo\\\nbar*", "<p><em>foo<br />\nbar</em></p>\n"),
+            ("`code  \nspan`", "<p><code>code   span</code></p>\n"),
+            ("`code\\\nspan`", "<p><code>code\\ span</code></p>\n"),
+            ("foo\\", "<p>foo\\</p>\n"),
+            ("foo  ", "<p>foo</p>\n"),
+            ("### foo\\", "<h3>foo\\</h3>\n"),
+            ("### foo  ", "<h3>foo</h3>\n"),
+
+            // Soft line breaks
+            ("foo\nbaz", "<p>foo\nbaz</p>\n"),
+            ("foo \n baz", "<p>foo\nbaz</p>\n"),
+
+            // Textual content
+            ("hello $.;'there", "<p>hello $.;'there</p>\n"),
+            ("Foo χρῆν", "<p>Foo χρῆν</p>\n"),
+            ("Multiple     spaces", "<p>Multiple     spaces</p>\n"),
+        ]);
+    }
+
+    #[test]
+    fn follows_spec_for_links() {
+        assert_spec_examples(&[
+            // Links
+            ("[link](/uri \"title\")", "<p><a href=\"/uri\" title=\"title\">link</a></p>\n"),
+            ("[link](/uri)", "<p><a href=\"/uri\">link</a></p>\n"),
+            ("[](./target.md)", "<p><a href=\"./target.md\"></a></p>\n"),
+            ("[link]()", "<p><a href=\"\">link</a></p>\n"),
+            ("[link](<>)", "<p><a href=\"\">link</a></p>\n"),
+            ("[]()", "<p><a href=\"\"></a></p>\n"),
+            ("[link](/my uri)", "<p>[link](/my uri)</p>\n"),
+            ("[link](foo\nbar)", "<p>[link](foo\nbar)</p>\n"),
+            ("[a](<b)c>)", "<p><a href=\"b)c\">a</a></p>\n"),
+            ("[link](<foo\\>)", "<p>[link](&lt;foo&gt;)</p>\n"),
+            ("[link](\\(foo\\))", "<p><a href=\"(foo)\">link</a></p>\n"),
+            ("[link](foo(and(bar)))", "<p><a href=\"foo(and(bar))\">link</a></p>\n"),
+            ("[link](foo(and(bar))", "<p>[link](foo(and(bar))</p>\n"),
+            ("[link](foo\\(and\\(bar\\))", "<p><a href=\"foo(and(bar)\">link</a></p>\n"),
+            ("[link](<foo(and(bar)>)", "<p><a href=\"foo(and(bar)\">link</a></p>\n"),
+            ("[link](#fragment)\n\n[link](http://example.com#fragment)\n\n[link](http://example.com?foo=3#frag)", "<p><a href=\"#fragment\">link</a></p>\n<p><a href=\"http://example.com#fragment\">link</a></p>\n<p><a href=\"http://example.com?foo=3#frag\">link</a></p>\n"),
+            ("[link](/url \"title\")\n[link](/url 'title')\n[link](/url (title))", "<p><a href=\"/url\" title=\"title\">link</a>\n<a href=\"/url\" title=\"title\">link</a>\n<a href=\"/url\" title=\"title\">link</a></p>\n"),
+            ("[link](/url \"title \\\"&quot;\")", "<p><a href=\"/url\" title=\"title &quot;&quot;\">link</a></p>\n"),
+            ("[link](/url \"title \"and\" title\")", "<p>[link](/url &quot;title &quot;and&quot; title&quot;)</p>\n"),
+            ("[link](/url 'title \"and\" title')", "<p><a href=\"/url\" title=\"title &quot;and&quot; title\">link</a></p>\n"),
+            ("[link](   /uri\n  \"title\"  )", "<p><a href=\"/uri\" title=\"title\">link</a></p>\n"),
+            ("[link] (/uri)", "<p>[link] (/uri)</p>\n"),
+            ("[link [foo [bar]]](/uri)", "<p><a href=\"/uri\">link [foo [bar]]</a></p>\n"),
+            ("[link] bar](/uri)", "<p>[link] bar](/uri)</p>\n"),
+            ("[link [bar](/uri)", "<p>[link <a href=\"/uri\">bar</a></p>\n"),
+            ("[link \\[bar](/uri)", "<p><a href=\"/uri\">link [bar</a></p>\n"),
+            ("[link *foo **bar** `#`*](/uri)", "<p><a href=\"/uri\">link <em>foo <strong>bar</strong> <code>#</code></em></a></p>\n"),
+            ("[![moon](moon.jpg)](/uri)", "<p><a href=\"/uri\"><img src=\"moon.jpg\" alt=\"moon\" /></a></p>\n"),
+            ("*[foo*](/uri)", "<p>*<a href=\"/uri\">foo*</a></p>\n"),
+            ("[foo *bar](baz*)", "<p><a href=\"baz*\">foo *bar</a></p>\n"),
+            ("*foo [bar* baz]", "<p><em>foo [bar</em> baz]</p>\n"),
+            ("[foo`](/uri)`", "<p>[foo<code>](/uri)</code></p>\n"),
+            ("[foo][bar]\n\n[bar]: /url \"title\"", "<p><a href=\"/url\" title=\"title\">foo</a></p>\n"),
+            ("[link [foo [bar]]][ref]\n\n[ref]: /uri", "<p><a href=\"/uri\">link [foo [bar]]</a></p>\n"),
+            ("[link \\[bar][ref]\n\n[ref]: /uri", "<p><a href=\"/uri\">link [bar</a></p>\n"),
+            ("[foo][BaR]\n\n[bar]: /url \"title\"", "<p><a href=\"/url\" title=\"title\">foo</a></p>\n"),
+            ("[foo] [bar]\n\n[bar]: /url \"title\"", "<p>[foo] <a href=\"/url\" title=\"title\">bar</a></p>\n"),
+            ("[foo]: /url1\n\n[foo]: /url2\n\n[bar][foo]", "<p><a href=\"/url1\">bar</a></p>\n"),
+            ("[bar][foo\\!]\n\n[foo!]: /url", "<p>[bar][foo!]</p>\n"),
+            ("[foo][ref[]\n\n[ref[]: /uri", "<p>[foo][ref[]</p>\n<p>[ref[]: /uri</p>\n"),
+            ("[bar\\\\]: /uri\n\n[bar\\\\]", "<p><a href=\"/uri\">bar\\</a></p>\n"),
+            ("[]\n\n[]: /uri", "<p>[]</p>\n<p>[]: /uri</p>\n"),
+            ("[foo][]\n\n[foo]: /url \"title\"", "<p><a href=\"/url\" title=\"title\">foo</a></p>\n"),
+            ("[*foo* bar][]\n\n[*foo* bar]: /url \"title\"", "<p><a href=\"/url\" title=\"title\"><em>foo</em> bar</a></p>\n"),
+            ("[foo] \n[]\n\n[foo]: /url \"title\"", "<p><a href=\"/url\" title=\"title\">foo</a>\n[]</p>\n"),
+            ("[foo]\n\n[foo]: /url \"title\"", "<p><a href=\"/url\" title=\"title\">foo</a></p>\n"),
+            ("[[*foo* bar]]\n\n[*foo* bar]: /url \"title\"", "<p>[<a href=\"/url\" title=\"title\"><em>foo</em> bar</a>]</p>\n"),
+            ("[[bar [foo]\n\n[foo]: /url", "<p>[[bar <a href=\"/url\">foo</a></p>\n"),
+            ("[foo] bar\n\n[foo]: /url", "<p><a href=\"/url\">foo</a> bar</p>\n"),
+            ("\\[foo]\n\n[foo]: /url \"title\"", "<p>[foo]</p>\n"),
+            ("[foo*]: /url\n\n*[foo*]", "<p>*<a href=\"/url\">foo*</a></p>\n"),
+            ("[foo][bar]\n\n[foo]: /url1\n[bar]: /url2", "<p><a href=\"/url2\">foo</a></p>\n"),
+            ("[foo][]\n\n[foo]: /url1", "<p><a href=\"/url1\">foo</a></p>\n"),
+            ("[foo]()\n\n[foo]: /url1", "<p><a href=\"\">foo</a></p>\n"),
+            ("[foo](not a link)\n\n[foo]: /url1", "<p><a href=\"/url1\">foo</a>(not a link)</p>\n"),
+            ("[foo][bar][baz]\n\n[baz]: /url", "<p>[foo]<a href=\"/url\">bar</a></p>\n"),
+            ("[foo][bar][baz]\n\n[baz]: /url1\n[bar]: /url2", "<p><a href=\"/url2\">foo</a><a href=\"/url1\">baz</a></p>\n"),
+            ("[foo][bar][baz]\n\n[baz]: /url1\n[foo]: /url2", "<p>[foo]<a href=\"/url1\">bar</a></p>\n"),
+
+            // Link reference definitions
+            ("[foo]: /url \"title\"\n\n[foo]", "<p><a href=\"/url\" title=\"title\">foo</a></p>\n"),
+            ("   [foo]: \n      /url  \n           'the title'  \n\n[foo]", "<p><a href=\"/url\" title=\"the title\">foo</a></p>\n"),
+            ("[foo]: /url 'title\n\nwith blank line'\n\n[foo]", "<p>[foo]: /url 'title</p>\n<p>with blank line'</p>\n<p>[foo]</p>\n"),
+            ("[foo]:\n/url\n\n[foo]", "<p><a href=\"/url\">foo</a></p>\n"),
+            ("[foo]:\n\n[foo]", "<p>[foo]:</p>\n<p>[foo]</p>\n"),
+            ("[foo]: <>\n\n[foo]", "<p><a href=\"\">foo</a></p>\n"),
+            ("[foo]\n\n[foo]: url", "<p><a href=\"url\">foo</a></p>\n"),
+            ("[foo]\n\n[foo]: first\n[foo]: second", "<p><a href=\"first\">foo</a></p>\n"),
+            ("[FOO]: /url\n\n[Foo]", "<p><a href=\"/url\">Foo</a></p>\n"),
+            ("[foo]: /url", ""),
+            ("[foo]: /url \"title\" ok", "<p>[foo]: /url &quot;title&quot; ok</p>\n"),
+            ("[foo]: /url\n\"title\" ok", "<p>&quot;title&quot; ok</p>\n"),
+            ("    [foo]: /url \"title\"\n\n[foo]", "<pre><code>[foo]: /url &quot;title&quot;\n</code></pre>\n<p>[foo]</p>\n"),
+            ("```\n[foo]: /url\n```\n\n[foo]", "<pre><code>[foo]: /url\n</code></pre>\n<p>[foo]</p>\n"),
+            ("Foo\n[bar]: /baz\n\n[bar]", "<p>Foo\n[bar]: /baz</p>\n<p>[bar]</p>\n"),
+            ("# [Foo]\n[foo]: /url\n> bar", "<h1><a href=\"/url\">Foo</a></h1>\n<blockquote>\n<p>bar</p>\n</blockquote>\n"),
+            ("[foo]: /url\nbar\n===\n[foo]", "<h1>bar</h1>\n<p><a href=\"/url\">foo</a></p>\n"),
+            ("[foo]: /foo-url \"foo\"\n[bar]: /bar-url\n  \"bar\"\n[baz]: /baz-url\n\n[foo],\n[bar],\n[baz]", "<p><a href=\"/foo-url\" title=\"foo\">foo</a>,\n<a href=\"/bar-url\" title=\"bar\">bar</a>,\n<a href=\"/baz-url\">baz</a></p>\n"),
+            ("[foo]\n\n> [foo]: /url", "<p><a href=\"/url\">foo</a></p>\n<blockquote>\n</blockquote>\n"),
+
+            // Images
+            ("![foo](/url \"title\")", "<p><img src=\"/url\" alt=\"foo\" title=\"title\" /></p>\n"),
+            ("![foo *bar*]\n\n[foo *bar*]: train.jpg \"train & tracks\"", "<p><img src=\"train.jpg\" alt=\"foo bar\" title=\"train &amp; tracks\" /></p>\n"),
+            ("![foo [bar](/url)](/url2)", "<p><img src=\"/url2\" alt=\"foo bar\" /></p>\n"),
+            ("![foo](train.jpg)", "<p><img src=\"train.jpg\" alt=\"foo\" /></p>\n"),
+            ("My ![foo bar](/path/to/train.jpg  \"title\"   )", "<p>My <img src=\"/path/to/train.jpg\" alt=\"foo bar\" title=\"title\" /></p>\n"),
+            ("![foo](<url>)", "<p><img src=\"url\" alt=\"foo\" /></p>\n"),
+            ("![](/url)", "<p><img src=\"/url\" alt=\"\" /></p>\n"),
+            ("![foo][bar]\n\n[bar]: /url", "<p><img src=\"/url\" alt=\"foo\" /></p>\n"),
+            ("![foo][bar]\n\n[BAR]: /url", "<p><img src=\"/url\" alt=\"foo\" /></p>\n"),
+            ("![foo][]\n\n[foo]: /url \"title\"", "<p><img src=\"/url\" alt=\"foo\" title=\"title\" /></p>\n"),
+            ("![*foo* bar][]\n\n[*foo* bar]: /url \"title\"", "<p><img src=\"/url\" alt=\"foo bar\" title=\"title\" /></p>\n"),
+            ("![Foo][]\n\n[foo]: /url \"title\"", "<p><img src=\"/url\" alt=\"Foo\" title=\"title\" /></p>\n"),
+            ("![foo] \n[]\n\n[foo]: /url \"title\"", "<p><img src=\"/url\" alt=\"foo\" title=\"title\" />\n[]</p>\n"),
+            ("![foo]\n\n[foo]: /url \"title\"", "<p><img src=\"/url\" alt=\"foo\" title=\"title\" /></p>\n"),
+            ("![[foo]]\n\n[[foo]]: /url \"title\"", "<p>![[foo]]</p>\n<p>[[foo]]: /url &quot;title&quot;</p>\n"),
+            ("\\!\\[foo]\n\n[foo]: /url \"title\"", "<p>![foo]</p>\n"),
+            ("\\![foo]\n\n[foo]: /url \"title\"", "<p>!<a href=\"/url\" title=\"title\">foo</a></p>\n"),
+        ]);
+    }
+}
@@ -0,0 +1,1510 @@
+/* Converts LaTeX math to MathML, so equations in exports render without client-side JavaScript.
+
+Covers the commonly used part of LaTeX and AMS math: scripts, fractions, roots, Greek letters,
+operators, arrows, accents, fonts, `\left` and `\right` delimiters, `\text` and the matrix,
+cases, array and aligned environments. Anything else is rejected with an error, rather than
+rendered differently than the author expects. */
+
+/* Groups, arguments and environments nested deeper than this are rejected, which keeps parsing
+from exhausting the stack. */
+const MAX_NESTING: usize = 64;
+
+/* Letters and letter-like symbols, written as identifiers. */
+const IDENTIFIERS: &[(&str, &str)] = &[
+    ("alpha", "\u{3B1}"),
+    ("beta", "\u{3B2}"),
+    ("gamma", "\u{3B3}"),
+    ("delta", "\u{3B4}"),
+    ("epsilon", "\u{3F5}"),
+    ("varepsilon", "\u{3B5}"),
+    ("zeta", "\u{3B6}"),
+    ("eta", "\u{3B7}"),
+    ("theta", "\u{3B8}"),
+    ("vartheta", "\u{3D1}"),
+    ("iota", "\u{3B9}"),
+    ("kappa", "\u{3BA}"),
+    ("varkappa", "\u{3F0}"),
+    ("lambda", "\u{3BB}"),
+    ("mu", "\u{3BC}"),
+    ("nu", "\u{3BD}"),
+    ("xi", "\u{3BE}"),
+    ("omicron", "\u{3BF}"),
+    ("pi", "\u{3C0}"),
+    ("varpi", "\u{3D6}"),
+    ("rho", "\u{3C1}"),
+    ("varrho", "\u{3F1}"),
+    ("sigma", "\u{3C3}"),
+    ("varsigma", "\u{3C2}"),
+    ("tau", "\u{3C4}"),
+    ("upsilon", "\u{3C5}"),
+    ("phi", "\u{3D5}"),
+    ("varphi", "\u{3C6}"),
+    ("chi", "\u{3C7}"),
+    ("psi", "\u{3C8}"),
+    ("omega", "\u{3C9}"),
+    ("digamma", "\u{3DD}"),
+    ("infty", "\u{221E}"),
+    ("partial", "\u{2202}"),
+    ("nabla", "\u{2207}"),
+    ("hbar", "\u{210F}"),
+    ("hslash", "\u{210F}"),
+    ("ell", "\u{2113}"),
+    ("wp", "\u{2118}"),
+    ("Re", "\u{211C}"),
+    ("Im", "\u{2111}"),
+    ("aleph", "\u{2135}"),
+    ("beth", "\u{2136}"),
+    ("mho", "\u{2127}"),
+    ("emptyset", "\u{2205}"),
+    ("varnothing", "\u{2205}"),
+    ("imath", "\u{131}"),
+    ("jmath", "\u{237}"),
+    ("angle", "\u{2220}"),
+    ("triangle", "\u{25B3}"),
+    ("top", "\u{22A4}"),
+    ("bot", "\u{22A5}"),
+    ("complement", "\u{2201}"),
+    ("$", "$"),
+    ("#", "#"),
+    ("_", "_"),
+];
+
+/* Capital Greek letters, which are upright unlike other letters. */
+const CAPITAL_GREEK: &[(&str, &str)] = &[
+    ("Gamma", "\u{393}"),
+    ("Delta", "\u{394}"),
+    ("Theta", "\u{398}"),
+    ("Lambda", "\u{39B}"),
+    ("Xi", "\u{39E}"),
+    ("Pi", "\u{3A0}"),
+    ("Sigma", "\u{3A3}"),
+    ("Upsilon", "\u{3A5}"),
+    ("Phi", "\u{3A6}"),
+    ("Psi", "\u{3A8}"),
+    ("Omega", "\u{3A9}"),
+];
+
+/* Binary operators, relations, arrows and punctuation. */
+const OPERATORS: &[(&str, &str)] = &[
+    ("pm", "\u{B1}"),
+    ("mp", "\u{2213}"),
+    ("times", "\u{D7}"),
+    ("div", "\u{F7}"),
+    ("cdot", "\u{22C5}"),
+    ("ast", "\u{2217}"),
+    ("star", "\u{22C6}"),
+    ("circ", "\u{2218}"),
+    ("bullet", "\u{2219}"),
+    ("oplus", "\u{2295}"),
+    ("ominus", "\u{2296}"),
+    ("otimes", "\u{2297}"),
+    ("oslash", "\u{2298}"),
+    ("odot", "\u{2299}"),
+    ("cup", "\u{222A}"),
+    ("cap", "\u{2229}"),
+    ("sqcup", "\u{2294}"),
+    ("sqcap", "\u{2293}"),
+    ("uplus", "\u{228E}"),
+    ("setminus", "\u{2216}"),
+    ("wedge", "\u{2227}"),
+    ("land", "\u{2227}"),
+    ("vee", "\u{2228}"),
+    ("lor", "\u{2228}"),
+    ("neg", "\u{AC}"),
+    ("lnot", "\u{AC}"),
+    ("dagger", "\u{2020}"),
+    ("ddagger", "\u{2021}"),
+    ("amalg", "\u{2A3F}"),
+    ("wr", "\u{2240}"),
+    ("leq", "\u{2264}"),
+    ("le", "\u{2264}"),
+    ("geq", "\u{2265}"),
+    ("ge", "\u{2265}"),
+    ("leqslant", "\u{2A7D}"),
+    ("geqslant", "\u{2A7E}"),
+    ("neq", "\u{2260}"),
+    ("ne", "\u{2260}"),
+    ("lt", "<"),
+    ("gt", ">"),
+    ("nless", "\u{226E}"),
+    ("ngtr", "\u{226F}"),
+    ("nleq", "\u{2270}"),
+    ("ngeq", "\u{2271}"),
+    ("approx", "\u{2248}"),
+    ("approxeq", "\u{224A}"),
+    ("equiv", "\u{2261}"),
+    ("sim", "\u{223C}"),
+    ("simeq", "\u{2243}"),
+    ("cong", "\u{2245}"),
+    ("ncong", "\u{2247}"),
+    ("propto", "\u{221D}"),
+    ("ll", "\u{226A}"),
+    ("gg", "\u{226B}"),
+    ("prec", "\u{227A}"),
+    ("succ", "\u{227B}"),
+    ("preceq", "\u{2AAF}"),
+    ("succeq", "\u{2AB0}"),
+    ("doteq", "\u{2250}"),
+    ("triangleq", "\u{225C}"),
+    ("coloneqq", "\u{2254}"),
+    ("subset", "\u{2282}"),
+    ("supset", "\u{2283}"),
+    ("subseteq", "\u{2286}"),
+    ("supseteq", "\u{2287}"),
+    ("nsubseteq", "\u{2288}"),
+    ("nsupseteq", "\u{2289}"),
+    ("subsetneq", "\u{228A}"),
+    ("supsetneq", "\u{228B}"),
+    ("in", "\u{2208}"),
+    ("notin", "\u{2209}"),
+    ("ni", "\u{220B}"),
+    ("mid", "\u{2223}"),
+    ("nmid", "\u{2224}"),
+    ("parallel", "\u{2225}"),
+    ("nparallel", "\u{2226}"),
+    ("perp", "\u{27C2}"),
+    ("vdash", "\u{22A2}"),
+    ("dashv", "\u{22A3}"),
+    ("models", "\u{22A8}"),
+    ("to", "\u{2192}"),
+    ("rightarrow", "\u{2192}"),
+    ("leftarrow", "\u{2190}"),
+    ("gets", "\u{2190}"),
+    ("leftrightarrow", "\u{2194}"),
+    ("Rightarrow", "\u{21D2}"),
+    ("Leftarrow", "\u{21D0}"),
+    ("Leftrightarrow", "\u{21D4}"),
+    ("longrightarrow", "\u{27F6}"),
+    ("longleftarrow", "\u{27F5}"),
+    ("longleftrightarrow", "\u{27F7}"),
+    ("Longrightarrow", "\u{27F9}"),
+    ("Longleftarrow", "\u{27F8}"),
+    ("Longleftrightarrow", "\u{27FA}"),
+    ("implies", "\u{27F9}"),
+    ("impliedby", "\u{27F8}"),
+    ("iff", "\u{27FA}"),
+    ("mapsto", "\u{21A6}"),
+    ("longmapsto", "\u{27FC}"),
+    ("hookrightarrow", "\u{21AA}"),
+    ("hookleftarrow", "\u{21A9}"),
+    ("rightharpoonup", "\u{21C0}"),
+    ("leftharpoonup", "\u{21BC}"),
+    ("rightleftharpoons", "\u{21CC}"),
+    ("nearrow", "\u{2197}"),
+    ("searrow", "\u{2198}"),
+    ("swarrow", "\u{2199}"),
+    ("nwarrow", "\u{2196}"),
+    ("forall", "\u{2200}"),
+    ("exists", "\u{2203}"),
+    ("nexists", "\u{2204}"),
+    ("therefore", "\u{2234}"),
+    ("because", "\u{2235}"),
+    ("colon", ":"),
+    ("ldots", "\u{2026}"),
+    ("dots", "\u{2026}"),
+    ("cdots", "\u{22EF}"),
+    ("vdots", "\u{22EE}"),
+    ("ddots", "\u{22F1}"),
+    ("prime", "\u{2032}"),
+    ("%", "%"),
+    ("&", "&"),
+];
+
+/* Brackets and bars, which can also follow `\left`, `\right` and `\big`. */
+const DELIMITERS: &[(&str, &str)] = &[
+    ("{", "{"),
+    ("}", "}"),
+    ("lbrace", "{"),
+    ("rbrace", "}"),
+    ("lbrack", "["),
+    ("rbrack", "]"),
+    ("langle", "\u{27E8}"),
+    ("rangle", "\u{27E9}"),
+    ("lfloor", "\u{230A}"),
+    ("rfloor", "\u{230B}"),
+    ("lceil", "\u{2308}"),
+    ("rceil", "\u{2309}"),
+    ("vert", "|"),
+    ("lvert", "|"),
+    ("rvert", "|"),
+    ("|", "\u{2016}"),
+    ("Vert", "\u{2016}"),
+    ("lVert", "\u{2016}"),
+    ("rVert", "\u{2016}"),
+    ("backslash", "\\"),
+    ("uparrow", "\u{2191}"),
+    ("downarrow", "\u{2193}"),
+    ("updownarrow", "\u{2195}"),
+    ("Uparrow", "\u{21D1}"),
+    ("Downarrow", "\u{21D3}"),
+];
+
+/* Sums, integrals and the like, and whether they take limits above and below in display mode. */
+const LARGE_OPERATORS: &[(&str, &str, bool)] = &[
+    ("sum", "\u{2211}", true),
+    ("prod", "\u{220F}", true),
+    ("coprod", "\u{2210}", true),
+    ("bigcup", "\u{22C3}", true),
+    ("bigcap", "\u{22C2}", true),
+    ("bigvee", "\u{22C1}", true),
+    ("bigwedge", "\u{22C0}", true),
+    ("bigoplus", "\u{2A01}", true),
+    ("bigotimes", "\u{2A02}", true),
+    ("bigodot", "\u{2A00}", true),
+    ("biguplus", "\u{2A04}", true),
+    ("bigsqcup", "\u{2A06}", true),
+    ("int", "\u{222B}", false),
+    ("iint", "\u{222C}", false),
+    ("iiint", "\u{222D}", false),
+    ("oint", "\u{222E}", false),
+    ("oiint", "\u{222F}", false),
+];
+
+/* Function names set in upright type, and whether they take limits below in display mode. */
+const FUNCTIONS: &[(&str, &str, bool)] = &[
+    ("arccos", "arccos", false),
+    ("arcsin", "arcsin", false),
+    ("arctan", "arctan", false),
+    ("arg", "arg", false),
+    ("cos", "cos", false),
+    ("cosh", "cosh", false),
+    ("cot", "cot", false),
+    ("coth", "coth", false),
+    ("csc", "csc", false),
+    ("deg", "deg", false),
+    ("dim", "dim", false),
+    ("exp", "exp", false),
+    ("hom", "hom", false),
+    ("ker", "ker", false),
+    ("lg", "lg", false),
+    ("ln", "ln", false),
+    ("log", "log", false),
+    ("sec", "sec", false),
+    ("sin", "sin", false),
+    ("sinh", "sinh", false),
+    ("tan", "tan", false),
+    ("tanh", "tanh", false),
+    ("det", "det", true),
+    ("gcd", "gcd", true),
+    ("inf", "inf", true),
+    ("lim", "lim", true),
+    ("liminf", "lim inf", true),
+    ("limsup", "lim sup", true),
+    ("max", "max", true),
+    ("min", "min", true),
+    ("Pr", "Pr", true),
+    ("sup", "sup", true),
+];
+
+/* Accents above their argument, and whether they stretch to its width. */
+const ACCENTS: &[(&str, &str, bool)] = &[
+    ("hat", "^", false),
+    ("widehat", "^", true),
+    ("check", "\u{2C7}", false),
+    ("widecheck", "\u{2C7}", true),
+    ("tilde", "~", false),
+    ("widetilde", "~", true),
+    ("acute", "\u{B4}", false),
+    ("grave", "`", false),
+    ("dot", "\u{2D9}", false),
+    ("ddot", "\u{A8}", false),
+    ("breve", "\u{2D8}", false),
+    ("bar", "\u{AF}", false),
+    ("mathring", "\u{2DA}", false),
+    ("vec", "\u{2192}", false),
+    ("overline", "\u{203E}", true),
+    ("overrightarrow", "\u{2192}", true),
+    ("overleftarrow", "\u{2190}", true),
+    ("overleftrightarrow", "\u{2194}", true),
+];
+
+const SPACES: &[(&str, &str)] = &[
+    (",", "0.1667em"),
+    ("thinspace", "0.1667em"),
+    (":", "0.2222em"),
+    (">", "0.2222em"),
+    ("medspace", "0.2222em"),
+    (";", "0.2778em"),
+    ("thickspace", "0.2778em"),
+    ("!", "-0.1667em"),
+    ("negthinspace", "-0.1667em"),
+    ("enspace", "0.5em"),
+    ("quad", "1em"),
+    ("qquad", "2em"),
+];
+
+/* Heights of `\big` and its larger siblings. */
+const BIG_SIZES: &[(&str, &str)] = &[
+    ("big", "1.2em"),
+    ("Big", "1.8em"),
+    ("bigg", "2.4em"),
+    ("Bigg", "3em"),
+];
+
+const FONTS: &[(&str, Variant)] = &[
+    ("mathrm", Variant::Normal),
+    ("mathbf", Variant::Bold),
+    ("mathit", Variant::Italic),
+    ("boldsymbol", Variant::BoldItalic),
+    ("bm", Variant::BoldItalic),
+    ("mathbb", Variant::DoubleStruck),
+    ("mathcal", Variant::Script),
+    ("mathscr", Variant::Script),
+    ("mathfrak", Variant::Fraktur),
+    ("mathsf", Variant::SansSerif),
+    ("mathtt", Variant::Monospace),
+];
+
+const TEXT_COMMANDS: &[(&str, Variant)] = &[
+    ("text", Variant::Default),
+    ("textrm", Variant::Default),
+    ("textnormal", Variant::Default),
+    ("mbox", Variant::Default),
+    ("textbf", Variant::Bold),
+    ("textit", Variant::Italic),
+    ("textsf", Variant::SansSerif),
+    ("texttt", Variant::Monospace),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Char(char),
+    /* A control sequence without its backslash, like `frac` or `{`. */
+    Command(String),
+    End,
+}
+
+/* Fonts, which MathML expresses with the Mathematical Alphanumeric Symbols of Unicode. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Variant {
+    Default,
+    Normal,
+    Bold,
+    Italic,
+    BoldItalic,
+    DoubleStruck,
+    Script,
+    Fraktur,
+    SansSerif,
+    Monospace,
+}
+
+struct Atom {
+    mathml: String,
+    /* Set for large operators and function names, which can be followed by `\limits`. */
+    operator: bool,
+    /* Whether scripts go above and below instead of to the right. */
+    limits: bool,
+    /* Function names are followed by an invisible function application. */
+    function: bool,
+}
+
+impl Atom {
+    fn new(mathml: String) -> Atom {
+        Atom {
+            mathml,
+            operator: false,
+            limits: false,
+            function: false,
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+    display: bool,
+    variant: Variant,
+    depth: usize,
+}
+
+pub fn validate(latex: &str) -> Result<(), String> {
+    to_mathml(latex, false).map(|_| ())
+}
+
+/* A `<math>` element, which keeps the LaTeX source as an annotation for copying. */
+pub fn to_mathml(latex: &str, display: bool) -> Result<String, String> {
+    let mut parser = Parser {
+        source: latex,
+        position: 0,
+        display,
+        variant: Variant::Default,
+        depth: 0,
+    };
+    let mathml = parser.parse_expression(false)?;
+    match parser.next() {
+        Token::End => (),
+        token => return Err(unexpected(&token)),
+    }
+
+    Ok(format!(
+        "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"{}><semantics><mrow>{}</mrow>\
+         <annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        if display { " display=\"block\"" } else { "" },
+        mathml,
+        escape(latex)
+    ))
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Token {
+        self.read_token().0
+    }
+
+    fn next(&mut self) -> Token {
+        let (token, position) = self.read_token();
+        self.position = position;
+        token
+    }
+
+    /* The next token and the position after it. Spaces and comments are skipped, like TeX does
+    in math mode. */
+    fn read_token(&self) -> (Token, usize) {
+        let mut position = self.position;
+        loop {
+            let rest = &self.source[position..];
+            let c = match rest.chars().next() {
+                Some(c) => c,
+                None => return (Token::End, position),
+            };
+            if c.is_whitespace() {
+                position += c.len_utf8();
+            } else if c == '%' {
+                position += rest.find('\n').unwrap_or(rest.len());
+            } else if c != '\\' {
+                return (Token::Char(c), position + c.len_utf8());
+            } else {
+                let name_length = rest[1..]
+                    .find(|c: char| !c.is_ascii_alphabetic())
+                    .unwrap_or(rest.len() - 1);
+                if name_length > 0 {
+                    let name = &rest[1..=name_length];
+                    return (Token::Command(name.to_string()), position + 1 + name_length);
+                }
+                return match rest[1..].chars().next() {
+                    Some(c) => (Token::Command(c.to_string()), position + 1 + c.len_utf8()),
+                    None => (Token::Command(String::new()), position + 1),
+                };
+            }
+        }
+    }
+
+    /* Items up to the end of the group, cell or formula. */
+    fn parse_expression(&mut self, in_brackets: bool) -> Result<String, String> {
+        let mut mathml = String::new();
+        // Style switches apply to the rest of the expression, including where limits go.
+        let mut styles = 0;
+        let display = self.display;
+        loop {
+            match self.peek() {
+                Token::End | Token::Char('}') | Token::Char('&') => break,
+                Token::Char(']') if in_brackets => break,
+                Token::Command(ref name) if is_terminator(name) => break,
+                Token::Command(ref name) if name == "displaystyle" || name == "textstyle" => {
+                    mathml.push_str(&format!(
+                        "<mstyle displaystyle=\"{}\">",
+                        name == "displaystyle"
+                    ));
+                    styles += 1;
+                    self.display = name == "displaystyle";
+                    self.next();
+                }
+                _ => mathml.push_str(&self.parse_scripted()?),
+            }
+        }
+        self.display = display;
+        mathml.push_str(&"</mstyle>".repeat(styles));
+        Ok(mathml)
+    }
+
+    fn parse_scripted(&mut self) -> Result<String, String> {
+        let mut atom = self.parse_atom(false)?;
+        let mut subscript = None;
+        let mut superscript = None;
+        let mut primes = 0;
+        loop {
+            match self.peek() {
+                Token::Char('^') | Token::Char('\'') if superscript.is_some() => {
+                    return Err("Double superscript, use braces to clarify".to_string());
+                }
+                Token::Char('_') if subscript.is_some() => {
+                    return Err("Double subscript, use braces to clarify".to_string());
+                }
+                Token::Char('^') => {
+                    self.next();
+                    superscript = Some(self.parse_argument("^")?);
+                }
+                Token::Char('_') => {
+                    self.next();
+                    subscript = Some(self.parse_argument("_")?);
+                }
+                Token::Char('\'') => {
+                    self.next();
+                    primes += 1;
+                }
+                Token::Command(ref name) if name == "limits" || name == "nolimits" => {
+                    if !atom.operator {
+                        return Err(format!("\\{} must follow an operator like \\sum", name));
+                    }
+                    self.next();
+                    atom.limits = name == "limits";
+                }
+                _ => break,
+            }
+        }
+        if primes > 0 {
+            let primes = format!("<mo>{}</mo>", "\u{2032}".repeat(primes));
+            superscript = Some(match superscript {
+                Some(superscript) => format!("<mrow>{}{}</mrow>", primes, superscript),
+                None => primes,
+            });
+        }
+
+        let (sub, sup, subsup) = if atom.limits {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        let mut mathml = match (subscript, superscript) {
+            (None, None) => atom.mathml,
+            (Some(subscript), None) => format!("<{0}>{1}{2}</{0}>", sub, atom.mathml, subscript),
+            (None, Some(superscript)) => {
+                format!("<{0}>{1}{2}</{0}>", sup, atom.mathml, superscript)
+            }
+            (Some(subscript), Some(superscript)) => format!(
+                "<{0}>{1}{2}{3}</{0}>",
+                subsup, atom.mathml, subscript, superscript
+            ),
+        };
+        if atom.function {
+            mathml.push_str("<mo>\u{2061}</mo>");
+        }
+        Ok(mathml)
+    }
+
+    /* A braced group or a single token, like the arguments of `\frac` and scripts. */
+    fn parse_argument(&mut self, command: &str) -> Result<String, String> {
+        match self.peek() {
+            Token::End
+            | Token::Char('}')
+            | Token::Char('&')
+            | Token::Char('^')
+            | Token::Char('_') => Err(format!("Missing argument for {}", command)),
+            Token::Command(ref name) if is_terminator(name) => {
+                Err(format!("Missing argument for {}", command))
+            }
+            _ => Ok(self.parse_atom(true)?.mathml),
+        }
+    }
+
+    /* A single element. With `single`, a number only takes its first digit, like in `\frac12`. */
+    fn parse_atom(&mut self, single: bool) -> Result<Atom, String> {
+        if self.depth == MAX_NESTING {
+            return Err(format!(
+                "Math can be nested at most {} levels deep",
+                MAX_NESTING
+            ));
+        }
+
+        self.depth += 1;
+        let atom = match self.peek() {
+            // Scripts without a base, like `^2`, attach to an empty one.
+            Token::Char('^') | Token::Char('_') => Ok(Atom::new("<mrow></mrow>".to_string())),
+            _ => match self.next() {
+                Token::Char(c) => self.parse_char(c, single),
+                Token::Command(name) => self.parse_command(&name),
+                Token::End => Err(unexpected(&Token::End)),
+            },
+        };
+        self.depth -= 1;
+        atom
+    }
+
+    fn parse_char(&mut self, c: char, single: bool) -> Result<Atom, String> {
+        let mathml = match c {
+            '{' => format!("<mrow>{}</mrow>", self.parse_group()?),
+            '\'' => "<mo>\u{2032}</mo>".to_string(),
+            '~' => "<mtext>\u{A0}</mtext>".to_string(),
+            '-' => "<mo>\u{2212}</mo>".to_string(),
+            '*' => "<mo>\u{2217}</mo>".to_string(),
+            '(' | ')' | '[' | ']' | '|' | '/' => fence(&c.to_string()),
+            '$' => return Err("Unexpected '$', math doesn't need dollar signs".to_string()),
+            '#' | '}' | '&' => return Err(unexpected(&Token::Char(c))),
+            _ if c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                if !single {
+                    let bytes = &self.source.as_bytes()[self.position..];
+                    let mut length = 0;
+                    while bytes.get(length).is_some_and(u8::is_ascii_digit)
+                        || (bytes.get(length) == Some(&b'.')
+                            && bytes.get(length + 1).is_some_and(u8::is_ascii_digit))
+                    {
+                        length += 1;
+                    }
+                    number.push_str(&self.source[self.position..self.position + length]);
+                    self.position += length;
+                }
+                let number: String = number.chars().map(|c| styled(c, self.variant)).collect();
+                format!("<mn>{}</mn>", number)
+            }
+            _ if c.is_alphabetic() => self.identifier(c),
+            _ => format!("<mo>{}</mo>", escape(&c.to_string())),
+        };
+        Ok(Atom::new(mathml))
+    }
+
+    fn parse_command(&mut self, name: &str) -> Result<Atom, String> {
+        if let Some((_, symbol)) = IDENTIFIERS.iter().find(|(n, _)| *n == name) {
+            let c = symbol.chars().next().unwrap_or_default();
+            return Ok(Atom::new(self.identifier(c)));
+        }
+        if let Some((_, symbol)) = CAPITAL_GREEK.iter().find(|(n, _)| *n == name) {
+            // Capital Greek letters are upright unless a font with Greek letters says otherwise.
+            let c = symbol.chars().next().unwrap_or_default();
+            let letter = styled(c, self.variant);
+            return Ok(Atom::new(if letter == c {
+                format!("<mi mathvariant=\"normal\">{}</mi>", symbol)
+            } else {
+                format!("<mi>{}</mi>", letter)
+            }));
+        }
+        if let Some((_, symbol)) = OPERATORS.iter().find(|(n, _)| *n == name) {
+            return Ok(Atom::new(format!("<mo>{}</mo>", escape(symbol))));
+        }
+        if let Some((_, symbol)) = DELIMITERS.iter().find(|(n, _)| *n == name) {
+            return Ok(Atom::new(fence(symbol)));
+        }
+        if let Some((_, symbol, limits)) = LARGE_OPERATORS.iter().find(|(n, _, _)| *n == name) {
+            return Ok(Atom {
+                mathml: format!("<mo>{}</mo>", symbol),
+                operator: true,
+                limits: self.display && *limits,
+                function: false,
+            });
+        }
+        if let Some((_, text, limits)) = FUNCTIONS.iter().find(|(n, _, _)| *n == name) {
+            return Ok(Atom {
+                mathml: format!("<mi>{}</mi>", text),
+                operator: true,
+                limits: self.display && *limits,
+                function: true,
+            });
+        }
+        if let Some((_, accent, stretchy)) = ACCENTS.iter().find(|(n, _, _)| *n == name) {
+            let base = self.parse_argument(&format!("\\{}", name))?;
+            return Ok(Atom::new(format!(
+                "<mover accent=\"true\">{}<mo stretchy=\"{}\">{}</mo></mover>",
+                base, stretchy, accent
+            )));
+        }
+        if let Some((_, width)) = SPACES.iter().find(|(n, _)| *n == name) {
+            return Ok(Atom::new(format!("<mspace width=\"{}\"/>", width)));
+        }
+        if let Some((_, variant)) = FONTS.iter().find(|(n, _)| *n == name) {
+            let previous = std::mem::replace(&mut self.variant, *variant);
+            let argument = self.parse_argument(&format!("\\{}", name));
+            self.variant = previous;
+            return Ok(Atom::new(argument?));
+        }
+        if let Some((_, variant)) = TEXT_COMMANDS.iter().find(|(n, _)| *n == name) {
+            let text = self.parse_text(&format!("\\{}", name))?;
+            let text: String = text.chars().map(|c| styled(c, *variant)).collect();
+            return Ok(Atom::new(format!("<mtext>{}</mtext>", escape(&text))));
+        }
+        let big = name.strip_suffix(['l', 'r', 'm']).unwrap_or(name);
+        if let Some((_, size)) = BIG_SIZES.iter().find(|(n, _)| *n == big) {
+            return Ok(Atom::new(
+                match self.parse_delimiter(&format!("\\{}", name))? {
+                    Some(delimiter) => format!(
+                        "<mo minsize=\"{0}\" maxsize=\"{0}\">{1}</mo>",
+                        size,
+                        escape(delimiter)
+                    ),
+                    None => "<mrow></mrow>".to_string(),
+                },
+            ));
+        }
+
+        let mathml = match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.parse_argument(&format!("\\{}", name))?;
+                let denominator = self.parse_argument(&format!("\\{}", name))?;
+                let fraction = format!("<mfrac>{}{}</mfrac>", numerator, denominator);
+                match name {
+                    "dfrac" | "cfrac" => {
+                        format!("<mstyle displaystyle=\"true\">{}</mstyle>", fraction)
+                    }
+                    "tfrac" => format!("<mstyle displaystyle=\"false\">{}</mstyle>", fraction),
+                    _ => fraction,
+                }
+            }
+            "binom" | "dbinom" | "tbinom" => {
+                let top = self.parse_argument(&format!("\\{}", name))?;
+                let bottom = self.parse_argument(&format!("\\{}", name))?;
+                format!(
+                    "<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>",
+                    top, bottom
+                )
+            }
+            "sqrt" => {
+                let index = if self.peek() == Token::Char('[') {
+                    self.next();
+                    let index = self.parse_expression(true)?;
+                    match self.next() {
+                        Token::Char(']') => Some(index),
+                        Token::End => return Err("Missing ']' after \\sqrt[".to_string()),
+                        token => return Err(unexpected(&token)),
+                    }
+                } else {
+                    None
+                };
+                let radicand = self.parse_argument("\\sqrt")?;
+                match index {
+                    Some(index) => format!("<mroot>{}<mrow>{}</mrow></mroot>", radicand, index),
+                    None => format!("<msqrt>{}</msqrt>", radicand),
+                }
+            }
+            "overset" | "stackrel" | "underset" => {
+                let script = self.parse_argument(&format!("\\{}", name))?;
+                let base = self.parse_argument(&format!("\\{}", name))?;
+                let element = if name == "underset" {
+                    "munder"
+                } else {
+                    "mover"
+                };
+                format!("<{0}>{1}{2}</{0}>", element, base, script)
+            }
+            "overbrace" | "underbrace" => {
+                // Scripts label the brace, so they go above or below it.
+                let base = self.parse_argument(&format!("\\{}", name))?;
+                let mathml = if name == "overbrace" {
+                    format!("<mover>{}<mo stretchy=\"true\">\u{23DE}</mo></mover>", base)
+                } else {
+                    format!(
+                        "<munder>{}<mo stretchy=\"true\">\u{23DF}</mo></munder>",
+                        base
+                    )
+                };
+                return Ok(Atom {
+                    mathml,
+                    operator: true,
+                    limits: true,
+                    function: false,
+                });
+            }
+            "underline" => {
+                let base = self.parse_argument("\\underline")?;
+                format!(
+                    "<munder accentunder=\"true\">{}<mo stretchy=\"true\">\u{203E}</mo></munder>",
+                    base
+                )
+            }
+            "operatorname" => {
+                let limits = self.source[self.position..].starts_with('*');
+                if limits {
+                    self.position += 1;
+                }
+                let text = self.parse_text("\\operatorname")?;
+                let mathml = if text.chars().count() == 1 {
+                    format!("<mi mathvariant=\"normal\">{}</mi>", escape(&text))
+                } else {
+                    format!("<mi>{}</mi>", escape(&text))
+                };
+                return Ok(Atom {
+                    mathml,
+                    operator: true,
+                    limits: self.display && limits,
+                    function: true,
+                });
+            }
+            "not" => {
+                let relation = self.parse_argument("\\not")?;
+                match relation.strip_suffix("</mo>") {
+                    Some(relation) if relation.starts_with("<mo>") => {
+                        format!("{}\u{338}</mo>", relation)
+                    }
+                    _ => return Err("\\not must be followed by a relation like =".to_string()),
+                }
+            }
+            "left" => self.parse_left()?,
+            "begin" => {
+                let environment = self.parse_text("\\begin")?;
+                self.parse_environment(&environment)?
+            }
+            "limits" | "nolimits" => {
+                return Err(format!("\\{} must follow an operator like \\sum", name));
+            }
+            "" => return Err("Unexpected '\\' at the end".to_string()),
+            _ if name.chars().all(char::is_whitespace) => "<mtext>\u{A0}</mtext>".to_string(),
+            _ if is_terminator(name) => return Err(unexpected(&Token::Command(name.to_string()))),
+            _ => return Err(format!("Unknown command \\{}", name)),
+        };
+        Ok(Atom::new(mathml))
+    }
+
+    /* The contents of a group whose `{` was already read. */
+    fn parse_group(&mut self) -> Result<String, String> {
+        let mathml = self.parse_expression(false)?;
+        match self.next() {
+            Token::Char('}') => Ok(mathml),
+            Token::End => Err("Missing '}'".to_string()),
+            token => Err(unexpected(&token)),
+        }
+    }
+
+    /* The raw text of a braced argument, like of `\text` and environment names. */
+    fn parse_text(&mut self, command: &str) -> Result<String, String> {
+        let rest = self.source[self.position..].trim_start();
+        if !rest.starts_with('{') {
+            return Err(format!("Missing '{{' after {}", command));
+        }
+        self.position = self.source.len() - rest.len() + 1;
+
+        let mut text = String::new();
+        let mut depth = 0;
+        let mut chars = self.source[self.position..].char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    self.position += index + 1;
+                    return Ok(text);
+                }
+                '}' => depth -= 1,
+                '\\' => match chars.next() {
+                    Some((_, c)) if "{}$%&#_".contains(c) => text.push(c),
+                    Some((_, c)) if c.is_whitespace() => text.push('\u{A0}'),
+                    _ => return Err(format!("Commands aren't supported in {}", command)),
+                },
+                // Like in TeX, runs of spaces are one space.
+                _ if c.is_whitespace() => {
+                    if !text.ends_with('\u{A0}') {
+                        text.push('\u{A0}');
+                    }
+                }
+                _ => text.push(c),
+            }
+        }
+        Err(format!("Missing '}}' after {}", command))
+    }
+
+    /* The delimiter after `\left`, `\right` or `\big`, or `None` for the invisible `.`. */
+    fn parse_delimiter(&mut self, command: &str) -> Result<Option<&'static str>, String> {
+        let delimiter = match self.next() {
+            Token::Char('.') => return Ok(None),
+            Token::Char('(') => "(",
+            Token::Char(')') => ")",
+            Token::Char('[') => "[",
+            Token::Char(']') => "]",
+            Token::Char('|') => "|",
+            Token::Char('/') => "/",
+            Token::Char('<') => "\u{27E8}",
+            Token::Char('>') => "\u{27E9}",
+            Token::Command(name) => match DELIMITERS.iter().find(|(n, _)| *n == name) {
+                Some((_, delimiter)) => delimiter,
+                None => "",
+            },
+            _ => "",
+        };
+        if delimiter.is_empty() {
+            Err(format!("Missing or unknown delimiter after {}", command))
+        } else {
+            Ok(Some(delimiter))
+        }
+    }
+
+    /* Everything from after `\left` to the matching `\right`, with `\middle` in between. */
+    fn parse_left(&mut self) -> Result<String, String> {
+        let mut mathml = String::from("<mrow>");
+        mathml.push_str(&stretchy_fence(self.parse_delimiter("\\left")?));
+        loop {
+            mathml.push_str(&self.parse_expression(false)?);
+            match self.next() {
+                Token::Command(ref name) if name == "middle" => {
+                    mathml.push_str(&stretchy_fence(self.parse_delimiter("\\middle")?));
+                }
+                Token::Command(ref name) if name == "right" => {
+                    mathml.push_str(&stretchy_fence(self.parse_delimiter("\\right")?));
+                    break;
+                }
+                Token::End => return Err("Missing \\right after \\left".to_string()),
+                token => return Err(unexpected(&token)),
+            }
+        }
+        mathml.push_str("</mrow>");
+        Ok(mathml)
+    }
+
+    fn parse_environment(&mut self, name: &str) -> Result<String, String> {
+        let (open, close) = match name {
+            "matrix" | "array" | "aligned" | "align" | "align*" | "gathered" | "gather"
+            | "gather*" => ("", ""),
+            "pmatrix" => ("(", ")"),
+            "bmatrix" => ("[", "]"),
+            "Bmatrix" => ("{", "}"),
+            "vmatrix" => ("|", "|"),
+            "Vmatrix" => ("\u{2016}", "\u{2016}"),
+            "cases" => ("{", ""),
+            "rcases" => ("", "}"),
+            _ => return Err(format!("Unknown environment {}", name)),
+        };
+        let aligned = name.starts_with("align");
+        let columns = match name {
+            "array" => {
+                let mut columns = Vec::new();
+                for c in self.parse_text("\\begin{array}")?.chars() {
+                    match c {
+                        'l' => columns.push("left"),
+                        'c' => columns.push("center"),
+                        'r' => columns.push("right"),
+                        '|' | '\u{A0}' => (),
+                        _ => return Err(format!("Unknown array column type {}", c)),
+                    }
+                }
+                columns
+            }
+            "cases" | "rcases" => vec!["left", "left"],
+            // Aligned columns pair up around relations like =.
+            _ if aligned => ["right", "left"].repeat(4),
+            _ => Vec::new(),
+        };
+
+        let mut rows = Vec::new();
+        let mut row = Vec::new();
+        loop {
+            let mut cell = self.parse_expression(false)?;
+            if aligned && row.len() % 2 == 1 {
+                // Like TeX, so a relation at the start of the cell is spaced as one.
+                cell.insert_str(0, "<mrow></mrow>");
+            }
+            row.push(cell);
+            match self.next() {
+                Token::Char('&') => (),
+                Token::Command(ref command) if command == "\\" => {
+                    rows.push(std::mem::take(&mut row));
+                }
+                Token::Command(ref command) if command == "end" => {
+                    let end = self.parse_text("\\end")?;
+                    if end != name {
+                        return Err(format!("\\begin{{{}}} ended by \\end{{{}}}", name, end));
+                    }
+                    break;
+                }
+                Token::End => return Err(format!("Missing \\end{{{}}}", name)),
+                token => return Err(unexpected(&token)),
+            }
+        }
+        // A line break before `\end` doesn't start another row.
+        if rows.is_empty() || row.len() > 1 || row.iter().any(|cell| !cell.is_empty()) {
+            rows.push(row);
+        }
+
+        let mut mathml = String::from("<mtable");
+        if !columns.is_empty() {
+            mathml.push_str(&format!(" columnalign=\"{}\"", columns.join(" ")));
+        }
+        if aligned || name.starts_with("gather") {
+            mathml.push_str(" displaystyle=\"true\"");
+        }
+        mathml.push('>');
+        for row in rows {
+            mathml.push_str("<mtr>");
+            for cell in row {
+                mathml.push_str(&format!("<mtd>{}</mtd>", cell));
+            }
+            mathml.push_str("</mtr>");
+        }
+        mathml.push_str("</mtable>");
+
+        if open.is_empty() && close.is_empty() {
+            return Ok(mathml);
+        }
+        Ok(format!(
+            "<mrow>{}{}{}</mrow>",
+            stretchy_fence(Some(open).filter(|open| !open.is_empty())),
+            mathml,
+            stretchy_fence(Some(close).filter(|close| !close.is_empty()))
+        ))
+    }
+
+    fn identifier(&self, c: char) -> String {
+        let c = escape(&c.to_string());
+        match self.variant {
+            Variant::Default => format!("<mi>{}</mi>", c),
+            Variant::Normal => format!("<mi mathvariant=\"normal\">{}</mi>", c),
+            variant => format!(
+                "<mi>{}</mi>",
+                c.chars().map(|c| styled(c, variant)).collect::<String>()
+            ),
+        }
+    }
+}
+
+/* Commands that end the current expression. */
+fn is_terminator(name: &str) -> bool {
+    name == "\\" || name == "right" || name == "middle" || name == "end"
+}
+
+fn unexpected(token: &Token) -> String {
+    match token {
+        Token::End => "Unexpected end of the formula".to_string(),
+        Token::Char('}') => "Unexpected '}' without a matching '{'".to_string(),
+        Token::Char('&') => "'&' is only allowed in environments like matrix".to_string(),
+        Token::Char(c) => format!("Unexpected '{}'", c),
+        Token::Command(name) => match name.as_str() {
+            "\\" => "Line breaks are only allowed in environments like aligned".to_string(),
+            "right" => "\\right without a matching \\left".to_string(),
+            "middle" => "\\middle without a matching \\left".to_string(),
+            "end" => "\\end without a matching \\begin".to_string(),
+            _ => format!("Unexpected \\{}", name),
+        },
+    }
+}
+
+fn fence(delimiter: &str) -> String {
+    format!("<mo stretchy=\"false\">{}</mo>", escape(delimiter))
+}
+
+fn stretchy_fence(delimiter: Option<&str>) -> String {
+    match delimiter {
+        Some(delimiter) => format!(
+            "<mo fence=\"true\" stretchy=\"true\">{}</mo>",
+            escape(delimiter)
+        ),
+        None => String::new(),
+    }
+}
+
+/* The letter or digit in the given font. */
+fn styled(c: char, variant: Variant) -> char {
+    let (capitals, small_letters, digits, greek) = match variant {
+        Variant::Bold => (0x1D400, 0x1D41A, 0x1D7CE, Some((0x1D6A8, 0x1D6C2))),
+        Variant::Italic => (0x1D434, 0x1D44E, 0, Some((0x1D6E2, 0x1D6FC))),
+        Variant::BoldItalic => (0x1D468, 0x1D482, 0x1D7CE, Some((0x1D71C, 0x1D736))),
+        Variant::Script => (0x1D49C, 0x1D4B6, 0, None),
+        Variant::Fraktur => (0x1D504, 0x1D51E, 0, None),
+        Variant::DoubleStruck => (0x1D538, 0x1D552, 0x1D7D8, None),
+        Variant::SansSerif => (0x1D5A0, 0x1D5BA, 0x1D7E2, None),
+        Variant::Monospace => (0x1D670, 0x1D68A, 0x1D7F6, None),
+        Variant::Default | Variant::Normal => return c,
+    };
+    // Letters that were in Unicode before the mathematical alphabets kept their code points.
+    let existing = match (variant, c) {
+        (Variant::Italic, 'h') => Some('\u{210E}'),
+        (Variant::Script, 'B') => Some('\u{212C}'),
+        (Variant::Script, 'E') => Some('\u{2130}'),
+        (Variant::Script, 'F') => Some('\u{2131}'),
+        (Variant::Script, 'H') => Some('\u{210B}'),
+        (Variant::Script, 'I') => Some('\u{2110}'),
+        (Variant::Script, 'L') => Some('\u{2112}'),
+        (Variant::Script, 'M') => Some('\u{2133}'),
+        (Variant::Script, 'R') => Some('\u{211B}'),
+        (Variant::Script, 'e') => Some('\u{212F}'),
+        (Variant::Script, 'g') => Some('\u{210A}'),
+        (Variant::Script, 'o') => Some('\u{2134}'),
+        (Variant::Fraktur, 'C') => Some('\u{212D}'),
+        (Variant::Fraktur, 'H') => Some('\u{210C}'),
+        (Variant::Fraktur, 'I') => Some('\u{2111}'),
+        (Variant::Fraktur, 'R') => Some('\u{211C}'),
+        (Variant::Fraktur, 'Z') => Some('\u{2128}'),
+        (Variant::DoubleStruck, 'C') => Some('\u{2102}'),
+        (Variant::DoubleStruck, 'H') => Some('\u{210D}'),
+        (Variant::DoubleStruck, 'N') => Some('\u{2115}'),
+        (Variant::DoubleStruck, 'P') => Some('\u{2119}'),
+        (Variant::DoubleStruck, 'Q') => Some('\u{211A}'),
+        (Variant::DoubleStruck, 'R') => Some('\u{211D}'),
+        (Variant::DoubleStruck, 'Z') => Some('\u{2124}'),
+        _ => None,
+    };
+    if let Some(existing) = existing {
+        return existing;
+    }
+
+    let code = match (c, greek) {
+        ('A'..='Z', _) => capitals + (c as u32 - 'A' as u32),
+        ('a'..='z', _) => small_letters + (c as u32 - 'a' as u32),
+        ('0'..='9', _) if digits != 0 => digits + (c as u32 - '0' as u32),
+        // The Greek alphabets follow the order of the Greek block.
+        ('\u{391}'..='\u{3A9}', Some((capitals, _))) if c != '\u{3A2}' => {
+            capitals + (c as u32 - 0x391)
+        }
+        ('\u{3B1}'..='\u{3C9}', Some((_, small_letters))) => small_letters + (c as u32 - 0x3B1),
+        _ => return c,
+    };
+    std::char::from_u32(code).unwrap_or(c)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* The MathML of the formula, without the `<math>` element around it. */
+    fn inner(latex: &str) -> String {
+        let mathml = to_mathml(latex, false).unwrap();
+        let start = mathml.find("<mrow>").unwrap() + "<mrow>".len();
+        let end = mathml.rfind("</mrow><annotation").unwrap();
+        mathml[start..end].to_string()
+    }
+
+    #[test]
+    fn converts_formulas() {
+        let cases = [
+            ("x", "<mi>x</mi>"),
+            ("12.5 + 3", "<mn>12.5</mn><mo>+</mo><mn>3</mn>"),
+            ("a - b", "<mi>a</mi><mo>\u{2212}</mo><mi>b</mi>"),
+            ("x^2", "<msup><mi>x</mi><mn>2</mn></msup>"),
+            ("x_i^2", "<msubsup><mi>x</mi><mi>i</mi><mn>2</mn></msubsup>"),
+            ("x_{i+1}", "<msub><mi>x</mi><mrow><mi>i</mi><mo>+</mo><mn>1</mn></mrow></msub>"),
+            ("f'", "<msup><mi>f</mi><mo>\u{2032}</mo></msup>"),
+            ("\\frac12", "<mfrac><mn>1</mn><mn>2</mn></mfrac>"),
+            ("\\frac{a}{b}", "<mfrac><mrow><mi>a</mi></mrow><mrow><mi>b</mi></mrow></mfrac>"),
+            ("\\sqrt{x}", "<msqrt><mrow><mi>x</mi></mrow></msqrt>"),
+            ("\\sqrt[3]x", "<mroot><mi>x</mi><mrow><mn>3</mn></mrow></mroot>"),
+            ("\\alpha \\Gamma", "<mi>\u{3B1}</mi><mi mathvariant=\"normal\">\u{393}</mi>"),
+            ("\\sin x", "<mi>sin</mi><mo>\u{2061}</mo><mi>x</mi>"),
+            ("\\sum_{i=1}^n", "<msubsup><mo>\u{2211}</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></msubsup>"),
+            ("a \\le b", "<mi>a</mi><mo>\u{2264}</mo><mi>b</mi>"),
+            ("\\not=", "<mo>=\u{338}</mo>"),
+            ("\\mathbb{R}", "<mrow><mi>\u{211D}</mi></mrow>"),
+            ("\\hat x", "<mover accent=\"true\"><mi>x</mi><mo stretchy=\"false\">^</mo></mover>"),
+            ("\\left( x \\right)", "<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mi>x</mi><mo fence=\"true\" stretchy=\"true\">)</mo></mrow>"),
+            ("\\begin{pmatrix} a & b \\\\ c & d \\end{pmatrix}", "<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mtable><mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr><mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr></mtable><mo fence=\"true\" stretchy=\"true\">)</mo></mrow>"),
+            ("a \\quad b", "<mi>a</mi><mspace width=\"1em\"/><mi>b</mi>"),
+            ("x % comment", "<mi>x</mi>"),
+        ];
+        for (latex, expected) in &cases {
+            assert_eq!(inner(latex), *expected, "{}", latex);
+        }
+    }
+
+    #[test]
+    fn converts_every_symbol() {
+        for (name, symbol) in IDENTIFIERS {
+            assert_eq!(
+                inner(&format!("\\{}", name)),
+                format!("<mi>{}</mi>", escape(symbol))
+            );
+        }
+        for (name, symbol) in CAPITAL_GREEK {
+            assert_eq!(
+                inner(&format!("\\{}", name)),
+                format!("<mi mathvariant=\"normal\">{}</mi>", symbol)
+            );
+        }
+        for (name, symbol) in OPERATORS {
+            assert_eq!(
+                inner(&format!("\\{}", name)),
+                format!("<mo>{}</mo>", escape(symbol))
+            );
+        }
+        for (name, symbol) in DELIMITERS {
+            assert_eq!(inner(&format!("\\{}", name)), fence(symbol));
+            assert_eq!(
+                inner(&format!("\\left\\{} x \\right.", name)),
+                format!("<mrow>{}<mi>x</mi></mrow>", stretchy_fence(Some(symbol)))
+            );
+        }
+        for (name, symbol, _) in LARGE_OPERATORS {
+            assert_eq!(
+                inner(&format!("\\{}", name)),
+                format!("<mo>{}</mo>", symbol)
+            );
+        }
+        for (name, text, _) in FUNCTIONS {
+            assert_eq!(
+                inner(&format!("\\{}", name)),
+                format!("<mi>{}</mi><mo>\u{2061}</mo>", text)
+            );
+        }
+        for (name, accent, stretchy) in ACCENTS {
+            assert_eq!(
+                inner(&format!("\\{} x", name)),
+                format!(
+                    "<mover accent=\"true\"><mi>x</mi><mo stretchy=\"{}\">{}</mo></mover>",
+                    stretchy, accent
+                )
+            );
+        }
+        for (name, width) in SPACES {
+            assert_eq!(
+                inner(&format!("\\{}", name)),
+                format!("<mspace width=\"{}\"/>", width)
+            );
+        }
+        for (name, size) in BIG_SIZES {
+            for suffix in &["", "l", "r", "m"] {
+                assert_eq!(
+                    inner(&format!("\\{}{}(", name, suffix)),
+                    format!("<mo minsize=\"{0}\" maxsize=\"{0}\">(</mo>", size)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn applies_fonts() {
+        let cases = [
+            (
+                "\\mathrm{d}x",
+                "<mrow><mi mathvariant=\"normal\">d</mi></mrow><mi>x</mi>",
+            ),
+            (
+                "\\mathbf{x1}",
+                "<mrow><mi>\u{1D431}</mi><mn>\u{1D7CF}</mn></mrow>",
+            ),
+            (
+                "\\mathit{ab}",
+                "<mrow><mi>\u{1D44E}</mi><mi>\u{1D44F}</mi></mrow>",
+            ),
+            ("\\mathit{h}", "<mrow><mi>\u{210E}</mi></mrow>"),
+            ("\\boldsymbol{x}", "<mrow><mi>\u{1D499}</mi></mrow>"),
+            ("\\bm{\\alpha}", "<mrow><mi>\u{1D736}</mi></mrow>"),
+            ("\\boldsymbol{\\omega}", "<mrow><mi>\u{1D74E}</mi></mrow>"),
+            ("\\mathbf{\\Gamma}", "<mrow><mi>\u{1D6AA}</mi></mrow>"),
+            ("\\mathit{\\Omega}", "<mrow><mi>\u{1D6FA}</mi></mrow>"),
+            (
+                "\\mathrm{\\Gamma}",
+                "<mrow><mi mathvariant=\"normal\">\u{393}</mi></mrow>",
+            ),
+            (
+                "\\mathbb{\\Gamma}",
+                "<mrow><mi mathvariant=\"normal\">\u{393}</mi></mrow>",
+            ),
+            (
+                "\\mathbb{N}_0",
+                "<msub><mrow><mi>\u{2115}</mi></mrow><mn>0</mn></msub>",
+            ),
+            ("\\mathbb{1}", "<mrow><mn>\u{1D7D9}</mn></mrow>"),
+            (
+                "\\mathcal{AB}",
+                "<mrow><mi>\u{1D49C}</mi><mi>\u{212C}</mi></mrow>",
+            ),
+            ("\\mathscr{L}", "<mrow><mi>\u{2112}</mi></mrow>"),
+            (
+                "\\mathfrak{gR}",
+                "<mrow><mi>\u{1D524}</mi><mi>\u{211C}</mi></mrow>",
+            ),
+            ("\\mathsf{a}", "<mrow><mi>\u{1D5BA}</mi></mrow>"),
+            ("\\mathtt{a}", "<mrow><mi>\u{1D68A}</mi></mrow>"),
+            (
+                "\\mathbf{a + b}",
+                "<mrow><mi>\u{1D41A}</mi><mo>+</mo><mi>\u{1D41B}</mi></mrow>",
+            ),
+            ("\\text{if } x", "<mtext>if\u{A0}</mtext><mi>x</mi>"),
+            ("\\textrm{a}", "<mtext>a</mtext>"),
+            ("\\textnormal{a}", "<mtext>a</mtext>"),
+            ("\\mbox{a}", "<mtext>a</mtext>"),
+            ("\\textbf{a b}", "<mtext>\u{1D41A}\u{A0}\u{1D41B}</mtext>"),
+            ("\\textit{hi}", "<mtext>\u{210E}\u{1D456}</mtext>"),
+            ("\\textsf{a}", "<mtext>\u{1D5BA}</mtext>"),
+            ("\\texttt{a}", "<mtext>\u{1D68A}</mtext>"),
+            ("\\text{a}_1", "<msub><mtext>a</mtext><mn>1</mn></msub>"),
+        ];
+        for (latex, expected) in &cases {
+            assert_eq!(inner(latex), *expected, "{}", latex);
+        }
+    }
+
+    #[test]
+    fn places_limits() {
+        let cases = [
+            ("\\sum_i^n", false, "<msubsup><mo>\u{2211}</mo><mi>i</mi><mi>n</mi></msubsup>"),
+            ("\\sum_i^n", true, "<munderover><mo>\u{2211}</mo><mi>i</mi><mi>n</mi></munderover>"),
+            ("\\sum\\limits_i", false, "<munder><mo>\u{2211}</mo><mi>i</mi></munder>"),
+            ("\\sum\\nolimits_i", true, "<msub><mo>\u{2211}</mo><mi>i</mi></msub>"),
+            // Integrals keep their limits to the right unless asked otherwise.
+            ("\\int_0^1", true, "<msubsup><mo>\u{222B}</mo><mn>0</mn><mn>1</mn></msubsup>"),
+            ("\\int\\limits_0^1", false, "<munderover><mo>\u{222B}</mo><mn>0</mn><mn>1</mn></munderover>"),
+            ("\\max_i x", false, "<msub><mi>max</mi><mi>i</mi></msub><mo>\u{2061}</mo><mi>x</mi>"),
+            ("\\max_i x", true, "<munder><mi>max</mi><mi>i</mi></munder><mo>\u{2061}</mo><mi>x</mi>"),
+            ("\\sin^2 x", true, "<msup><mi>sin</mi><mn>2</mn></msup><mo>\u{2061}</mo><mi>x</mi>"),
+            ("\\operatorname{rank} A", true, "<mi>rank</mi><mo>\u{2061}</mo><mi>A</mi>"),
+            ("\\operatorname*{argmax}_x", false, "<msub><mi>argmax</mi><mi>x</mi></msub><mo>\u{2061}</mo>"),
+            ("\\operatorname*{argmax}_x", true, "<munder><mi>argmax</mi><mi>x</mi></munder><mo>\u{2061}</mo>"),
+            ("\\displaystyle \\sum_i", false, "<mstyle displaystyle=\"true\"><munder><mo>\u{2211}</mo><mi>i</mi></munder></mstyle>"),
+            ("\\textstyle \\sum_i", true, "<mstyle displaystyle=\"false\"><msub><mo>\u{2211}</mo><mi>i</mi></msub></mstyle>"),
+            // Style switches end with their group.
+            ("{\\displaystyle x} \\sum_i", false, "<mrow><mstyle displaystyle=\"true\"><mi>x</mi></mstyle></mrow><msub><mo>\u{2211}</mo><mi>i</mi></msub>"),
+        ];
+        for (latex, display, expected) in &cases {
+            let mathml = to_mathml(latex, *display).unwrap();
+            assert!(
+                mathml.contains(&format!("<mrow>{}</mrow><annotation", expected)),
+                "{}: {}",
+                latex,
+                mathml
+            );
+        }
+    }
+
+    #[test]
+    fn converts_layouts() {
+        let cases = [
+            ("\\dfrac12", "<mstyle displaystyle=\"true\"><mfrac><mn>1</mn><mn>2</mn></mfrac></mstyle>"),
+            ("\\tfrac12", "<mstyle displaystyle=\"false\"><mfrac><mn>1</mn><mn>2</mn></mfrac></mstyle>"),
+            ("\\binom nk", "<mrow><mo>(</mo><mfrac linethickness=\"0\"><mi>n</mi><mi>k</mi></mfrac><mo>)</mo></mrow>"),
+            ("\\sqrt[n]{x}", "<mroot><mrow><mi>x</mi></mrow><mrow><mi>n</mi></mrow></mroot>"),
+            ("\\overset{!}{=}", "<mover><mrow><mo>=</mo></mrow><mrow><mo>!</mo></mrow></mover>"),
+            ("\\underset{x}{y}", "<munder><mrow><mi>y</mi></mrow><mrow><mi>x</mi></mrow></munder>"),
+            ("\\overbrace{a}^{n}", "<mover><mover><mrow><mi>a</mi></mrow><mo stretchy=\"true\">\u{23DE}</mo></mover><mrow><mi>n</mi></mrow></mover>"),
+            ("\\underbrace{a}_{n}", "<munder><munder><mrow><mi>a</mi></mrow><mo stretchy=\"true\">\u{23DF}</mo></munder><mrow><mi>n</mi></mrow></munder>"),
+            ("\\underline x", "<munder accentunder=\"true\"><mi>x</mi><mo stretchy=\"true\">\u{203E}</mo></munder>"),
+            ("x''", "<msup><mi>x</mi><mo>\u{2032}\u{2032}</mo></msup>"),
+            ("\\not\\in", "<mo>\u{2208}\u{338}</mo>"),
+            ("\\{a\\}", "<mo stretchy=\"false\">{</mo><mi>a</mi><mo stretchy=\"false\">}</mo>"),
+            ("|x|", "<mo stretchy=\"false\">|</mo><mi>x</mi><mo stretchy=\"false\">|</mo>"),
+            ("\\left. x \\middle| y \\right\\}", "<mrow><mi>x</mi><mo fence=\"true\" stretchy=\"true\">|</mo><mi>y</mi><mo fence=\"true\" stretchy=\"true\">}</mo></mrow>"),
+            ("\\left< x \\right>", "<mrow><mo fence=\"true\" stretchy=\"true\">\u{27E8}</mo><mi>x</mi><mo fence=\"true\" stretchy=\"true\">\u{27E9}</mo></mrow>"),
+            ("\\begin{matrix} 1 \\end{matrix}", "<mtable><mtr><mtd><mn>1</mn></mtd></mtr></mtable>"),
+            ("\\begin{bmatrix} 1 \\end{bmatrix}", "<mrow><mo fence=\"true\" stretchy=\"true\">[</mo><mtable><mtr><mtd><mn>1</mn></mtd></mtr></mtable><mo fence=\"true\" stretchy=\"true\">]</mo></mrow>"),
+            ("\\begin{vmatrix} 1 \\end{vmatrix}", "<mrow><mo fence=\"true\" stretchy=\"true\">|</mo><mtable><mtr><mtd><mn>1</mn></mtd></mtr></mtable><mo fence=\"true\" stretchy=\"true\">|</mo></mrow>"),
+            ("\\begin{Vmatrix} 1 \\end{Vmatrix}", "<mrow><mo fence=\"true\" stretchy=\"true\">\u{2016}</mo><mtable><mtr><mtd><mn>1</mn></mtd></mtr></mtable><mo fence=\"true\" stretchy=\"true\">\u{2016}</mo></mrow>"),
+            ("\\begin{cases} 1 & x > 0 \\\\ 0 & \\text{else} \\end{cases}", "<mrow><mo fence=\"true\" stretchy=\"true\">{</mo><mtable columnalign=\"left left\"><mtr><mtd><mn>1</mn></mtd><mtd><mi>x</mi><mo>&gt;</mo><mn>0</mn></mtd></mtr><mtr><mtd><mn>0</mn></mtd><mtd><mtext>else</mtext></mtd></mtr></mtable></mrow>"),
+            ("\\begin{rcases} a \\end{rcases}", "<mrow><mtable columnalign=\"left left\"><mtr><mtd><mi>a</mi></mtd></mtr></mtable><mo fence=\"true\" stretchy=\"true\">}</mo></mrow>"),
+            ("\\begin{array}{lcr} a & b & c \\end{array}", "<mtable columnalign=\"left center right\"><mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd><mtd><mi>c</mi></mtd></mtr></mtable>"),
+            // Relations after `&` keep their spacing, as if something came before them.
+            ("\\begin{aligned} a &= b \\\\ &= c \\end{aligned}", "<mtable columnalign=\"right left right left right left right left\" displaystyle=\"true\"><mtr><mtd><mi>a</mi></mtd><mtd><mrow></mrow><mo>=</mo><mi>b</mi></mtd></mtr><mtr><mtd></mtd><mtd><mrow></mrow><mo>=</mo><mi>c</mi></mtd></mtr></mtable>"),
+        ];
+        for (latex, expected) in &cases {
+            assert_eq!(inner(latex), *expected, "{}", latex);
+        }
+    }
+
+    #[test]
+    fn wraps_formulas_with_their_source() {
+        assert_eq!(
+            to_mathml("x", false).unwrap(),
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"><semantics><mrow><mi>x</mi></mrow>\
+             <annotation encoding=\"application/x-tex\">x</annotation></semantics></math>"
+        );
+        // Large operators take their limits above and below only in display math.
+        assert_eq!(
+            to_mathml("\\sum_i", true).unwrap(),
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"block\"><semantics>\
+             <mrow><munder><mo>\u{2211}</mo><mi>i</mi></munder></mrow>\
+             <annotation encoding=\"application/x-tex\">\\sum_i</annotation></semantics></math>"
+        );
+    }
+
+    #[test]
+    fn escapes_text_and_annotation() {
+        let cases = [
+            (
+                "\\text{<b>\"&\"</b>}",
+                "<mtext>&lt;b&gt;&quot;&amp;&quot;&lt;/b&gt;</mtext>",
+                "\\text{&lt;b&gt;&quot;&amp;&quot;&lt;/b&gt;}",
+            ),
+            (
+                "a < b > c",
+                "<mi>a</mi><mo>&lt;</mo><mi>b</mi><mo>&gt;</mo><mi>c</mi>",
+                "a &lt; b &gt; c",
+            ),
+            (
+                "\\operatorname{<x>}",
+                "<mi>&lt;x&gt;</mi><mo>\u{2061}</mo>",
+                "\\operatorname{&lt;x&gt;}",
+            ),
+            (
+                "\\text{</mtext><script>}",
+                "<mtext>&lt;/mtext&gt;&lt;script&gt;</mtext>",
+                "\\text{&lt;/mtext&gt;&lt;script&gt;}",
+            ),
+        ];
+        for (latex, mathml, annotation) in &cases {
+            let output = to_mathml(latex, false).unwrap();
+            assert!(output.contains(mathml), "{} in {}", mathml, output);
+            assert!(
+                output.contains(&format!(
+                    "<annotation encoding=\"application/x-tex\">{}</annotation>",
+                    annotation
+                )),
+                "{}",
+                output
+            );
+            assert!(!output.contains("<script") && !output.contains("<b>"));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_formulas() {
+        let cases = [
+            ("\\foo", "Unknown command \\foo"),
+            ("\\script{x}", "Unknown command \\script"),
+            (
+                "\\begin{tabular}x\\end{tabular}",
+                "Unknown environment tabular",
+            ),
+            (
+                "\\begin{array}{lx}a\\end{array}",
+                "Unknown array column type x",
+            ),
+            ("\\text{\\foo}", "Commands aren't supported in \\text"),
+            ("x^2^3", "Double superscript, use braces to clarify"),
+            ("x_1_2", "Double subscript, use braces to clarify"),
+            ("\\frac{a}", "Missing argument for \\frac"),
+            ("{x", "Missing '}'"),
+            ("x}", "Unexpected '}' without a matching '{'"),
+            ("a & b", "'&' is only allowed in environments like matrix"),
+            (
+                "a \\\\ b",
+                "Line breaks are only allowed in environments like aligned",
+            ),
+            ("\\left( x", "Missing \\right after \\left"),
+            ("x \\right)", "\\right without a matching \\left"),
+            (
+                "\\left x \\right)",
+                "Missing or unknown delimiter after \\left",
+            ),
+            (
+                "\\begin{matrix} a \\end{pmatrix}",
+                "\\begin{matrix} ended by \\end{pmatrix}",
+            ),
+            ("$x$", "Unexpected '$', math doesn't need dollar signs"),
+            ("x \\limits", "\\limits must follow an operator like \\sum"),
+            ("\\not x", "\\not must be followed by a relation like ="),
+            ("x\\", "Unexpected '\\' at the end"),
+        ];
+        for (latex, expected) in &cases {
+            assert_eq!(
+                to_mathml(latex, false).err().as_deref(),
+                Some(*expected),
+                "{}",
+                latex
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let nested = |depth| format!("{}x{}", "{".repeat(depth), "}".repeat(depth));
+        assert!(validate(&nested(MAX_NESTING - 1)).is_ok());
+        assert_eq!(
+            validate(&nested(MAX_NESTING)).err(),
+            Some("Math can be nested at most 64 levels deep".to_string())
+        );
+
+        let scripts = format!("x{}", "^{x".repeat(MAX_NESTING) + &"}".repeat(MAX_NESTING));
+        assert_eq!(
+            validate(&scripts).err(),
+            Some("Math can be nested at most 64 levels deep".to_string())
+        );
+        assert_eq!(
+            validate(&"\\sqrt".repeat(100_000)).err(),
+            Some("Math can be nested at most 64 levels deep".to_string())
+        );
+    }
+}
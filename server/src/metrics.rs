@@ -0,0 +1,361 @@
+use actix_web::middleware::{Middleware, Response, Started};
+use actix_web::{AsyncResponder, Error, HttpRequest, HttpResponse, Result as ActixResult};
+use futures::future::{self, Future};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::actix_state::State;
+use super::auth;
+use super::config::Config;
+use super::data::ResourceCounts;
+use super::repo_actor::GetResourceCountsMessage;
+
+static DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+static SIZE_BUCKETS: &[f64] = &[
+    1_000.0,
+    10_000.0,
+    100_000.0,
+    1_000_000.0,
+    10_000_000.0,
+    100_000_000.0,
+];
+
+struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Histogram {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bucket, count) in self.buckets.iter().zip(self.counts.iter_mut()) {
+            if value <= *bucket {
+                *count += 1;
+            }
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+
+        for (bucket, count) in self.buckets.iter().zip(self.counts.iter()) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, bucket, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, separator, self.count
+        );
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+/* The route pattern as mounted, e.g. `/api/notes/{id}`, and the request method. */
+type RouteKey = (String, String);
+
+/* Collects metrics in memory, shared between all workers. Database statistics are
+gathered when the metrics are scraped. */
+pub struct Metrics {
+    token_hash: Option<String>,
+    database_path: PathBuf,
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    request_durations: Mutex<BTreeMap<RouteKey, Histogram>>,
+    db_queue_depth: AtomicIsize,
+    db_durations: Mutex<BTreeMap<&'static str, Histogram>>,
+    sync_payload_sizes: Mutex<Histogram>,
+}
+
+impl Metrics {
+    pub fn new(config: &Config) -> Self {
+        Metrics {
+            token_hash: config
+                .metrics
+                .token
+                .as_ref()
+                .map(|token| auth::hash_token(token)),
+            database_path: PathBuf::from(&config.database.url),
+            requests: Mutex::new(BTreeMap::new()),
+            request_durations: Mutex::new(BTreeMap::new()),
+            db_queue_depth: AtomicIsize::new(0),
+            db_durations: Mutex::new(BTreeMap::new()),
+            sync_payload_sizes: Mutex::new(Histogram::new(SIZE_BUCKETS)),
+        }
+    }
+
+    fn record_request(&self, route: String, method: String, status: u16, duration: Duration) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((route.clone(), method.clone(), status))
+            .or_insert(0) += 1;
+
+        self.request_durations
+            .lock()
+            .unwrap()
+            .entry((route, method))
+            .or_insert_with(|| Histogram::new(DURATION_BUCKETS))
+            .observe(seconds(duration));
+    }
+
+    pub fn requests_handled(&self) -> u64 {
+        self.requests.lock().unwrap().values().sum()
+    }
+
+    pub fn db_queue_depth(&self) -> isize {
+        self.db_queue_depth.load(Ordering::Relaxed)
+    }
+
+    pub fn db_message_queued(&self) {
+        self.db_queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn db_message_handled(&self, message: &'static str, duration: Duration) {
+        self.db_queue_depth.fetch_sub(1, Ordering::Relaxed);
+
+        self.db_durations
+            .lock()
+            .unwrap()
+            .entry(message)
+            .or_insert_with(|| Histogram::new(DURATION_BUCKETS))
+            .observe(seconds(duration));
+    }
+
+    pub fn record_sync_payload(&self, bytes: usize) {
+        self.sync_payload_sizes
+            .lock()
+            .unwrap()
+            .observe(bytes as f64);
+    }
+
+    /* Renders all metrics in the Prometheus text format. */
+    fn render(&self, resource_counts: &ResourceCounts) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "pragma_http_requests_total",
+            "counter",
+            "HTTP requests by route, method and status.",
+        );
+        for ((route, method, status), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "pragma_http_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                escape(route),
+                method,
+                status,
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "pragma_http_request_duration_seconds",
+            "histogram",
+            "HTTP request latency by route and method.",
+        );
+        for ((route, method), histogram) in self.request_durations.lock().unwrap().iter() {
+            let labels = format!("route=\"{}\",method=\"{}\"", escape(route), method);
+            histogram.render(&mut out, "pragma_http_request_duration_seconds", &labels);
+        }
+
+        header(
+            &mut out,
+            "pragma_db_queue_depth",
+            "gauge",
+            "Database messages queued or being handled.",
+        );
+        let _ = writeln!(
+            out,
+            "pragma_db_queue_depth {}",
+            self.db_queue_depth.load(Ordering::Relaxed).max(0)
+        );
+
+        header(
+            &mut out,
+            "pragma_db_message_duration_seconds",
+            "histogram",
+            "Time until database messages are handled, including time spent queued, by message.",
+        );
+        for (message, histogram) in self.db_durations.lock().unwrap().iter() {
+            let labels = format!("message=\"{}\"", message);
+            histogram.render(&mut out, "pragma_db_message_duration_seconds", &labels);
+        }
+
+        header(
+            &mut out,
+            "pragma_sync_payload_bytes",
+            "histogram",
+            "Size of sync responses.",
+        );
+        self.sync_payload_sizes
+            .lock()
+            .unwrap()
+            .render(&mut out, "pragma_sync_payload_bytes", "");
+
+        if let Ok(metadata) = fs::metadata(&self.database_path) {
+            header(
+                &mut out,
+                "pragma_database_size_bytes",
+                "gauge",
+                "Size of the SQLite database file.",
+            );
+            let _ = writeln!(out, "pragma_database_size_bytes {}", metadata.len());
+        }
+
+        header(
+            &mut out,
+            "pragma_resources",
+            "gauge",
+            "Stored resources by type.",
+        );
+        for (resource_type, count) in &[
+            ("notebook", resource_counts.notebooks),
+            ("note", resource_counts.notes),
+            ("content_block", resource_counts.content_blocks),
+            ("deletion", resource_counts.deletions),
+        ] {
+            let _ = writeln!(
+                out,
+                "pragma_resources{{type=\"{}\"}} {}",
+                resource_type, count
+            );
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, type_: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, type_);
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+/* Strips the module path and `Message` suffix from a message type name. */
+pub fn message_name(type_name: &'static str) -> &'static str {
+    let name = type_name.rsplit("::").next().unwrap_or(type_name);
+    name.strip_suffix("Message").unwrap_or(name)
+}
+
+struct RequestStart(Instant);
+
+/* The route matched inside a scope, which the app level middleware can't see. */
+struct ScopedRoute(String);
+
+pub struct MetricsMiddleware;
+
+pub fn middleware() -> MetricsMiddleware {
+    MetricsMiddleware
+}
+
+impl Middleware<State> for MetricsMiddleware {
+    fn start(&self, req: &HttpRequest<State>) -> ActixResult<Started> {
+        req.extensions_mut().insert(RequestStart(Instant::now()));
+        Ok(Started::Done)
+    }
+
+    fn response(&self, req: &HttpRequest<State>, resp: HttpResponse) -> ActixResult<Response> {
+        let request_start = req.extensions_mut().remove::<RequestStart>();
+        let scoped_route = req.extensions_mut().remove::<ScopedRoute>();
+
+        if let Some(RequestStart(start)) = request_start {
+            let route = match scoped_route {
+                Some(ScopedRoute(route)) => route,
+                None => route_pattern(req).unwrap_or_else(|| "unmatched".to_string()),
+            };
+
+            req.state().metrics.record_request(
+                route,
+                req.method().to_string(),
+                resp.status().as_u16(),
+                start.elapsed(),
+            );
+        }
+
+        Ok(Response::Done(resp))
+    }
+}
+
+/* Labels requests with the route matched inside a scope, e.g. `/api/notes/{id}`
+instead of `/api`. */
+pub struct ScopeMiddleware(&'static str);
+
+pub fn scope_middleware(prefix: &'static str) -> ScopeMiddleware {
+    ScopeMiddleware(prefix)
+}
+
+impl Middleware<State> for ScopeMiddleware {
+    fn response(&self, req: &HttpRequest<State>, resp: HttpResponse) -> ActixResult<Response> {
+        if let Some(pattern) = route_pattern(req) {
+            req.extensions_mut()
+                .insert(ScopedRoute(format!("{}{}", self.0, pattern)));
+        }
+
+        Ok(Response::Done(resp))
+    }
+}
+
+fn route_pattern(req: &HttpRequest<State>) -> Option<String> {
+    req.resource().rdef().map(|rdef| rdef.pattern().to_string())
+}
+
+/* With a metrics token configured, scrapers have to send it as a bearer token. */
+pub fn metrics(req: HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let metrics = req.state().metrics.clone();
+
+    if let Some(ref token_hash) = metrics.token_hash {
+        let authorized = auth::extract_bearer_token(&req)
+            .is_some_and(|token| auth::constant_time_eq(token_hash, &auth::hash_token(&token)));
+
+        if !authorized {
+            return Box::new(future::ok(HttpResponse::Unauthorized().finish()));
+        }
+    }
+
+    req.state()
+        .db
+        .for_request(&req)
+        .send(GetResourceCountsMessage)
+        .from_err()
+        .and_then(move |res| match res {
+            Ok(resource_counts) => Ok(HttpResponse::Ok()
+                .content_type("text/plain; version=0.0.4")
+                .body(metrics.render(&resource_counts))),
+            Err(reason) => Ok(HttpResponse::InternalServerError().body(reason)),
+        })
+        .responder()
+}
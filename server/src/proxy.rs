@@ -0,0 +1,250 @@
+use actix_web::HttpRequest;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/* An IP address range in CIDR notation, e.g. `10.0.0.0/8`. A plain address is a range of one. */
+#[derive(Debug, Clone)]
+pub struct Network {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(network: &str) -> Result<Self, Self::Err> {
+        let mut parts = network.splitn(2, '/');
+        let address: IpAddr = parts
+            .next()
+            .unwrap_or("")
+            .parse()
+            .map_err(|_| format!("Invalid address {}", network))?;
+
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match parts.next() {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("Invalid prefix length in {}", network))?,
+            None => max_prefix,
+        };
+
+        Ok(Network { address, prefix })
+    }
+}
+
+impl Network {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, canonical(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/* Dual-stack sockets report IPv4 peers as IPv4-mapped IPv6 addresses like `::ffff:10.0.0.1`,
+which are treated as the IPv4 address they contain. */
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<Network>,
+}
+
+impl TrustedProxies {
+    pub fn new(networks: Vec<Network>) -> Self {
+        TrustedProxies { networks }
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    /* Requests over the Unix socket have no peer address. Only local processes with access to
+    the socket can connect, such as a reverse proxy, so they are trusted. */
+    pub fn is_trusted_peer<S>(&self, req: &HttpRequest<S>) -> bool {
+        match req.peer_addr() {
+            Some(address) => self.is_trusted(address.ip()),
+            None => true,
+        }
+    }
+
+    /* The address of the client. X-Forwarded-For is only honoured when the request comes from a
+    trusted proxy, in which case the right-most untrusted address in the header is the client. */
+    pub fn client_ip<S>(&self, req: &HttpRequest<S>) -> Option<IpAddr> {
+        let peer = req.peer_addr().map(|address| canonical(address.ip()));
+
+        if !self.is_trusted_peer(req) {
+            return peer;
+        }
+
+        let forwarded_for = req
+            .headers()
+            .get_all("X-Forwarded-For")
+            .iter()
+            .map(|header| header.to_str().unwrap_or(""))
+            .flat_map(|header| header.split(','));
+
+        self.forwarded_client_ip(peer, forwarded_for)
+    }
+
+    /* Walks the X-Forwarded-For entries from the right, past the trusted proxies. An entry that
+    isn't an address can't be trusted, and neither can anything to the left of it, so the walk
+    stops there and the last proxy that was reached is taken to be the client. */
+    fn forwarded_client_ip<'a, I>(&self, peer: Option<IpAddr>, forwarded_for: I) -> Option<IpAddr>
+    where
+        I: DoubleEndedIterator<Item = &'a str>,
+    {
+        let mut client = peer;
+
+        for address in forwarded_for.rev() {
+            let ip = match address.trim().parse() {
+                Ok(ip) => canonical(ip),
+                Err(_) => break,
+            };
+            client = Some(ip);
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(network: &str) -> Network {
+        network.parse().unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn proxies(networks: &[&str]) -> TrustedProxies {
+        TrustedProxies::new(networks.iter().map(|n| network(n)).collect())
+    }
+
+    fn client_ip(proxies: &TrustedProxies, peer: &str, forwarded_for: &str) -> Option<IpAddr> {
+        proxies.forwarded_client_ip(Some(ip(peer)), forwarded_for.split(','))
+    }
+
+    #[test]
+    fn parses_networks() {
+        assert!("10.0.0.0/8".parse::<Network>().is_ok());
+        assert!("10.0.0.1".parse::<Network>().is_ok());
+        assert!("fd00::/8".parse::<Network>().is_ok());
+        assert!("10.0.0.0/33".parse::<Network>().is_err());
+        assert!("fd00::/129".parse::<Network>().is_err());
+        assert!("10.0.0.0/".parse::<Network>().is_err());
+        assert!("10.0.0/8".parse::<Network>().is_err());
+        assert!("example.org".parse::<Network>().is_err());
+    }
+
+    #[test]
+    fn matches_prefixes() {
+        assert!(network("0.0.0.0/0").contains(ip("203.0.113.7")));
+        assert!(network("10.0.0.0/8").contains(ip("10.255.0.1")));
+        assert!(!network("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(network("10.0.0.1/32").contains(ip("10.0.0.1")));
+        assert!(!network("10.0.0.1/32").contains(ip("10.0.0.2")));
+        assert!(network("10.0.0.1").contains(ip("10.0.0.1")));
+        assert!(!network("10.0.0.1").contains(ip("10.0.0.2")));
+
+        assert!(network("::/0").contains(ip("2001:db8::1")));
+        assert!(network("fd00::/8").contains(ip("fd12::1")));
+        assert!(!network("fd00::/8").contains(ip("fe80::1")));
+        assert!(network("2001:db8::1/128").contains(ip("2001:db8::1")));
+        assert!(!network("2001:db8::1/128").contains(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn matches_address_families() {
+        assert!(!network("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(!network("::/0").contains(ip("10.0.0.1")));
+        assert!(network("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+        assert!(network("127.0.0.1").contains(ip("::ffff:127.0.0.1")));
+        assert!(!network("10.0.0.0/8").contains(ip("::ffff:11.0.0.1")));
+    }
+
+    #[test]
+    fn trusts_mapped_addresses() {
+        let proxies = proxies(&["10.0.0.0/8"]);
+
+        assert!(!proxies.is_trusted(ip("203.0.113.7")));
+        assert!(proxies.is_trusted(ip("::ffff:10.0.0.1")));
+        assert!(proxies.is_trusted(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn finds_client_behind_proxies() {
+        let proxies = proxies(&["10.0.0.0/8", "fd00::/8"]);
+
+        assert_eq!(
+            client_ip(&proxies, "10.0.0.1", "203.0.113.7"),
+            Some(ip("203.0.113.7"))
+        );
+        assert_eq!(
+            client_ip(&proxies, "10.0.0.1", "203.0.113.7, 10.0.0.2, fd00::1"),
+            Some(ip("203.0.113.7"))
+        );
+        assert_eq!(
+            client_ip(&proxies, "::ffff:10.0.0.1", "::ffff:203.0.113.7"),
+            Some(ip("203.0.113.7"))
+        );
+        // Only trusted proxies forwarded the request.
+        assert_eq!(
+            client_ip(&proxies, "10.0.0.1", "10.0.0.3, 10.0.0.2"),
+            Some(ip("10.0.0.3"))
+        );
+        assert_eq!(
+            proxies.forwarded_client_ip(Some(ip("10.0.0.1")), std::iter::empty()),
+            Some(ip("10.0.0.1"))
+        );
+    }
+
+    #[test]
+    fn ignores_spoofed_forwarded_for() {
+        let proxies = proxies(&["10.0.0.0/8"]);
+
+        // The client prepended addresses of its own.
+        assert_eq!(
+            client_ip(&proxies, "10.0.0.1", "127.0.0.1, 10.0.0.5, 203.0.113.7"),
+            Some(ip("203.0.113.7"))
+        );
+        assert_eq!(
+            client_ip(&proxies, "10.0.0.1", "10.0.0.5, 203.0.113.7, 10.0.0.2"),
+            Some(ip("203.0.113.7"))
+        );
+        // Nothing left of an invalid entry is used.
+        assert_eq!(
+            client_ip(&proxies, "10.0.0.1", "203.0.113.7, unknown, 10.0.0.2"),
+            Some(ip("10.0.0.2"))
+        );
+        assert_eq!(
+            client_ip(&proxies, "10.0.0.1", "203.0.113.7, 10.0.0.2:8080"),
+            Some(ip("10.0.0.1"))
+        );
+        assert_eq!(client_ip(&proxies, "10.0.0.1", ""), Some(ip("10.0.0.1")));
+    }
+}
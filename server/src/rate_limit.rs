@@ -0,0 +1,155 @@
+use super::config::RateLimitConfig;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/* Above this many tracked clients, stale entries are cleaned up. */
+const PRUNE_THRESHOLD: usize = 1000;
+
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+/* Tracks failed authentication attempts per client address. After `max_failures` failures a client
+is locked out, for a period that doubles with every further failure. Shared between all workers. */
+pub struct RateLimiter {
+    max_failures: u32,
+    lockout: Duration,
+    max_lockout: Duration,
+    attempts: Mutex<HashMap<Option<IpAddr>, Attempts>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        RateLimiter {
+            max_failures: config.max_failures,
+            lockout: Duration::from_secs(config.lockout_seconds),
+            max_lockout: Duration::from_secs(config.max_lockout_seconds),
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remaining lockout time for the client, if it is locked out.
+    pub fn lockout(&self, client: Option<IpAddr>) -> Option<Duration> {
+        let attempts = self.attempts.lock().unwrap();
+        let now = Instant::now();
+
+        attempts
+            .get(&client)
+            .and_then(|attempts| attempts.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now)
+    }
+
+    pub fn record_failure(&self, client: Option<IpAddr>) {
+        let mut attempts = self.attempts.lock().unwrap();
+        let now = Instant::now();
+
+        if attempts.len() > PRUNE_THRESHOLD {
+            let max_lockout = self.max_lockout;
+            attempts.retain(|_, attempts| now - attempts.last_failure < max_lockout);
+        }
+
+        let entry = attempts.entry(client).or_insert(Attempts {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+
+        // Failures are forgotten after a quiet period as long as the maximum lockout.
+        if now - entry.last_failure > self.max_lockout {
+            entry.failures = 0;
+        }
+
+        entry.failures += 1;
+        entry.last_failure = now;
+
+        if entry.failures >= self.max_failures {
+            let exponent = (entry.failures - self.max_failures).min(16);
+            // Checked, as a panic here would poison the mutex for every later request.
+            let lockout = self
+                .lockout
+                .checked_mul(2u32.pow(exponent))
+                .unwrap_or(self.max_lockout)
+                .min(self.max_lockout);
+
+            entry.locked_until = Some(now + lockout);
+        }
+    }
+
+    pub fn record_success(&self, client: Option<IpAddr>) {
+        self.attempts.lock().unwrap().remove(&client);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(max_failures: u32, lockout_seconds: u64, max_lockout_seconds: u64) -> RateLimiter {
+        RateLimiter::new(&RateLimitConfig {
+            max_failures,
+            lockout_seconds,
+            max_lockout_seconds,
+        })
+    }
+
+    fn client(last: u8) -> Option<IpAddr> {
+        Some(IpAddr::from([192, 0, 2, last]))
+    }
+
+    #[test]
+    fn locks_out_after_max_failures() {
+        let limiter = limiter(3, 30, 3600);
+
+        limiter.record_failure(client(1));
+        limiter.record_failure(client(1));
+        assert!(limiter.lockout(client(1)).is_none());
+
+        limiter.record_failure(client(1));
+        let lockout = limiter.lockout(client(1)).unwrap();
+        assert!(lockout > Duration::from_secs(29) && lockout <= Duration::from_secs(30));
+        assert!(limiter.lockout(client(2)).is_none());
+        assert!(limiter.lockout(None).is_none());
+    }
+
+    #[test]
+    fn doubles_lockout_up_to_maximum() {
+        let limiter = limiter(1, 30, 100);
+
+        limiter.record_failure(client(1));
+        limiter.record_failure(client(1));
+        let lockout = limiter.lockout(client(1)).unwrap();
+        assert!(lockout > Duration::from_secs(59) && lockout <= Duration::from_secs(60));
+
+        limiter.record_failure(client(1));
+        let lockout = limiter.lockout(client(1)).unwrap();
+        assert!(lockout > Duration::from_secs(99) && lockout <= Duration::from_secs(100));
+    }
+
+    #[test]
+    fn caps_overflowing_lockout() {
+        let limiter = limiter(1, 1 << 50, 1 << 60);
+
+        for _ in 0..20 {
+            limiter.record_failure(client(1));
+        }
+        assert!(limiter.lockout(client(1)).is_some());
+        // The mutex is still usable.
+        limiter.record_success(client(1));
+        assert!(limiter.lockout(client(1)).is_none());
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let limiter = limiter(2, 30, 3600);
+
+        limiter.record_failure(client(1));
+        limiter.record_success(client(1));
+        limiter.record_failure(client(1));
+        assert!(limiter.lockout(client(1)).is_none());
+    }
+}
@@ -0,0 +1,108 @@
+use super::data::{ChecklistItem, ColumnAlign, Content, Table, TableCell};
+use super::diagram;
+use super::markdown;
+use super::math;
+
+/* HTML for a content block, for clients that don't render the type themselves and for exports.
+Text is sanitized when it's stored and everything else is escaped or sanitized here. */
+pub fn to_html(content: &Content) -> Result<String, String> {
+    match content {
+        Content::Text { text } => Ok(text.clone()),
+        Content::Code { language, code } => Ok(code_html(language, code)),
+        Content::Checklist { items } => {
+            let mut html = String::new();
+            push_checklist_html(items, &mut html);
+            Ok(html)
+        }
+        Content::Markdown { markdown } => markdown::to_html(markdown),
+        Content::Math { latex, display } => {
+            math::to_mathml(latex, *display).map(|mathml| mathml + "\n")
+        }
+        Content::Diagram { dot } => diagram::to_svg(dot),
+        Content::Table(table) => Ok(table_html(table)),
+    }
+}
+
+fn code_html(language: &str, code: &str) -> String {
+    if language.is_empty() {
+        format!("<pre><code>{}</code></pre>\n", escape_html(code))
+    } else {
+        format!(
+            "<pre><code class=\"language-{}\">{}</code></pre>\n",
+            escape_html(language),
+            escape_html(code)
+        )
+    }
+}
+
+fn push_checklist_html(items: &[ChecklistItem], html: &mut String) {
+    html.push_str("<ul>\n");
+    for item in items {
+        html.push_str("<li><input type=\"checkbox\"");
+        if item.checked {
+            html.push_str(" checked=\"\"");
+        }
+        html.push_str(" disabled=\"\"/> ");
+        html.push_str(&escape_html(&item.text));
+        if let Some(due_date) = item.due_date {
+            html.push_str(&format!(" <time datetime=\"{0}\">{0}</time>", due_date));
+        }
+        if !item.items.is_empty() {
+            html.push('\n');
+            push_checklist_html(&item.items, html);
+        }
+        html.push_str("</li>\n");
+    }
+    html.push_str("</ul>\n");
+}
+
+fn table_html(table: &Table) -> String {
+    let cell_tag = |tag: &str, align: ColumnAlign| match align {
+        ColumnAlign::Left => format!("<{}>", tag),
+        ColumnAlign::Center => format!("<{} align=\"center\">", tag),
+        ColumnAlign::Right => format!("<{} align=\"right\">", tag),
+    };
+
+    let mut html = String::from("<table>\n<thead>\n<tr>\n");
+    for column in &table.columns {
+        html.push_str(&cell_tag("th", column.align));
+        html.push_str(&escape_html(&column.header));
+        html.push_str("</th>\n");
+    }
+    html.push_str("</tr>\n</thead>\n");
+
+    if !table.rows.is_empty() {
+        html.push_str("<tbody>\n");
+        for row in &table.rows {
+            html.push_str("<tr>\n");
+            for (cell, column) in row.iter().zip(&table.columns) {
+                html.push_str(&cell_tag("td", column.align));
+                match cell {
+                    TableCell::Empty => (),
+                    TableCell::Boolean(value) => html.push_str(&value.to_string()),
+                    TableCell::Number(value) => html.push_str(&value.to_string()),
+                    TableCell::Text(text) => html.push_str(&escape_html(text)),
+                }
+                html.push_str("</td>\n");
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</tbody>\n");
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
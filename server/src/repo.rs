@@ -3,20 +3,36 @@
 
 use super::data;
 use super::repo_id;
+use super::sanitize;
 use chrono::prelude::*;
 use diesel;
 use diesel::prelude::*;
 use diesel::sql_query;
 use serde_json;
 use std;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use super::schema::api_tokens;
+use super::schema::audit_log;
 use super::schema::content_blocks;
+use super::schema::deletions;
 use super::schema::notebooks;
 use super::schema::notes;
+use super::schema::recovery_codes;
+use super::schema::sessions;
+use super::schema::two_factor;
+use super::totp;
 
 embed_migrations!("./migrations");
 static WELCOME_TEXT: &str = include_str!("./static/welcome.html");
 static TEXT_FORMATTING_INFO: &str = include_str!("./static/text_formatting.html");
+static BACKUP_PREFIX: &str = "pragma-backup-";
+const AUDIT_PAGE_SIZE: i64 = 100;
+/* Marks where text block HTML started being sanitized on write. The migration itself is empty,
+`setup` sanitizes the rows written before it. */
+const SANITIZE_TEXT_MIGRATION: &str = "20261019160000";
+const MAX_AUDIT_PAGE_SIZE: i64 = 1000;
 
 #[derive(Queryable)]
 struct Notebook {
@@ -89,6 +105,94 @@ struct NewContentBlock {
 pub enum Content {
     Text { text: String },
     Code { language: String, code: String },
+    Checklist { items: Vec<data::ChecklistItem> },
+    Markdown { markdown: String },
+    Math { latex: String, display: bool },
+    Diagram { dot: String },
+    Table(data::Table),
+}
+
+#[derive(Queryable)]
+struct ApiToken {
+    id: String,
+    name: String,
+    #[allow(dead_code)]
+    token_hash: String,
+    scope: String,
+    created_at: NaiveDateTime,
+    last_used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "api_tokens"]
+struct NewApiToken {
+    id: String,
+    name: String,
+    token_hash: String,
+    scope: String,
+    created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Insertable)]
+#[table_name = "sessions"]
+struct Session {
+    id_hash: String,
+    username: String,
+    created_at: NaiveDateTime,
+    expires_at: NaiveDateTime,
+    /* The hash of the session this one replaced when it was rotated. */
+    rotated_from: Option<String>,
+}
+
+#[derive(Queryable)]
+struct TwoFactor {
+    #[allow(dead_code)]
+    username: String,
+    secret: String,
+    confirmed: bool,
+    last_used_step: Option<i64>,
+    #[allow(dead_code)]
+    created_at: NaiveDateTime,
+    pending_secret: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "two_factor"]
+struct NewTwoFactor {
+    username: String,
+    secret: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "recovery_codes"]
+struct NewRecoveryCode {
+    code_hash: String,
+    username: String,
+    pending: bool,
+}
+
+#[derive(Queryable)]
+struct AuditEntry {
+    id: i64,
+    created_at: NaiveDateTime,
+    resource_type: String,
+    resource_id: String,
+    action: String,
+    actor: String,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_log"]
+struct NewAuditEntry<'a> {
+    created_at: NaiveDateTime,
+    resource_type: &'a str,
+    resource_id: &'a str,
+    action: &'a str,
+    actor: &'a str,
+    client_ip: Option<&'a str>,
+    user_agent: Option<&'a str>,
 }
 
 #[derive(Queryable)]
@@ -181,12 +285,94 @@ fn seed(connection: &SqliteConnection) {
 
 pub fn setup(connection: &SqliteConnection) {
     let is_first_run = is_first_run(connection);
-
-    embedded_migrations::run_with_output(connection, &mut std::io::stdout()).unwrap();
+    let sanitize_text = !is_first_run
+        && pending_migrations(connection)
+            .unwrap()
+            .iter()
+            .any(|version| version == SANITIZE_TEXT_MIGRATION);
+
+    // The sanitize pass runs in the same transaction as the migrations, so it's retried on the
+    // next start if it fails.
+    let sanitized = transaction(connection, || {
+        embedded_migrations::run_with_output(connection, &mut std::io::stdout())
+            .map_err(|e| format!("{}", e))?;
+
+        if sanitize_text {
+            sanitize_text_content_blocks(connection).map(Some)
+        } else {
+            Ok(None)
+        }
+    })
+    .unwrap();
 
     if is_first_run {
         seed(connection);
     }
+
+    if let Some(sanitized) = sanitized {
+        println!("Sanitized HTML of {} text content blocks", sanitized);
+    }
+}
+
+/* Sanitizes text blocks stored before HTML was sanitized on write. Changed blocks get a new
+revision so clients pick up the sanitized HTML on their next sync. */
+fn sanitize_text_content_blocks(connection: &SqliteConnection) -> Result<usize, String> {
+    use super::schema::content_blocks::dsl::*;
+
+    let now = to_naive(Utc::now());
+
+    let result = connection.transaction::<usize, diesel::result::Error, _>(|| {
+        let text_blocks = content_blocks
+            .filter(type_.eq("text"))
+            .load::<ContentBlock>(connection)?;
+
+        let mut sanitized = 0;
+        for content_block in text_blocks {
+            // Text never fails validation.
+            let content_string = match content_to_string(map_content(&content_block)) {
+                Ok((content_string, _)) => content_string,
+                Err(_) => continue,
+            };
+            if content_string == content_block.content {
+                continue;
+            }
+
+            diesel::update(content_blocks.filter(id.eq(&content_block.id)))
+                .set((content.eq(content_string), system_updated_at.eq(now)))
+                .execute(connection)?;
+            sanitized += 1;
+        }
+
+        Ok(sanitized)
+    });
+
+    result.map_err(|e| format!("{}", e))
+}
+
+/* Migrations embedded in this build that haven't been applied to the database, which happens
+when the database was replaced after startup, e.g. by restoring an old backup. */
+pub fn pending_migrations(connection: &SqliteConnection) -> Result<Vec<String>, String> {
+    type TextSql = ::diesel::sql_types::Text;
+    #[derive(QueryableByName, Debug)]
+    struct AppliedMigration {
+        #[sql_type = "TextSql"]
+        version: String,
+    }
+
+    let applied: Vec<AppliedMigration> =
+        sql_query("SELECT version FROM __diesel_schema_migrations;")
+            .load(connection)
+            .map_err(|e| format!("{}", e))?;
+
+    Ok(env!("PRAGMA_MIGRATION_VERSIONS")
+        .split(',')
+        .filter(|version| {
+            !applied
+                .iter()
+                .any(|migration| migration.version == *version)
+        })
+        .map(String::from)
+        .collect())
 }
 
 pub fn notebooks(
@@ -276,6 +462,20 @@ pub fn content_blocks(
         .map(map_content_blocks)
 }
 
+pub fn content_block(
+    content_block_id: &str,
+    connection: &SqliteConnection,
+) -> Result<Option<data::ContentBlock>, String> {
+    use super::schema::content_blocks::dsl::*;
+
+    content_blocks
+        .find(content_block_id)
+        .first::<ContentBlock>(connection)
+        .optional()
+        .map_err(|e| format!("{}", e))
+        .map(|content_block| content_block.map(map_content_block))
+}
+
 fn map_content_blocks(content_blocks: Vec<ContentBlock>) -> Vec<data::ContentBlock> {
     content_blocks.into_iter().map(map_content_block).collect()
 }
@@ -299,6 +499,90 @@ fn map_content(content_block: &ContentBlock) -> data::Content {
     match content {
         Content::Text { text } => data::Content::Text { text },
         Content::Code { language, code } => data::Content::Code { language, code },
+        Content::Checklist { items } => data::Content::Checklist { items },
+        Content::Markdown { markdown } => data::Content::Markdown { markdown },
+        Content::Math { latex, display } => data::Content::Math { latex, display },
+        Content::Diagram { dot } => data::Content::Diagram { dot },
+        Content::Table(table) => data::Content::Table(table),
+    }
+}
+
+/* Unchecked items of all checklists, soonest due first and undated items last. Items below a
+checked item count as done. */
+pub fn open_checklist_items(
+    filter: data::ChecklistItemFilter,
+    connection: &SqliteConnection,
+) -> Result<Vec<data::OpenChecklistItem>, String> {
+    let checklists = content_blocks::table
+        .inner_join(notes::table)
+        .filter(content_blocks::type_.eq("checklist"))
+        .select((content_blocks::all_columns, notes::title))
+        .load::<(ContentBlock, String)>(connection)
+        .map_err(|e| format!("{}", e))?;
+
+    let mut open_items = vec![];
+    for (content_block, note_title) in checklists {
+        if let data::Content::Checklist { items } = map_content(&content_block) {
+            collect_open_checklist_items(
+                items,
+                &[],
+                &content_block.note_id,
+                &note_title,
+                &content_block.id,
+                &mut open_items,
+            );
+        }
+    }
+
+    if let Some(due_by) = filter.due_by {
+        open_items.retain(|item| item.due_date.is_some_and(|due_date| due_date <= due_by));
+    }
+
+    open_items.sort_by(|a, b| {
+        (a.due_date.is_none(), a.due_date, &a.note_title, &a.path).cmp(&(
+            b.due_date.is_none(),
+            b.due_date,
+            &b.note_title,
+            &b.path,
+        ))
+    });
+
+    Ok(open_items)
+}
+
+fn collect_open_checklist_items(
+    items: Vec<data::ChecklistItem>,
+    parent_path: &[usize],
+    note_id: &str,
+    note_title: &str,
+    content_block_id: &str,
+    open_items: &mut Vec<data::OpenChecklistItem>,
+) {
+    for (position, item) in items.into_iter().enumerate() {
+        if item.checked {
+            continue;
+        }
+
+        let mut path = parent_path.to_vec();
+        path.push(position);
+
+        collect_open_checklist_items(
+            item.items,
+            &path,
+            note_id,
+            note_title,
+            content_block_id,
+            open_items,
+        );
+
+        open_items.push(data::OpenChecklistItem {
+            note_id: note_id.to_string(),
+            note_title: note_title.to_string(),
+            content_block_id: content_block_id.to_string(),
+            path,
+            text: item.text,
+            due_date: item.due_date,
+        });
     }
 }
 
@@ -463,7 +747,7 @@ pub fn create_content_block(
 
     let now = Utc::now();
 
-    let (content_string, content_type) = content_to_string(content_block.content);
+    let (content_string, content_type) = content_to_string(content_block.content)?;
 
     let new_content_block = NewContentBlock {
         id: content_block.id.unwrap_or_else(repo_id::generate),
@@ -496,7 +780,7 @@ pub fn update_content_block(
 ) -> Result<data::ContentBlock, String> {
     use super::schema::content_blocks::dsl::*;
 
-    let (content_string, content_type) = content_to_string(update.content);
+    let (content_string, content_type) = content_to_string(update.content)?;
 
     let result = diesel::update(content_blocks.filter(id.eq(&content_block_id)))
         .set((
@@ -527,14 +811,625 @@ pub fn delete_contentblock(
     }
 }
 
-fn content_to_string(content: data::Content) -> (String, String) {
+pub fn api_tokens(connection: &SqliteConnection) -> Result<Vec<data::ApiToken>, String> {
+    use super::schema::api_tokens::dsl::*;
+
+    api_tokens
+        .order(created_at.asc())
+        .load::<ApiToken>(connection)
+        .map_err(|e| format!("{}", e))
+        .map(|tokens| tokens.into_iter().map(map_api_token).collect())
+}
+
+pub fn create_api_token(
+    api_token: data::NewApiToken,
+    hash: String,
+    conn: &SqliteConnection,
+) -> Result<data::ApiToken, String> {
+    use super::schema::api_tokens::dsl::*;
+
+    let new_api_token = NewApiToken {
+        id: repo_id::generate(),
+        name: api_token.name,
+        token_hash: hash,
+        scope: api_token.scope.as_str().to_string(),
+        created_at: to_naive(Utc::now()),
+    };
+
+    let result = diesel::insert_into(api_tokens)
+        .values(&new_api_token)
+        .execute(conn)
+        .and_then(|_num_rows| api_tokens.find(&new_api_token.id).first(conn));
+
+    match result {
+        Ok(api_token) => Ok(map_api_token(api_token)),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+pub fn delete_api_token(api_token_id: &str, connection: &SqliteConnection) -> Result<(), String> {
+    use super::schema::api_tokens::dsl::*;
+
+    let result = diesel::delete(api_tokens.filter(id.eq(api_token_id))).execute(connection);
+
+    match result {
+        Ok(0) => Err(format!("No token with id {}", api_token_id)),
+        Ok(_num_rows) => Ok(()),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+/* Looks up a token by its hash, and records it was used. To avoid a write on every request
+last_used_at is only updated once a minute. */
+pub fn authenticate_api_token(
+    hash: &str,
+    connection: &SqliteConnection,
+) -> Result<Option<data::ApiToken>, String> {
+    use super::schema::api_tokens::dsl::*;
+
+    let api_token = api_tokens
+        .filter(token_hash.eq(hash))
+        .first::<ApiToken>(connection)
+        .optional()
+        .map_err(|e| format!("{}", e))?;
+
+    let mut api_token = match api_token {
+        Some(api_token) => api_token,
+        None => return Ok(None),
+    };
+
+    let now = to_naive(Utc::now());
+    let is_stale = match api_token.last_used_at {
+        Some(used_at) => now - used_at > chrono::Duration::minutes(1),
+        None => true,
+    };
+
+    if is_stale {
+        diesel::update(api_tokens.find(&api_token.id))
+            .set(last_used_at.eq(now))
+            .execute(connection)
+            .map_err(|e| format!("{}", e))?;
+
+        api_token.last_used_at = Some(now);
+    }
+
+    Ok(Some(map_api_token(api_token)))
+}
+
+fn map_api_token(api_token: ApiToken) -> data::ApiToken {
+    data::ApiToken {
+        id: api_token.id,
+        name: api_token.name,
+        // Scopes are only ever written from a data::Scope.
+        scope: api_token.scope.parse().unwrap(),
+        created_at: to_utc(api_token.created_at),
+        last_used_at: api_token.last_used_at.map(to_utc),
+    }
+}
+
+/* Expired sessions are cleaned up whenever a new session is created. */
+pub fn create_session(
+    session_hash: String,
+    session: data::Session,
+    connection: &SqliteConnection,
+) -> Result<(), String> {
+    use super::schema::sessions::dsl::*;
+
+    let new_session = Session {
+        id_hash: session_hash,
+        username: session.username,
+        created_at: to_naive(session.created_at),
+        expires_at: to_naive(session.expires_at),
+        rotated_from: None,
+    };
+
+    let result = connection.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::delete(sessions.filter(expires_at.lt(to_naive(Utc::now())))).execute(connection)?;
+
+        diesel::insert_into(sessions)
+            .values(&new_session)
+            .execute(connection)
+    });
+
+    match result {
+        Ok(_num_rows) => Ok(()),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+pub fn session(
+    session_hash: &str,
+    connection: &SqliteConnection,
+) -> Result<Option<data::Session>, String> {
+    use super::schema::sessions::dsl::*;
+
+    sessions
+        .find(session_hash)
+        .filter(expires_at.gt(to_naive(Utc::now())))
+        .first::<Session>(connection)
+        .optional()
+        .map_err(|e| format!("{}", e))
+        .map(|session| session.map(map_session))
+}
+
+/* The old session is kept until the given time, for requests that are already underway. */
+pub fn rotate_session(
+    old_session_hash: &str,
+    new_session_hash: String,
+    session: data::Session,
+    old_session_expires_at: DateTime<Utc>,
+    connection: &SqliteConnection,
+) -> Result<(), String> {
+    use super::schema::sessions::dsl::*;
+
+    let new_session = Session {
+        id_hash: new_session_hash,
+        username: session.username,
+        created_at: to_naive(session.created_at),
+        expires_at: to_naive(session.expires_at),
+        rotated_from: Some(old_session_hash.to_string()),
+    };
+
+    let result = connection.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::update(sessions.find(old_session_hash))
+            .set(expires_at.eq(to_naive(old_session_expires_at)))
+            .execute(connection)?;
+
+        diesel::insert_into(sessions)
+            .values(&new_session)
+            .execute(connection)
+    });
+
+    match result {
+        Ok(_num_rows) => Ok(()),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+/* Also deletes the session it was rotated from and the one it was rotated to, so that neither id
+keeps working during the rotation grace period. */
+pub fn delete_session(session_hash: &str, connection: &SqliteConnection) -> Result<(), String> {
+    use super::schema::sessions::dsl::*;
+
+    let result = connection.transaction::<_, diesel::result::Error, _>(|| {
+        let previous_hash = sessions
+            .find(session_hash)
+            .select(rotated_from)
+            .first::<Option<String>>(connection)
+            .optional()?
+            .flatten();
+
+        diesel::delete(
+            sessions.filter(
+                id_hash
+                    .eq(session_hash)
+                    .or(rotated_from.eq(session_hash))
+                    .or(id_hash.nullable().eq(previous_hash)),
+            ),
+        )
+        .execute(connection)
+    });
+
+    match result {
+        Ok(_num_rows) => Ok(()),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+fn map_session(session: Session) -> data::Session {
+    data::Session {
+        username: session.username,
+        created_at: to_utc(session.created_at),
+        expires_at: to_utc(session.expires_at),
+    }
+}
+
+/* Replaces any unconfirmed enrollment. The new secret and recovery codes stay pending until
+confirmed, so a confirmed second factor and its recovery codes keep working until then. */
+pub fn enroll_two_factor(
+    user: &str,
+    totp_secret: String,
+    recovery_code_hashes: Vec<String>,
+    connection: &SqliteConnection,
+) -> Result<(), String> {
+    let new_recovery_codes: Vec<NewRecoveryCode> = recovery_code_hashes
+        .into_iter()
+        .map(|hash| NewRecoveryCode {
+            code_hash: hash,
+            username: user.to_string(),
+            pending: true,
+        })
+        .collect();
+
+    let result = connection.transaction::<_, diesel::result::Error, _>(|| {
+        let is_confirmed = two_factor::table
+            .find(user)
+            .select(two_factor::confirmed)
+            .first::<bool>(connection)
+            .optional()?
+            .unwrap_or(false);
+
+        if is_confirmed {
+            diesel::update(two_factor::table.find(user))
+                .set(two_factor::pending_secret.eq(&totp_secret))
+                .execute(connection)?;
+
+            diesel::delete(
+                recovery_codes::table
+                    .filter(recovery_codes::username.eq(user))
+                    .filter(recovery_codes::pending.eq(true)),
+            )
+            .execute(connection)?;
+        } else {
+            diesel::delete(recovery_codes::table.filter(recovery_codes::username.eq(user)))
+                .execute(connection)?;
+            diesel::delete(two_factor::table.find(user)).execute(connection)?;
+
+            diesel::insert_into(two_factor::table)
+                .values(&NewTwoFactor {
+                    username: user.to_string(),
+                    secret: totp_secret.clone(),
+                })
+                .execute(connection)?;
+        }
+
+        diesel::insert_into(recovery_codes::table)
+            .values(&new_recovery_codes)
+            .execute(connection)
+    });
+
+    match result {
+        Ok(_num_rows) => Ok(()),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+/* Swaps the pending secret and recovery codes in for the current ones if the code is valid. */
+pub fn confirm_two_factor(
+    user: &str,
+    code: &str,
+    connection: &SqliteConnection,
+) -> Result<bool, String> {
+    let enrollment = two_factor::table
+        .find(user)
+        .first::<TwoFactor>(connection)
+        .optional()
+        .map_err(|e| format!("{}", e))?;
+
+    let new_secret = match enrollment {
+        Some(TwoFactor {
+            pending_secret: Some(pending_secret),
+            ..
+        }) => pending_secret,
+        Some(TwoFactor {
+            confirmed: false,
+            secret,
+            ..
+        }) => secret,
+        _ => return Err("No two-factor enrollment is waiting for confirmation".to_string()),
+    };
+
+    let step = match totp::verify(&new_secret, code, Utc::now().timestamp()) {
+        Some(step) => step,
+        None => return Ok(false),
+    };
+
+    let result = connection.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::update(two_factor::table.find(user))
+            .set((
+                two_factor::secret.eq(&new_secret),
+                two_factor::pending_secret.eq(None::<String>),
+                two_factor::confirmed.eq(true),
+                two_factor::last_used_step.eq(step),
+            ))
+            .execute(connection)?;
+
+        diesel::delete(
+            recovery_codes::table
+                .filter(recovery_codes::username.eq(user))
+                .filter(recovery_codes::pending.eq(false)),
+        )
+        .execute(connection)?;
+
+        diesel::update(recovery_codes::table.filter(recovery_codes::username.eq(user)))
+            .set(recovery_codes::pending.eq(false))
+            .execute(connection)
+    });
+
+    match result {
+        Ok(_num_rows) => Ok(true),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+pub fn disable_two_factor(user: &str, connection: &SqliteConnection) -> Result<(), String> {
+    use super::schema::two_factor::dsl::*;
+
+    let result = diesel::delete(two_factor.find(user)).execute(connection);
+
+    match result {
+        Ok(_num_rows) => Ok(()),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+/* Returns None if the user has no confirmed second factor. Otherwise checks the code as
+a TOTP code, which can only be used once, or as an unused recovery code. */
+pub fn verify_second_factor(
+    user: &str,
+    code: Option<&str>,
+    recovery_code_hash: Option<&str>,
+    connection: &SqliteConnection,
+) -> Result<Option<bool>, String> {
+    use super::schema::two_factor::dsl::*;
+
+    let enrollment = two_factor
+        .find(user)
+        .filter(confirmed.eq(true))
+        .first::<TwoFactor>(connection)
+        .optional()
+        .map_err(|e| format!("{}", e))?;
+
+    let enrollment = match enrollment {
+        Some(enrollment) => enrollment,
+        None => return Ok(None),
+    };
+
+    let code = match code {
+        Some(code) => code,
+        None => return Ok(Some(false)),
+    };
+
+    if let Some(step) = totp::verify(&enrollment.secret, code, Utc::now().timestamp()) {
+        if enrollment
+            .last_used_step
+            .is_some_and(|last_step| step <= last_step)
+        {
+            return Ok(Some(false));
+        }
+
+        return diesel::update(two_factor.find(user))
+            .set(last_used_step.eq(step))
+            .execute(connection)
+            .map(|_num_rows| Some(true))
+            .map_err(|e| format!("{}", e));
+    }
+
+    let recovery_code_hash = match recovery_code_hash {
+        Some(recovery_code_hash) => recovery_code_hash,
+        None => return Ok(Some(false)),
+    };
+
+    diesel::update(
+        recovery_codes::table
+            .filter(recovery_codes::code_hash.eq(recovery_code_hash))
+            .filter(recovery_codes::username.eq(user))
+            .filter(recovery_codes::pending.eq(false))
+            .filter(recovery_codes::used_at.is_null()),
+    )
+    .set(recovery_codes::used_at.eq(to_naive(Utc::now())))
+    .execute(connection)
+    .map(|num_rows| Some(num_rows == 1))
+    .map_err(|e| format!("{}", e))
+}
+
+pub fn resource_counts(connection: &SqliteConnection) -> Result<data::ResourceCounts, String> {
+    let count = || -> Result<data::ResourceCounts, diesel::result::Error> {
+        Ok(data::ResourceCounts {
+            notebooks: notebooks::table.count().get_result(connection)?,
+            notes: notes::table.count().get_result(connection)?,
+            content_blocks: content_blocks::table.count().get_result(connection)?,
+            deletions: deletions::table.count().get_result(connection)?,
+        })
+    };
+
+    count().map_err(|e| format!("{}", e))
+}
+
+/* Runs the given changes in one transaction, such as a change and its audit log entry, so
+either all of them are kept or none. */
+pub fn transaction<T, F>(connection: &SqliteConnection, changes: F) -> Result<T, String>
+where
+    F: FnOnce() -> Result<T, String>,
+{
+    let mut failure = None;
+
+    connection
+        .transaction::<_, diesel::result::Error, _>(|| {
+            changes().map_err(|reason| {
+                failure = Some(reason);
+                diesel::result::Error::RollbackTransaction
+            })
+        })
+        .map_err(|err| failure.unwrap_or_else(|| format!("{}", err)))
+}
+
+pub fn record_audit(
+    context: &data::AuditContext,
+    resource_type: &str,
+    resource_id: &str,
+    action: data::AuditAction,
+    connection: &SqliteConnection,
+) -> Result<(), String> {
+    let new_audit_entry = NewAuditEntry {
+        created_at: to_naive(Utc::now()),
+        resource_type,
+        resource_id,
+        action: action.as_str(),
+        actor: &context.actor,
+        client_ip: context.client_ip.as_deref(),
+        user_agent: context.user_agent.as_deref(),
+    };
+
+    diesel::insert_into(audit_log::table)
+        .values(&new_audit_entry)
+        .execute(connection)
+        .map(|_num_rows| ())
+        .map_err(|e| format!("{}", e))
+}
+
+/* Newest entries first. Pages are continued with `before_id`, which avoids skipping or
+repeating entries while new ones are added. */
+pub fn audit_log(
+    filter: data::AuditFilter,
+    connection: &SqliteConnection,
+) -> Result<data::AuditPage, String> {
+    use super::schema::audit_log::dsl::*;
+
+    let limit = filter
+        .limit
+        .unwrap_or(AUDIT_PAGE_SIZE)
+        .clamp(1, MAX_AUDIT_PAGE_SIZE);
+
+    let mut query = audit_log.into_boxed();
+
+    if let Some(filter_resource_type) = filter.resource_type {
+        query = query.filter(resource_type.eq(filter_resource_type));
+    }
+    if let Some(filter_resource_id) = filter.resource_id {
+        query = query.filter(resource_id.eq(filter_resource_id));
+    }
+    if let Some(filter_action) = filter.action {
+        query = query.filter(action.eq(filter_action));
+    }
+    if let Some(filter_actor) = filter.actor {
+        query = query.filter(actor.eq(filter_actor));
+    }
+    if let Some(since) = filter.since {
+        query = query.filter(created_at.ge(to_naive(since)));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(created_at.lt(to_naive(until)));
+    }
+    if let Some(before_id) = filter.before_id {
+        query = query.filter(id.lt(before_id));
+    }
+
+    let mut entries = query
+        .order(id.desc())
+        .limit(limit + 1)
+        .load::<AuditEntry>(connection)
+        .map_err(|e| format!("{}", e))?;
+
+    let next_before_id = if entries.len() as i64 > limit {
+        entries.truncate(limit as usize);
+        entries.last().map(|entry| entry.id)
+    } else {
+        None
+    };
+
+    Ok(data::AuditPage {
+        entries: entries.into_iter().map(map_audit_entry).collect(),
+        next_before_id,
+    })
+}
+
+fn map_audit_entry(entry: AuditEntry) -> data::AuditEntry {
+    data::AuditEntry {
+        id: entry.id,
+        created_at: to_utc(entry.created_at),
+        resource_type: entry.resource_type,
+        resource_id: entry.resource_id,
+        action: entry.action,
+        actor: entry.actor,
+        client_ip: entry.client_ip,
+        user_agent: entry.user_agent,
+    }
+}
+
+pub fn prune_audit_log(
+    older_than: DateTime<Utc>,
+    connection: &SqliteConnection,
+) -> Result<usize, String> {
+    use super::schema::audit_log::dsl::*;
+
+    diesel::delete(audit_log.filter(created_at.lt(to_naive(older_than))))
+        .execute(connection)
+        .map_err(|e| format!("{}", e))
+}
+
+pub fn prune_deletions(
+    older_than: DateTime<Utc>,
+    connection: &SqliteConnection,
+) -> Result<usize, String> {
+    use super::schema::deletions::dsl::*;
+
+    diesel::delete(deletions.filter(system_updated_at.lt(to_naive(older_than))))
+        .execute(connection)
+        .map_err(|e| format!("{}", e))
+}
+
+/* Copies the database file while holding a write lock, so no writes can happen halfway
+through the copy. Only the newest `keep` backups are retained. */
+pub fn backup(
+    database_path: &Path,
+    directory: &Path,
+    keep: usize,
+    connection: &SqliteConnection,
+) -> Result<PathBuf, String> {
+    fs::create_dir_all(directory).map_err(|e| format!("{}", e))?;
+
+    let file_name = format!(
+        "{}{}.sqlite",
+        BACKUP_PREFIX,
+        Utc::now().format("%Y%m%dT%H%M%S")
+    );
+    let destination = directory.join(file_name);
+
+    connection
+        .execute("BEGIN IMMEDIATE")
+        .map_err(|e| format!("{}", e))?;
+    let copy_result = fs::copy(database_path, &destination);
+    connection.execute("COMMIT").map_err(|e| format!("{}", e))?;
+
+    copy_result.map_err(|e| format!("{}", e))?;
+    prune_backups(directory, keep)?;
+
+    Ok(destination)
+}
+
+fn prune_backups(directory: &Path, keep: usize) -> Result<(), String> {
+    let mut backups: Vec<PathBuf> = fs::read_dir(directory)
+        .map_err(|e| format!("{}", e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(BACKUP_PREFIX))
+        })
+        .collect();
+
+    // Backup file names contain a sortable timestamp, newest last.
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(keep);
+    for path in backups.into_iter().take(excess) {
+        fs::remove_file(path).map_err(|e| format!("{}", e))?;
+    }
+
+    Ok(())
+}
+
+fn content_to_string(content: data::Content) -> Result<(String, String), String> {
+    content.validate()?;
+
     let (content_, content_type) = match content {
-        data::Content::Text { text } => (Content::Text { text }, "text"),
+        data::Content::Text { text } => (
+            Content::Text {
+                text: sanitize::sanitize_html(&text),
+            },
+            "text",
+        ),
         data::Content::Code { language, code } => (Content::Code { language, code }, "code"),
+        data::Content::Checklist { items } => (Content::Checklist { items }, "checklist"),
+        data::Content::Markdown { markdown } => (Content::Markdown { markdown }, "markdown"),
+        data::Content::Math { latex, display } => (Content::Math { latex, display }, "math"),
+        data::Content::Diagram { dot } => (Content::Diagram { dot }, "diagram"),
+        data::Content::Table(table) => (Content::Table(table), "table"),
     };
     let content_string = serde_json::to_string(&content_).unwrap(); // FIXME
 
-    (content_string, content_type.to_string())
+    Ok((content_string, content_type.to_string()))
 }
 
 fn tags_to_string(tags: &[data::Tag]) -> String {
@@ -554,3 +1449,120 @@ fn to_naive(date_time: DateTime<Utc>) -> NaiveDateTime {
 fn to_utc(date_time: NaiveDateTime) -> DateTime<Utc> {
     DateTime::<Utc>::from_utc(date_time, Utc)
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /* A migrated in-memory database, like the one the server uses. */
+    pub fn connection() -> SqliteConnection {
+        let connection = SqliteConnection::establish(":memory:").unwrap();
+        connection.execute("PRAGMA foreign_keys = ON").unwrap();
+        embedded_migrations::run(&connection).unwrap();
+        connection
+    }
+
+    #[test]
+    fn rejects_reused_second_factor_codes() {
+        let connection = connection();
+        let secret = totp::generate_secret();
+        let recovery_code_hash = "recovery-code-hash";
+        enroll_two_factor(
+            "alice",
+            secret.clone(),
+            vec![recovery_code_hash.to_string()],
+            &connection,
+        )
+        .unwrap();
+
+        let now = Utc::now().timestamp();
+        let code = totp::code_at(&secret, now).unwrap();
+        let next_code = totp::code_at(&secret, now + 30).unwrap();
+        let verify = |code: &str, recovery_code_hash: Option<&str>| {
+            verify_second_factor("alice", Some(code), recovery_code_hash, &connection).unwrap()
+        };
+
+        // Unconfirmed enrollments don't need a second factor yet.
+        assert_eq!(verify(&code, None), None);
+        assert_eq!(confirm_two_factor("alice", &code, &connection), Ok(true));
+
+        // Confirming used up the code.
+        assert_eq!(verify(&code, None), Some(false));
+        assert_eq!(verify(&next_code, None), Some(true));
+        assert_eq!(verify(&next_code, None), Some(false));
+        assert_eq!(verify(&code, None), Some(false));
+
+        assert_eq!(verify("recovery", Some(recovery_code_hash)), Some(true));
+        assert_eq!(verify("recovery", Some(recovery_code_hash)), Some(false));
+    }
+
+    fn new_session(age: chrono::Duration) -> data::Session {
+        let now = Utc::now();
+        data::Session {
+            username: "alice".to_string(),
+            created_at: now - age,
+            expires_at: now - age + chrono::Duration::days(30),
+        }
+    }
+
+    #[test]
+    fn keeps_rotated_sessions_during_grace_period() {
+        let connection = connection();
+        create_session(
+            "old".to_string(),
+            new_session(chrono::Duration::days(2)),
+            &connection,
+        )
+        .unwrap();
+
+        let grace = chrono::Duration::seconds(1);
+        rotate_session(
+            "old",
+            "new".to_string(),
+            new_session(chrono::Duration::zero()),
+            Utc::now() + grace,
+            &connection,
+        )
+        .unwrap();
+        assert!(session("old", &connection).unwrap().is_some());
+        assert!(session("new", &connection).unwrap().is_some());
+
+        std::thread::sleep(
+            (grace + chrono::Duration::milliseconds(100))
+                .to_std()
+                .unwrap(),
+        );
+        assert!(session("old", &connection).unwrap().is_none());
+        assert!(session("new", &connection).unwrap().is_some());
+    }
+
+    #[test]
+    fn logout_ends_rotated_sessions() {
+        let connection = connection();
+        for logout_hash in &["old", "new"] {
+            for hash in &["old", "other"] {
+                create_session(
+                    hash.to_string(),
+                    new_session(chrono::Duration::days(2)),
+                    &connection,
+                )
+                .unwrap();
+            }
+            rotate_session(
+                "old",
+                "new".to_string(),
+                new_session(chrono::Duration::zero()),
+                Utc::now() + chrono::Duration::minutes(2),
+                &connection,
+            )
+            .unwrap();
+
+            // Logging out with either id ends both, but not other sessions.
+            delete_session(logout_hash, &connection).unwrap();
+            assert!(session("old", &connection).unwrap().is_none());
+            assert!(session("new", &connection).unwrap().is_none());
+            assert!(session("other", &connection).unwrap().is_some());
+            delete_session("other", &connection).unwrap();
+        }
+    }
+}
@@ -1,8 +1,19 @@
 use super::data::*;
+use super::logging::{self, RequestId};
+use super::metrics::{self, Metrics};
 use super::repo;
 use super::repo_connection;
+use super::two_factor;
 use ::actix::prelude::*;
+use actix_web::HttpRequest;
 use chrono::prelude::*;
+use diesel::SqliteConnection;
+use futures::future::Future;
+use log::warn;
+use std::any;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 pub struct DbExecutor(pub repo_connection::Pool);
 
@@ -10,6 +21,126 @@ impl Actor for DbExecutor {
     type Context = SyncContext<Self>;
 }
 
+/* Address of the DbExecutor that records how many messages are in flight, and how long
+they take including time spent waiting in the mailbox. */
+#[derive(Clone)]
+pub struct Db {
+    pub addr: Addr<DbExecutor>,
+    pub metrics: Arc<Metrics>,
+    pub request_id: Option<String>,
+}
+
+impl Db {
+    /* Sends messages on behalf of `req`, so that what is logged while handling them carries
+    its request ID. */
+    pub fn for_request<S>(&self, req: &HttpRequest<S>) -> Db {
+        Db {
+            request_id: req.extensions().get::<RequestId>().map(|id| id.0.clone()),
+            ..self.clone()
+        }
+    }
+
+    pub fn send<M, T>(&self, message: M) -> impl Future<Item = M::Result, Error = MailboxError>
+    where
+        M: Message<Result = Result<T, String>> + Send + 'static,
+        T: Send + 'static,
+        DbExecutor: Handler<M, Result = Result<T, String>>,
+    {
+        let message = ForRequest::new(self.request_id.clone(), message);
+        self.instrument::<M, _>(move |addr| addr.send::<ForRequest<T>>(message))
+    }
+
+    /* Fails with `MailboxError::Timeout` if the message isn't handled in time. It is still
+    handled eventually, but no longer counted as queued. */
+    pub fn send_timeout<M, T>(
+        &self,
+        message: M,
+        timeout: Duration,
+    ) -> impl Future<Item = M::Result, Error = MailboxError>
+    where
+        M: Message<Result = Result<T, String>> + Send + 'static,
+        T: Send + 'static,
+        DbExecutor: Handler<M, Result = Result<T, String>>,
+    {
+        let message = ForRequest::new(self.request_id.clone(), message);
+        self.instrument::<M, _>(move |addr| addr.send::<ForRequest<T>>(message).timeout(timeout))
+    }
+
+    fn instrument<M, F>(
+        &self,
+        send: impl FnOnce(&Addr<DbExecutor>) -> F,
+    ) -> impl Future<Item = M::Result, Error = MailboxError>
+    where
+        M: Message,
+        F: Future<Item = M::Result, Error = MailboxError>,
+    {
+        let metrics = self.metrics.clone();
+        let start = Instant::now();
+        metrics.db_message_queued();
+
+        send(&self.addr).then(move |res| {
+            metrics.db_message_handled(
+                metrics::message_name(any::type_name::<M>()),
+                start.elapsed(),
+            );
+            res
+        })
+    }
+}
+
+/* A message sent on behalf of a request. The request ID is set while it's handled, and a
+failure is logged along with it. */
+struct ForRequest<T> {
+    request_id: Option<String>,
+    message_name: &'static str,
+    handle: HandleFn<T>,
+}
+
+type HandleFn<T> =
+    Box<dyn FnOnce(&mut DbExecutor, &mut SyncContext<DbExecutor>) -> Result<T, String> + Send>;
+
+impl<T> ForRequest<T> {
+    fn new<M>(request_id: Option<String>, message: M) -> Self
+    where
+        M: Message<Result = Result<T, String>> + Send + 'static,
+        DbExecutor: Handler<M, Result = Result<T, String>>,
+    {
+        ForRequest {
+            request_id,
+            message_name: metrics::message_name(any::type_name::<M>()),
+            handle: Box::new(move |executor, ctx| executor.handle(message, ctx)),
+        }
+    }
+}
+
+impl<T: 'static> Message for ForRequest<T> {
+    type Result = Result<T, String>;
+}
+
+impl<T: 'static> Handler<ForRequest<T>> for DbExecutor {
+    type Result = Result<T, String>;
+
+    fn handle(&mut self, msg: ForRequest<T>, ctx: &mut Self::Context) -> Self::Result {
+        let ForRequest {
+            request_id,
+            message_name,
+            handle,
+        } = msg;
+        let handle = || {
+            let result = handle(self, ctx);
+            if let Err(ref reason) = result {
+                warn!("{} failed: {}", message_name, reason);
+            }
+            result
+        };
+
+        match request_id {
+            Some(request_id) => logging::with_request_id(&request_id, handle),
+            None => handle(),
+        }
+    }
+}
+
 // Start GetNoteBooks
 
 pub struct GetNoteBooksMessage {
@@ -36,6 +167,7 @@ impl Handler<GetNoteBooksMessage> for DbExecutor {
 
 pub struct CreateNotebookMessage {
     pub new_notebook: NewNotebook,
+    pub audit: AuditContext,
 }
 
 impl Message for CreateNotebookMessage {
@@ -48,7 +180,17 @@ impl Handler<CreateNotebookMessage> for DbExecutor {
     fn handle(&mut self, msg: CreateNotebookMessage, _: &mut Self::Context) -> Self::Result {
         let pool = &self.0;
         let connection = pool.get().unwrap();
-        repo::create_notebook(msg.new_notebook, &connection)
+        repo::transaction(&connection, || {
+            let notebook = repo::create_notebook(msg.new_notebook, &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "notebook",
+                &notebook.id,
+                AuditAction::Create,
+                &connection,
+            )?;
+            Ok(notebook)
+        })
     }
 }
 
@@ -59,6 +201,7 @@ impl Handler<CreateNotebookMessage> for DbExecutor {
 pub struct UpdateNotebookMessage {
     pub id: String,
     pub update: NotebookUpdate,
+    pub audit: AuditContext,
 }
 
 impl Message for UpdateNotebookMessage {
@@ -71,7 +214,17 @@ impl Handler<UpdateNotebookMessage> for DbExecutor {
     fn handle(&mut self, msg: UpdateNotebookMessage, _: &mut Self::Context) -> Self::Result {
         let pool = &self.0;
         let connection = pool.get().unwrap();
-        repo::update_notebook(&msg.id, msg.update, &connection)
+        repo::transaction(&connection, || {
+            let notebook = repo::update_notebook(&msg.id, msg.update, &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "notebook",
+                &msg.id,
+                AuditAction::Update,
+                &connection,
+            )?;
+            Ok(notebook)
+        })
     }
 }
 
@@ -103,6 +256,7 @@ impl Handler<GetNotesMessage> for DbExecutor {
 
 pub struct CreateNoteMessage {
     pub new_note: NewNote,
+    pub audit: AuditContext,
 }
 
 impl Message for CreateNoteMessage {
@@ -115,7 +269,17 @@ impl Handler<CreateNoteMessage> for DbExecutor {
     fn handle(&mut self, msg: CreateNoteMessage, _: &mut Self::Context) -> Self::Result {
         let pool = &self.0;
         let connection = pool.get().unwrap();
-        repo::create_note(msg.new_note, &connection)
+        repo::transaction(&connection, || {
+            let note = repo::create_note(msg.new_note, &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "note",
+                &note.id,
+                AuditAction::Create,
+                &connection,
+            )?;
+            Ok(note)
+        })
     }
 }
 
@@ -126,6 +290,7 @@ impl Handler<CreateNoteMessage> for DbExecutor {
 pub struct UpdateNoteMessage {
     pub id: String,
     pub update: NoteUpdate,
+    pub audit: AuditContext,
 }
 
 impl Message for UpdateNoteMessage {
@@ -138,7 +303,17 @@ impl Handler<UpdateNoteMessage> for DbExecutor {
     fn handle(&mut self, msg: UpdateNoteMessage, _: &mut Self::Context) -> Self::Result {
         let pool = &self.0;
         let connection = pool.get().unwrap();
-        repo::update_note(&msg.id, msg.update, &connection)
+        repo::transaction(&connection, || {
+            let note = repo::update_note(&msg.id, msg.update, &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "note",
+                &msg.id,
+                AuditAction::Update,
+                &connection,
+            )?;
+            Ok(note)
+        })
     }
 }
 
@@ -148,6 +323,7 @@ impl Handler<UpdateNoteMessage> for DbExecutor {
 
 pub struct CreateContentBlockMessage {
     pub new_content_block: NewContentBlock,
+    pub audit: AuditContext,
 }
 
 impl Message for CreateContentBlockMessage {
@@ -160,7 +336,17 @@ impl Handler<CreateContentBlockMessage> for DbExecutor {
     fn handle(&mut self, msg: CreateContentBlockMessage, _: &mut Self::Context) -> Self::Result {
         let pool = &self.0;
         let connection = pool.get().unwrap();
-        repo::create_content_block(msg.new_content_block, &connection)
+        repo::transaction(&connection, || {
+            let content_block = repo::create_content_block(msg.new_content_block, &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "content_block",
+                &content_block.id,
+                AuditAction::Create,
+                &connection,
+            )?;
+            Ok(content_block)
+        })
     }
 }
 
@@ -171,6 +357,7 @@ impl Handler<CreateContentBlockMessage> for DbExecutor {
 pub struct UpdateContentBlockMessage {
     pub id: String,
     pub update: ContentBlockUpdate,
+    pub audit: AuditContext,
 }
 
 impl Message for UpdateContentBlockMessage {
@@ -183,7 +370,17 @@ impl Handler<UpdateContentBlockMessage> for DbExecutor {
     fn handle(&mut self, msg: UpdateContentBlockMessage, _: &mut Self::Context) -> Self::Result {
         let pool = &self.0;
         let connection = pool.get().unwrap();
-        repo::update_content_block(&msg.id, msg.update, &connection)
+        repo::transaction(&connection, || {
+            let content_block = repo::update_content_block(&msg.id, msg.update, &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "content_block",
+                &msg.id,
+                AuditAction::Update,
+                &connection,
+            )?;
+            Ok(content_block)
+        })
     }
 }
 
@@ -211,10 +408,33 @@ impl Handler<GetContentBlocksMessage> for DbExecutor {
 
 // End GetContentBlocks
 
+// Start GetContentBlock
+
+pub struct GetContentBlockMessage {
+    pub id: String,
+}
+
+impl Message for GetContentBlockMessage {
+    type Result = Result<Option<ContentBlock>, String>;
+}
+
+impl Handler<GetContentBlockMessage> for DbExecutor {
+    type Result = Result<Option<ContentBlock>, String>;
+
+    fn handle(&mut self, msg: GetContentBlockMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::content_block(&msg.id, &connection)
+    }
+}
+
+// End GetContentBlock
+
 // Start DeleteNotebook
 
 pub struct DeleteNotebookMessage {
     pub id: String,
+    pub audit: AuditContext,
 }
 
 impl Message for DeleteNotebookMessage {
@@ -227,7 +447,16 @@ impl Handler<DeleteNotebookMessage> for DbExecutor {
     fn handle(&mut self, msg: DeleteNotebookMessage, _: &mut Self::Context) -> Self::Result {
         let pool = &self.0;
         let connection = pool.get().unwrap();
-        repo::delete_notebook(msg.id, &connection)
+        repo::transaction(&connection, || {
+            repo::delete_notebook(msg.id.clone(), &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "notebook",
+                &msg.id,
+                AuditAction::Delete,
+                &connection,
+            )
+        })
     }
 }
 
@@ -237,6 +466,7 @@ impl Handler<DeleteNotebookMessage> for DbExecutor {
 
 pub struct DeleteNoteMessage {
     pub id: String,
+    pub audit: AuditContext,
 }
 
 impl Message for DeleteNoteMessage {
@@ -249,7 +479,16 @@ impl Handler<DeleteNoteMessage> for DbExecutor {
     fn handle(&mut self, msg: DeleteNoteMessage, _: &mut Self::Context) -> Self::Result {
         let pool = &self.0;
         let connection = pool.get().unwrap();
-        repo::delete_note(msg.id, &connection)
+        repo::transaction(&connection, || {
+            repo::delete_note(msg.id.clone(), &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "note",
+                &msg.id,
+                AuditAction::Delete,
+                &connection,
+            )
+        })
     }
 }
 
@@ -259,6 +498,7 @@ impl Handler<DeleteNoteMessage> for DbExecutor {
 
 pub struct DeleteContentBlockMessage {
     pub id: String,
+    pub audit: AuditContext,
 }
 
 impl Message for DeleteContentBlockMessage {
@@ -271,7 +511,16 @@ impl Handler<DeleteContentBlockMessage> for DbExecutor {
     fn handle(&mut self, msg: DeleteContentBlockMessage, _: &mut Self::Context) -> Self::Result {
         let pool = &self.0;
         let connection = pool.get().unwrap();
-        repo::delete_contentblock(msg.id, &connection)
+        repo::transaction(&connection, || {
+            repo::delete_contentblock(msg.id.clone(), &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "content_block",
+                &msg.id,
+                AuditAction::Delete,
+                &connection,
+            )
+        })
     }
 }
 
@@ -298,3 +547,556 @@ impl Handler<GetDeletionsMessage> for DbExecutor {
 }
 
 // End GetDeletions
+
+// Start Backup
+
+pub struct BackupMessage {
+    pub database_path: PathBuf,
+    pub directory: PathBuf,
+    pub keep: usize,
+}
+
+impl Message for BackupMessage {
+    type Result = Result<PathBuf, String>;
+}
+
+impl Handler<BackupMessage> for DbExecutor {
+    type Result = Result<PathBuf, String>;
+
+    fn handle(&mut self, msg: BackupMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::backup(&msg.database_path, &msg.directory, msg.keep, &connection)
+    }
+}
+
+// End Backup
+
+// Start PruneDeletions
+
+pub struct PruneDeletionsMessage {
+    pub older_than: DateTime<Utc>,
+}
+
+impl Message for PruneDeletionsMessage {
+    type Result = Result<usize, String>;
+}
+
+impl Handler<PruneDeletionsMessage> for DbExecutor {
+    type Result = Result<usize, String>;
+
+    fn handle(&mut self, msg: PruneDeletionsMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::prune_deletions(msg.older_than, &connection)
+    }
+}
+
+// End PruneDeletions
+
+// Start GetApiTokens
+
+pub struct GetApiTokensMessage;
+
+impl Message for GetApiTokensMessage {
+    type Result = Result<Vec<ApiToken>, String>;
+}
+
+impl Handler<GetApiTokensMessage> for DbExecutor {
+    type Result = Result<Vec<ApiToken>, String>;
+
+    fn handle(&mut self, _msg: GetApiTokensMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::api_tokens(&connection)
+    }
+}
+
+// End GetApiTokens
+
+// Start CreateApiToken
+
+pub struct CreateApiTokenMessage {
+    pub new_api_token: NewApiToken,
+    pub token_hash: String,
+    pub audit: AuditContext,
+}
+
+impl Message for CreateApiTokenMessage {
+    type Result = Result<ApiToken, String>;
+}
+
+impl Handler<CreateApiTokenMessage> for DbExecutor {
+    type Result = Result<ApiToken, String>;
+
+    fn handle(&mut self, msg: CreateApiTokenMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::transaction(&connection, || {
+            let api_token = repo::create_api_token(msg.new_api_token, msg.token_hash, &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "api_token",
+                &api_token.id,
+                AuditAction::Create,
+                &connection,
+            )?;
+            Ok(api_token)
+        })
+    }
+}
+
+// End CreateApiToken
+
+// Start DeleteApiToken
+
+pub struct DeleteApiTokenMessage {
+    pub id: String,
+    pub audit: AuditContext,
+}
+
+impl Message for DeleteApiTokenMessage {
+    type Result = Result<(), String>;
+}
+
+impl Handler<DeleteApiTokenMessage> for DbExecutor {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: DeleteApiTokenMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::transaction(&connection, || {
+            repo::delete_api_token(&msg.id, &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "api_token",
+                &msg.id,
+                AuditAction::Delete,
+                &connection,
+            )
+        })
+    }
+}
+
+// End DeleteApiToken
+
+// Start AuthenticateApiToken
+
+pub struct AuthenticateApiTokenMessage {
+    pub token_hash: String,
+}
+
+impl Message for AuthenticateApiTokenMessage {
+    type Result = Result<Option<ApiToken>, String>;
+}
+
+impl Handler<AuthenticateApiTokenMessage> for DbExecutor {
+    type Result = Result<Option<ApiToken>, String>;
+
+    fn handle(&mut self, msg: AuthenticateApiTokenMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::authenticate_api_token(&msg.token_hash, &connection)
+    }
+}
+
+// End AuthenticateApiToken
+
+// Start CreateSession
+
+pub struct CreateSessionMessage {
+    pub session_hash: String,
+    pub session: Session,
+    pub audit: AuditContext,
+}
+
+impl Message for CreateSessionMessage {
+    type Result = Result<(), String>;
+}
+
+impl Handler<CreateSessionMessage> for DbExecutor {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: CreateSessionMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::transaction(&connection, || {
+            let username = msg.session.username.clone();
+            repo::create_session(msg.session_hash, msg.session, &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "session",
+                &username,
+                AuditAction::Create,
+                &connection,
+            )
+        })
+    }
+}
+
+// End CreateSession
+
+// Start GetSession
+
+pub struct GetSessionMessage {
+    pub session_hash: String,
+}
+
+impl Message for GetSessionMessage {
+    type Result = Result<Option<Session>, String>;
+}
+
+impl Handler<GetSessionMessage> for DbExecutor {
+    type Result = Result<Option<Session>, String>;
+
+    fn handle(&mut self, msg: GetSessionMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::session(&msg.session_hash, &connection)
+    }
+}
+
+// End GetSession
+
+// Start RotateSession
+
+pub struct RotateSessionMessage {
+    pub old_session_hash: String,
+    pub new_session_hash: String,
+    pub session: Session,
+    pub old_session_expires_at: DateTime<Utc>,
+}
+
+impl Message for RotateSessionMessage {
+    type Result = Result<(), String>;
+}
+
+impl Handler<RotateSessionMessage> for DbExecutor {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: RotateSessionMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::rotate_session(
+            &msg.old_session_hash,
+            msg.new_session_hash,
+            msg.session,
+            msg.old_session_expires_at,
+            &connection,
+        )
+    }
+}
+
+// End RotateSession
+
+// Start DeleteSession
+
+pub struct DeleteSessionMessage {
+    pub session_hash: String,
+    pub audit: AuditContext,
+}
+
+impl Message for DeleteSessionMessage {
+    type Result = Result<(), String>;
+}
+
+impl Handler<DeleteSessionMessage> for DbExecutor {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: DeleteSessionMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::transaction(&connection, || {
+            let session = repo::session(&msg.session_hash, &connection)?;
+            repo::delete_session(&msg.session_hash, &connection)?;
+
+            let session = match session {
+                Some(session) => session,
+                None => return Ok(()),
+            };
+
+            // Logging out doesn't require authentication, so the actor is only known from the session.
+            let audit = AuditContext {
+                actor: format!("user:{}", session.username),
+                ..msg.audit
+            };
+            repo::record_audit(
+                &audit,
+                "session",
+                &session.username,
+                AuditAction::Delete,
+                &connection,
+            )
+        })
+    }
+}
+
+// End DeleteSession
+
+// Start EnrollTwoFactor
+
+/* Replacing a confirmed second factor needs a current code or recovery code. Resolves to false
+if it's missing or wrong. */
+pub struct EnrollTwoFactorMessage {
+    pub username: String,
+    pub secret: String,
+    pub recovery_code_hashes: Vec<String>,
+    pub current_code: Option<String>,
+    pub audit: AuditContext,
+}
+
+impl Message for EnrollTwoFactorMessage {
+    type Result = Result<bool, String>;
+}
+
+impl Handler<EnrollTwoFactorMessage> for DbExecutor {
+    type Result = Result<bool, String>;
+
+    fn handle(&mut self, msg: EnrollTwoFactorMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::transaction(&connection, || {
+            if !verify_current_factor(&msg.username, msg.current_code.as_deref(), &connection)? {
+                return Ok(false);
+            }
+            repo::enroll_two_factor(
+                &msg.username,
+                msg.secret,
+                msg.recovery_code_hashes,
+                &connection,
+            )?;
+            repo::record_audit(
+                &msg.audit,
+                "two_factor",
+                &msg.username,
+                AuditAction::Create,
+                &connection,
+            )?;
+            Ok(true)
+        })
+    }
+}
+
+/* True if the user has no confirmed second factor, or the code is valid for it. A valid code is
+used up like at login. */
+fn verify_current_factor(
+    username: &str,
+    code: Option<&str>,
+    connection: &SqliteConnection,
+) -> Result<bool, String> {
+    let recovery_code_hash = code.map(two_factor::recovery_code_hash);
+    repo::verify_second_factor(username, code, recovery_code_hash.as_deref(), connection)
+        .map(|verified| verified.unwrap_or(true))
+}
+
+// End EnrollTwoFactor
+
+// Start ConfirmTwoFactor
+
+pub struct ConfirmTwoFactorMessage {
+    pub username: String,
+    pub code: String,
+    pub audit: AuditContext,
+}
+
+impl Message for ConfirmTwoFactorMessage {
+    type Result = Result<bool, String>;
+}
+
+impl Handler<ConfirmTwoFactorMessage> for DbExecutor {
+    type Result = Result<bool, String>;
+
+    fn handle(&mut self, msg: ConfirmTwoFactorMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::transaction(&connection, || {
+            let confirmed = repo::confirm_two_factor(&msg.username, &msg.code, &connection)?;
+            if confirmed {
+                repo::record_audit(
+                    &msg.audit,
+                    "two_factor",
+                    &msg.username,
+                    AuditAction::Update,
+                    &connection,
+                )?;
+            }
+            Ok(confirmed)
+        })
+    }
+}
+
+// End ConfirmTwoFactor
+
+// Start DisableTwoFactor
+
+/* Needs a current code or recovery code like EnrollTwoFactorMessage. */
+pub struct DisableTwoFactorMessage {
+    pub username: String,
+    pub current_code: Option<String>,
+    pub audit: AuditContext,
+}
+
+impl Message for DisableTwoFactorMessage {
+    type Result = Result<bool, String>;
+}
+
+impl Handler<DisableTwoFactorMessage> for DbExecutor {
+    type Result = Result<bool, String>;
+
+    fn handle(&mut self, msg: DisableTwoFactorMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::transaction(&connection, || {
+            if !verify_current_factor(&msg.username, msg.current_code.as_deref(), &connection)? {
+                return Ok(false);
+            }
+            repo::disable_two_factor(&msg.username, &connection)?;
+            repo::record_audit(
+                &msg.audit,
+                "two_factor",
+                &msg.username,
+                AuditAction::Delete,
+                &connection,
+            )?;
+            Ok(true)
+        })
+    }
+}
+
+// End DisableTwoFactor
+
+// Start VerifySecondFactor
+
+pub struct VerifySecondFactorMessage {
+    pub username: String,
+    pub code: Option<String>,
+    pub recovery_code_hash: Option<String>,
+}
+
+impl Message for VerifySecondFactorMessage {
+    type Result = Result<Option<bool>, String>;
+}
+
+impl Handler<VerifySecondFactorMessage> for DbExecutor {
+    type Result = Result<Option<bool>, String>;
+
+    fn handle(&mut self, msg: VerifySecondFactorMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::verify_second_factor(
+            &msg.username,
+            msg.code.as_deref(),
+            msg.recovery_code_hash.as_deref(),
+            &connection,
+        )
+    }
+}
+
+// End VerifySecondFactor
+
+// Start GetAuditLog
+
+pub struct GetAuditLogMessage {
+    pub filter: AuditFilter,
+}
+
+impl Message for GetAuditLogMessage {
+    type Result = Result<AuditPage, String>;
+}
+
+impl Handler<GetAuditLogMessage> for DbExecutor {
+    type Result = Result<AuditPage, String>;
+
+    fn handle(&mut self, msg: GetAuditLogMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::audit_log(msg.filter, &connection)
+    }
+}
+
+// End GetAuditLog
+
+// Start PruneAuditLog
+
+pub struct PruneAuditLogMessage {
+    pub older_than: DateTime<Utc>,
+}
+
+impl Message for PruneAuditLogMessage {
+    type Result = Result<usize, String>;
+}
+
+impl Handler<PruneAuditLogMessage> for DbExecutor {
+    type Result = Result<usize, String>;
+
+    fn handle(&mut self, msg: PruneAuditLogMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::prune_audit_log(msg.older_than, &connection)
+    }
+}
+
+// End PruneAuditLog
+
+// Start GetOpenChecklistItems
+
+pub struct GetOpenChecklistItemsMessage {
+    pub filter: ChecklistItemFilter,
+}
+
+impl Message for GetOpenChecklistItemsMessage {
+    type Result = Result<Vec<OpenChecklistItem>, String>;
+}
+
+impl Handler<GetOpenChecklistItemsMessage> for DbExecutor {
+    type Result = Result<Vec<OpenChecklistItem>, String>;
+
+    fn handle(&mut self, msg: GetOpenChecklistItemsMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::open_checklist_items(msg.filter, &connection)
+    }
+}
+
+// End GetOpenChecklistItems
+
+// Start GetResourceCounts
+
+pub struct GetResourceCountsMessage;
+
+impl Message for GetResourceCountsMessage {
+    type Result = Result<ResourceCounts, String>;
+}
+
+impl Handler<GetResourceCountsMessage> for DbExecutor {
+    type Result = Result<ResourceCounts, String>;
+
+    fn handle(&mut self, _msg: GetResourceCountsMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().unwrap();
+        repo::resource_counts(&connection)
+    }
+}
+
+// End GetResourceCounts
+
+// Start CheckReadiness
+
+/* Replies with the pending migrations, or an error if the database can't be queried. */
+pub struct CheckReadinessMessage;
+
+impl Message for CheckReadinessMessage {
+    type Result = Result<Vec<String>, String>;
+}
+
+impl Handler<CheckReadinessMessage> for DbExecutor {
+    type Result = Result<Vec<String>, String>;
+
+    fn handle(&mut self, _msg: CheckReadinessMessage, _: &mut Self::Context) -> Self::Result {
+        let pool = &self.0;
+        let connection = pool.get().map_err(|e| format!("{}", e))?;
+        repo::pending_migrations(&connection)
+    }
+}
+
+// End CheckReadiness
@@ -4,6 +4,7 @@ use diesel::SqliteConnection;
 use r2d2;
 
 pub type Pool = r2d2::Pool<ConnectionManager<SqliteConnection>>;
+pub type PooledConnection = r2d2::PooledConnection<ConnectionManager<SqliteConnection>>;
 
 #[derive(Debug)]
 struct ConnectionCustomizer;
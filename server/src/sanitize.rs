@@ -0,0 +1,751 @@
+/* Allow-list HTML sanitizer for text content blocks.
+
+Only the markup the Draft.js editor produces is kept: paragraphs, headings, quotes, code, lists
+with checkboxes, links and inline emphasis. Other tags are dropped but their text is kept,
+except for elements like <script> whose contents aren't text. Attributes other than link
+targets and checkbox state are dropped. The output is well-formed, so sanitizing it again
+doesn't change it. Rendered Markdown additionally keeps tables, images and rules. */
+
+const ALLOWED_ELEMENTS: &[&str] = &[
+    "a",
+    "blockquote",
+    "br",
+    "code",
+    "del",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "input",
+    "li",
+    "mark",
+    "ol",
+    "p",
+    "pre",
+    "strong",
+    "u",
+    "ul",
+];
+
+const MARKDOWN_ELEMENTS: &[&str] = &[
+    "hr", "img", "kbd", "s", "sub", "sup", "table", "tbody", "td", "th", "thead", "tr",
+];
+
+const VOID_ELEMENTS: &[&str] = &["br", "hr", "img", "input"];
+
+/* Elements whose contents are dropped together with the element. */
+const DROPPED_ELEMENTS: &[&str] = &[
+    "iframe", "noembed", "noframes", "noscript", "object", "script", "style", "template",
+    "textarea", "title", "xmp",
+];
+
+/* Deeper elements are dropped, which keeps closing tags cheap to match. */
+const MAX_OPEN_ELEMENTS: usize = 256;
+
+const ALLOWED_URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Policy {
+    Text,
+    Markdown,
+}
+
+struct Tag {
+    name: String,
+    attributes: Vec<(String, String)>,
+    closing: bool,
+}
+
+pub fn sanitize_html(html: &str) -> String {
+    sanitize(html, Policy::Text)
+}
+
+pub fn sanitize_markdown_html(html: &str) -> String {
+    sanitize(html, Policy::Markdown)
+}
+
+fn sanitize(html: &str, policy: Policy) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut open: Vec<&'static str> = vec![];
+    let mut rest = html;
+
+    while let Some(position) = rest.find(['<', '>', '&', '\0']) {
+        output.push_str(&rest[..position]);
+        rest = &rest[position..];
+
+        match rest.as_bytes()[0] {
+            b'<' => rest = markup(rest, policy, &mut output, &mut open),
+            b'>' => {
+                output.push_str("&gt;");
+                rest = &rest[1..];
+            }
+            b'&' => {
+                let length = character_reference_length(rest);
+                if length > 0 {
+                    output.push_str(&rest[..length]);
+                    rest = &rest[length..];
+                } else {
+                    output.push_str("&amp;");
+                    rest = &rest[1..];
+                }
+            }
+            _ => rest = &rest[1..],
+        }
+    }
+    output.push_str(rest);
+
+    while let Some(name) = open.pop() {
+        push_end_tag(&mut output, name);
+    }
+
+    output
+}
+
+/* Handles the markup at the start of `input`, which starts with '<', and returns what's left. */
+fn markup<'a>(
+    input: &'a str,
+    policy: Policy,
+    output: &mut String,
+    open: &mut Vec<&'static str>,
+) -> &'a str {
+    if let Some(comment) = input.strip_prefix("<!--") {
+        return match comment.find("-->") {
+            Some(end) => &comment[end + 3..],
+            None => "",
+        };
+    }
+    if input.starts_with("<!") || input.starts_with("<?") {
+        return skip_past(input, '>');
+    }
+
+    let (tag, rest) = match parse_tag(input) {
+        Some(parsed) => parsed,
+        None => {
+            // Not a tag, browsers show it as text.
+            output.push_str("&lt;");
+            return &input[1..];
+        }
+    };
+
+    if DROPPED_ELEMENTS.contains(&tag.name.as_str()) {
+        return if tag.closing {
+            rest
+        } else {
+            skip_element(rest, &tag.name)
+        };
+    }
+
+    let markdown_elements = match policy {
+        Policy::Markdown => MARKDOWN_ELEMENTS,
+        Policy::Text => &[],
+    };
+    let name = match ALLOWED_ELEMENTS
+        .iter()
+        .chain(markdown_elements)
+        .find(|name| **name == tag.name)
+    {
+        Some(name) => *name,
+        None => return rest,
+    };
+
+    if tag.closing {
+        if let Some(index) = open.iter().rposition(|open_name| *open_name == name) {
+            for open_name in open.drain(index..).rev() {
+                push_end_tag(output, open_name);
+            }
+        }
+    } else if VOID_ELEMENTS.contains(&name) {
+        push_start_tag(output, name, &tag.attributes);
+    } else if open.len() < MAX_OPEN_ELEMENTS && push_start_tag(output, name, &tag.attributes) {
+        open.push(name);
+    }
+
+    rest
+}
+
+fn parse_tag(input: &str) -> Option<(Tag, &str)> {
+    let closing = input[1..].starts_with('/');
+    let start = if closing { 2 } else { 1 };
+    if !input[start..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let mut rest = &input[start..];
+    let name_length = rest
+        .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+        .unwrap_or(rest.len());
+    let name = rest[..name_length].to_ascii_lowercase();
+    rest = &rest[name_length..];
+
+    let mut attributes = vec![];
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+        if rest.starts_with('>') {
+            rest = &rest[1..];
+            break;
+        }
+
+        // The first character can be '=', it's part of the name.
+        let name_length = rest
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| c.is_ascii_whitespace() || *c == '/' || *c == '>' || *c == '=')
+            .map_or(rest.len(), |(length, _)| length);
+        let attribute_name = rest[..name_length].to_ascii_lowercase();
+        rest = rest[name_length..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+
+        let mut value = String::new();
+        if rest.starts_with('=') {
+            rest = rest[1..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+            let raw_value = if rest.starts_with('"') || rest.starts_with('\'') {
+                let quote = rest.as_bytes()[0] as char;
+                let end = rest[1..].find(quote).map_or(rest.len(), |end| end + 1);
+                let raw_value = &rest[1..end];
+                rest = &rest[(end + 1).min(rest.len())..];
+                raw_value
+            } else {
+                let end = rest
+                    .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                    .unwrap_or(rest.len());
+                let raw_value = &rest[..end];
+                rest = &rest[end..];
+                raw_value
+            };
+            value = decode_character_references(raw_value);
+        }
+
+        attributes.push((attribute_name, value));
+    }
+
+    Some((
+        Tag {
+            name,
+            attributes,
+            closing,
+        },
+        rest,
+    ))
+}
+
+/* Writes the start tag with the attributes that are allowed on it, or nothing if the element
+isn't allowed with these attributes. */
+fn push_start_tag(output: &mut String, name: &str, attributes: &[(String, String)]) -> bool {
+    let attribute = |wanted: &str| {
+        attributes
+            .iter()
+            .find(|(name, _)| name == wanted)
+            .map(|(_, value)| value.as_str())
+    };
+
+    match name {
+        "a" => {
+            output.push_str("<a");
+            if let Some(href) = attribute("href").filter(|href| is_allowed_url(href)) {
+                push_attribute(output, "href", href.trim());
+            }
+            if let Some(title) = attribute("title") {
+                push_attribute(output, "title", title);
+            }
+            output.push('>');
+        }
+        "img" => {
+            let src = match attribute("src").filter(|src| is_allowed_image_url(src)) {
+                Some(src) => src,
+                None => return false,
+            };
+
+            output.push_str("<img");
+            push_attribute(output, "src", src.trim());
+            push_attribute(output, "alt", attribute("alt").unwrap_or(""));
+            if let Some(title) = attribute("title") {
+                push_attribute(output, "title", title);
+            }
+            output.push_str("/>");
+        }
+        "input" => {
+            let is_checkbox = attribute("type")
+                .is_some_and(|type_| type_.trim().eq_ignore_ascii_case("checkbox"));
+            if !is_checkbox {
+                return false;
+            }
+
+            output.push_str("<input type=\"checkbox\"");
+            if attribute("checked").is_some() {
+                output.push_str(" checked=\"\"");
+            }
+            if attribute("disabled").is_some() {
+                output.push_str(" disabled=\"\"");
+            }
+            output.push_str("/>");
+        }
+        "code" => {
+            output.push_str("<code");
+            let is_language_class = |class: &&str| {
+                class.strip_prefix("language-").is_some_and(|language| {
+                    language
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+#._-".contains(c))
+                })
+            };
+            if let Some(class) = attribute("class").filter(is_language_class) {
+                push_attribute(output, "class", class);
+            }
+            output.push('>');
+        }
+        "ol" => {
+            output.push_str("<ol");
+            if let Some(start) = attribute("start")
+                .filter(|start| !start.is_empty() && start.chars().all(|c| c.is_ascii_digit()))
+            {
+                push_attribute(output, "start", start);
+            }
+            output.push('>');
+        }
+        "td" | "th" => {
+            output.push('<');
+            output.push_str(name);
+            if let Some(align) =
+                attribute("align").filter(|align| ["left", "center", "right"].contains(align))
+            {
+                push_attribute(output, "align", align);
+            }
+            output.push('>');
+        }
+        "br" | "hr" => {
+            output.push('<');
+            output.push_str(name);
+            output.push_str("/>");
+        }
+        _ => {
+            output.push('<');
+            output.push_str(name);
+            output.push('>');
+        }
+    }
+
+    true
+}
+
+fn push_end_tag(output: &mut String, name: &str) {
+    output.push_str("</");
+    output.push_str(name);
+    output.push('>');
+}
+
+fn push_attribute(output: &mut String, name: &str, value: &str) {
+    output.push(' ');
+    output.push_str(name);
+    output.push_str("=\"");
+    push_escaped(output, value);
+    output.push('"');
+}
+
+fn push_escaped(output: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '"' => output.push_str("&quot;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '\0' => (),
+            _ => output.push(c),
+        }
+    }
+}
+
+/* Relative URLs and URLs with an allowed scheme. */
+fn is_allowed_url(url: &str) -> bool {
+    url_scheme(url).is_none_or(|scheme| ALLOWED_URL_SCHEMES.contains(&scheme.as_str()))
+}
+
+fn is_allowed_image_url(url: &str) -> bool {
+    url_scheme(url).is_none_or(|scheme| scheme == "http" || scheme == "https")
+}
+
+/* Browsers ignore tabs and newlines anywhere in a URL and control characters around it, so
+"java\tscript:" has the scheme "javascript". */
+fn url_scheme(url: &str) -> Option<String> {
+    let url: String = url
+        .chars()
+        .filter(|c| *c != '\t' && *c != '\n' && *c != '\r')
+        .collect();
+    let url = url.trim_matches(|c: char| c <= ' ');
+
+    match url.find([':', '/', '?', '#']) {
+        Some(position) if url[position..].starts_with(':') => {
+            Some(url[..position].to_ascii_lowercase())
+        }
+        _ => None,
+    }
+}
+
+fn skip_past(input: &str, c: char) -> &str {
+    match input.find(c) {
+        Some(position) => &input[position + 1..],
+        None => "",
+    }
+}
+
+/* Skips everything up to and including the end tag of the element, or the rest of the
+document if it isn't closed. */
+fn skip_element<'a>(input: &'a str, name: &str) -> &'a str {
+    let mut rest = input;
+
+    while let Some(position) = rest.find("</") {
+        rest = &rest[position + 2..];
+        let is_end_tag = rest.len() >= name.len()
+            && rest.is_char_boundary(name.len())
+            && rest[..name.len()].eq_ignore_ascii_case(name)
+            && rest[name.len()..]
+                .chars()
+                .next()
+                .is_none_or(|c| c.is_ascii_whitespace() || c == '/' || c == '>');
+        if is_end_tag {
+            return skip_past(rest, '>');
+        }
+    }
+
+    ""
+}
+
+/* Length of the character reference at the start of `input`, or 0 if there is none. */
+fn character_reference_length(input: &str) -> usize {
+    let bytes = input.as_bytes();
+    let mut length = 1;
+
+    let is_reference_char: fn(&u8) -> bool = match (bytes.get(1), bytes.get(2)) {
+        (Some(b'#'), Some(b'x')) | (Some(b'#'), Some(b'X')) => {
+            length = 3;
+            u8::is_ascii_hexdigit
+        }
+        (Some(b'#'), _) => {
+            length = 2;
+            u8::is_ascii_digit
+        }
+        (Some(c), _) if c.is_ascii_alphabetic() => u8::is_ascii_alphanumeric,
+        _ => return 0,
+    };
+
+    let start = length;
+    while bytes.get(length).is_some_and(is_reference_char) {
+        length += 1;
+    }
+
+    if length > start && bytes.get(length) == Some(&b';') {
+        length + 1
+    } else {
+        0
+    }
+}
+
+/* Decodes numeric and the most common named references, other references are kept as text.
+That's safe because the value is escaped again on output: whatever isn't decoded here ends up
+as literal text in the browser too. */
+fn decode_character_references(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(position) = rest.find('&') {
+        decoded.push_str(&rest[..position]);
+        rest = &rest[position..];
+
+        match decode_character_reference(rest) {
+            Some((c, length)) => {
+                decoded.push(c);
+                rest = &rest[length..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+
+    decoded
+}
+
+fn decode_character_reference(input: &str) -> Option<(char, usize)> {
+    if let Some(number) = input.strip_prefix("&#") {
+        // Browsers accept numeric references without the semicolon.
+        let (radix, start) = if number.starts_with(['x', 'X']) {
+            (16, 3)
+        } else {
+            (10, 2)
+        };
+        let digits = input[start..]
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or_else(|| input.len() - start);
+        if digits == 0 {
+            return None;
+        }
+
+        let code = u32::from_str_radix(&input[start..start + digits], radix).unwrap_or(0xFFFD);
+        let c = std::char::from_u32(code)
+            .filter(|c| *c != '\0')
+            .unwrap_or('\u{FFFD}');
+        let length = start + digits;
+        let length = if input[length..].starts_with(';') {
+            length + 1
+        } else {
+            length
+        };
+
+        return Some((c, length));
+    }
+
+    let named = [
+        ("&amp;", '&'),
+        ("&lt;", '<'),
+        ("&gt;", '>'),
+        ("&quot;", '"'),
+        ("&apos;", '\''),
+        ("&nbsp;", '\u{A0}'),
+        ("&colon;", ':'),
+        ("&Tab;", '\t'),
+        ("&NewLine;", '\n'),
+    ];
+    named
+        .iter()
+        .find(|(reference, _)| input.starts_with(reference))
+        .map(|(reference, c)| (*c, reference.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Output of the web app's editor, which must be stored unchanged. */
+    const EDITOR_HTML: &[&str] = &[
+        "<p>Hello <strong>world</strong>, <em>this</em> is <u>a</u> <code>test</code>.</p>",
+        "<h1>Title</h1><h2>Sub</h2><h3>3</h3><h4>4</h4><h5>5</h5><h6>6</h6>",
+        "<blockquote>Quote</blockquote><pre>fn main() {}\nlet x = 1;</pre>",
+        "<ul>\n  <li>one</li>\n  <li>two</li>\n</ul>\n<ol>\n  <li>first</li>\n</ol>",
+        "<ul><li><input type=\"checkbox\" checked=\"\"/>done</li>\
+         <li><input type=\"checkbox\"/>todo</li></ul>",
+        "<p><a href=\"https://example.com/a?b=1&amp;c=2#d\">link</a> and \
+         <a href=\"mailto:me@example.com\">mail</a> and <a href=\"/notes/1\">note</a></p>",
+        "<p><del>gone</del> <mark>marked</mark><br/>&amp; &lt;tag&gt; &quot;q&quot; &#39; \
+         &nbsp;&#x2603; caf\u{e9}</p>",
+        "<p></p>",
+        "",
+    ];
+
+    #[test]
+    fn keeps_editor_html_unchanged() {
+        for html in EDITOR_HTML {
+            assert_eq!(sanitize_html(html), *html);
+            assert_eq!(sanitize_markdown_html(html), *html);
+        }
+    }
+
+    #[test]
+    fn removes_scripts_and_event_handlers() {
+        let cases = [
+            ("<script>alert(1)</script>text", "text"),
+            ("<SCRIPT SRC=x></SCRIPT>text", "text"),
+            ("<scr<script>ipt>alert(1)</script>", "ipt&gt;alert(1)"),
+            ("<script>a</script >b", "b"),
+            ("<script>never closed", ""),
+            ("<style>p{}</style><p>x</p>", "<p>x</p>"),
+            ("<!--><script>alert(1)</script>-->", ""),
+            ("<!-- <script>alert(1)</script> -->ok", "ok"),
+            ("<!--", ""),
+            ("<![CDATA[<script>]]>x", "]]&gt;x"),
+            ("<?php echo 1 ?>x", "x"),
+            ("<img src=x onerror=alert(1)>", ""),
+            ("<p onclick=\"alert(1)\" style=\"x\">a</p>", "<p>a</p>"),
+            (
+                "<a href=\"https://x.com\" onmouseover=\"alert(1)\">a</a>",
+                "<a href=\"https://x.com\">a</a>",
+            ),
+            ("<svg onload=alert(1)><circle/></svg>x", "x"),
+            ("<iframe src=\"https://x.com\"></iframe>x", "x"),
+            ("<textarea><p>a</p></textarea>x", "x"),
+            (
+                "<math><mtext><table><mglyph><style><img src=x onerror=alert(1)>",
+                "",
+            ),
+            ("<p>a<p>b", "<p>a<p>b</p></p>"),
+            ("</p>x</ul>", "x"),
+            ("a < b > c", "a &lt; b &gt; c"),
+            ("x\0y", "xy"),
+        ];
+        for (html, expected) in &cases {
+            assert_eq!(sanitize_html(html), *expected, "{}", html);
+        }
+    }
+
+    #[test]
+    fn removes_unsafe_urls() {
+        let cases = [
+            "javascript:alert(1)",
+            "JaVaScRiPt:alert(1)",
+            " javascript:alert(1)",
+            "\u{1}javascript:alert(1)",
+            "java\tscript:alert(1)",
+            "java\nscript:alert(1)",
+            "jav&#x09;ascript:alert(1)",
+            "jav&#9;ascript:alert(1)",
+            "&#0000106avascript:alert(1)",
+            "&#x6A;avascript:alert(1)",
+            "javascript&colon;alert(1)",
+            "vbscript:msgbox(1)",
+            "data:text/html,<script>alert(1)</script>",
+            "file:///etc/passwd",
+        ];
+        for url in &cases {
+            for quote in &["\"", "'"] {
+                let html = format!("<a href={0}{1}{0}>a</a>", quote, url);
+                assert_eq!(sanitize_html(&html), "<a>a</a>", "{}", html);
+            }
+            // Unquoted, whitespace ends the value.
+            let sanitized = sanitize_html(&format!("<a href={}>a</a>", url));
+            assert!(!sanitized.contains(':'), "{} became {}", url, sanitized);
+        }
+
+        assert_eq!(
+            sanitize_markdown_html("<img src=\"javascript:alert(1)\" alt=\"x\">"),
+            ""
+        );
+        assert_eq!(
+            sanitize_markdown_html("<img src=\"https://x.com/a.png\" alt='a\"b'>"),
+            "<img src=\"https://x.com/a.png\" alt=\"a&quot;b\"/>"
+        );
+        assert_eq!(sanitize_markdown_html("<img src=\"mailto:a@b.c\">"), "");
+    }
+
+    #[test]
+    fn escapes_attribute_values() {
+        let cases = [
+            (
+                "<a href=\"/x\" title='\"><script>alert(1)</script>'>a</a>",
+                "<a href=\"/x\" title=\"&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;\">a</a>",
+            ),
+            (
+                "<a href=/x title=a&quot;b>a</a>",
+                "<a href=\"/x\" title=\"a&quot;b\">a</a>",
+            ),
+            (
+                "<code class=\"language-rust\">x</code>",
+                "<code class=\"language-rust\">x</code>",
+            ),
+            ("<code class=\"x\\\" onclick\">x</code>", "<code>x</code>"),
+            ("<ol start=\"3\">", "<ol start=\"3\"></ol>"),
+            ("<ol start=\"3\\\"x\">", "<ol></ol>"),
+            ("<input type=\"text\" value=\"x\">", ""),
+            (
+                "<input type=checkbox checked onclick=x>",
+                "<input type=\"checkbox\" checked=\"\"/>",
+            ),
+        ];
+        for (html, expected) in &cases {
+            assert_eq!(sanitize_html(html), *expected, "{}", html);
+        }
+    }
+
+    #[test]
+    fn keeps_markdown_elements_only_for_markdown() {
+        let html = "<table><thead><tr><th align=\"center\">a</th></tr></thead>\
+                    <tbody><tr><td align=\"javascript\">b</td></tr></tbody></table><hr/>";
+        assert_eq!(
+            sanitize_markdown_html(html),
+            "<table><thead><tr><th align=\"center\">a</th></tr></thead>\
+             <tbody><tr><td>b</td></tr></tbody></table><hr/>"
+        );
+        assert_eq!(sanitize_html(html), "ab");
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let html = "<em>".repeat(MAX_OPEN_ELEMENTS + 10);
+        let sanitized = sanitize_html(&html);
+        assert_eq!(sanitized.matches("<em>").count(), MAX_OPEN_ELEMENTS);
+        assert_eq!(sanitized.matches("</em>").count(), MAX_OPEN_ELEMENTS);
+    }
+
+    #[test]
+    fn is_idempotent() {
+        let inputs = [
+            "<scr<script>ipt>alert(1)</script>",
+            "<!--><script>alert(1)</script>-->",
+            "<math><mtext><table><mglyph><style><img src=x onerror=alert(1)>",
+            "<a href=\"jav&#x09;ascript:alert(1)\" title=\"&amp;&quot;'\">a</a>",
+            "<p>a<ul><li>b</p>c</li>",
+            "a < b > c & d &amp; e &#0; &#x110000; &bogus;",
+            "<p title=a>&lt;script&gt;</p><br><hr><img src=/a.png>",
+            "<<p>>",
+            "<a href='x'>unclosed",
+            "<input type=checkbox disabled checked>",
+            "<pre><code class=language-c++>x</code></pre>",
+            "</a></p>",
+        ];
+        for input in inputs.iter().chain(EDITOR_HTML) {
+            for sanitize in &[sanitize_html, sanitize_markdown_html] {
+                let once = sanitize(input);
+                assert_eq!(sanitize(&once), once, "{}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn is_idempotent_for_random_markup() {
+        let fragments = [
+            "<",
+            ">",
+            "/",
+            "&",
+            "&amp;",
+            "&#",
+            "x",
+            ";",
+            "=",
+            "\"",
+            "'",
+            " ",
+            "\t",
+            "<!--",
+            "-->",
+            "<p>",
+            "</p>",
+            "<a href=",
+            "javascript:",
+            "<img src=",
+            "<script>",
+            "</script>",
+            "<input type=checkbox",
+            "<code class=",
+            "language-",
+            "<ol start=",
+            "<table>",
+            "<td",
+            "align=",
+            "<br/>",
+            "\0",
+        ];
+        // A fixed xorshift sequence, so failures can be reproduced.
+        let mut state: u32 = 2463534242;
+        let mut next = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as usize % bound
+        };
+        for _ in 0..5000 {
+            let length = 1 + next(30);
+            let input: String = (0..length)
+                .map(|_| fragments[next(fragments.len())])
+                .collect();
+            for sanitize in &[sanitize_html, sanitize_markdown_html] {
+                let once = sanitize(&input);
+                assert_eq!(sanitize(&once), once, "{:?}", input);
+            }
+        }
+    }
+}
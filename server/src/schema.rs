@@ -1,6 +1,30 @@
 // For Diesel 1.3 on Rust >= 1.29
 #![allow(proc_macro_derive_resolution_fallback)]
 
+table! {
+    api_tokens (id) {
+        id -> Text,
+        name -> Text,
+        token_hash -> Text,
+        scope -> Text,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> BigInt,
+        created_at -> Timestamp,
+        resource_type -> Text,
+        resource_id -> Text,
+        action -> Text,
+        actor -> Text,
+        client_ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+    }
+}
+
 table! {
     content_blocks (id) {
         id -> Text,
@@ -45,7 +69,48 @@ table! {
     }
 }
 
+table! {
+    recovery_codes (code_hash) {
+        code_hash -> Text,
+        username -> Text,
+        used_at -> Nullable<Timestamp>,
+        pending -> Bool,
+    }
+}
+
+table! {
+    sessions (id_hash) {
+        id_hash -> Text,
+        username -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        rotated_from -> Nullable<Text>,
+    }
+}
+
+table! {
+    two_factor (username) {
+        username -> Text,
+        secret -> Text,
+        confirmed -> Bool,
+        last_used_step -> Nullable<BigInt>,
+        created_at -> Timestamp,
+        pending_secret -> Nullable<Text>,
+    }
+}
+
 joinable!(content_blocks -> notes (note_id));
 joinable!(notes -> notebooks (notebook_id));
+joinable!(recovery_codes -> two_factor (username));
 
-allow_tables_to_appear_in_same_query!(content_blocks, deletions, notebooks, notes,);
+allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    audit_log,
+    content_blocks,
+    deletions,
+    notebooks,
+    notes,
+    recovery_codes,
+    sessions,
+    two_factor,
+);
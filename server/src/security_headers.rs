@@ -0,0 +1,60 @@
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::middleware::{Middleware, Response};
+use actix_web::{HttpRequest, HttpResponse, Result};
+
+use super::config::Config;
+
+/* Hardening headers added to every response, unless a handler already set them. Notes contain
+user HTML, so the Content-Security-Policy limits what a script that slips through can do. */
+#[derive(Clone)]
+pub struct SecurityHeaders {
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl SecurityHeaders {
+    pub fn from_config(config: &Config) -> Self {
+        let settings = &config.security_headers;
+
+        let mut headers = vec![(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        )];
+
+        let configured = vec![
+            (
+                header::CONTENT_SECURITY_POLICY,
+                &settings.content_security_policy,
+            ),
+            (header::X_FRAME_OPTIONS, &settings.frame_options),
+            (header::REFERRER_POLICY, &settings.referrer_policy),
+        ];
+        for (name, value) in configured {
+            if !value.is_empty() {
+                headers.push((name, HeaderValue::from_str(value).unwrap()));
+            }
+        }
+
+        // Browsers ignore HSTS over plain HTTP, behind a TLS proxy it's up to the proxy.
+        if config.tls.enabled && settings.hsts_max_age_seconds > 0 {
+            headers.push((
+                header::STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_str(&format!("max-age={}", settings.hsts_max_age_seconds))
+                    .unwrap(),
+            ));
+        }
+
+        SecurityHeaders { headers }
+    }
+}
+
+impl<S> Middleware<S> for SecurityHeaders {
+    fn response(&self, _req: &HttpRequest<S>, mut resp: HttpResponse) -> Result<Response> {
+        for (name, value) in &self.headers {
+            if !resp.headers().contains_key(name) {
+                resp.headers_mut().insert(name.clone(), value.clone());
+            }
+        }
+
+        Ok(Response::Done(resp))
+    }
+}
@@ -0,0 +1,202 @@
+use ::actix::actors::signal::{ProcessSignals, Signal, SignalType, Subscribe};
+use ::actix::fut;
+use ::actix::prelude::*;
+use actix_net::server::{PauseServer, Server, StopServer};
+use actix_web::middleware::{Finished, Middleware, Started};
+use actix_web::{HttpRequest, HttpResponse, Result};
+use futures::future;
+use log::{info, warn};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::actix_state::State;
+use super::repo_connection::{self, PooledConnection};
+use super::systemd;
+use super::tls::{CertificateWatcher, ReloadCertificates};
+use super::unix_socket::UnixSocket;
+
+const DRAIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/* Counts the requests being handled, across all workers. actix-web's own graceful shutdown
+only waits for connections that are still being accepted. */
+#[derive(Clone, Default)]
+pub struct InFlightRequests(Arc<AtomicUsize>);
+
+impl InFlightRequests {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+struct Counted;
+
+impl Middleware<State> for InFlightRequests {
+    fn start(&self, req: &HttpRequest<State>) -> Result<Started> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        req.extensions_mut().insert(Counted);
+
+        Ok(Started::Done)
+    }
+
+    /* Called once the response has been sent. */
+    fn finish(&self, req: &HttpRequest<State>, _resp: &HttpResponse) -> Finished {
+        if req.extensions_mut().remove::<Counted>().is_some() {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        Finished::Done
+    }
+}
+
+/* Replaces the signal handling of the HTTP servers, so the database is only closed once
+the servers have stopped:
+
+- SIGTERM stops accepting connections and waits for in-flight requests, up to
+  `listen.shutdown_timeout_seconds`.
+- SIGINT and SIGQUIT close connections right away. A second signal exits during a
+  graceful shutdown.
+- SIGHUP reloads the TLS certificates. */
+pub struct SignalHandler {
+    servers: Vec<Addr<Server>>,
+    unix_socket: Option<UnixSocket>,
+    in_flight: InFlightRequests,
+    shutdown_timeout: Duration,
+    pool: repo_connection::Pool,
+    certificates: Option<Addr<CertificateWatcher>>,
+    /* Held from shutdown until exit, so no database write is cut off. */
+    connection: Option<PooledConnection>,
+    draining: Option<SpawnHandle>,
+    stopping: bool,
+}
+
+impl SignalHandler {
+    pub fn new(
+        servers: Vec<Addr<Server>>,
+        unix_socket: Option<UnixSocket>,
+        in_flight: InFlightRequests,
+        shutdown_timeout: Duration,
+        pool: repo_connection::Pool,
+        certificates: Option<Addr<CertificateWatcher>>,
+    ) -> Self {
+        SignalHandler {
+            servers,
+            unix_socket,
+            in_flight,
+            shutdown_timeout,
+            pool,
+            certificates,
+            connection: None,
+            draining: None,
+            stopping: false,
+        }
+    }
+
+    fn drain(&mut self, ctx: &mut Context<Self>) {
+        if self.draining.is_some() || self.stopping {
+            return self.stop(ctx);
+        }
+
+        info!("Waiting for {} in-flight requests", self.in_flight.count());
+        systemd::notify("STOPPING=1");
+
+        for server in &self.servers {
+            server.do_send(PauseServer);
+        }
+        if let Some(ref unix_socket) = self.unix_socket {
+            unix_socket.close();
+        }
+
+        let deadline = Instant::now() + self.shutdown_timeout;
+        self.draining = Some(ctx.run_interval(DRAIN_INTERVAL, move |act, ctx| {
+            let in_flight = act.in_flight.count();
+
+            if in_flight == 0 {
+                act.stop(ctx);
+            } else if Instant::now() >= deadline {
+                warn!(
+                    "Shutdown timeout reached with {} in-flight requests",
+                    in_flight
+                );
+                act.stop(ctx);
+            }
+        }));
+    }
+
+    fn stop(&mut self, ctx: &mut Context<Self>) {
+        match self.draining.take() {
+            Some(draining) => {
+                ctx.cancel_future(draining);
+            }
+            None if !self.stopping => systemd::notify("STOPPING=1"),
+            None => {}
+        }
+
+        if self.stopping {
+            info!("Exiting without waiting for the servers to stop");
+            System::current().stop();
+            return;
+        }
+        self.stopping = true;
+
+        if let Some(ref unix_socket) = self.unix_socket {
+            unix_socket.close();
+            if let Err(e) = unix_socket.remove() {
+                warn!("Removing the socket file failed: {}", e);
+            }
+        }
+
+        let stopped = self
+            .servers
+            .iter()
+            .map(|server| server.send(StopServer { graceful: false }))
+            .collect::<Vec<_>>();
+
+        ctx.spawn(
+            future::join_all(stopped)
+                .into_actor(self)
+                .then(|_, act, _ctx| {
+                    // Database messages are handled one at a time with the only pooled
+                    // connection, so once it is available no write is in progress.
+                    match act.pool.get() {
+                        Ok(connection) => act.connection = Some(connection),
+                        Err(e) => warn!("Stopping while the database is busy: {}", e),
+                    }
+
+                    System::current().stop();
+                    fut::ok(())
+                }),
+        );
+    }
+}
+
+impl Actor for SignalHandler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let signals = System::current().registry().get::<ProcessSignals>();
+        signals.do_send(Subscribe(ctx.address().recipient()));
+    }
+}
+
+impl Handler<Signal> for SignalHandler {
+    type Result = ();
+
+    fn handle(&mut self, msg: Signal, ctx: &mut Self::Context) -> Self::Result {
+        match msg.0 {
+            SignalType::Term => {
+                info!("SIGTERM received, finishing in-flight requests");
+                self.drain(ctx);
+            }
+            SignalType::Int | SignalType::Quit => {
+                info!("SIGINT or SIGQUIT received, exiting");
+                self.stop(ctx);
+            }
+            SignalType::Hup => match self.certificates {
+                Some(ref certificates) => certificates.do_send(ReloadCertificates),
+                None => info!("SIGHUP received, nothing to reload"),
+            },
+            _ => (),
+        }
+    }
+}
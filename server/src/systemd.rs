@@ -0,0 +1,137 @@
+use ::actix::prelude::*;
+use futures::future::Future;
+use log::warn;
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::metrics::Metrics;
+use super::repo_actor::{CheckReadinessMessage, Db};
+use super::signals::InFlightRequests;
+
+const STATUS_INTERVAL: Duration = Duration::from_secs(30);
+
+/* Whether the server runs as a `Type=notify` systemd service. */
+pub fn enabled() -> bool {
+    env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/* Sends a state like `READY=1` to systemd, see sd_notify(3). Does nothing outside systemd. */
+pub fn notify(state: &str) {
+    if let Err(e) = send(state) {
+        warn!("Notifying systemd failed: {}", e);
+    }
+}
+
+fn send(state: &str) -> io::Result<()> {
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return Ok(()),
+    };
+
+    let socket = UnixDatagram::unbound()?;
+
+    if let Some(name) = path.strip_prefix('@') {
+        send_abstract(&socket, name, state)?;
+    } else {
+        socket.send_to(state.as_bytes(), &path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(socket: &UnixDatagram, name: &str, state: &str) -> io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let address = SocketAddr::from_abstract_name(name)?;
+    socket.send_to_addr(state.as_bytes(), &address)?;
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_socket: &UnixDatagram, name: &str, _state: &str) -> io::Result<()> {
+    Err(io::Error::other(format!("Unsupported socket @{}", name)))
+}
+
+/* The interval of `WatchdogSec=`, if the watchdog is enabled for this process. */
+fn watchdog_timeout() -> Option<Duration> {
+    let pid = env::var("WATCHDOG_PID").ok();
+    if pid.is_some() && pid != Some(process::id().to_string()) {
+        return None;
+    }
+
+    env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse().ok())
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros)
+}
+
+/* Reports the server's status to systemd. With `WatchdogSec=`, the watchdog is pinged at half
+the interval, but only as long as the database answers, so systemd restarts a server that hangs. */
+pub struct Watchdog {
+    db: Db,
+    metrics: Arc<Metrics>,
+    in_flight: InFlightRequests,
+    timeout: Option<Duration>,
+}
+
+impl Watchdog {
+    pub fn new(db: Db, metrics: Arc<Metrics>, in_flight: InFlightRequests) -> Self {
+        Watchdog {
+            db,
+            metrics,
+            in_flight,
+            timeout: watchdog_timeout(),
+        }
+    }
+
+    fn status(&self) -> String {
+        format!(
+            "STATUS={} requests handled, {} in flight, {} queued for the database",
+            self.metrics.requests_handled(),
+            self.in_flight.count(),
+            self.metrics.db_queue_depth()
+        )
+    }
+
+    fn check(&self, timeout: Duration) {
+        let status = self.status();
+
+        Arbiter::spawn(
+            self.db
+                .send_timeout(CheckReadinessMessage, timeout)
+                .then(move |res| {
+                    match res {
+                        Ok(Ok(_)) => notify(&format!("WATCHDOG=1\n{}", status)),
+                        Ok(Err(reason)) => warn!("Skipping watchdog ping: {}", reason),
+                        Err(e) => warn!("Skipping watchdog ping, database not responding: {}", e),
+                    }
+
+                    Ok(())
+                }),
+        );
+    }
+}
+
+impl Actor for Watchdog {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        match self.timeout {
+            Some(timeout) => {
+                let interval = timeout / 2;
+                ctx.run_interval(interval, move |act, _ctx| act.check(interval));
+            }
+            None => {
+                ctx.run_interval(STATUS_INTERVAL, |act, _ctx| notify(&act.status()));
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+/* Table blocks: their checks and conversion from and to CSV, in the dialect of RFC 4180 that
+spreadsheets read and write. */
+
+use super::data::{ColumnAlign, Table, TableCell, TableColumn};
+use serde_json::Number;
+
+const MAX_COLUMNS: usize = 100;
+const MAX_ROWS: usize = 10000;
+
+/* Spreadsheets run cells starting with these as formulas, so exported text cells that start
+with one get a leading `'`, which spreadsheets hide and imports remove again. */
+const FORMULA_PREFIXES: &[char] = &['=', '+', '-', '@', '\t', '\r'];
+
+pub fn validate(table: &Table) -> Result<(), String> {
+    let Table { columns, rows } = table;
+    if columns.is_empty() {
+        return Err("A table needs at least one column".to_string());
+    }
+    if columns.len() > MAX_COLUMNS {
+        return Err(format!("Tables can have at most {} columns", MAX_COLUMNS));
+    }
+    if rows.len() > MAX_ROWS {
+        return Err(format!("Tables can have at most {} rows", MAX_ROWS));
+    }
+    for (index, row) in rows.iter().enumerate() {
+        if row.len() != columns.len() {
+            return Err(format!(
+                "Row {} needs {} cells, one for each column, but has {}",
+                index + 1,
+                columns.len(),
+                row.len()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/* The first record is the header. Cells that are JSON numbers or booleans get those types,
+empty cells are empty and everything else is text. Numbers that JSON would write differently,
+such as `1.50` or very large ones, stay text so exports keep them as they were. Columns of numbers are right-aligned. The
+delimiter is a comma, semicolon or tab, whichever the header has most of. */
+pub fn from_csv(csv: &str) -> Result<Table, String> {
+    let csv = csv.trim_start_matches('\u{feff}');
+    let mut records = parse_records(csv, delimiter(csv))?.into_iter();
+    let header = records
+        .next()
+        .ok_or_else(|| "The CSV file is empty".to_string())?;
+
+    let mut rows: Vec<Vec<TableCell>> = records
+        .map(|record| record.into_iter().map(cell).collect())
+        .collect();
+    // Spreadsheets leave out empty cells at the end of records.
+    let width = rows.iter().map(Vec::len).fold(header.len(), usize::max);
+    for row in &mut rows {
+        row.resize(width, TableCell::Empty);
+    }
+
+    let mut columns: Vec<TableColumn> = header
+        .into_iter()
+        .map(|header| TableColumn {
+            header: unguard(header),
+            align: ColumnAlign::Left,
+        })
+        .collect();
+    columns.resize_with(width, || TableColumn {
+        header: String::new(),
+        align: ColumnAlign::Left,
+    });
+    for (index, column) in columns.iter_mut().enumerate() {
+        let cells = || rows.iter().map(|row| &row[index]);
+        let numeric = cells().any(|cell| matches!(cell, TableCell::Number(_)))
+            && cells().all(|cell| matches!(cell, TableCell::Number(_) | TableCell::Empty));
+        if numeric {
+            column.align = ColumnAlign::Right;
+        }
+    }
+
+    let table = Table { columns, rows };
+    validate(&table)?;
+    Ok(table)
+}
+
+/* CSV with a byte order mark, which Excel needs to read it as UTF-8, and CRLF line endings. */
+pub fn to_csv(table: &Table) -> String {
+    let mut csv = String::from("\u{feff}");
+    let headers: Vec<String> = table
+        .columns
+        .iter()
+        .map(|column| quote(&guard(&column.header)))
+        .collect();
+    csv.push_str(&headers.join(","));
+    csv.push_str("\r\n");
+
+    for row in &table.rows {
+        let cells: Vec<String> = row
+            .iter()
+            .map(|cell| match cell {
+                TableCell::Empty => String::new(),
+                TableCell::Boolean(value) => value.to_string(),
+                TableCell::Number(value) => value.to_string(),
+                TableCell::Text(text) => quote(&guard(text)),
+            })
+            .collect();
+        csv.push_str(&cells.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+fn delimiter(csv: &str) -> char {
+    let header = csv.lines().next().unwrap_or("");
+    let mut best = ',';
+    let mut best_count = header.matches(',').count();
+    for &candidate in &[';', '\t'] {
+        let count = header.matches(candidate).count();
+        if count > best_count {
+            best = candidate;
+            best_count = count;
+        }
+    }
+    best
+}
+
+fn parse_records(csv: &str, delimiter: char) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut quote_line = 1;
+
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            line += 1;
+        }
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                in_quotes = true;
+                quoted = true;
+                quote_line = line;
+            }
+            _ if c == delimiter => {
+                record.push(std::mem::take(&mut field));
+                quoted = false;
+            }
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' | '\r' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+                quoted = false;
+            }
+            // Like spreadsheets, text after the closing quote is kept.
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(format!("Unterminated quoted cell in line {}", quote_line));
+    }
+    if !field.is_empty() || !record.is_empty() || quoted {
+        record.push(field);
+        records.push(record);
+    }
+
+    records.retain(|record| record.len() > 1 || record.iter().any(|field| !field.is_empty()));
+    Ok(records)
+}
+
+fn cell(text: String) -> TableCell {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return TableCell::Empty;
+    }
+    if let Ok(number) = serde_json::from_str::<Number>(trimmed) {
+        if number.to_string() == trimmed {
+            return TableCell::Number(number);
+        }
+    }
+    if trimmed.eq_ignore_ascii_case("true") {
+        return TableCell::Boolean(true);
+    }
+    if trimmed.eq_ignore_ascii_case("false") {
+        return TableCell::Boolean(false);
+    }
+    TableCell::Text(unguard(text))
+}
+
+fn guard(text: &str) -> String {
+    if text.starts_with(FORMULA_PREFIXES) {
+        format!("'{}", text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn unguard(text: String) -> String {
+    match text.strip_prefix('\'') {
+        Some(rest) if rest.starts_with(FORMULA_PREFIXES) => rest.to_string(),
+        _ => text,
+    }
+}
+
+fn quote(text: &str) -> String {
+    let needs_quotes = text.contains([',', '"', '\r', '\n']) || text.trim() != text;
+    if needs_quotes {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_numbers_that_json_would_change_as_text() {
+        let cases = [
+            ("12", TableCell::Number(12.into())),
+            ("-3", TableCell::Number((-3).into())),
+            ("1.5", TableCell::Number(Number::from_f64(1.5).unwrap())),
+            ("1.50", TableCell::Text("1.50".to_string())),
+            ("1e3", TableCell::Text("1e3".to_string())),
+            ("-0", TableCell::Text("-0".to_string())),
+            (
+                "12345678901234567890123",
+                TableCell::Text("12345678901234567890123".to_string()),
+            ),
+        ];
+        for (text, expected) in &cases {
+            assert_eq!(cell(text.to_string()), *expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn round_trips_csv() {
+        let csv = "\u{feff}Item,Price,Note\r\n\"a, b\",1.50,'=1+1\r\nc,2,\r\n";
+        assert_eq!(to_csv(&from_csv(csv).unwrap()), csv);
+    }
+}
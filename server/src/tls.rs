@@ -0,0 +1,170 @@
+use ::actix::prelude::*;
+use actix_web::server::{openssl_acceptor_with_flags, ServerFlags};
+use log::{error, info};
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod, SslVerifyMode};
+use openssl::x509::X509Name;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use super::config::TlsConfig;
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/* The TLS acceptor used for new connections. It is replaced when the certificate, key or
+client CA files are reloaded, while established connections keep using the old one. */
+#[derive(Clone)]
+pub struct ReloadableAcceptor {
+    config: TlsConfig,
+    current: Arc<RwLock<SslAcceptor>>,
+}
+
+impl ReloadableAcceptor {
+    pub fn new(config: &TlsConfig) -> Result<Self, String> {
+        Ok(ReloadableAcceptor {
+            config: config.clone(),
+            current: Arc::new(RwLock::new(build_acceptor(config)?)),
+        })
+    }
+
+    pub fn current(&self) -> SslAcceptor {
+        self.current.read().unwrap().clone()
+    }
+
+    /* Keeps the current acceptor if the files can't be loaded, e.g. while they are being renewed. */
+    pub fn reload(&self) -> Result<(), String> {
+        let acceptor = build_acceptor(&self.config)?;
+        *self.current.write().unwrap() = acceptor;
+
+        Ok(())
+    }
+
+    fn files(&self) -> Vec<&PathBuf> {
+        self.config
+            .key
+            .iter()
+            .chain(self.config.cert.iter())
+            .chain(self.config.client_ca.iter())
+            .collect()
+    }
+}
+
+/* Supports HTTP/2 like `HttpServer::bind_ssl`. */
+fn build_acceptor(config: &TlsConfig) -> Result<SslAcceptor, String> {
+    openssl_acceptor_with_flags(
+        acceptor_builder(config)?,
+        ServerFlags::HTTP1 | ServerFlags::HTTP2,
+    )
+    .map_err(|e| format!("{}", e))
+}
+
+fn acceptor_builder(tls: &TlsConfig) -> Result<SslAcceptorBuilder, String> {
+    let key_file = tls.key.as_ref().ok_or("Missing TLS key file.")?;
+    let cert_file = tls.cert.as_ref().ok_or("Missing TLS certificate file.")?;
+
+    let mut builder =
+        SslAcceptor::mozilla_intermediate(SslMethod::tls()).map_err(|e| format!("{}", e))?;
+    builder
+        .set_private_key_file(key_file, SslFiletype::PEM)
+        .map_err(|e| format!("Invalid TLS key file {}: {}", key_file.display(), e))?;
+    builder.set_certificate_chain_file(cert_file).map_err(|e| {
+        format!(
+            "Invalid TLS certificate file {}: {}",
+            cert_file.display(),
+            e
+        )
+    })?;
+    builder
+        .check_private_key()
+        .map_err(|e| format!("TLS key doesn't match the certificate: {}", e))?;
+
+    // Client certificates are verified against the CA bundle. Without `require_client_cert`,
+    // clients may still connect without one and authenticate otherwise.
+    if let Some(ref client_ca) = tls.client_ca {
+        let invalid_ca = |e| format!("Invalid client CA file {}: {}", client_ca.display(), e);
+
+        builder.set_ca_file(client_ca).map_err(invalid_ca)?;
+        builder.set_client_ca_list(X509Name::load_client_ca_file(client_ca).map_err(invalid_ca)?);
+
+        let mut verify_mode = SslVerifyMode::PEER;
+        if tls.require_client_cert {
+            verify_mode |= SslVerifyMode::FAIL_IF_NO_PEER_CERT;
+        }
+        builder.set_verify(verify_mode);
+    }
+
+    Ok(builder)
+}
+
+/* Reloads the TLS files when they change, checked every minute, or on `ReloadCertificates`. */
+pub struct CertificateWatcher {
+    acceptor: ReloadableAcceptor,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl CertificateWatcher {
+    pub fn new(acceptor: ReloadableAcceptor) -> Self {
+        let modified = modified_times(&acceptor);
+        CertificateWatcher { acceptor, modified }
+    }
+
+    fn check(&mut self) {
+        let modified = modified_times(&self.acceptor);
+
+        // On failure the change is picked up again on the next check, in case the
+        // files were only partially renewed.
+        if modified != self.modified && self.reload() {
+            self.modified = modified;
+        }
+    }
+
+    fn reload(&self) -> bool {
+        match self.acceptor.reload() {
+            Ok(()) => {
+                info!("Reloaded TLS certificates");
+                true
+            }
+            Err(reason) => {
+                error!("Reloading TLS certificates failed: {}", reason);
+                false
+            }
+        }
+    }
+}
+
+fn modified_times(acceptor: &ReloadableAcceptor) -> Vec<Option<SystemTime>> {
+    acceptor
+        .files()
+        .iter()
+        .map(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+impl Actor for CertificateWatcher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(WATCH_INTERVAL, |act, _ctx| act.check());
+    }
+}
+
+pub struct ReloadCertificates;
+
+impl Message for ReloadCertificates {
+    type Result = ();
+}
+
+impl Handler<ReloadCertificates> for CertificateWatcher {
+    type Result = ();
+
+    fn handle(&mut self, _msg: ReloadCertificates, _: &mut Self::Context) -> Self::Result {
+        if self.reload() {
+            self.modified = modified_times(&self.acceptor);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use super::auth;
+use super::config::Config;
+use super::data::{AuditAction, AuditContext, NewApiToken, Scope};
+use super::repo;
+use super::repo_connection;
+use diesel::SqliteConnection;
+
+static USAGE: &str = "Usage:
+  tokens list
+  tokens create <name> [read-only|read-write|admin]
+  tokens revoke <id>";
+
+/* Runs a `tokens` subcommand given on the command line, e.g. `pragma-server tokens list`. */
+pub fn run(config: &Config, args: &[String]) -> Result<(), String> {
+    let pool = repo_connection::create_pool(&config.database.url);
+    let connection = pool.get().map_err(|e| format!("{}", e))?;
+    repo::setup(&connection);
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["list"] => list(&connection),
+        ["create", name] => create(name, Scope::ReadWrite, &connection),
+        ["create", name, scope] => create(name, scope.parse()?, &connection),
+        ["revoke", id] => revoke(id, &connection),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn list(connection: &SqliteConnection) -> Result<(), String> {
+    for api_token in repo::api_tokens(connection)? {
+        let last_used_at = api_token
+            .last_used_at
+            .map_or_else(|| "never".to_string(), |date| date.to_rfc3339());
+
+        println!(
+            "{}\t{}\t{}\tcreated {}\tlast used {}",
+            api_token.id,
+            api_token.name,
+            api_token.scope.as_str(),
+            api_token.created_at.to_rfc3339(),
+            last_used_at
+        );
+    }
+
+    Ok(())
+}
+
+fn create(name: &str, scope: Scope, connection: &SqliteConnection) -> Result<(), String> {
+    let token = auth::random_token();
+    let new_api_token = NewApiToken {
+        name: name.to_string(),
+        scope,
+    };
+
+    let api_token = repo::transaction(connection, || {
+        let api_token =
+            repo::create_api_token(new_api_token, auth::hash_token(&token), connection)?;
+        repo::record_audit(
+            &AuditContext::command_line(),
+            "api_token",
+            &api_token.id,
+            AuditAction::Create,
+            connection,
+        )?;
+        Ok(api_token)
+    })?;
+    println!("{}\t{}\t{}", api_token.id, api_token.name, token);
+
+    Ok(())
+}
+
+fn revoke(id: &str, connection: &SqliteConnection) -> Result<(), String> {
+    repo::transaction(connection, || {
+        repo::delete_api_token(id, connection)?;
+        repo::record_audit(
+            &AuditContext::command_line(),
+            "api_token",
+            id,
+            AuditAction::Delete,
+            connection,
+        )
+    })
+}
+
+/* Without a configured AUTH_TOKEN the server would be unusable on first run,
+so an admin token is created and shown once. It's written to stderr rather than logged,
+so that it doesn't end up in log files or log collectors. */
+pub fn ensure_initial_token(connection: &SqliteConnection) -> Result<(), String> {
+    if !repo::api_tokens(connection)?.is_empty() {
+        return Ok(());
+    }
+
+    let token = auth::random_token();
+    let new_api_token = NewApiToken {
+        name: "initial".to_string(),
+        scope: Scope::Admin,
+    };
+    repo::create_api_token(new_api_token, auth::hash_token(&token), connection)?;
+
+    eprintln!(
+        "No AUTH_TOKEN was specified, created admin token {}. It will not be shown again.",
+        token
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::AuditFilter;
+
+    #[test]
+    fn rejects_revoked_tokens() {
+        let connection = repo::tests::connection();
+        let token = auth::random_token();
+        let new_api_token = NewApiToken {
+            name: "backup".to_string(),
+            scope: Scope::ReadOnly,
+        };
+        let api_token =
+            repo::create_api_token(new_api_token, auth::hash_token(&token), &connection).unwrap();
+        assert!(api_token.last_used_at.is_none());
+
+        let authenticated = repo::authenticate_api_token(&auth::hash_token(&token), &connection)
+            .unwrap()
+            .unwrap();
+        assert_eq!(authenticated.id, api_token.id);
+        assert_eq!(authenticated.scope, Scope::ReadOnly);
+        assert!(authenticated.last_used_at.is_some());
+        let unknown = auth::hash_token(&auth::random_token());
+        assert!(repo::authenticate_api_token(&unknown, &connection)
+            .unwrap()
+            .is_none());
+
+        revoke(&api_token.id, &connection).unwrap();
+        assert!(
+            repo::authenticate_api_token(&auth::hash_token(&token), &connection)
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            revoke(&api_token.id, &connection).err(),
+            Some(format!("No token with id {}", api_token.id))
+        );
+
+        let filter = AuditFilter {
+            resource_id: Some(api_token.id.clone()),
+            ..AuditFilter::default()
+        };
+        let entries = repo::audit_log(filter, &connection).unwrap().entries;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, "delete");
+        assert_eq!(entries[0].actor, "cli");
+    }
+
+    #[test]
+    fn creates_initial_token_once() {
+        let connection = repo::tests::connection();
+        ensure_initial_token(&connection).unwrap();
+        ensure_initial_token(&connection).unwrap();
+
+        let api_tokens = repo::api_tokens(&connection).unwrap();
+        assert_eq!(api_tokens.len(), 1);
+        assert_eq!(api_tokens[0].name, "initial");
+        assert_eq!(api_tokens[0].scope, Scope::Admin);
+    }
+}
@@ -0,0 +1,129 @@
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::io::{self, BufRead};
+
+use super::auth;
+use super::config::Config;
+use super::data::{AuditAction, AuditContext, TwoFactorEnrollment};
+use super::repo;
+use super::repo_connection;
+use super::totp;
+
+static ISSUER: &str = "Pragma";
+const RECOVERY_CODE_COUNT: usize = 10;
+
+static USAGE: &str = "Usage:
+  two-factor enroll
+  two-factor disable";
+
+/* A new TOTP secret and recovery codes. They are stored unconfirmed, so they only take effect once
+the user has entered a code from their authenticator app. */
+pub struct Enrollment {
+    pub secret: String,
+    pub recovery_code_hashes: Vec<String>,
+    pub enrollment: TwoFactorEnrollment,
+}
+
+pub fn new_enrollment(username: &str) -> Enrollment {
+    let secret = totp::generate_secret();
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .collect::<String>()
+                .to_lowercase()
+        })
+        .collect();
+
+    Enrollment {
+        recovery_code_hashes: recovery_codes
+            .iter()
+            .map(|code| recovery_code_hash(code))
+            .collect(),
+        enrollment: TwoFactorEnrollment {
+            uri: totp::provisioning_uri(&secret, username, ISSUER),
+            recovery_codes,
+        },
+        secret,
+    }
+}
+
+/* Recovery codes are random like tokens, and are compared case-insensitively. */
+pub fn recovery_code_hash(code: &str) -> String {
+    auth::hash_token(&code.trim().to_lowercase())
+}
+
+/* Runs a `two-factor` subcommand given on the command line, e.g. `pragma-server two-factor enroll`. */
+pub fn run(config: &Config, args: &[String]) -> Result<(), String> {
+    let username = config
+        .auth
+        .username
+        .as_ref()
+        .ok_or_else(|| "Two-factor authentication requires auth.username".to_string())?;
+
+    let pool = repo_connection::create_pool(&config.database.url);
+    let connection = pool.get().map_err(|e| format!("{}", e))?;
+    repo::setup(&connection);
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["enroll"] => {
+            let Enrollment {
+                secret,
+                recovery_code_hashes,
+                enrollment,
+            } = new_enrollment(username);
+            repo::enroll_two_factor(username, secret, recovery_code_hashes, &connection)?;
+
+            println!(
+                "Add this URI to your authenticator app:\n{}\n",
+                enrollment.uri
+            );
+            println!("Recovery codes, each can be used once instead of a code:");
+            for code in &enrollment.recovery_codes {
+                println!("{}", code);
+            }
+            eprintln!("\nEnter the code shown by your authenticator app:");
+
+            let mut code = String::new();
+            io::stdin()
+                .lock()
+                .read_line(&mut code)
+                .map_err(|e| format!("{}", e))?;
+
+            let confirmed = repo::transaction(&connection, || {
+                let confirmed = repo::confirm_two_factor(username, code.trim(), &connection)?;
+                if confirmed {
+                    repo::record_audit(
+                        &AuditContext::command_line(),
+                        "two_factor",
+                        username,
+                        AuditAction::Create,
+                        &connection,
+                    )?;
+                }
+                Ok(confirmed)
+            })?;
+
+            if confirmed {
+                println!("Two-factor authentication is enabled.");
+                Ok(())
+            } else {
+                Err("Invalid code, the enrollment is not confirmed.".to_string())
+            }
+        }
+        ["disable"] => repo::transaction(&connection, || {
+            repo::disable_two_factor(username, &connection)?;
+            repo::record_audit(
+                &AuditContext::command_line(),
+                "two_factor",
+                username,
+                AuditAction::Delete,
+                &connection,
+            )
+        }),
+        _ => Err(USAGE.to_string()),
+    }
+}
@@ -0,0 +1,76 @@
+use actix_web::server::{HttpServer, IntoHttpHandler};
+use futures::Stream;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio_reactor::Handle;
+
+/* A Unix domain socket the server is listening on, either bound from `listen.unix_socket`
+or passed in by systemd socket activation. */
+pub struct UnixSocket {
+    /* Only set if the socket file was created here, systemd removes its own. */
+    path: Option<PathBuf>,
+    accepting: Arc<AtomicBool>,
+}
+
+/* Replaces a socket file left behind by a previous run, but no other kind of file. */
+pub fn bind(path: &Path, mode: u32) -> Result<net::UnixListener, String> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path.display()));
+        }
+        fs::remove_file(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+
+    let listener =
+        net::UnixListener::bind(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    Ok(listener)
+}
+
+impl UnixSocket {
+    /* actix-web 0.7 can only serve a stream of connections on the current thread, so requests
+    over the socket are handled by a single worker. */
+    #[allow(deprecated)]
+    pub fn start<H, F>(
+        server: HttpServer<H, F>,
+        listener: net::UnixListener,
+        path: Option<PathBuf>,
+    ) -> Result<UnixSocket, String>
+    where
+        H: IntoHttpHandler + 'static,
+        F: Fn() -> H + Send + Clone + 'static,
+    {
+        let listener = tokio_uds::UnixListener::from_std(listener, &Handle::default())
+            .map_err(|e| format!("{}", e))?;
+
+        let accepting = Arc::new(AtomicBool::new(true));
+        let open = accepting.clone();
+
+        // The listener is closed with the first connection after `close`.
+        let incoming = listener
+            .incoming()
+            .take_while(move |_| Ok(open.load(Ordering::SeqCst)));
+        server.start_incoming(incoming, false);
+
+        Ok(UnixSocket { path, accepting })
+    }
+
+    /* Stops accepting connections, requests on established connections are still handled. */
+    pub fn close(&self) {
+        self.accepting.store(false, Ordering::SeqCst);
+    }
+
+    pub fn remove(&self) -> io::Result<()> {
+        match self.path {
+            Some(ref path) => fs::remove_file(path),
+            None => Ok(()),
+        }
+    }
+}
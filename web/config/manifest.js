@@ -1,7 +1,7 @@
 const WebpackPwaManifest = require("webpack-pwa-manifest");
 module.exports = new WebpackPwaManifest({
   ios: true,
-  start_url: "/",
+  start_url: ".",
   name: "Pragma",
   short_name: "Pragma",
   description: "Open source personal note taking.",
@@ -230,7 +230,7 @@ module.exports = {
         console.log(message);
       },
       minify: true,
-      navigateFallback: "/",
+      navigateFallback: "index.html",
       navigateFallbackWhitelist: [/^(?!\/__).*/],
       staticFileGlobsIgnorePatterns: [/\.map$/],
     }),